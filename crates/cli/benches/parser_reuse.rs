@@ -0,0 +1,82 @@
+//! Compares extracting the injected regions of many small documents with a new parser for each,
+//! as was done before parsers were reused, against a single reused parser as `format` now does on
+//! each thread. Nested regions are each parsed once for their own injections, so a document with
+//! many regions pays this cost per region.
+//!
+//! The test grammars have to be cloned first with `just prepare`, then run with
+//! `cargo bench --bench parser_reuse`.
+
+use anyhow::Result;
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use pruner::api::injections::{self, ExtractOpts};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+const REGIONS: usize = 500;
+const ROUNDS: usize = 5;
+
+fn main() -> Result<()> {
+  let grammars = common::grammars()?;
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar, run `just prepare`"))?;
+
+  // The contents of every region of one large document, each parsed on its own
+  let regions = (0..REGIONS)
+    .map(|i| format!("# Section {i}\n\nSome *text*.\n\n```sql\nselect {i}\n```\n"))
+    .collect::<Vec<_>>();
+
+  let fresh = fastest(|| {
+    for region in &regions {
+      let mut parser = tree_sitter::Parser::new();
+      let extracted = injections::extract_language_injections(
+        &mut parser,
+        markdown,
+        region.as_bytes(),
+        &ExtractOpts::default(),
+      )?;
+      black_box(extracted);
+    }
+    Ok(())
+  })?;
+
+  let mut parser = tree_sitter::Parser::new();
+  let reused = fastest(|| {
+    for region in &regions {
+      let extracted = injections::extract_language_injections(
+        &mut parser,
+        markdown,
+        region.as_bytes(),
+        &ExtractOpts::default(),
+      )?;
+      black_box(extracted);
+    }
+    Ok(())
+  })?;
+
+  println!("{REGIONS} regions, fastest of {ROUNDS} rounds");
+  println!("  new parser per region: {fresh:?}");
+  println!("  reused parser:         {reused:?}");
+  println!(
+    "  saved per region:      {:?}",
+    fresh.saturating_sub(reused) / REGIONS as u32
+  );
+  Ok(())
+}
+
+/// The fastest of [`ROUNDS`] runs of `f`, after one run to warm up.
+fn fastest(mut f: impl FnMut() -> Result<()>) -> Result<Duration> {
+  f()?;
+  let mut fastest = Duration::MAX;
+  for _ in 0..ROUNDS {
+    let start = Instant::now();
+    f()?;
+    fastest = fastest.min(start.elapsed());
+  }
+  Ok(fastest)
+}
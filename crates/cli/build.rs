@@ -6,4 +6,10 @@ fn main() {
 
   println!("cargo:rerun-if-env-changed=VERSION");
   println!("cargo:rustc-env=VERSION={version}");
+
+  // Grammars are compiled at runtime for the platform pruner itself was built for
+  for key in ["TARGET", "HOST"] {
+    let value = std::env::var(key).expect("cargo sets the build target and host");
+    println!("cargo:rustc-env=BUILD_{key}={value}");
+  }
 }
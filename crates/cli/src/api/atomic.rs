@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use std::{
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Write `contents` to `path` via a temporary file in the same directory which is then renamed into
+/// place, so that readers never observe a partially written file. The permissions of an existing
+/// file are kept, and symlinks are written through rather than replaced.
+pub fn write(path: &Path, contents: &[u8]) -> Result<()> {
+  write_with(path, |file| Ok(file.write_all(contents)?))
+}
+
+/// Like [`write`], with the contents written to the temporary file by `write_contents`. Should it
+/// fail then the temporary file is removed and `path` is left untouched.
+///
+/// As the file is replaced rather than rewritten it is owned by the user running pruner.
+pub fn write_with(
+  path: &Path,
+  write_contents: impl FnOnce(&mut fs::File) -> Result<()>,
+) -> Result<()> {
+  let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+  let temp_path = temp_path_for(&path)?;
+
+  let result = (|| -> Result<()> {
+    let mut file = fs::File::create(&temp_path)?;
+    write_contents(&mut file)?;
+    file.sync_all()?;
+    if let Ok(metadata) = fs::metadata(&path) {
+      fs::set_permissions(&temp_path, metadata.permissions())?;
+    }
+    match fs::rename(&temp_path, &path) {
+      // The temporary file is a sibling of `path`, but bind mounts can still put them on
+      // different devices. Copying is the best that can be done then, though it is not atomic.
+      Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+        log::debug!("Unable to rename over {path:?}, copying instead: {err}");
+        fs::copy(&temp_path, &path)?;
+        fs::remove_file(&temp_path)?;
+      }
+      result => result?,
+    }
+    Ok(())
+  })();
+
+  if result.is_err() {
+    let _ = fs::remove_file(&temp_path);
+  }
+  result.with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn temp_path_for(path: &Path) -> Result<PathBuf> {
+  let file_name = path
+    .file_name()
+    .ok_or_else(|| anyhow::anyhow!("Cannot write to {:?}, it is not a file path", path))?;
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  Ok(path.with_file_name(format!(
+    ".{}.pruner-{}-{nanos}",
+    file_name.to_string_lossy(),
+    std::process::id()
+  )))
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// Where remote config includes are cached within `cache_dir`.
+pub fn include_cache_dir(cache_dir: &Path) -> PathBuf {
+  cache_dir.join("configs")
+}
+
+/// Where the wasm plugin `name` is cached within `cache_dir`, both as downloaded and compiled.
+pub fn plugin_cache_dir(cache_dir: &Path, name: &str) -> PathBuf {
+  cache_dir.join("wasm").join(name)
+}
+
+/// Where the compiled components of the wasm plugin `name` are cached within `cache_dir`.
+pub fn compiled_plugin_dir(cache_dir: &Path, name: &str) -> PathBuf {
+  plugin_cache_dir(cache_dir, name).join("compiled")
+}
+
+/// The caches within `cache_dir` cleared by `clean --format-cache`: remote config includes and
+/// compiled wasm plugins. Downloaded plugins are kept.
+pub fn format_cache_dirs(cache_dir: &Path) -> Result<Vec<PathBuf>> {
+  let mut dirs = vec![include_cache_dir(cache_dir)];
+  let plugins_dir = cache_dir.join("wasm");
+  if plugins_dir.is_dir() {
+    let mut plugins = fs::read_dir(&plugins_dir)
+      .and_then(|entries| {
+        entries
+          .map(|entry| entry.map(|entry| entry.file_name()))
+          .collect::<std::io::Result<Vec<_>>>()
+      })
+      .with_context(|| format!("Failed to read directory {:?}", plugins_dir))?;
+    plugins.sort();
+    for name in plugins {
+      dirs.push(compiled_plugin_dir(cache_dir, &name.to_string_lossy()));
+    }
+  }
+  Ok(dirs)
+}
+
+/// Remove every entry within `dir`, leaving the directory itself in place. Returns the paths which
+/// were (or, when `dry_run` is set, would have been) removed. A missing directory is treated as
+/// already clean.
+pub fn clear_dir(dir: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+
+  let mut entries = fs::read_dir(dir)
+    .with_context(|| format!("Failed to read directory {:?}", dir))?
+    .map(|entry| entry.map(|entry| entry.path()))
+    .collect::<std::io::Result<Vec<_>>>()
+    .with_context(|| format!("Failed to read directory {:?}", dir))?;
+  entries.sort();
+
+  if dry_run {
+    return Ok(entries);
+  }
+
+  for path in &entries {
+    let result = if path.is_dir() {
+      fs::remove_dir_all(path)
+    } else {
+      fs::remove_file(path)
+    };
+    result.with_context(|| format!("Failed to remove {:?}", path))?;
+  }
+
+  Ok(entries)
+}
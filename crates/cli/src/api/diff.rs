@@ -0,0 +1,155 @@
+/// The number of unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Above this many cells the LCS table is considered too expensive to build, and the differing
+/// middle section is reported as a single replacement instead.
+const MAX_LCS_CELLS: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+  Equal(usize, usize),
+  Delete(usize),
+  Insert(usize),
+}
+
+/// Split text into lines, keeping line terminators so that a missing final newline can be detected.
+fn lines(text: &str) -> Vec<&str> {
+  text.split_inclusive('\n').collect()
+}
+
+fn diff_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+  let prefix = old
+    .iter()
+    .zip(new.iter())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let old_mid = &old[prefix..old.len() - suffix];
+  let new_mid = &new[prefix..new.len() - suffix];
+
+  let mut ops = (0..prefix).map(|i| Op::Equal(i, i)).collect::<Vec<_>>();
+
+  let (n, m) = (old_mid.len(), new_mid.len());
+  if (n + 1).saturating_mul(m + 1) > MAX_LCS_CELLS {
+    ops.extend((0..n).map(|i| Op::Delete(prefix + i)));
+    ops.extend((0..m).map(|j| Op::Insert(prefix + j)));
+  } else {
+    // lcs[i][j] is the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let index = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+      for j in (0..m).rev() {
+        lcs[index(i, j)] = if old_mid[i] == new_mid[j] {
+          lcs[index(i + 1, j + 1)] + 1
+        } else {
+          lcs[index(i + 1, j)].max(lcs[index(i, j + 1)])
+        };
+      }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+      if i < n && j < m && old_mid[i] == new_mid[j] {
+        ops.push(Op::Equal(prefix + i, prefix + j));
+        i += 1;
+        j += 1;
+      } else if i < n && (j == m || lcs[index(i + 1, j)] >= lcs[index(i, j + 1)]) {
+        ops.push(Op::Delete(prefix + i));
+        i += 1;
+      } else {
+        ops.push(Op::Insert(prefix + j));
+        j += 1;
+      }
+    }
+  }
+
+  ops.extend((0..suffix).map(|k| Op::Equal(old.len() - suffix + k, new.len() - suffix + k)));
+  ops
+}
+
+fn push_line(out: &mut String, marker: char, line: &str) {
+  out.push(marker);
+  out.push_str(line);
+  if !line.ends_with('\n') {
+    out.push_str("\n\\ No newline at end of file\n");
+  }
+}
+
+/// Format the start and length of a hunk range as used in a unified diff header. Empty ranges refer
+/// to the line before the change.
+fn hunk_range(start: usize, len: usize) -> String {
+  if len == 0 {
+    format!("{start},0")
+  } else if len == 1 {
+    format!("{}", start + 1)
+  } else {
+    format!("{},{len}", start + 1)
+  }
+}
+
+/// Produce a unified diff between `old` and `new`, or `None` if they are identical.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> Option<String> {
+  if old == new {
+    return None;
+  }
+
+  let old_lines = lines(old);
+  let new_lines = lines(new);
+  let ops = diff_ops(&old_lines, &new_lines);
+
+  let changes = ops
+    .iter()
+    .enumerate()
+    .filter(|(_, op)| !matches!(op, Op::Equal(..)))
+    .map(|(index, _)| index)
+    .collect::<Vec<_>>();
+
+  // Group changes whose context would overlap into the same hunk
+  let mut hunks: Vec<(usize, usize)> = Vec::new();
+  for index in changes {
+    let start = index.saturating_sub(CONTEXT_LINES);
+    let end = (index + CONTEXT_LINES + 1).min(ops.len());
+    match hunks.last_mut() {
+      Some((_, last_end)) if start <= *last_end => *last_end = end,
+      _ => hunks.push((start, end)),
+    }
+  }
+
+  let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+  for (start, end) in hunks {
+    let hunk = &ops[start..end];
+
+    let old_start = ops[..start]
+      .iter()
+      .filter(|op| !matches!(op, Op::Insert(_)))
+      .count();
+    let new_start = ops[..start]
+      .iter()
+      .filter(|op| !matches!(op, Op::Delete(_)))
+      .count();
+    let old_len = hunk.iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+    let new_len = hunk.iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+
+    out.push_str(&format!(
+      "@@ -{} +{} @@\n",
+      hunk_range(old_start, old_len),
+      hunk_range(new_start, new_len)
+    ));
+
+    for op in hunk {
+      match *op {
+        Op::Equal(i, _) => push_line(&mut out, ' ', old_lines[i]),
+        Op::Delete(i) => push_line(&mut out, '-', old_lines[i]),
+        Op::Insert(j) => push_line(&mut out, '+', new_lines[j]),
+      }
+    }
+  }
+
+  Some(out)
+}
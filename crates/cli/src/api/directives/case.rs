@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use tree_sitter::{QueryPredicate, QueryPredicateArg};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTransform {
+  Lower,
+  Upper,
+}
+
+impl CaseTransform {
+  fn from_operator(operator: &str) -> Option<Self> {
+    match operator {
+      "lower!" => Some(Self::Lower),
+      "upper!" => Some(Self::Upper),
+      _ => None,
+    }
+  }
+
+  pub fn apply(&self, text: &str) -> String {
+    match self {
+      Self::Lower => text.to_ascii_lowercase(),
+      Self::Upper => text.to_ascii_uppercase(),
+    }
+  }
+}
+
+/// Collect all `lower!` and `upper!` predicates keyed by capture. If a capture has multiple case
+/// predicates then the last one wins.
+pub fn collect(
+  predicates: &[QueryPredicate],
+  strict: bool,
+) -> anyhow::Result<HashMap<u32, CaseTransform>> {
+  let mut map = HashMap::new();
+
+  for pred in predicates {
+    let Some(transform) = CaseTransform::from_operator(pred.operator.as_ref()) else {
+      continue;
+    };
+
+    let Some(capture) = super::parsed(parse_case_predicate(pred), &pred.operator, strict)? else {
+      continue;
+    };
+
+    map.insert(capture, transform);
+  }
+
+  Ok(map)
+}
+
+pub fn apply_case(modifiers: &HashMap<u32, CaseTransform>, capture: u32, text: &str) -> String {
+  match modifiers.get(&capture) {
+    Some(transform) => transform.apply(text),
+    None => text.to_string(),
+  }
+}
+
+fn parse_case_predicate(pred: &QueryPredicate) -> anyhow::Result<u32> {
+  let [QueryPredicateArg::Capture(capture)] = pred.args.as_ref() else {
+    anyhow::bail!("{} predicate requires a single capture argument", pred.operator);
+  };
+  Ok(*capture)
+}
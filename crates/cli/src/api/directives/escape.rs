@@ -1,7 +1,10 @@
 use std::collections::{HashMap, HashSet};
 use tree_sitter::{QueryPredicate, QueryPredicateArg};
 
-pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, HashSet<String>> {
+pub fn collect(
+  predicates: &[QueryPredicate],
+  strict: bool,
+) -> anyhow::Result<HashMap<u32, HashSet<String>>> {
   let mut map: HashMap<u32, HashSet<String>> = HashMap::new();
 
   for pred in predicates {
@@ -9,14 +12,16 @@ pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, HashSet<String>> {
       continue;
     }
 
-    let Ok((capture, escape_chars)) = parse_escape_predicate(pred) else {
+    let Some((capture, escape_chars)) =
+      super::parsed(parse_escape_predicate(pred), "escape!", strict)?
+    else {
       continue;
     };
 
     map.entry(capture).or_default().extend(escape_chars);
   }
 
-  map
+  Ok(map)
 }
 
 pub fn escape_chars(modifiers: &HashMap<u32, HashSet<String>>, capture: u32) -> HashSet<String> {
@@ -37,6 +42,9 @@ fn parse_escape_predicate(pred: &QueryPredicate) -> anyhow::Result<(u32, HashSet
     let QueryPredicateArg::String(value) = arg else {
       anyhow::bail!("Escape predicate only supports string arguments");
     };
+    if value.is_empty() {
+      anyhow::bail!("Escape predicate arguments must not be empty");
+    }
     escape_chars.insert(value.to_string());
   }
 
@@ -1,5 +1,11 @@
 use regex::Regex;
-use std::collections::HashMap;
+use std::{
+  collections::HashMap,
+  sync::{
+    Mutex, OnceLock,
+    atomic::{AtomicUsize, Ordering},
+  },
+};
 use tree_sitter::{QueryPredicate, QueryPredicateArg};
 
 #[derive(Debug, Clone)]
@@ -8,7 +14,10 @@ pub struct GsubRule {
   pub replacement: String,
 }
 
-pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, Vec<GsubRule>> {
+pub fn collect(
+  predicates: &[QueryPredicate],
+  strict: bool,
+) -> anyhow::Result<HashMap<u32, Vec<GsubRule>>> {
   let mut map: HashMap<u32, Vec<GsubRule>> = HashMap::new();
 
   for pred in predicates {
@@ -16,18 +25,17 @@ pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, Vec<GsubRule>> {
       continue;
     }
 
-    let Ok((capture, lua_pattern, lua_replacement)) = parse_gsub_predicate(pred) else {
-      continue;
-    };
-
-    let Ok(rule) = compile_gsub_rule(&lua_pattern, &lua_replacement) else {
+    let rule = parse_gsub_predicate(pred).and_then(|(capture, lua_pattern, lua_replacement)| {
+      Ok((capture, compile_gsub_rule(&lua_pattern, &lua_replacement)?))
+    });
+    let Some((capture, rule)) = super::parsed(rule, "gsub!", strict)? else {
       continue;
     };
 
     map.entry(capture).or_default().push(rule);
   }
 
-  map
+  Ok(map)
 }
 
 pub fn apply_gsub(modifiers: &HashMap<u32, Vec<GsubRule>>, capture: u32, text: &str) -> String {
@@ -66,17 +74,57 @@ fn parse_gsub_predicate(pred: &QueryPredicate) -> anyhow::Result<(u32, String, S
   Ok((*capture, pattern.to_string(), replacement.to_string()))
 }
 
-fn compile_gsub_rule(lua_pattern_src: &str, lua_replacement: &str) -> anyhow::Result<GsubRule> {
-  let ast = lua_pattern::parse(lua_pattern_src)?;
-  let re_src = lua_pattern::try_to_regex(&ast, false, false)?;
-  let regex = Regex::new(&re_src)?;
+/// The most patterns kept compiled at once. The cache is cleared when it fills up, which only
+/// happens when far more distinct patterns are used than any set of queries contains.
+const MAX_CACHED_PATTERNS: usize = 256;
+
+static COMPILATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of Lua patterns compiled to a regex so far, which excludes those served from the
+/// cache.
+#[allow(dead_code)]
+pub fn compilations() -> usize {
+  COMPILATIONS.load(Ordering::Relaxed)
+}
 
+/// Compile a `gsub!` rule from a Lua pattern and replacement.
+pub fn compile_gsub_rule(
+  lua_pattern_src: &str,
+  lua_replacement: &str,
+) -> anyhow::Result<GsubRule> {
   Ok(GsubRule {
-    regex,
+    regex: compile_lua_pattern(lua_pattern_src)?,
     replacement: lua_replacement_to_regex(lua_replacement),
   })
 }
 
+/// Compile `lua_pattern_src` to a regex. Directives are collected afresh for every document and
+/// injected region, so compiled patterns are shared between them rather than compiled each time.
+fn compile_lua_pattern(lua_pattern_src: &str) -> anyhow::Result<Regex> {
+  static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+
+  // The lock is held while compiling so that regions formatted in parallel compile each pattern
+  // only once
+  let mut cache = CACHE
+    .get_or_init(Default::default)
+    .lock()
+    .map_err(|_| anyhow::anyhow!("Gsub pattern cache lock poisoned"))?;
+  if let Some(regex) = cache.get(lua_pattern_src) {
+    return Ok(regex.clone());
+  }
+
+  let ast = lua_pattern::parse(lua_pattern_src)?;
+  let re_src = lua_pattern::try_to_regex(&ast, false, false)?;
+  let regex = Regex::new(&re_src)?;
+  COMPILATIONS.fetch_add(1, Ordering::Relaxed);
+
+  if cache.len() >= MAX_CACHED_PATTERNS {
+    cache.clear();
+  }
+  cache.insert(lua_pattern_src.to_owned(), regex.clone());
+  Ok(regex)
+}
+
 fn lua_replacement_to_regex(repl: &str) -> String {
   // Lua `string.gsub` uses `%1`..`%9` (and `%0`) for capture references and `%%` for a literal `%`.
   // Rust `regex` uses `$1`..`$9` (and `$0`) for capture references and `$$` for a literal `$`.
@@ -1,12 +1,58 @@
 use tree_sitter::QueryProperty;
 
-pub fn is_indented(properties: &[QueryProperty]) -> bool {
-  properties
+const INDENTED_PROPERTY: &str = "pruner.injection.indented";
+
+/// Controls which edges of an indented region get trimmed. Set via the value of the
+/// `pruner.injection.indented` property:
+///
+/// - no value or `"both"`: trim both the leading blank line and the closing delimiter indentation
+/// - `"leading"`: only trim the leading blank line
+/// - `"trailing"`: only trim the closing delimiter indentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndentedTrim {
+  pub leading_line: bool,
+  pub trailing_indent: bool,
+}
+
+impl Default for IndentedTrim {
+  fn default() -> Self {
+    Self {
+      leading_line: true,
+      trailing_indent: true,
+    }
+  }
+}
+
+pub fn trim_spec(properties: &[QueryProperty]) -> Option<IndentedTrim> {
+  let property = properties
     .iter()
-    .any(|property| property.key.as_ref() == "pruner.injection.indented")
+    .find(|property| property.key.as_ref() == INDENTED_PROPERTY)?;
+
+  let spec = match property.value.as_deref() {
+    Some("leading") => IndentedTrim {
+      leading_line: true,
+      trailing_indent: false,
+    },
+    Some("trailing") => IndentedTrim {
+      leading_line: false,
+      trailing_indent: true,
+    },
+    Some("both") | None => IndentedTrim::default(),
+    Some(value) => {
+      log::warn!("Unknown {INDENTED_PROPERTY} value {value:?}, trimming both edges");
+      IndentedTrim::default()
+    }
+  };
+
+  Some(spec)
 }
 
-pub fn trim_bytes(source: &[u8], start_byte: usize, end_byte: usize) -> (usize, usize) {
+pub fn trim_bytes(
+  source: &[u8],
+  start_byte: usize,
+  end_byte: usize,
+  spec: IndentedTrim,
+) -> (usize, usize) {
   let mut start = start_byte;
   let mut end = end_byte;
 
@@ -15,9 +61,12 @@ pub fn trim_bytes(source: &[u8], start_byte: usize, end_byte: usize) -> (usize,
   }
 
   // If the first line is whitespace-only (usually just the newline after an opening delimiter),
-  // drop it so embedded formatters don't see a phantom leading blank line.
+  // drop it so embedded formatters don't see a phantom leading blank line. When content starts on
+  // the same line as the delimiter the first line is not whitespace-only and is kept as-is.
   let slice = &source[start..end];
-  if let Some(newline_index) = slice.iter().position(|b| *b == b'\n') {
+  if spec.leading_line
+    && let Some(newline_index) = slice.iter().position(|b| *b == b'\n')
+  {
     let prefix = &slice[..newline_index];
     let is_whitespace_only = prefix.iter().all(|b| matches!(*b, b' ' | b'\t' | b'\r'));
 
@@ -26,14 +75,18 @@ pub fn trim_bytes(source: &[u8], start_byte: usize, end_byte: usize) -> (usize,
     }
   }
 
-  // Drop trailing indentation before a closing delimiter, but never remove newlines.
-  while end > start {
-    let last = source[end - 1];
-    if matches!(last, b' ' | b'\t') {
-      end -= 1;
-      continue;
+  // Drop trailing indentation before a closing delimiter, but never remove newlines. This only
+  // applies when the closing delimiter sits on its own line, otherwise the trailing whitespace is
+  // part of the content.
+  if spec.trailing_indent {
+    let mut trimmed_end = end;
+    while trimmed_end > start && matches!(source[trimmed_end - 1], b' ' | b'\t') {
+      trimmed_end -= 1;
+    }
+
+    if trimmed_end == start || source[trimmed_end - 1] == b'\n' {
+      end = trimmed_end;
     }
-    break;
   }
 
   (start, end)
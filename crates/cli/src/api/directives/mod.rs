@@ -1,5 +1,24 @@
+pub mod case;
 pub mod escape;
 pub mod gsub;
 pub mod indented;
 pub mod offset;
+pub mod printwidth;
 pub mod trim;
+
+/// Resolve the result of parsing a directive predicate. Invalid predicates are skipped unless
+/// `strict` is set, in which case the parse error is returned.
+pub(crate) fn parsed<T>(
+  result: anyhow::Result<T>,
+  operator: &str,
+  strict: bool,
+) -> anyhow::Result<Option<T>> {
+  match result {
+    Ok(value) => Ok(Some(value)),
+    Err(err) if strict => Err(err.context(format!("Invalid {operator} predicate"))),
+    Err(err) => {
+      log::debug!("Skipping invalid {operator} predicate: {err}");
+      Ok(None)
+    }
+  }
+}
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, ops::Deref};
 use tree_sitter::{Point, QueryPredicate, QueryPredicateArg, Range};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RangeOffset {
   pub start_row: isize,
   pub start_col: isize,
@@ -9,22 +9,47 @@ pub struct RangeOffset {
   pub end_col: isize,
 }
 
-pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, RangeOffset> {
-  let mut map = HashMap::new();
+impl RangeOffset {
+  /// Combine two offsets targeting the same capture by summing each component.
+  pub fn compose(&self, other: &RangeOffset) -> RangeOffset {
+    RangeOffset {
+      start_row: self.start_row + other.start_row,
+      start_col: self.start_col + other.start_col,
+      end_row: self.end_row + other.end_row,
+      end_col: self.end_col + other.end_col,
+    }
+  }
+}
+
+/// Collect all `offset!` predicates keyed by capture. When multiple predicates target the same
+/// capture (for example when an `;; extends` query adds to a base query) they are composed in the
+/// order they appear.
+pub fn collect(
+  predicates: &[QueryPredicate],
+  strict: bool,
+) -> anyhow::Result<HashMap<u32, RangeOffset>> {
+  let mut map: HashMap<u32, RangeOffset> = HashMap::new();
 
   for pred in predicates {
     if pred.operator.deref() != "offset!" {
       continue;
     }
 
-    let Ok((capture, range)) = parse_offset_predicate(pred) else {
+    let Some((capture, range)) = super::parsed(parse_offset_predicate(pred), "offset!", strict)?
+    else {
       continue;
     };
 
-    map.insert(capture, range);
+    map
+      .entry(capture)
+      .and_modify(|existing| {
+        log::debug!("Composing multiple offset! predicates for capture {capture}");
+        *existing = existing.compose(&range);
+      })
+      .or_insert(range);
   }
 
-  map
+  Ok(map)
 }
 
 pub fn apply_offset_to_range(source: &str, range: &Range, offset: &RangeOffset) -> Option<Range> {
@@ -39,6 +64,9 @@ pub fn apply_offset_to_range(source: &str, range: &Range, offset: &RangeOffset)
 
   let new_start_byte = point_to_byte(source, new_start_point)?;
   let new_end_byte = point_to_byte(source, new_end_point)?;
+  if new_start_byte > new_end_byte {
+    return None;
+  }
 
   Some(Range {
     start_byte: new_start_byte,
@@ -95,5 +123,7 @@ fn point_to_byte(source: &str, point: Point) -> Option<usize> {
     byte_index += line.len();
   }
 
-  None
+  // The point just past a trailing newline is the end of the source
+  let rows = source.split_inclusive('\n').count();
+  (point.row == rows && (source.is_empty() || source.ends_with('\n'))).then_some(byte_index)
 }
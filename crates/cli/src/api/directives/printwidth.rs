@@ -0,0 +1,38 @@
+use std::{collections::HashMap, ops::Deref};
+use tree_sitter::{QueryPredicate, QueryPredicateArg};
+
+/// Collect all `printwidth!` predicates keyed by capture, such as
+/// `(#printwidth! @injection.content 120)`. The width is used for the injected region as is, rather
+/// than the parent's width less the region's indent. If a capture has multiple `printwidth!`
+/// predicates then the last one wins.
+pub fn collect(predicates: &[QueryPredicate], strict: bool) -> anyhow::Result<HashMap<u32, u32>> {
+  let mut map = HashMap::new();
+
+  for pred in predicates {
+    if pred.operator.deref() != "printwidth!" {
+      continue;
+    }
+
+    let Some((capture, printwidth)) =
+      super::parsed(parse_printwidth_predicate(pred), "printwidth!", strict)?
+    else {
+      continue;
+    };
+
+    map.insert(capture, printwidth);
+  }
+
+  Ok(map)
+}
+
+fn parse_printwidth_predicate(pred: &QueryPredicate) -> anyhow::Result<(u32, u32)> {
+  let [QueryPredicateArg::Capture(capture), QueryPredicateArg::String(value)] = pred.args.as_ref()
+  else {
+    anyhow::bail!("Printwidth predicate requires a capture and a width argument");
+  };
+
+  let printwidth = value
+    .parse::<u32>()
+    .map_err(|err| anyhow::anyhow!("Invalid printwidth {value:?}: {err}"))?;
+  Ok((*capture, printwidth))
+}
@@ -1,26 +1,31 @@
 use std::{collections::HashMap, ops::Deref};
 use tree_sitter::{QueryPredicate, QueryPredicateArg};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Default)]
 pub struct TrimSpec {
   pub start_linewise: bool,
   pub start_charwise: bool,
   pub end_linewise: bool,
   pub end_charwise: bool,
+  /// A marker line, such as a heredoc's `<<EOF`, removed from the start of the region if present.
+  pub start_delimiter: Option<String>,
+  /// A marker line removed from the end of the region if present.
+  pub end_delimiter: Option<String>,
 }
 
 impl TrimSpec {
   fn default_end_linewise_only() -> Self {
     Self {
-      start_linewise: false,
-      start_charwise: false,
       end_linewise: true,
-      end_charwise: false,
+      ..Default::default()
     }
   }
 }
 
-pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, TrimSpec> {
+pub fn collect(
+  predicates: &[QueryPredicate],
+  strict: bool,
+) -> anyhow::Result<HashMap<u32, TrimSpec>> {
   let mut map = HashMap::new();
 
   for pred in predicates {
@@ -28,21 +33,21 @@ pub fn collect(predicates: &[QueryPredicate]) -> HashMap<u32, TrimSpec> {
       continue;
     }
 
-    let Ok((capture, spec)) = parse_trim_predicate(pred) else {
+    let Some((capture, spec)) = super::parsed(parse_trim_predicate(pred), "trim!", strict)? else {
       continue;
     };
 
     map.insert(capture, spec);
   }
 
-  map
+  Ok(map)
 }
 
 pub fn apply_trim(
   source: &[u8],
   start_byte: usize,
   end_byte: usize,
-  spec: TrimSpec,
+  spec: &TrimSpec,
 ) -> (usize, usize) {
   let mut start = start_byte;
   let mut end = end_byte;
@@ -50,6 +55,13 @@ pub fn apply_trim(
     return (start_byte, end_byte);
   }
 
+  if let Some(delimiter) = &spec.start_delimiter {
+    start = trim_start_delimiter(source, start, end, delimiter.as_bytes());
+  }
+  if let Some(delimiter) = &spec.end_delimiter {
+    end = trim_end_delimiter(source, start, end, delimiter.as_bytes());
+  }
+
   if spec.start_linewise {
     start = trim_start_linewise(source, start, end);
   }
@@ -75,6 +87,22 @@ fn parse_trim_predicate(pred: &QueryPredicate) -> anyhow::Result<(u32, TrimSpec)
       };
       Ok((*capture, TrimSpec::default_end_linewise_only()))
     }
+    3 => {
+      let [QueryPredicateArg::Capture(capture), QueryPredicateArg::String(start_delimiter), QueryPredicateArg::String(end_delimiter)] =
+        pred.args.as_ref()
+      else {
+        anyhow::bail!("Trim predicate contained unexpected arguments");
+      };
+
+      // An empty delimiter leaves that edge of the region alone
+      let delimiter = |value: &str| (!value.is_empty()).then(|| value.to_string());
+      let spec = TrimSpec {
+        start_delimiter: delimiter(start_delimiter),
+        end_delimiter: delimiter(end_delimiter),
+        ..Default::default()
+      };
+      Ok((*capture, spec))
+    }
     5 => {
       let [QueryPredicateArg::Capture(capture), QueryPredicateArg::String(start_linewise), QueryPredicateArg::String(start_charwise), QueryPredicateArg::String(end_linewise), QueryPredicateArg::String(end_charwise)] =
         pred.args.as_ref()
@@ -87,10 +115,11 @@ fn parse_trim_predicate(pred: &QueryPredicate) -> anyhow::Result<(u32, TrimSpec)
         start_charwise: parse_bool_int(start_charwise)?,
         end_linewise: parse_bool_int(end_linewise)?,
         end_charwise: parse_bool_int(end_charwise)?,
+        ..Default::default()
       };
       Ok((*capture, spec))
     }
-    _ => anyhow::bail!("Trim predicate requires 1 or 5 arguments"),
+    _ => anyhow::bail!("Trim predicate requires 1, 3 or 5 arguments"),
   }
 }
 
@@ -106,6 +135,45 @@ fn is_line_whitespace_only(bytes: &[u8]) -> bool {
   bytes.iter().all(|b| matches!(*b, b' ' | b'\t' | b'\r'))
 }
 
+fn trim_line_whitespace(mut line: &[u8]) -> &[u8] {
+  while let [b' ' | b'\t' | b'\r', rest @ ..] = line {
+    line = rest;
+  }
+  while let [rest @ .., b' ' | b'\t' | b'\r'] = line {
+    line = rest;
+  }
+  line
+}
+
+/// Skip the first line of the region if, ignoring surrounding whitespace, it is `delimiter`.
+fn trim_start_delimiter(source: &[u8], start: usize, end: usize, delimiter: &[u8]) -> usize {
+  let slice = &source[start..end];
+  let line_end = slice.iter().position(|b| *b == b'\n');
+  let line = &slice[..line_end.unwrap_or(slice.len())];
+  if trim_line_whitespace(line) != delimiter {
+    return start;
+  }
+
+  line_end.map(|nl_rel| start + nl_rel + 1).unwrap_or(end)
+}
+
+/// Drop the last line of the region if, ignoring surrounding whitespace, it is `delimiter`. The
+/// newline before the dropped line is kept as the end of the remaining content.
+fn trim_end_delimiter(source: &[u8], start: usize, end: usize, delimiter: &[u8]) -> usize {
+  let slice = &source[start..end];
+  let content = slice.strip_suffix(b"\n").unwrap_or(slice);
+  let line_start = content
+    .iter()
+    .rposition(|b| *b == b'\n')
+    .map(|nl_rel| nl_rel + 1)
+    .unwrap_or(0);
+  if trim_line_whitespace(&content[line_start..]) != delimiter {
+    return end;
+  }
+
+  start + line_start
+}
+
 fn trim_start_linewise(source: &[u8], mut start: usize, end: usize) -> usize {
   while start < end {
     let slice = &source[start..end];
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use std::{
+  path::PathBuf,
+  sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{
+  api::{
+    self,
+    format::{FormatContext, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{Config, GrammarCompilerConfig, GrammarSpecs, PluginSpecs},
+  wasm::formatter::WasmFormatter,
+};
+
+/// Options for [`format_str`].
+#[derive(Debug, Clone)]
+pub struct FormatStrOpts {
+  /// The desired print-width of the document, or `0` to disable wrapping.
+  pub printwidth: u32,
+  /// Only format regions containing injected languages, leaving the document root untouched.
+  pub skip_root: bool,
+}
+
+impl Default for FormatStrOpts {
+  fn default() -> Self {
+    Self {
+      printwidth: 80,
+      skip_root: false,
+    }
+  }
+}
+
+/// Everything in a [`Config`] (and the process) which affects how grammars and plugins are
+/// loaded. Two calls with an equal key can share what was loaded for the first.
+#[derive(Debug, PartialEq)]
+struct LoadKey {
+  cwd: PathBuf,
+  grammar_paths: Vec<PathBuf>,
+  query_paths: Vec<PathBuf>,
+  grammar_download_dir: PathBuf,
+  grammar_build_dir: PathBuf,
+  grammar_compiler: GrammarCompilerConfig,
+  grammars: GrammarSpecs,
+  cache_dir: PathBuf,
+  plugins: PluginSpecs,
+  languages: Vec<String>,
+}
+
+struct Loaded {
+  grammars: Grammars,
+  wasm_formatter: WasmFormatter,
+}
+
+type LoadCache = Vec<(LoadKey, Arc<Loaded>)>;
+
+/// Grammars and wasm plugins are expensive to load, so they are kept for the lifetime of the
+/// process and shared between calls using an equivalent config.
+fn cached_load(config: &Config, lang: &str) -> Result<Arc<Loaded>> {
+  static CACHE: OnceLock<Mutex<LoadCache>> = OnceLock::new();
+
+  // Only the grammars referenced by the config, or needed for `lang`, are loaded
+  let mut languages = api::grammar::referenced_languages(config);
+  if let Err(index) = languages.binary_search_by(|language| language.as_str().cmp(lang)) {
+    languages.insert(index, lang.to_string());
+  }
+  let key = LoadKey {
+    cwd: std::env::current_dir()?,
+    grammar_paths: config.grammar_paths.clone(),
+    query_paths: config.query_paths.clone(),
+    grammar_download_dir: config.grammar_download_dir.clone(),
+    grammar_build_dir: config.grammar_build_dir.clone(),
+    grammar_compiler: config.grammar_compiler.clone(),
+    grammars: config.grammars.clone(),
+    cache_dir: config.cache_dir.clone(),
+    plugins: config.plugins.clone(),
+    languages,
+  };
+
+  let mut cache = CACHE
+    .get_or_init(Default::default)
+    .lock()
+    .map_err(|_| anyhow::anyhow!("Grammar cache lock poisoned"))?;
+  if let Some((_, loaded)) = cache.iter().find(|(cached, _)| *cached == key) {
+    return Ok(loaded.clone());
+  }
+
+  let grammars =
+    api::grammar::load_configured_grammars(config, &key.cwd, false, Some(&key.languages))?;
+  let wasm_formatter = WasmFormatter::from_config(config)?;
+  let loaded = Arc::new(Loaded {
+    grammars,
+    wasm_formatter,
+  });
+  cache.push((key, loaded.clone()));
+  Ok(loaded)
+}
+
+/// Format `source` as the language `lang` using a loaded [`Config`].
+///
+/// This is a convenience entrypoint for editor integrations and other library users. Grammars and
+/// wasm plugins are loaded (and cloned if necessary) on first use and cached for subsequent calls.
+pub fn format_str(
+  config: &Config,
+  source: &str,
+  lang: &str,
+  opts: &FormatStrOpts,
+) -> Result<String> {
+  let loaded = cached_load(config, lang)?;
+
+  let context = FormatContext {
+    grammars: &loaded.grammars,
+    languages: &config.formatter_languages,
+    language_aliases: &config.language_aliases,
+    formatters: &config.formatters,
+    wasm_formatter: &loaded.wasm_formatter,
+    formatter_kinds: &config.formatter_kinds,
+    formatter_environment: &config.formatter_environment,
+    strict: false,
+    injection_trailing_newlines: &config.injection_trailing_newlines,
+    injection_indent: &config.injection_indent,
+    injection_reindent: &config.injection_reindent,
+    print_width: &config.print_width,
+    region_stats: None,
+    file_pool: None,
+    region_pool: None,
+    skip_formatters: false,
+    max_depth: None,
+    strict_ignore: config.strict_ignore,
+    grammar_language_map: &config.grammar_language_map,
+    formatter_language_map: &config.formatter_language_map,
+    exclude_languages: &[],
+    document_stats: false,
+    verify_roundtrip: false,
+  };
+  let formatter_kinds = api::format::resolve_formatter_kinds(&context)?;
+  let context = FormatContext {
+    formatter_kinds: &formatter_kinds,
+    ..context
+  };
+
+  let result = api::format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: opts.printwidth,
+      language: lang,
+      indent: 0,
+    },
+    !opts.skip_root,
+    true,
+    &context,
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
@@ -1,11 +1,23 @@
 use anyhow::{Context, Result};
 use rayon::prelude::*;
-use std::{fs, path::Path};
-use tree_sitter::Parser;
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  fs,
+  ops,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+use tree_sitter::{Parser, Range};
 
 use crate::{
-  api::{self, grammar::Grammars, text},
-  config::{FormatterSpecs, LanguageFormatters},
+  api::{
+    self,
+    grammar::{Grammar, Grammars},
+    stats::RegionStats,
+    text::{self, IndentUnit, ReindentMode},
+  },
+  config::{FormatterEnvironment, FormatterKind, FormatterSpecs, LanguageFormatters},
   wasm::formatter::WasmFormatter,
 };
 
@@ -15,9 +27,102 @@ pub use runner::FormatOpts;
 pub struct FormatContext<'a> {
   pub grammars: &'a Grammars,
   pub languages: &'a LanguageFormatters,
-  pub language_aliases: &'a std::collections::HashMap<String, String>,
+  pub language_aliases: &'a HashMap<String, String>,
   pub formatters: &'a FormatterSpecs,
   pub wasm_formatter: &'a WasmFormatter,
+  /// Overrides the kind of formatter a name resolves to, see [`resolve_formatter`]. Filled in by
+  /// [`resolve_formatter_kinds`] so that formatters are not resolved again for every region.
+  pub formatter_kinds: &'a HashMap<String, FormatterKind>,
+  pub formatter_environment: &'a FormatterEnvironment,
+  /// Turn content which would otherwise be silently skipped into hard errors. This is intended for
+  /// CI, editors will generally prefer the default lenient behaviour.
+  pub strict: bool,
+  /// The number of trailing newlines kept by injected regions, keyed by host language.
+  pub injection_trailing_newlines: &'a HashMap<String, usize>,
+  /// The unit used to indent injected regions, keyed by host language. Hosts that are not listed
+  /// infer it from each region.
+  pub injection_indent: &'a HashMap<String, IndentUnit>,
+  /// How injected regions are re-indented, keyed by host language.
+  pub injection_reindent: &'a HashMap<String, ReindentMode>,
+  /// Print widths keyed by language, replacing the width a document of that language would
+  /// otherwise be formatted with.
+  pub print_width: &'a HashMap<String, u32>,
+  /// When set, the injected regions of every document formatted are tallied into it by language.
+  pub region_stats: Option<&'a Mutex<RegionStats>>,
+  /// The pool files are formatted on, bounding how many are formatted at once. Defaults to the
+  /// global rayon pool.
+  pub file_pool: Option<&'a rayon::ThreadPool>,
+  /// The pool injected regions are formatted on, shared by nested regions. Defaults to the pool
+  /// their document is being formatted on, so a single threaded `file_pool` also formats every
+  /// region on that thread.
+  pub region_pool: Option<&'a rayon::ThreadPool>,
+  /// Skip running formatters, so that injected regions only go through pruner's own normalization
+  /// of their indentation, escaping and trailing newlines.
+  pub skip_formatters: bool,
+  /// How deeply injected regions may nest before they are left unformatted, guarding against
+  /// grammars which inject themselves. Defaults to [`DEFAULT_MAX_DEPTH`].
+  pub max_depth: Option<usize>,
+  /// Skip injected regions which a `pruner-ignore` range only partially overlaps.
+  pub strict_ignore: bool,
+  /// Maps a language to the name of the grammar used to parse it, for languages whose grammar is
+  /// named differently.
+  pub grammar_language_map: &'a HashMap<String, String>,
+  /// Maps a language to the language used to select its formatters and substituted for
+  /// `$language`, for formatters which name the language differently to its grammar.
+  pub formatter_language_map: &'a HashMap<String, String>,
+  /// Languages whose injected regions are left untouched, matched against both the language named
+  /// by the injection and the language its alias resolves to.
+  pub exclude_languages: &'a [String],
+  /// Count the injected regions of each language visited and changed within each document,
+  /// reporting them on [`FormatOutcome::stats`].
+  pub document_stats: bool,
+  /// Re-parse every document and injected region which has a grammar after formatting it, failing
+  /// if it has more syntax errors than it did beforehand. This catches formatters which corrupt
+  /// the document they are embedded in. With [`format_lenient`] regions which fail are left
+  /// unformatted, while a failing root document is still an error.
+  pub verify_roundtrip: bool,
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+/// Build a pool running at most `jobs` tasks at once, or `None` to use the global pool.
+pub fn thread_pool(jobs: Option<usize>) -> Result<Option<rayon::ThreadPool>> {
+  jobs
+    .map(|jobs| {
+      rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .context("Failed to build thread pool")
+    })
+    .transpose()
+}
+
+/// Run `op` on `pool`, or on the current pool when none is given.
+fn run_in<R: Send>(pool: Option<&rayon::ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+  match pool {
+    Some(pool) => pool.install(op),
+    None => op(),
+  }
+}
+
+/// A failure to format a single injected region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionError {
+  /// The range of the failed region within the formatted document. Failures in nested injections
+  /// are reported against the range of the outermost region containing them.
+  pub range: Range,
+  pub language: String,
+  pub error: String,
+}
+
+/// The result of [`format_lenient`]. Regions listed in `errors` are left unformatted in `formatted`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOutcome {
+  pub formatted: Vec<u8>,
+  pub errors: Vec<RegionError>,
+  /// Counts of the regions visited and changed, when [`FormatContext::document_stats`] or
+  /// [`FormatContext::region_stats`] is set.
+  pub stats: Option<RegionStats>,
 }
 
 pub fn format(
@@ -27,108 +132,275 @@ pub fn format(
   is_root: bool,
   format_context: &FormatContext,
 ) -> Result<Vec<u8>> {
-  let mut parser = Parser::new();
+  let document = DocumentOpts {
+    format_root,
+    is_root,
+    ..Default::default()
+  };
+  let outcome = format_outcome(source, opts, &document, format_context)?;
+  Ok(outcome.formatted)
+}
+
+/// Format only the injected regions of `source` which overlap `range`, such as an editor selection,
+/// leaving everything else byte-identical. An empty range selects the region containing it.
+///
+/// The root formatter would rewrite the whole document so it is not run. Regions are formatted as a
+/// whole, including the parts outside of `range` when it only partially overlaps them.
+#[allow(dead_code)]
+pub fn format_range(
+  source: &[u8],
+  range: ops::Range<usize>,
+  opts: &FormatOpts,
+  format_context: &FormatContext,
+) -> Result<Vec<u8>> {
+  let document = DocumentOpts {
+    is_root: true,
+    selection: Some(&range),
+    ..Default::default()
+  };
+  let outcome = format_outcome(source, opts, &document, format_context)?;
+  Ok(outcome.formatted)
+}
+
+/// Options shared by every document formatted by [`format_many`].
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOpts {
+  pub printwidth: u32,
+  /// Format the root of each document, not only its injected regions.
+  pub format_root: bool,
+}
+
+/// Format a batch of in-memory documents in parallel, each paired with its root language. Results
+/// are returned in the same order as `inputs`, and a failure only affects its own document.
+#[allow(dead_code)]
+pub fn format_many(
+  inputs: &[(String, Vec<u8>)],
+  opts: &BatchOpts,
+  format_context: &FormatContext,
+) -> Vec<Result<Vec<u8>>> {
+  run_in(format_context.file_pool, || {
+    inputs
+      .par_iter()
+      .map(|(language, source)| {
+        format(
+          source,
+          &FormatOpts {
+            printwidth: opts.printwidth,
+            language,
+            indent: 0,
+          },
+          opts.format_root,
+          true,
+          format_context,
+        )
+      })
+      .collect()
+  })
+}
+
+/// Like [`format`], but failures formatting injected regions are collected into the returned
+/// [`FormatOutcome`] rather than aborting. The regions that succeeded are still applied. Failures
+/// formatting the document root are returned as errors.
+#[allow(dead_code)]
+pub fn format_lenient(
+  source: &[u8],
+  opts: &FormatOpts,
+  format_root: bool,
+  is_root: bool,
+  format_context: &FormatContext,
+) -> Result<FormatOutcome> {
+  let document = DocumentOpts {
+    format_root,
+    is_root,
+    lenient: true,
+    ..Default::default()
+  };
+  format_outcome(source, opts, &document, format_context)
+}
+
+/// Where a document passed to [`format_outcome`] sits, and how it and its regions are formatted.
+#[derive(Debug, Clone, Copy, Default)]
+struct DocumentOpts<'a> {
+  /// The language of the document this one was injected within, or `None` for the root document.
+  parent_language: Option<&'a str>,
+  /// The number of injections this document is nested within.
+  depth: usize,
+  /// Run the root formatter, not only the formatters of injected regions.
+  format_root: bool,
+  /// Whether this is the root document rather than an injected region.
+  is_root: bool,
+  /// Collect failures formatting injected regions into the outcome rather than aborting.
+  lenient: bool,
+  /// Only format the regions overlapping this range.
+  selection: Option<&'a ops::Range<usize>>,
+}
+
+fn format_outcome(
+  source: &[u8],
+  opts: &FormatOpts,
+  document: &DocumentOpts,
+  format_context: &FormatContext,
+) -> Result<FormatOutcome> {
+  let DocumentOpts {
+    parent_language,
+    format_root,
+    is_root,
+    lenient,
+    selection,
+    ..
+  } = *document;
+  let root_opts;
+  let opts = match format_context.print_width.get(opts.language) {
+    Some(printwidth) if is_root => {
+      root_opts = FormatOpts {
+        printwidth: *printwidth,
+        ..*opts
+      };
+      &root_opts
+    }
+    _ => opts,
+  };
 
   let mut formatted_result = Vec::from(source);
 
-  if !is_root || format_root {
+  if (!is_root || format_root) && !format_context.skip_formatters {
+    let formatter_opts = FormatOpts {
+      language: mapped_language(format_context.formatter_language_map, opts.language),
+      ..*opts
+    };
     for format_spec in format_context
       .languages
-      .get(opts.language)
+      .get(formatter_opts.language)
       .unwrap_or(&Vec::new())
     {
-      if (is_root && format_spec.run_in_root()) || (!is_root && format_spec.run_in_injections()) {
+      let runs_here = (is_root && format_spec.run_in_root())
+        || (!is_root
+          && format_spec.run_in_injections()
+          && format_spec.runs_within(parent_language));
+      if runs_here {
         let formatter_name = format_spec.formatter();
 
-        formatted_result = if let Some(formatter) = format_context.formatters.get(formatter_name) {
-          runner::format(formatter, &formatted_result, opts)
-            .context(format!("Failed to run formatter: {formatter_name}"))?
-        } else if format_context.wasm_formatter.has_formatter(formatter_name) {
-          format_context
-            .wasm_formatter
-            .format(formatter_name, &formatted_result, opts)?
-        } else {
-          formatted_result
+        let kind = match format_context.formatter_kinds.get(formatter_name) {
+          Some(kind) => Some(*kind),
+          None => resolve_formatter(formatter_name, format_context)?,
+        };
+        let output = match kind {
+          Some(FormatterKind::Shell) => runner::format(
+            &format_context.formatters[formatter_name],
+            &formatted_result,
+            &formatter_opts,
+            format_context.formatter_environment,
+          )
+          .context(format!("Failed to run formatter: {formatter_name}"))?,
+          Some(FormatterKind::Wasm) => {
+            format_context
+              .wasm_formatter
+              .format(formatter_name, &formatted_result, &formatter_opts)?
+          }
+          None => continue,
+        };
+
+        let unchanged = output == formatted_result;
+        formatted_result = output;
+        if unchanged && format_spec.stop_if_unchanged() {
+          break;
         }
       }
     }
   }
 
-  let Some(grammar) = format_context.grammars.get(opts.language) else {
-    return Ok(formatted_result);
+  // Formatters commonly emit `\n` regardless of their input, so the line endings of the source are
+  // restored to avoid mixed endings
+  if formatted_result != source
+    && let Some(line_ending) = text::LineEnding::detect(source)
+  {
+    text::normalize_line_endings(&mut formatted_result, line_ending);
+  }
+
+  let grammar_language = mapped_language(format_context.grammar_language_map, opts.language);
+  let Some(grammar) = format_context.grammars.get(grammar_language) else {
+    return Ok(FormatOutcome {
+      formatted: formatted_result,
+      errors: Vec::new(),
+      stats: collected_stats(format_context),
+    });
   };
 
-  let mut injected_regions =
-    api::injections::extract_language_injections(&mut parser, grammar, &formatted_result)?;
+  let mut injected_regions = with_parser(|parser| {
+    api::injections::extract_language_injections(
+      parser,
+      grammar,
+      &formatted_result,
+      &api::injections::ExtractOpts {
+        strict: format_context.strict,
+        strict_ignore: format_context.strict_ignore,
+      },
+    )
+  })?;
+  // Regions which are excluded, or can neither be formatted nor contain further injections, are
+  // left untouched
+  injected_regions.retain(|region| {
+    let language = region_language(region, format_context);
+    !is_excluded(region, language, format_context) && is_formattable(language, format_context)
+  });
+  if let Some(selection) = selection {
+    let selection_end = selection.end.max(selection.start + 1);
+    injected_regions.retain(|region| {
+      region.range.start_byte < selection_end && selection.start < region.range.end_byte
+    });
+  }
   // Sort in reverse order. File modifications can therefore be applied from end to start
   injected_regions.sort_by(|a, b| b.range.start_byte.cmp(&a.range.start_byte));
 
-  let formatted_regions = injected_regions
-    .par_iter()
-    .map(|region| {
-      let source_slice = &formatted_result[region.range.start_byte..region.range.end_byte];
-      let escape_chars = text::sort_escape_chars(&region.opts.escape_chars);
-      let source_str = String::from_utf8(Vec::from(source_slice))?;
-      let unescaped_source_str = if escape_chars.is_empty() {
-        source_str
-      } else {
-        text::unescape_text(&source_str, &escape_chars)
-      };
+  let nested = DocumentOpts {
+    parent_language: Some(opts.language),
+    depth: document.depth + 1,
+    is_root: false,
+    selection: None,
+    ..*document
+  };
+  let formatted_regions = run_in(format_context.region_pool, || {
+    injected_regions
+      .par_iter()
+      .map(|region| {
+        let result = format_region(region, &formatted_result, opts, &nested, format_context);
+        (region, result)
+      })
+      .collect::<Vec<_>>()
+  });
 
-      let mut indent = text::column_for_byte(&formatted_result, region.range.start_byte);
-      let mut indent_from_content = false;
-      let mut normalized_source = unescaped_source_str;
-      if indent > 0 {
-        normalized_source = text::strip_leading_indent(&normalized_source, indent);
-      } else {
-        let min_indent = text::min_leading_indent(&normalized_source);
-        if min_indent > 0 {
-          normalized_source = text::strip_leading_indent(&normalized_source, min_indent);
-          indent = min_indent;
-          indent_from_content = true;
+  let mut errors = Vec::new();
+  let mut stats = collected_stats(format_context);
+  let mut region_results = Vec::with_capacity(formatted_regions.len());
+  for (region, result) in formatted_regions {
+    if let Some(stats) = &mut stats {
+      let original = &formatted_result[region.range.start_byte..region.range.end_byte];
+      let changed = result
+        .as_ref()
+        .is_ok_and(|outcome| outcome.formatted != original);
+      stats.record(region_language(region, format_context), changed);
+    }
+    match result {
+      Ok(outcome) => {
+        if let (Some(stats), Some(nested)) = (&mut stats, outcome.stats) {
+          stats.merge(nested);
         }
+        errors.extend(outcome.errors.into_iter().map(|error| RegionError {
+          range: region.range,
+          ..error
+        }));
+        region_results.push((region, outcome.formatted));
       }
-
-      let unescaped_source = normalized_source.into_bytes();
-      let trailing_newlines = text::trailing_newlines(source_slice);
-      let adjusted_printwidth = opts.printwidth.saturating_sub(indent as u32);
-      let mut formatted_sub_result = format(
-        &unescaped_source,
-        &FormatOpts {
-          printwidth: adjusted_printwidth.max(1),
-          language: format_context
-            .language_aliases
-            .get(&region.lang)
-            .map(|s| s.as_str())
-            .unwrap_or(region.lang.as_str()),
-        },
-        format_root,
-        false,
-        format_context,
-      )?;
-      if !escape_chars.is_empty() {
-        let formatted_str = String::from_utf8(formatted_sub_result)?;
-        formatted_sub_result = text::escape_text(&formatted_str, &escape_chars).into_bytes();
+      Err(err) if lenient => {
+        log::warn!("Failed to format {} region: {err:#}", region.lang);
+        errors.push(RegionError {
+          range: region.range,
+          language: region.lang.clone(),
+          error: format!("{err:#}"),
+        });
       }
-
-      text::strip_trailing_newlines(&mut formatted_sub_result);
-      formatted_sub_result.extend_from_slice(&trailing_newlines);
-      if indent_from_content && indent > 0 {
-        if formatted_sub_result.first() != Some(&b'\n')
-          && formatted_sub_result.first() != Some(&b'\r')
-        {
-          let spaces = vec![b' '; indent];
-          formatted_sub_result.splice(0..0, spaces);
-        }
-      }
-      text::offset_lines(&mut formatted_sub_result, indent);
-      Ok((region.clone(), formatted_sub_result))
-    })
-    .collect::<Vec<Result<(api::injections::InjectedRegion, Vec<u8>)>>>();
-
-  let mut region_results = Vec::with_capacity(formatted_regions.len());
-  for result in formatted_regions {
-    region_results.push(result?);
+      Err(err) => return Err(err),
+    }
   }
 
   region_results.sort_by(|(a, _), (b, _)| b.range.start_byte.cmp(&a.range.start_byte));
@@ -140,9 +412,356 @@ pub fn format(
     );
   }
 
-  Ok(formatted_result)
+  if format_context.verify_roundtrip
+    && formatted_result != source
+    && syntax_error_count(grammar, &formatted_result)? > syntax_error_count(grammar, source)?
+  {
+    anyhow::bail!(
+      "Formatting introduced syntax errors into {} document",
+      opts.language
+    );
+  }
+
+  // Nested documents are merged into their parent's counts, so only the root adds to the run
+  if document.parent_language.is_none()
+    && let (Some(run_stats), Some(stats)) = (format_context.region_stats, &stats)
+  {
+    let mut run_stats = run_stats.lock().unwrap_or_else(|err| err.into_inner());
+    run_stats.merge(stats.clone());
+  }
+
+  Ok(FormatOutcome {
+    formatted: formatted_result,
+    errors,
+    stats,
+  })
+}
+
+/// Empty counts to fill in for a document, when either its own or the run's stats are collected.
+fn collected_stats(format_context: &FormatContext) -> Option<RegionStats> {
+  (format_context.document_stats || format_context.region_stats.is_some())
+    .then(RegionStats::default)
+}
+
+thread_local! {
+  static PARSER: RefCell<Parser> = RefCell::new(Parser::new());
+}
+
+/// Run `f` with this thread's parser. Documents with many injected regions are parsed once per
+/// region, so reusing a parser saves allocating a new one and, for sibling regions in the same
+/// language, resetting its language each time.
+///
+/// The parser is only borrowed for the duration of `f`, which must not format nested regions, as
+/// rayon may run them on this same thread while it waits.
+fn with_parser<T>(f: impl FnOnce(&mut Parser) -> T) -> T {
+  PARSER.with_borrow_mut(f)
+}
+
+/// The number of `ERROR` and missing nodes in `source` when parsed with `grammar`. Comparing counts
+/// rather than whether there are any errors at all still catches formatters which corrupt a
+/// document that was already partially broken.
+fn syntax_error_count(grammar: &Grammar, source: &[u8]) -> Result<usize> {
+  let tree = with_parser(|parser| {
+    grammar.configure_parser(parser)?;
+    parser
+      .parse(source, None)
+      .ok_or_else(|| anyhow::anyhow!("Failed to parse {} document", grammar.name))
+  })?;
+
+  let mut count = 0;
+  let mut cursor = tree.walk();
+  let mut descend = tree.root_node().has_error();
+  loop {
+    let node = cursor.node();
+    if descend && (node.is_error() || node.is_missing()) {
+      count += 1;
+      descend = false;
+    }
+    if descend && cursor.goto_first_child() {
+      descend = cursor.node().has_error();
+      continue;
+    }
+    loop {
+      if cursor.goto_next_sibling() {
+        descend = cursor.node().has_error();
+        break;
+      }
+      if !cursor.goto_parent() {
+        return Ok(count);
+      }
+    }
+  }
+}
+
+/// The canonical language of an injected region, with any alias resolved.
+fn region_language<'a>(
+  region: &'a api::injections::InjectedRegion,
+  format_context: &'a FormatContext,
+) -> &'a str {
+  format_context
+    .language_aliases
+    .get(&region.lang)
+    .map(|s| s.as_str())
+    .unwrap_or(region.lang.as_str())
+}
+
+fn is_excluded(
+  region: &api::injections::InjectedRegion,
+  language: &str,
+  format_context: &FormatContext,
+) -> bool {
+  format_context
+    .exclude_languages
+    .iter()
+    .any(|excluded| *excluded == region.lang || excluded == language)
+}
+
+/// Resolve the kind of formatter `name` refers to, or `None` if it is neither a formatter nor a
+/// plugin, in which case it leaves documents unchanged. A name configured as both resolves to the
+/// formatter unless [`FormatContext::formatter_kinds`] says otherwise, and it is an error for that
+/// override to name a kind which is not configured.
+pub fn resolve_formatter(
+  name: &str,
+  format_context: &FormatContext,
+) -> Result<Option<FormatterKind>> {
+  let is_shell = format_context.formatters.contains_key(name);
+  let is_wasm = format_context.wasm_formatter.has_formatter(name);
+  match format_context.formatter_kinds.get(name) {
+    Some(FormatterKind::Shell) => {
+      anyhow::ensure!(is_shell, "Formatter {name} is configured as shell but is not a formatter");
+      Ok(Some(FormatterKind::Shell))
+    }
+    Some(FormatterKind::Wasm) => {
+      anyhow::ensure!(is_wasm, "Formatter {name} is configured as wasm but is not a plugin");
+      Ok(Some(FormatterKind::Wasm))
+    }
+    None if is_shell => Ok(Some(FormatterKind::Shell)),
+    None if is_wasm => Ok(Some(FormatterKind::Wasm)),
+    None => Ok(None),
+  }
+}
+
+/// Resolve the kind of every formatter named in [`FormatContext::languages`] up front, for use as
+/// the `formatter_kinds` of the context that documents are then formatted with. This reports any
+/// misconfigured override before anything is formatted, and saves resolving each formatter again
+/// for every document and region it runs on.
+pub fn resolve_formatter_kinds(
+  format_context: &FormatContext,
+) -> Result<HashMap<String, FormatterKind>> {
+  let mut kinds = HashMap::new();
+  for spec in format_context.languages.values().flatten() {
+    let name = spec.formatter();
+    if !kinds.contains_key(name)
+      && let Some(kind) = resolve_formatter(name, format_context)?
+    {
+      kinds.insert(name.to_string(), kind);
+    }
+  }
+  Ok(kinds)
+}
+
+/// Whether a document in `language` has a formatter to run, or a grammar with which to discover
+/// regions nested within it.
+fn is_formattable(language: &str, format_context: &FormatContext) -> bool {
+  format_context
+    .languages
+    .get(mapped_language(format_context.formatter_language_map, language))
+    .is_some_and(|specs| !specs.is_empty())
+    || format_context
+      .grammars
+      .contains_key(mapped_language(format_context.grammar_language_map, language))
+}
+
+/// `language` as renamed by `map`, or unchanged if it is not listed.
+fn mapped_language<'a>(map: &'a HashMap<String, String>, language: &'a str) -> &'a str {
+  map.get(language).map(String::as_str).unwrap_or(language)
+}
+
+fn format_region(
+  region: &api::injections::InjectedRegion,
+  formatted_result: &[u8],
+  opts: &FormatOpts,
+  document: &DocumentOpts,
+  format_context: &FormatContext,
+) -> Result<FormatOutcome> {
+  let source_slice = &formatted_result[region.range.start_byte..region.range.end_byte];
+  let max_depth = format_context.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+  if document.depth > max_depth {
+    log::warn!(
+      "Leaving {} region unformatted, injections are nested more than {max_depth} levels deep",
+      region.lang
+    );
+    return Ok(FormatOutcome {
+      formatted: source_slice.to_vec(),
+      errors: Vec::new(),
+      stats: collected_stats(format_context),
+    });
+  }
+  // A combined region is formatted as one unit, with the document text between its captures, such
+  // as string interpolations, swapped for placeholders which are then split back out of the result
+  let gaps = region
+    .opts
+    .gaps
+    .iter()
+    .map(|gap| &formatted_result[gap.clone()])
+    .collect::<Vec<_>>();
+  let relative_gaps = region
+    .opts
+    .gaps
+    .iter()
+    .map(|gap| gap.start - region.range.start_byte..gap.end - region.range.start_byte)
+    .collect::<Vec<_>>();
+  let (masked_source, placeholders) = text::mask_gaps(source_slice, &relative_gaps);
+
+  let escape_chars = text::sort_escape_chars(&region.opts.escape_chars);
+  let source_str = String::from_utf8(masked_source)?;
+  let unescaped_source_str = if escape_chars.is_empty() {
+    source_str
+  } else {
+    text::unescape_text(&source_str, &escape_chars)
+  };
+
+  let configured_indent_unit = format_context.injection_indent.get(opts.language).copied();
+  let mut indent = text::column_for_byte(formatted_result, region.range.start_byte);
+  let mut indent_from_content = false;
+  let mut normalized_source = unescaped_source_str;
+  let indent_unit;
+  if indent > 0 {
+    // A region starting after only tabs is tab-indented, anything else is measured in spaces
+    let line_start = text::line_start(formatted_result, region.range.start_byte);
+    let prefix = &formatted_result[line_start..region.range.start_byte];
+    indent_unit = configured_indent_unit.unwrap_or(if prefix.iter().all(|byte| *byte == b'\t') {
+      IndentUnit::Tab
+    } else {
+      IndentUnit::Space
+    });
+    normalized_source = text::strip_leading_indent(&normalized_source, indent, indent_unit);
+  } else {
+    indent_unit = configured_indent_unit
+      .or_else(|| IndentUnit::detect(&normalized_source))
+      .unwrap_or_default();
+    let min_indent = text::min_leading_indent(&normalized_source, indent_unit);
+    if min_indent > 0 {
+      normalized_source = text::strip_leading_indent(&normalized_source, min_indent, indent_unit);
+      indent = min_indent;
+      indent_from_content = true;
+    }
+  }
+
+  let unescaped_source = normalized_source.into_bytes();
+  let mut trailing_newlines = text::trailing_newlines(source_slice);
+  if let Some(count) = format_context.injection_trailing_newlines.get(opts.language) {
+    trailing_newlines = text::normalized_trailing_newlines(&trailing_newlines, *count);
+  }
+  let language = region_language(region, format_context);
+  let base_printwidth = format_context
+    .print_width
+    .get(language)
+    .copied()
+    .unwrap_or(opts.printwidth);
+  let indent_columns = indent * indent_unit.width();
+  // A width of `0` disables wrapping, which stays disabled however deeply the region is indented
+  let adjusted_printwidth = match (region.opts.printwidth, base_printwidth) {
+    (Some(printwidth), _) => printwidth,
+    (None, 0) => 0,
+    (None, printwidth) => printwidth.saturating_sub(indent_columns as u32).max(1),
+  };
+  let FormatOutcome {
+    formatted: mut formatted_sub_result,
+    errors: nested_errors,
+    stats: nested_stats,
+  } = format_outcome(
+    &unescaped_source,
+    &FormatOpts {
+      printwidth: adjusted_printwidth,
+      language,
+      indent: indent_columns,
+    },
+    document,
+    format_context,
+  )?;
+  // Regions spanning a single line take the line ending style of the surrounding document
+  if formatted_sub_result != unescaped_source {
+    let line_ending = text::LineEnding::detect(source_slice)
+      .or_else(|| text::LineEnding::detect(formatted_result))
+      .unwrap_or(text::LineEnding::Lf);
+    text::normalize_line_endings(&mut formatted_sub_result, line_ending);
+  }
+  if !escape_chars.is_empty() {
+    let formatted_str = String::from_utf8(formatted_sub_result)?;
+    formatted_sub_result = text::escape_text(&formatted_str, &escape_chars).into_bytes();
+  }
+
+  text::strip_trailing_newlines(&mut formatted_sub_result);
+  formatted_sub_result.extend_from_slice(&trailing_newlines);
+  let reindent = format_context
+    .injection_reindent
+    .get(opts.language)
+    .copied()
+    .unwrap_or_default();
+  if indent_from_content
+    && indent > 0
+    && formatted_sub_result.first() != Some(&b'\n')
+    && formatted_sub_result.first() != Some(&b'\r')
+    && (reindent == ReindentMode::Always
+      || text::leading_indent(&formatted_sub_result, indent_unit) < indent)
+  {
+    let leading_indent = vec![indent_unit.char() as u8; indent];
+    formatted_sub_result.splice(0..0, leading_indent);
+  }
+  text::offset_lines(&mut formatted_sub_result, indent, indent_unit, reindent);
+
+  if !placeholders.is_empty() {
+    formatted_sub_result = text::unmask_gaps(&formatted_sub_result, &placeholders, &gaps)
+      .ok_or_else(|| {
+        anyhow::anyhow!(
+          "Formatting the combined {} region did not keep the text between its captures in place",
+          region.lang
+        )
+      })?;
+  }
+
+  Ok(FormatOutcome {
+    formatted: formatted_sub_result,
+    errors: nested_errors,
+    stats: nested_stats,
+  })
+}
+
+/// Format the contents of a file. Formatters which only add or drop the final newline are
+/// overruled so that the file keeps its own trailing newline policy, avoiding spurious diffs.
+///
+/// A leading UTF-8 BOM is stripped before parsing, so that it is not mistaken for document content
+/// by the root formatter and region offsets, and is then restored on the formatted result.
+fn format_file_contents(
+  content: &[u8],
+  opts: &FormatOpts,
+  skip_root: bool,
+  format_context: &FormatContext,
+) -> Result<FormatOutcome> {
+  let (bom, source) = match content.strip_prefix(text::UTF8_BOM) {
+    Some(source) => (text::UTF8_BOM, source),
+    None => (&[][..], content),
+  };
+
+  let document = DocumentOpts {
+    format_root: !skip_root,
+    is_root: true,
+    ..Default::default()
+  };
+  let mut outcome = format_outcome(source, opts, &document, format_context)
+    .context("Failed to format file contents")?;
+
+  if text::without_final_newline(&outcome.formatted) == text::without_final_newline(source) {
+    outcome.formatted = content.to_vec();
+  } else if !outcome.formatted.starts_with(text::UTF8_BOM) {
+    outcome.formatted.splice(0..0, bom.iter().copied());
+  }
+  Ok(outcome)
 }
 
+/// Format `file`, returning whether its contents changed. When `write` is set the formatted
+/// contents are written atomically with [`api::atomic::write`], keeping the file's permissions.
 pub fn format_file(
   file: &Path,
   write: bool,
@@ -151,65 +770,372 @@ pub fn format_file(
   format_context: &FormatContext,
 ) -> Result<bool> {
   let content = fs::read(file).context("Failed to read temp file after formatting")?;
+  if text::is_binary(&content) {
+    log::debug!("Skipping binary file {}", file.to_string_lossy());
+    return Ok(false);
+  }
 
-  let result = format(&content, opts, !skip_root, true, format_context)
-    .context("Failed to format file contents")?;
+  // Regions are only counted when their summary will be logged, as doing so compares each one
+  let stats_context;
+  let format_context = if log::log_enabled!(log::Level::Debug) {
+    stats_context = FormatContext {
+      document_stats: true,
+      ..*format_context
+    };
+    &stats_context
+  } else {
+    format_context
+  };
+
+  let outcome = format_file_contents(&content, opts, skip_root, format_context)?;
+  if let Some(stats) = &outcome.stats {
+    log::debug!("{}: {stats}", file.to_string_lossy());
+  }
 
+  let result = outcome.formatted;
   if result == content {
     return Ok(false);
   }
 
   if write {
-    fs::write(file, &result).context("Failed to write formatted contents to file")?;
+    api::atomic::write(file, &result).context("Failed to write formatted contents to file")?;
   }
 
   Ok(true)
 }
 
+/// Format `file` without modifying it, returning a unified diff of the changes which formatting
+/// would make or `None` if the file is already formatted.
+#[allow(dead_code)]
+pub fn format_file_diff(
+  file: &Path,
+  opts: &FormatOpts,
+  skip_root: bool,
+  format_context: &FormatContext,
+) -> Result<Option<String>> {
+  let content = fs::read(file).with_context(|| format!("Failed to read {file:?}"))?;
+  if text::is_binary(&content) {
+    log::debug!("Skipping binary file {}", file.to_string_lossy());
+    return Ok(None);
+  }
+
+  let result = format_file_contents(&content, opts, skip_root, format_context)?.formatted;
+
+  if result == content {
+    return Ok(None);
+  }
+
+  let name = file.to_string_lossy();
+  Ok(api::diff::unified_diff(
+    &String::from_utf8_lossy(&content),
+    &String::from_utf8_lossy(&result),
+    &format!("a/{name}"),
+    &format!("b/{name}"),
+  ))
+}
+
+/// Resolve the root language of a file from its extension, returning `None` if the extension is not
+/// present in `language_extensions`.
+pub fn language_for_path<'a>(
+  path: &Path,
+  language_extensions: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+  let extension = path.extension()?.to_str()?;
+  language_extensions.get(extension).map(|lang| lang.as_str())
+}
+
+/// Options controlling which files are visited by [`discover_files`].
+#[derive(Debug, Default, Clone)]
+pub struct WalkOpts {
+  /// Also visit hidden files and directories, which are skipped by default.
+  pub include_hidden: bool,
+  /// Visit files excluded by `.gitignore`, `.ignore` and other ignore files, as well as hidden
+  /// files. By default these are skipped.
+  pub no_ignore: bool,
+  /// Return an error if no files matched the include glob, rather than silently formatting nothing.
+  pub fail_if_empty: bool,
+  /// Only visit files which have changed since this git ref, or are untracked.
+  pub since: Option<String>,
+}
+
+/// A file found by [`discover_files`], along with the root language it will be formatted as.
+#[derive(Debug, Clone)]
+pub struct DiscoveredFile<'a> {
+  pub path: PathBuf,
+  pub language: &'a str,
+}
+
+/// Walk `dir` for files matching any of `include_globs` and none of `exclude_globs`, resolving the
+/// root language of each from `language_extensions`. Files whose extension is not mapped fall back
+/// to `fallback_language`, and are skipped when there is none.
+///
+/// This is the file set visited by [`format_files`], sorted by path.
+#[allow(clippy::too_many_arguments)]
+pub fn discover_files<'a>(
+  dir: &Path,
+  include_globs: &[&str],
+  exclude_globs: Option<Vec<String>>,
+  walk_opts: &WalkOpts,
+  fallback_language: Option<&'a str>,
+  language_extensions: &'a HashMap<String, String>,
+  strict: bool,
+) -> Result<Vec<DiscoveredFile<'a>>> {
+  let paths = discover_paths(dir, include_globs, exclude_globs, walk_opts, strict)?;
+  Ok(with_languages(paths, fallback_language, language_extensions))
+}
+
+/// The files [`discover_files`] visits before their languages are resolved, sorted by path.
+pub fn discover_paths(
+  dir: &Path,
+  include_globs: &[&str],
+  exclude_globs: Option<Vec<String>>,
+  walk_opts: &WalkOpts,
+  strict: bool,
+) -> Result<Vec<PathBuf>> {
+  let mut include_glob_builder = globset::GlobSetBuilder::new();
+  for glob in include_globs {
+    include_glob_builder.add(globset::Glob::new(glob)?);
+  }
+
+  let include_matcher = include_glob_builder.build()?;
+
+  let mut exclude_glob_builder = globset::GlobSetBuilder::new();
+  for glob in exclude_globs.unwrap_or_default() {
+    exclude_glob_builder.add(globset::Glob::new(&glob)?);
+  }
+
+  let exclude_matcher = exclude_glob_builder.build()?;
+
+  let walker = ignore::WalkBuilder::new(dir)
+    .current_dir(dir)
+    .standard_filters(!walk_opts.no_ignore)
+    .hidden(!walk_opts.include_hidden && !walk_opts.no_ignore)
+    .build();
+  let changed = walk_opts
+    .since
+    .as_deref()
+    .map(|since| api::git::changed_files(dir, since))
+    .transpose()?;
+  let mut matched = 0;
+  let mut files = Vec::new();
+  for entry in walker {
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(err) if strict => return Err(anyhow::Error::from(err).context("Failed to walk files")),
+      Err(err) => {
+        log::warn!("Skipping unreadable entry: {err}");
+        continue;
+      }
+    };
+    let path = entry.path();
+    if path.is_dir() || !include_matcher.is_match(path) || exclude_matcher.is_match(path) {
+      continue;
+    }
+    matched += 1;
+    if let Some(changed) = &changed
+      && !fs::canonicalize(path).is_ok_and(|path| changed.contains(&path))
+    {
+      continue;
+    }
+    files.push(entry.into_path());
+  }
+
+  if walk_opts.fail_if_empty && matched == 0 {
+    let globs = include_globs
+      .iter()
+      .map(|glob| format!("{glob:?}"))
+      .collect::<Vec<_>>();
+    anyhow::bail!("No files in {:?} matched {}", dir, globs.join(", "));
+  }
+
+  // Files are reported in this order, so it is kept stable between runs regardless of how the
+  // file system orders directory entries
+  files.sort();
+  Ok(files)
+}
+
+/// Pair each of `paths` with its language as resolved by [`file_language`], skipping those which
+/// have none.
+pub fn with_languages<'a>(
+  paths: Vec<PathBuf>,
+  fallback_language: Option<&'a str>,
+  language_extensions: &'a HashMap<String, String>,
+) -> Vec<DiscoveredFile<'a>> {
+  paths
+    .into_iter()
+    .filter_map(|path| {
+      let language = file_language(&path, fallback_language, language_extensions)?;
+      Some(DiscoveredFile { path, language })
+    })
+    .collect()
+}
+
+/// The language `path` is formatted as, or `None` if it should be skipped.
+pub fn file_language<'a>(
+  path: &Path,
+  fallback_language: Option<&'a str>,
+  language_extensions: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+  let language = language_for_path(path, language_extensions).or(fallback_language);
+  if language.is_none() {
+    log::info!(
+      "Skipping {}, no language is mapped to its extension",
+      path.to_string_lossy()
+    );
+  }
+  language
+}
+
+/// The files to format from a list of `paths` produced elsewhere, such as by `git` or `fd`, rather
+/// than by walking `dir`. Relative paths are resolved against `dir`, and files are skipped as
+/// [`discover_files`] would if they match an exclude glob or have no language. Paths which are not
+/// files are an error when `strict` is set, and are otherwise skipped. The listed order is kept.
+pub fn listed_files<'a>(
+  dir: &Path,
+  paths: &[PathBuf],
+  exclude_globs: Option<Vec<String>>,
+  fallback_language: Option<&'a str>,
+  language_extensions: &'a HashMap<String, String>,
+  strict: bool,
+) -> Result<Vec<DiscoveredFile<'a>>> {
+  let paths = listed_paths(dir, paths, exclude_globs, strict)?;
+  Ok(with_languages(paths, fallback_language, language_extensions))
+}
+
+/// The files [`listed_files`] keeps before their languages are resolved, in the listed order.
+pub fn listed_paths(
+  dir: &Path,
+  paths: &[PathBuf],
+  exclude_globs: Option<Vec<String>>,
+  strict: bool,
+) -> Result<Vec<PathBuf>> {
+  let mut exclude_glob_builder = globset::GlobSetBuilder::new();
+  for glob in exclude_globs.unwrap_or_default() {
+    exclude_glob_builder.add(globset::Glob::new(&glob)?);
+  }
+  let exclude_matcher = exclude_glob_builder.build()?;
+
+  let mut files = Vec::new();
+  for path in paths {
+    let path = dir.join(path);
+    if !path.is_file() {
+      if strict {
+        anyhow::bail!("Listed file {:?} does not exist or is not a file", path);
+      }
+      log::warn!("Skipping {}, it is not a file", path.to_string_lossy());
+      continue;
+    }
+    if exclude_matcher.is_match(&path) {
+      continue;
+    }
+    files.push(path);
+  }
+  Ok(files)
+}
+
+/// Format the files [`discover_files`] finds, returning the paths of those which changed. Files
+/// whose extension is not mapped are formatted as `opts.language`, or skipped when it is empty.
+#[allow(clippy::too_many_arguments, dead_code)]
 pub fn format_files(
   dir: &Path,
-  include_glob: &str,
+  include_globs: &[&str],
   exclude_globs: Option<Vec<String>>,
+  walk_opts: &WalkOpts,
 
   write: bool,
 
   opts: &FormatOpts,
+  language_extensions: &HashMap<String, String>,
   skip_root: bool,
   format_context: &FormatContext,
 ) -> Result<Vec<String>> {
-  let include_matcher = globset::Glob::new(include_glob)?.compile_matcher();
+  let files = discover_files(
+    dir,
+    include_globs,
+    exclude_globs,
+    walk_opts,
+    (!opts.language.is_empty()).then_some(opts.language),
+    language_extensions,
+    format_context.strict,
+  )?;
+  format_discovered_files(&files, write, opts, skip_root, format_context)
+}
 
-  let mut exclude_glob_builder = globset::GlobSetBuilder::new();
-  for glob in exclude_globs.unwrap_or_default() {
-    exclude_glob_builder.add(globset::Glob::new(&glob)?);
+/// The outcome of formatting each of a set of files with [`format_discovered_file_results`].
+#[derive(Debug, Default)]
+pub struct FileResults {
+  /// The paths of the files which changed, or would have had they been written.
+  pub changed: Vec<String>,
+  /// The paths of the files which failed to format, along with why.
+  pub errors: Vec<(String, anyhow::Error)>,
+}
+
+impl FileResults {
+  pub fn append(&mut self, other: FileResults) {
+    self.changed.extend(other.changed);
+    self.errors.extend(other.errors);
   }
 
-  let exclude_matcher = exclude_glob_builder.build()?;
+  /// The changed paths, or the first error should any file have failed.
+  pub fn into_result(self) -> Result<Vec<String>> {
+    match self.errors.into_iter().next() {
+      Some((path, err)) => Err(err.context(format!("Failed to format file {path}"))),
+      None => Ok(self.changed),
+    }
+  }
+}
 
-  let walker = ignore::WalkBuilder::new(dir).current_dir(dir).build();
-  walker
-    .filter_map(|entry| entry.ok())
-    .filter(|entry| !entry.path().is_dir())
-    .filter(|entry| {
-      include_matcher.is_match(entry.path()) && !exclude_matcher.is_match(entry.path())
-    })
-    .par_bridge()
-    .filter_map(
-      |entry| match format_file(entry.path(), write, opts, skip_root, format_context) {
-        Err(err) => {
-          log::error!(
-            "Failed to format file {}: {err}",
-            entry.path().to_string_lossy()
-          );
-          Some(Err(err))
-        }
-        Ok(true) => {
-          let path = entry.path().to_string_lossy();
-          log::info!("{path}");
-          Some(Ok(String::from(path)))
-        }
-        Ok(false) => None,
-      },
-    )
-    .collect::<Result<Vec<String>>>()
+/// Format `files` as found by [`discover_files`], returning the paths of those which changed.
+#[allow(dead_code)]
+pub fn format_discovered_files(
+  files: &[DiscoveredFile],
+  write: bool,
+  opts: &FormatOpts,
+  skip_root: bool,
+  format_context: &FormatContext,
+) -> Result<Vec<String>> {
+  let results = format_discovered_file_results(files, write, opts, skip_root, format_context);
+  for path in &results.changed {
+    log::info!("{path}");
+  }
+  results.into_result()
+}
+
+/// Format `files` as found by [`discover_files`], each as its own language rather than
+/// `opts.language`, continuing past any which fail so that the outcome of every file is reported.
+/// Failures are logged, but it is left to the caller to report the files which changed.
+pub fn format_discovered_file_results(
+  files: &[DiscoveredFile],
+  write: bool,
+  opts: &FormatOpts,
+  skip_root: bool,
+  format_context: &FormatContext,
+) -> FileResults {
+  // Collecting from the pool keeps the discovered order, however the files are scheduled
+  let results = run_in(format_context.file_pool, || {
+    files
+      .par_iter()
+      .map(|file| {
+        let opts = FormatOpts {
+          language: file.language,
+          ..*opts
+        };
+        let result = format_file(&file.path, write, &opts, skip_root, format_context);
+        (file.path.to_string_lossy().to_string(), result)
+      })
+      .collect::<Vec<_>>()
+  });
+
+  let mut file_results = FileResults::default();
+  for (path, result) in results {
+    match result {
+      Err(err) => {
+        log::error!("Failed to format file {path}: {err}");
+        file_results.errors.push((path, err));
+      }
+      Ok(true) => file_results.changed.push(path),
+      Ok(false) => {}
+    }
+  }
+  file_results
 }
@@ -1,18 +1,78 @@
 use anyhow::{Context, Result};
 use std::{
   fs,
-  io::Write,
-  path::PathBuf,
-  process::{Command, Stdio},
-  time::{Instant, SystemTime, UNIX_EPOCH},
+  io::{ErrorKind, Read, Write},
+  path::{Path, PathBuf},
+  process::{Child, Command, Output, Stdio},
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+  thread,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::config::FormatterSpec;
+use encoding_rs::{Encoding, UTF_8};
+use regex::Regex;
+
+use crate::config::{FormatterEnvironment, FormatterSpec};
+
+/// The `sysexits.h` code a formatter can exit with to signal a temporary failure.
+const EX_TEMPFAIL: i32 = 75;
+
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+
+/// A failure which may not recur if the formatter is run again.
+#[derive(Debug)]
+struct TransientError(String);
+
+impl std::fmt::Display for TransientError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for TransientError {}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+  err.downcast_ref::<TransientError>().is_some()
+}
+
+/// Spawning a formatter only fails transiently when the system is briefly out of processes or the
+/// call is interrupted. Anything else, such as a missing command, would fail again.
+fn spawn_error(cmd: &str, err: std::io::Error) -> anyhow::Error {
+  match err.kind() {
+    ErrorKind::WouldBlock | ErrorKind::Interrupted | ErrorKind::ResourceBusy => {
+      TransientError(format!("Failed to spawn formatter {cmd}: {err}")).into()
+    }
+    _ => anyhow::Error::new(err).context(format!("Failed to spawn formatter {cmd}")),
+  }
+}
+
+/// Substituted for `$textwidth` when wrapping is disabled, large enough that nothing wraps while
+/// still fitting the signed 32-bit integers many formatters parse widths into.
+pub const UNLIMITED_PRINTWIDTH: u32 = i32::MAX as u32;
 
 #[derive(Debug)]
 pub struct FormatOpts<'a> {
+  /// The width text should wrap at, or `0` to disable wrapping.
   pub printwidth: u32,
   pub language: &'a str,
+  /// The column an injected region starts at within its host, counted in spaces even when the
+  /// region is indented with tabs. Substituted for `$indent`, and `0` for root documents.
+  /// Formatters which indent their own output should be paired with `if_needed` injection
+  /// reindenting so that the indent is not applied twice.
+  pub indent: usize,
+}
+
+impl FormatOpts<'_> {
+  /// The print width passed to formatters, with `0` standing in for [`UNLIMITED_PRINTWIDTH`].
+  pub fn effective_printwidth(&self) -> u32 {
+    match self.printwidth {
+      0 => UNLIMITED_PRINTWIDTH,
+      printwidth => printwidth,
+    }
+  }
 }
 
 fn unique_temp_file() -> std::io::Result<PathBuf> {
@@ -25,15 +85,294 @@ fn unique_temp_file() -> std::io::Result<PathBuf> {
   Ok(path)
 }
 
-pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Result<Vec<u8>> {
+fn apply_environment(command: &mut Command, environment: &FormatterEnvironment) {
+  if environment.isolated {
+    command.env_clear();
+    for key in ["PATH", "HOME"] {
+      if let Some(value) = std::env::var_os(key) {
+        command.env(key, value);
+      }
+    }
+  }
+
+  if let Some(locale) = &environment.locale {
+    command.env("LC_ALL", locale).env("LANG", locale);
+  }
+
+  if let Some(timezone) = &environment.timezone {
+    command.env("TZ", timezone);
+  }
+}
+
+#[cfg(unix)]
+fn create_fifo(path: &Path) -> std::io::Result<()> {
+  use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+  let c_path = CString::new(path.as_os_str().as_bytes())?;
+  // SAFETY: `c_path` is a valid NUL terminated string which outlives the call.
+  if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } == 0 {
+    Ok(())
+  } else {
+    Err(std::io::Error::last_os_error())
+  }
+}
+
+#[cfg(not(unix))]
+fn create_fifo(_path: &Path) -> std::io::Result<()> {
+  Err(std::io::Error::new(
+    std::io::ErrorKind::Unsupported,
+    "named pipes are not supported on this platform",
+  ))
+}
+
+/// Stream `input` into the named pipe at `path`. Opening the pipe blocks until the formatter opens
+/// it for reading.
+fn spawn_fifo_writer(path: PathBuf, input: Vec<u8>) -> thread::JoinHandle<std::io::Result<()>> {
+  thread::spawn(move || fs::OpenOptions::new().write(true).open(path)?.write_all(&input))
+}
+
+/// Wait for the writer of the named pipe at `path` to finish. If the formatter exited without
+/// reading all of its input the writer would block forever, so the pipe is drained here instead.
+#[cfg(unix)]
+fn finish_fifo_writer(writer: thread::JoinHandle<std::io::Result<()>>, path: &Path) -> Result<()> {
+  use std::os::unix::fs::OpenOptionsExt;
+
+  if !writer.is_finished() {
+    // Opened non-blocking so that this cannot itself block waiting on a writer
+    let mut reader = fs::OpenOptions::new()
+      .read(true)
+      .custom_flags(libc::O_NONBLOCK)
+      .open(path)?;
+    let mut buf = [0; 8192];
+    while !writer.is_finished() {
+      match reader.read(&mut buf) {
+        Ok(read) if read > 0 => continue,
+        Ok(_) => thread::sleep(Duration::from_millis(1)),
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+          thread::sleep(Duration::from_millis(1))
+        }
+        Err(err) => return Err(err.into()),
+      }
+    }
+  }
+
+  join(writer)
+}
+
+#[cfg(not(unix))]
+fn finish_fifo_writer(writer: thread::JoinHandle<std::io::Result<()>>, _path: &Path) -> Result<()> {
+  join(writer)
+}
+
+/// Read all of `reader`, or stop once more than `limit` bytes have been read and set `exceeded`.
+fn spawn_reader<R: Read + Send + 'static>(
+  reader: R,
+  limit: Option<u64>,
+  exceeded: Arc<AtomicBool>,
+) -> thread::JoinHandle<std::io::Result<Vec<u8>>> {
+  thread::spawn(move || {
+    let mut buf = Vec::new();
+    let Some(limit) = limit else {
+      let mut reader = reader;
+      reader.read_to_end(&mut buf)?;
+      return Ok(buf);
+    };
+    reader.take(limit + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > limit {
+      exceeded.store(true, Ordering::SeqCst);
+    }
+    Ok(buf)
+  })
+}
+
+fn join<T>(handle: thread::JoinHandle<std::io::Result<T>>) -> Result<T> {
+  handle
+    .join()
+    .map_err(|_| anyhow::anyhow!("Formatter io thread panicked"))?
+    .map_err(anyhow::Error::from)
+}
+
+/// Feed `input` to the process over stdin and collect its output, killing the process if it has not
+/// exited within `timeout` or if it writes more than `max_output_bytes` to stdout or stderr.
+///
+/// Stdin is written and stdout/stderr are read on separate threads so that a formatter producing
+/// output before consuming all of its input cannot deadlock, and so the limits can be enforced
+/// while the process is still blocked on io.
+fn wait_with_timeout(
+  mut proc: Child,
+  input: Option<&[u8]>,
+  timeout: Option<Duration>,
+  max_output_bytes: Option<u64>,
+  cmd: &str,
+) -> Result<Output> {
+  let stdin = proc.stdin.take();
+  let writer = input.map(Vec::from).map(|input| {
+    thread::spawn(move || -> std::io::Result<()> {
+      if let Some(mut stdin) = stdin {
+        stdin.write_all(&input)?;
+      }
+      Ok(())
+    })
+  });
+
+  let exceeded = Arc::new(AtomicBool::new(false));
+  let stdout = proc
+    .stdout
+    .take()
+    .map(|stdout| spawn_reader(stdout, max_output_bytes, exceeded.clone()))
+    .ok_or_else(|| anyhow::anyhow!("Failed to open stdout"))?;
+  let stderr = proc
+    .stderr
+    .take()
+    .map(|stderr| spawn_reader(stderr, max_output_bytes, exceeded.clone()))
+    .ok_or_else(|| anyhow::anyhow!("Failed to open stderr"))?;
+
+  let output_limit_error = |limit: u64| {
+    anyhow::anyhow!("Formatter {cmd} produced more than {limit} bytes of output")
+  };
+
+  let status = match (timeout, max_output_bytes) {
+    (None, None) => proc.wait()?,
+    _ => {
+      let deadline = timeout.map(|timeout| Instant::now() + timeout);
+      let mut interval = Duration::from_millis(1);
+      loop {
+        if let Some(status) = proc.try_wait()? {
+          break status;
+        }
+
+        // The io threads are deliberately not joined when the process is killed. A process
+        // spawned by the formatter may still be holding the pipes open.
+        if let Some(limit) = max_output_bytes
+          && exceeded.load(Ordering::SeqCst)
+        {
+          let _ = proc.kill();
+          let _ = proc.wait();
+          return Err(output_limit_error(limit));
+        }
+
+        let now = Instant::now();
+        if let (Some(timeout), Some(deadline)) = (timeout, deadline) {
+          if now >= deadline {
+            let _ = proc.kill();
+            let _ = proc.wait();
+            anyhow::bail!(
+              "Formatter {cmd} timed out after {}ms",
+              timeout.as_millis()
+            );
+          }
+          interval = interval.min(deadline - now);
+        }
+
+        thread::sleep(interval);
+        interval = (interval * 2).min(Duration::from_millis(50));
+      }
+    }
+  };
+
+  if let Some(limit) = max_output_bytes
+    && exceeded.load(Ordering::SeqCst)
+  {
+    return Err(output_limit_error(limit));
+  }
+
+  if let Some(writer) = writer {
+    join(writer)?;
+  }
+
+  Ok(Output {
+    status,
+    stdout: join(stdout)?,
+    stderr: join(stderr)?,
+  })
+}
+
+fn encoding(formatter: &FormatterSpec) -> Result<Option<&'static Encoding>> {
+  let Some(label) = &formatter.encoding else {
+    return Ok(None);
+  };
+  match Encoding::for_label(label.as_bytes()) {
+    Some(encoding) if encoding == UTF_8 => Ok(None),
+    Some(encoding) => Ok(Some(encoding)),
+    None => anyhow::bail!("Unknown encoding '{label}' for formatter {}", formatter.cmd),
+  }
+}
+
+fn stderr_ignore(formatter: &FormatterSpec) -> Result<Option<Regex>> {
+  formatter
+    .stderr_ignore
+    .as_deref()
+    .map(|pattern| {
+      Regex::new(pattern)
+        .with_context(|| format!("Invalid stderr_ignore pattern for formatter {}", formatter.cmd))
+    })
+    .transpose()
+}
+
+/// The lines of `stderr` which should fail the run. Without an `ignore` pattern that is any output
+/// at all, otherwise blank lines and those matching `ignore` are skipped.
+fn significant_stderr(stderr: &[u8], ignore: Option<&Regex>) -> Vec<String> {
+  let stderr = String::from_utf8_lossy(stderr);
+  let lines = stderr.lines().map(String::from);
+  match ignore {
+    Some(ignore) => lines
+      .filter(|line| !line.trim().is_empty() && !ignore.is_match(line))
+      .collect(),
+    None => lines.collect(),
+  }
+}
+
+/// Prepare `source` to be passed to the formatter, appending a trailing newline and transcoding it
+/// as configured.
+fn formatter_input(
+  formatter: &FormatterSpec,
+  source: &[u8],
+  use_stdin: bool,
+  encoding: Option<&'static Encoding>,
+) -> Result<Vec<u8>> {
+  let mut input = Vec::from(source);
+  if use_stdin && formatter.stdin_append_newline.unwrap_or(false) && !input.ends_with(b"\n") {
+    input.push(b'\n');
+  }
+
+  let Some(encoding) = encoding else {
+    return Ok(input);
+  };
+
+  let input = String::from_utf8(input).context("Formatter source is not valid UTF-8")?;
+  let (encoded, _, had_errors) = encoding.encode(&input);
+  if had_errors {
+    anyhow::bail!(
+      "Source contains characters which cannot be encoded as {}",
+      encoding.name()
+    );
+  }
+  Ok(encoded.into_owned())
+}
+
+pub fn format(
+  formatter: &FormatterSpec,
+  source: &[u8],
+  opts: &FormatOpts,
+  environment: &FormatterEnvironment,
+) -> Result<Vec<u8>> {
   log::trace!("Calling formatter [{}] with opts {:?}", formatter.cmd, opts);
 
   let use_stdin = formatter.stdin.unwrap_or(true);
+  let encoding = encoding(formatter)?;
+  let stderr_ignore = stderr_ignore(formatter)?;
+  let source = formatter_input(formatter, source, use_stdin, encoding)?;
   let mut temp_file: Option<PathBuf> = None;
+  let mut use_fifo = false;
 
   if !use_stdin {
     let path = unique_temp_file().context("Failed to create temp file for fomatting")?;
-    fs::write(&path, source).context("Failed to write to temp file")?;
+    if formatter.fifo.unwrap_or(false) {
+      match create_fifo(&path) {
+        Ok(()) => use_fifo = true,
+        Err(err) => log::debug!("Falling back to a temp file for [{}]: {err}", formatter.cmd),
+      }
+    }
     temp_file = Some(path);
   }
 
@@ -42,44 +381,80 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
     .map(|path| path.to_string_lossy().to_string())
     .unwrap_or_default();
 
-  let args = formatter.args.iter().map(|arg| {
-    arg
-      .replace("$textwidth", &format!("{}", opts.printwidth))
+  let textwidth = match (&formatter.unlimited_textwidth, opts.printwidth) {
+    (Some(unlimited), 0) => unlimited.clone(),
+    _ => opts.effective_printwidth().to_string(),
+  };
+
+  // Only the known variables are substituted, any other `$` is passed through untouched.
+  let substitute = |value: &str| {
+    value
+      .replace("$textwidth", &textwidth)
       .replace("$language", opts.language)
+      .replace("$indent", &opts.indent.to_string())
       .replace("$file", &file_var)
-  });
+  };
+
+  let args = formatter.args.iter().map(|arg| substitute(arg));
 
-  let mut command = Command::new(&formatter.cmd);
+  let mut command = Command::new(substitute(&formatter.cmd));
   command
     .args(args)
     .stdout(Stdio::piped())
     .stderr(Stdio::piped())
     .stdin(Stdio::piped());
+  apply_environment(&mut command, environment);
+  if let Some(env) = &formatter.env {
+    command.envs(env.iter().map(|(key, value)| (key, substitute(value))));
+  }
 
   let start = Instant::now();
 
-  let result = || -> Result<Vec<u8>> {
-    let mut proc = command.spawn()?;
-
-    if use_stdin {
-      let stdin = proc
-        .stdin
-        .as_mut()
-        .ok_or_else(|| anyhow::anyhow!("Failed to open stdin"))?;
-      stdin.write_all(source)?;
+  let mut attempt = || -> Result<Vec<u8>> {
+    // The temp file is rewritten on every attempt as a failed run may have left it half-formatted
+    if let Some(path) = temp_file.as_ref()
+      && !use_fifo
+    {
+      fs::write(path, &source).context("Failed to write to temp file")?;
     }
 
-    let output = proc.wait_with_output()?;
+    let proc = command
+      .spawn()
+      .map_err(|err| spawn_error(&formatter.cmd, err))?;
 
-    if !output.status.success() {
-      anyhow::bail!(
-        "Failed to run formatter {}: {}",
-        formatter.cmd,
-        String::from_utf8_lossy(&output.stderr)
+    let fifo = temp_file
+      .as_ref()
+      .filter(|_| use_fifo)
+      .map(|path| (path, spawn_fifo_writer(path.clone(), source.clone())));
+
+    let output = wait_with_timeout(
+      proc,
+      use_stdin.then_some(source.as_slice()),
+      formatter.timeout_ms.map(Duration::from_millis),
+      formatter.max_output_bytes,
+      &formatter.cmd,
+    );
+    if let Some((path, writer)) = fifo {
+      finish_fifo_writer(writer, path).context("Failed to write to named pipe")?;
+    }
+    let output = output?;
+    let succeeded = match &formatter.success_codes {
+      Some(codes) => output.status.code().is_some_and(|code| codes.contains(&code)),
+      None => output.status.success(),
+    };
+
+    if !succeeded && output.status.code() == Some(EX_TEMPFAIL) {
+      return Err(
+        TransientError(format!(
+          "Formatter {} failed temporarily: {}",
+          formatter.cmd,
+          String::from_utf8_lossy(&output.stderr)
+        ))
+        .into(),
       );
     }
 
-    if formatter.fail_on_stderr.unwrap_or(false) && !output.stderr.is_empty() {
+    if !succeeded {
       anyhow::bail!(
         "Failed to run formatter {}: {}",
         formatter.cmd,
@@ -87,16 +462,59 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
       );
     }
 
+    if formatter.fail_on_stderr.unwrap_or(false) {
+      let stderr = significant_stderr(&output.stderr, stderr_ignore.as_ref());
+      if !stderr.is_empty() {
+        anyhow::bail!(
+          "Failed to run formatter {}: {}",
+          formatter.cmd,
+          stderr.join("\n")
+        );
+      }
+    }
+
     let mut result = output.stdout;
 
-    if !use_stdin {
-      if let Some(path) = temp_file.as_ref() {
-        result = fs::read(path).context("Failed to read temp file after formatting")?;
+    if !use_stdin
+      && !use_fifo
+      && let Some(path) = temp_file.as_ref()
+    {
+      result = fs::read(path).context("Failed to read temp file after formatting")?;
+    }
+
+    if let Some(encoding) = encoding {
+      let (decoded, _, had_errors) = encoding.decode(&result);
+      if had_errors {
+        anyhow::bail!(
+          "Formatter {} produced output which is not valid {}",
+          formatter.cmd,
+          encoding.name()
+        );
       }
+      result = decoded.into_owned().into_bytes();
     }
 
     Ok(result)
-  }();
+  };
+
+  let retries = formatter.retries.unwrap_or(0);
+  let mut backoff =
+    Duration::from_millis(formatter.retry_backoff_ms.unwrap_or(DEFAULT_RETRY_BACKOFF_MS));
+  let mut result = attempt();
+  for retry in 1..=retries {
+    match &result {
+      Err(err) if is_transient(err) => {
+        log::warn!(
+          "Retrying formatter [{}] in {backoff:?} ({retry}/{retries}): {err}",
+          formatter.cmd
+        );
+        thread::sleep(backoff);
+        backoff *= 2;
+        result = attempt();
+      }
+      _ => break,
+    }
+  }
 
   log::debug!(
     "Formatted using [{}] in: {:?}",
@@ -104,10 +522,10 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
     Instant::now().duration_since(start)
   );
 
-  if let Some(ref path) = temp_file {
-    if let Err(err) = fs::remove_file(path) {
-      log::warn!("Failed to remove temp file {path:?}: {err}");
-    }
+  if let Some(ref path) = temp_file
+    && let Err(err) = fs::remove_file(path)
+  {
+    log::warn!("Failed to remove temp file {path:?}: {err}");
   }
 
   match result {
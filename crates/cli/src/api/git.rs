@@ -1,53 +1,203 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
+  fs,
   path::{Path, PathBuf},
   process::Command,
 };
 use url::Url;
 
+use super::lock::Lockfile;
 use crate::config::GrammarSpec;
 
 pub struct CloneArgs<'a> {
   pub repo: &'a Url,
   pub target_dir: &'a PathBuf,
   pub rev: Option<&'a str>,
+  /// How many commits of history to fetch, where `0` fetches the full history.
+  pub depth: u32,
 }
+
+/// The git config key recording which rev a checkout was fetched for, so that changes to a pinned
+/// rev can be detected without contacting the remote.
+const CHECKED_OUT_REV_KEY: &str = "pruner.rev";
+
+fn run_git(dir: Option<&Path>, args: &[&str]) -> Result<()> {
+  let mut command = Command::new("git");
+  if let Some(dir) = dir {
+    command.arg("-C").arg(dir);
+  }
+  let status = command.args(args).status()?;
+  if !status.success() {
+    anyhow::bail!("Failed to run git {}: {status}", args.join(" "));
+  }
+  Ok(())
+}
+
+fn depth_arg(depth: u32) -> Option<String> {
+  (depth > 0).then(|| format!("--depth={depth}"))
+}
+
+fn checked_out_rev(target_dir: &Path) -> Option<String> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(target_dir)
+    .args(["config", "--get", CHECKED_OUT_REV_KEY])
+    .output()
+    .ok()?;
+  output
+    .status
+    .success()
+    .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Fetch only `rev` into the repo at `target_dir` and check it out, rather than the history of
+/// every branch.
+fn checkout_rev(target_dir: &Path, rev: &str, depth: u32) -> Result<()> {
+  let depth_arg = depth_arg(depth);
+  let mut fetch_args = vec!["fetch", "--quiet"];
+  fetch_args.extend(depth_arg.as_deref());
+  fetch_args.extend(["origin", rev]);
+  run_git(Some(target_dir), &fetch_args)?;
+  run_git(Some(target_dir), &["checkout", "--quiet", "--detach", "FETCH_HEAD"])?;
+  run_git(Some(target_dir), &["config", CHECKED_OUT_REV_KEY, rev])
+}
+
+/// Clone `args.repo` into `args.target_dir`. Repos pinned to a rev have just that rev fetched, and
+/// existing checkouts are moved to it in place when the pinned rev changes. Existing checkouts of
+/// unpinned repos are left as they are.
 pub fn clone(args: CloneArgs) -> Result<()> {
   if args.target_dir.exists() {
-    return Ok(());
+    return match args.rev {
+      Some(rev) if checked_out_rev(args.target_dir).as_deref() != Some(rev) => {
+        log::info!("Updating {} to {rev} ...", args.repo);
+        checkout_rev(args.target_dir, rev, args.depth)
+      }
+      _ => Ok(()),
+    };
   }
 
   log::info!("Cloning {} ...", args.repo);
 
-  let mut clone_args = Vec::from(["clone", "--depth", "1"]);
-  if let Some(rev) = args.rev {
-    clone_args.push("--revision");
-    clone_args.push(rev);
-  }
-
-  clone_args.push(args.repo.as_str());
-  clone_args.push(args.target_dir.to_str().ok_or(anyhow::format_err!(
+  let target_dir = args.target_dir.to_str().ok_or(anyhow::format_err!(
     "Could not convert target dir to string"
-  ))?);
+  ))?;
 
-  let status = Command::new("git").args(clone_args).status()?;
-  if !status.success() {
-    anyhow::bail!("Failed to clone repo: {status}");
-  }
-  Ok(())
+  let Some(rev) = args.rev else {
+    let depth_arg = depth_arg(args.depth);
+    let mut clone_args = vec!["clone"];
+    clone_args.extend(depth_arg.as_deref());
+    clone_args.extend([args.repo.as_str(), target_dir]);
+    return run_git(None, &clone_args).context("Failed to clone repo");
+  };
+
+  run_git(None, &["init", "--quiet", target_dir])?;
+  run_git(Some(args.target_dir), &["remote", "add", "origin", args.repo.as_str()])?;
+  checkout_rev(args.target_dir, rev, args.depth).context("Failed to clone repo")
+}
+
+/// The files within the git repo containing `dir` which differ from `since`, including untracked
+/// files that are not ignored. Paths are canonicalized.
+pub fn changed_files(dir: &Path, since: &str) -> Result<HashSet<PathBuf>> {
+  let git_lines = |args: &[&str]| -> Result<Vec<String>> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+      anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(
+      String::from_utf8(output.stdout)?
+        .split(['\0', '\n'])
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect(),
+    )
+  };
+
+  let toplevel = git_lines(&["rev-parse", "--show-toplevel"])
+    .with_context(|| format!("--since requires {:?} to be within a git repository", dir))?;
+  let toplevel = fs::canonicalize(toplevel.first().map(String::as_str).unwrap_or_default())?;
+
+  let mut changed = git_lines(&["diff", "--name-only", "-z", since, "--"])
+    .with_context(|| format!("Failed to list files changed since {since}"))?;
+  changed.extend(git_lines(&[
+    "ls-files",
+    "--others",
+    "--exclude-standard",
+    "--full-name",
+    "-z",
+    ":/",
+  ])?);
+
+  Ok(changed.into_iter().map(|path| toplevel.join(path)).collect())
 }
 
+/// Clone every grammar which is not yet present. Grammars recorded in `locked` are cloned at their
+/// recorded commit rather than the tip of their `rev`.
 pub fn clone_all_grammars(
   clone_path: &Path,
   grammars: &HashMap<String, GrammarSpec>,
+  locked: Option<&Lockfile>,
+  depth: u32,
 ) -> Result<()> {
   for (lang, spec) in grammars {
+    let locked_commit = locked.and_then(|locked| locked.commit(lang, spec));
     clone(CloneArgs {
       repo: spec.url(),
       target_dir: &clone_path.join(lang),
-      rev: spec.rev(),
+      rev: locked_commit.or(spec.rev()),
+      depth,
     })?;
   }
   Ok(())
 }
+
+/// The full SHA of the commit checked out in `repo_dir`.
+pub fn head_commit(repo_dir: &Path) -> Result<String> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(repo_dir)
+    .args(["rev-parse", "HEAD"])
+    .output()?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "Failed to read the commit checked out in {:?}: {}",
+      repo_dir,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Check that `commit`, checked out in `repo_dir`, is the commit `rev` refers to. Commit SHAs, full
+/// or abbreviated, are compared directly. Other revs are resolved within the repo, and are trusted
+/// if that is not possible as shallow clones do not keep the ref they were cloned from.
+pub fn verify_rev(repo_dir: &Path, rev: &str, commit: &str) -> Result<()> {
+  let is_sha = rev.len() >= 4 && rev.chars().all(|c| c.is_ascii_hexdigit());
+  let expected = if is_sha {
+    Some(rev.to_ascii_lowercase())
+  } else {
+    let output = Command::new("git")
+      .arg("-C")
+      .arg(repo_dir)
+      .args(["rev-parse", "--verify", "--quiet"])
+      .arg(format!("{rev}^{{commit}}"))
+      .output()?;
+    output
+      .status
+      .success()
+      .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+  };
+
+  match expected {
+    Some(expected) if !commit.starts_with(&expected) => anyhow::bail!(
+      "Grammar in {:?} is checked out at {commit}, but is pinned to {rev}. Remove it to clone the \
+       pinned revision",
+      repo_dir
+    ),
+    Some(_) => Ok(()),
+    None => {
+      log::debug!("Unable to resolve {rev} in {repo_dir:?}, trusting the checked out commit");
+      Ok(())
+    }
+  }
+}
@@ -1,31 +1,189 @@
 use anyhow::{Context, Result};
 use rayon::prelude::*;
-use std::{collections::HashMap, fs, path::Path, path::PathBuf};
-use tree_sitter::{Language, Query};
-use tree_sitter_loader::{CompileConfig, Loader};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fs,
+  path::Path,
+  path::PathBuf,
+  time::Instant,
+};
+use tree_sitter::{Language, Parser, Query};
+use tree_sitter_loader::Loader;
 
-use super::queries;
+use super::{injections, lock, queries};
+use crate::config::{Config, GrammarCompilerConfig, GrammarSpec, LanguageFormatters};
+
+mod compile;
 
 #[derive(Debug)]
 pub struct Grammar {
-  #[allow(dead_code)]
   pub name: String,
   pub lang: Language,
   pub injections: Query,
+  /// The grammar's own injections query files, which `injections.scm` files found in the query
+  /// paths are merged onto.
+  pub injections_files: Vec<PathBuf>,
   pub pruner_ignore: Option<Query>,
 }
 
+impl Grammar {
+  /// Set `parser` to parse this grammar's language. Setting a language resets the parser, so it is
+  /// skipped when the parser already uses it.
+  pub fn configure_parser(&self, parser: &mut Parser) -> Result<()> {
+    if parser.language().is_none_or(|language| *language != self.lang) {
+      parser.set_language(&self.lang)?;
+    }
+    Ok(())
+  }
+}
+
 pub type Grammars = HashMap<String, Grammar>;
 
+/// Maps language names to a hash of the grammar sources they were last built from.
+type BuildManifest = BTreeMap<String, String>;
+
+const BUILD_MANIFEST_FILE: &str = "grammar-manifest.json";
+
+fn read_build_manifest(cache_dir: &Path) -> BuildManifest {
+  let path = cache_dir.join(BUILD_MANIFEST_FILE);
+  let Ok(contents) = fs::read(&path) else {
+    return BuildManifest::new();
+  };
+  serde_json::from_slice(&contents).unwrap_or_else(|err| {
+    log::warn!("Ignoring invalid grammar build manifest {path:?}: {err}");
+    BuildManifest::new()
+  })
+}
+
+fn write_build_manifest(cache_dir: &Path, manifest: &BuildManifest) -> Result<()> {
+  fs::create_dir_all(cache_dir)?;
+  fs::write(
+    cache_dir.join(BUILD_MANIFEST_FILE),
+    serde_json::to_vec_pretty(manifest)?,
+  )
+  .context("Failed to write grammar build manifest")
+}
+
+/// Hash every file within a grammar's `src` dir along with the `compiler` config it is built with.
+/// Files are visited in a stable order so that the hash only changes when the content does.
+fn source_hash(src_path: &Path, compiler: &GrammarCompilerConfig) -> Result<String> {
+  let mut files = ignore::WalkBuilder::new(src_path)
+    .standard_filters(false)
+    .build()
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+    .map(|entry| entry.into_path())
+    .collect::<Vec<_>>();
+  files.sort();
+
+  let mut hasher = Sha256::new();
+  for file in files {
+    hasher.update(file.strip_prefix(src_path)?.to_string_lossy().as_bytes());
+    hasher.update(fs::read(&file)?);
+  }
+  for (key, values) in [
+    ("cc", compiler.cc.as_slice()),
+    ("cflags", compiler.cflags.as_deref().unwrap_or_default()),
+    ("defines", compiler.defines.as_deref().unwrap_or_default()),
+  ] {
+    hasher.update(key.as_bytes());
+    for value in values {
+      hasher.update([0]);
+      hasher.update(value.as_bytes());
+    }
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The name the grammar in `src_path` declares, which names its library and language function.
+fn grammar_name(src_path: &Path) -> Result<String> {
+  let grammar_json: serde_json::Value =
+    serde_json::from_slice(&fs::read(src_path.join("grammar.json"))?)?;
+  grammar_json
+    .get("name")
+    .and_then(|name| name.as_str())
+    .map(str::to_string)
+    .ok_or_else(|| anyhow::anyhow!("Grammar in {src_path:?} has no name"))
+}
+
+/// Whether the grammar in `src_path` must be compiled before the library at `lib_path` is loaded.
+///
+/// Source mtimes are unreliable after a fresh clone, so when the manifest has a `previous` hash
+/// for the grammar the library is only rebuilt when the `hash` of its sources has changed.
+/// Otherwise it is rebuilt when any source is newer than it.
+fn needs_build(
+  src_path: &Path,
+  lib_path: &Path,
+  previous: Option<&String>,
+  hash: Option<&String>,
+) -> Result<bool> {
+  match (previous, hash) {
+    (Some(previous), Some(hash)) if previous == hash => Ok(!lib_path.exists()),
+    (Some(_), Some(_)) => Ok(true),
+    _ => compile::is_stale(src_path, lib_path),
+  }
+}
+
+/// The directory grammars are built into when none is given, which is shared with the tree-sitter
+/// CLI.
+fn default_lib_dir() -> PathBuf {
+  xdg::BaseDirectories::with_prefix("tree-sitter")
+    .get_cache_home()
+    .unwrap_or_else(|| std::env::temp_dir().join("tree-sitter"))
+    .join("lib")
+}
+
+/// A directory holding a grammar, along with the external scanner it is built with when that is not
+/// in its `src` directory.
+struct GrammarDir {
+  path: PathBuf,
+  scanner: Option<PathBuf>,
+}
+
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<()> {
+  if fs::read(path).is_ok_and(|existing| existing == contents) {
+    return Ok(());
+  }
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  fs::write(path, contents).with_context(|| format!("Failed to write {path:?}"))
+}
+
+/// Only a `scanner.c` found next to `parser.c` is built, so a grammar whose scanner lives elsewhere
+/// is built from a copy of its `src` dir with the scanner copied in. Files are only rewritten when
+/// their contents change, leaving the mtimes compared to the built library alone.
+fn stage_sources(src_path: &Path, scanner: &Path, staging_dir: &Path) -> Result<()> {
+  let files = ignore::WalkBuilder::new(src_path)
+    .standard_filters(false)
+    .build()
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()));
+  for entry in files {
+    let relative = entry.path().strip_prefix(src_path)?;
+    if relative == Path::new("scanner.c") {
+      continue;
+    }
+    write_if_changed(&staging_dir.join(relative), &fs::read(entry.path())?)?;
+  }
+
+  let scanner_contents =
+    fs::read(scanner).with_context(|| format!("Failed to read external scanner {scanner:?}"))?;
+  write_if_changed(&staging_dir.join("scanner.c"), &scanner_contents)
+}
+
 fn load_grammars_from_path(
-  grammar_path: &Path,
+  grammar_dir: &GrammarDir,
   query_search_paths: &[PathBuf],
-  lib_dir: &Option<PathBuf>,
-) -> Result<Grammars> {
-  let mut loader = match lib_dir {
-    Some(dir) => Loader::with_parser_lib_path(dir.clone()),
-    None => Loader::new()?,
-  };
+  lib_dir: &Path,
+  manifest: Option<&BuildManifest>,
+  compiler: &GrammarCompilerConfig,
+  wanted: &(dyn Fn(&str) -> bool + Sync),
+) -> Result<(Grammars, BuildManifest)> {
+  let grammar_path = &grammar_dir.path;
+  // The loader is only used to discover the grammars, they are compiled and loaded by pruner
+  let mut loader = Loader::with_parser_lib_path(lib_dir.to_path_buf());
 
   loader
     .find_language_configurations_at_path(grammar_path, false)
@@ -37,89 +195,376 @@ fn load_grammars_from_path(
     })?;
 
   let mut languages = HashMap::new();
+  let mut built = BuildManifest::new();
+
+  let configurations = loader
+    .get_all_language_configurations()
+    .into_iter()
+    .map(|(config, path)| {
+      let injections = config
+        .injections_filenames
+        .clone()
+        .unwrap_or_default()
+        .iter()
+        .map(|path| config.root_path.join(path))
+        .collect::<Vec<_>>();
+      (config.language_name.clone(), path.join("src"), injections)
+    })
+    .collect::<Vec<_>>();
+
+  for (language_name, mut src_path, injections) in configurations {
+    if !wanted(&language_name) {
+      log::trace!("Skipping unreferenced grammar {language_name}");
+      continue;
+    }
+
+    // Headers are still found in the original `src` dir and alongside the scanner
+    let mut header_paths = Vec::new();
+    if let Some(scanner) = &grammar_dir.scanner {
+      let staging_dir = lib_dir.join(format!("{language_name}-src"));
+      stage_sources(&src_path, scanner, &staging_dir)
+        .with_context(|| format!("Failed to prepare sources for language {language_name}"))?;
+      header_paths.extend(scanner.parent().map(Path::to_path_buf));
+      header_paths.push(std::mem::replace(&mut src_path, staging_dir));
+    }
 
-  for (config, path) in loader.get_all_language_configurations() {
-    let src_path = path.join("src");
+    let grammar_name = grammar_name(&src_path)?;
+    let mut lib_path = lib_dir.join(&grammar_name);
+    lib_path.set_extension(std::env::consts::DLL_EXTENSION);
 
-    let language = loader
-      .load_language_at_path(CompileConfig::new(&src_path, None, None))
-      .with_context(|| format!("Failed to load language {}", config.language_name))?;
+    let hash = manifest
+      .map(|_| source_hash(&src_path, compiler))
+      .transpose()?;
+    let previous = manifest.and_then(|manifest| manifest.get(&language_name));
+    if needs_build(&src_path, &lib_path, previous, hash.as_ref())? {
+      log::debug!("Compiling grammar {language_name} into {lib_path:?}");
+      compile::compile(&src_path, &header_paths, &lib_path, compiler)
+        .with_context(|| format!("Failed to compile language {language_name}"))?;
+    } else {
+      log::debug!("Grammar {language_name} is unchanged, reusing {lib_path:?}");
+    }
+    if let Some(hash) = hash {
+      built.insert(language_name.clone(), hash);
+    }
 
-    let injections = config
-      .injections_filenames
-      .clone()
-      .unwrap_or_default()
-      .iter()
-      .map(|path| config.root_path.join(path))
-      .collect::<Vec<_>>();
+    let language = compile::load_language(&lib_path, &grammar_name)
+      .with_context(|| format!("Failed to load language {}", language_name))?;
 
     let injections_query = queries::load_injections_query(
       &language,
-      &config.language_name,
+      &language_name,
       &injections,
       query_search_paths,
     )?;
 
     let pruner_ignore = queries::load_optional_query(
       &language,
-      &config.language_name,
+      &language_name,
       "pruner/ignore.scm",
       query_search_paths,
     )?;
 
     languages.insert(
-      config.language_name.clone(),
+      language_name.clone(),
       Grammar {
-        name: config.language_name.clone(),
+        name: language_name.clone(),
         lang: language,
         injections: injections_query,
+        injections_files: injections,
         pruner_ignore,
       },
     );
   }
 
-  Ok(languages)
+  Ok((languages, built))
+}
+
+/// The languages statically injected by `grammar`, those named by an `injection.language` property
+/// rather than captured from the document.
+fn injected_languages(grammar: &Grammar) -> impl Iterator<Item = String> + '_ {
+  (0..grammar.injections.pattern_count()).filter_map(|pattern_index| {
+    injections::get_lang_name(grammar.injections.property_settings(pattern_index))
+  })
 }
 
+/// Whether `grammar` injects languages named by a capture from the document, which could be any
+/// language.
+fn captures_injected_languages(grammar: &Grammar) -> bool {
+  grammar
+    .injections
+    .capture_index_for_name("injection.language")
+    .is_some()
+}
+
+/// Every language `config` refers to by name, which are the languages whose grammars may be needed
+/// to format with it.
+pub fn referenced_languages(config: &Config) -> Vec<String> {
+  let mut languages = config
+    .grammars
+    .keys()
+    .chain(config.languages.keys())
+    .chain(config.language_aliases.keys())
+    .chain(config.language_aliases.values())
+    .chain(config.language_extensions.values())
+    .chain(config.grammar_language_map.keys())
+    .chain(config.grammar_language_map.values())
+    .chain(config.formatter_language_map.keys())
+    .cloned()
+    .collect::<Vec<_>>();
+  languages.sort();
+  languages.dedup();
+  languages
+}
+
+/// Load every grammar found within `grammar_search_paths`, building them into `lib_dir` or, when
+/// none is given, the directory shared with the tree-sitter CLI.
+///
+/// When a `cache_dir` is given a manifest of grammar source hashes is kept there, so grammars whose
+/// sources are unchanged since they were last built are not recompiled.
+///
+/// When a `languages_filter` is given only those grammars, along with any they statically inject
+/// and so on transitively, are loaded. Languages injected under a name captured from the document
+/// can't be known ahead of time, so once a grammar which captures them is loaded every grammar is.
+#[allow(dead_code)]
 pub fn load_grammars(
   grammar_search_paths: &[PathBuf],
   query_search_paths: &[PathBuf],
   lib_dir: Option<PathBuf>,
+  cache_dir: Option<&Path>,
+  compiler: &GrammarCompilerConfig,
+  languages_filter: Option<&[String]>,
 ) -> Result<Grammars> {
-  let mut grammar_paths = grammar_search_paths
+  let grammar_paths = find_grammar_dirs(grammar_search_paths)?
+    .into_iter()
+    .map(|path| GrammarDir {
+      path,
+      scanner: None,
+    })
+    .collect();
+  load_grammar_dirs(
+    grammar_paths,
+    query_search_paths,
+    lib_dir,
+    cache_dir,
+    compiler,
+    languages_filter,
+  )
+}
+
+/// Every directory directly within `grammar_search_paths`, each of which holds a grammar.
+fn find_grammar_dirs(grammar_search_paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+  let grammar_paths = grammar_search_paths
     .par_iter()
     .map(|dir| {
-      let entries = fs::read_dir(dir)
-        .with_context(|| format!("Failed to read directory {:?}", dir))?
-        .filter_map(|entry| match entry {
-          Ok(entry) => {
-            let path = entry.path();
-            if path.is_dir() {
-              Some(path)
-            } else {
-              None
-            }
+      let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Grammar paths are often optional, such as a per-user directory which may not exist yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+          log::warn!("Skipping missing grammar directory {dir:?}");
+          return Ok(Vec::new());
+        }
+        Err(err) => {
+          return Err(err).with_context(|| format!("Failed to read directory {:?}", dir));
+        }
+      };
+      let entries = entries.filter_map(|entry| match entry {
+        Ok(entry) => {
+          let path = entry.path();
+          if path.is_dir() {
+            Some(path)
+          } else {
+            None
           }
-          Err(_) => None,
-        });
+        }
+        Err(_) => None,
+      });
       Ok(entries.collect::<Vec<_>>())
     })
     .collect::<Result<Vec<_>>>()?
     .into_iter()
     .flatten()
     .collect::<Vec<_>>();
+  Ok(grammar_paths)
+}
 
+/// Load the grammars in each of `grammar_paths`, as described for [`load_grammars`].
+fn load_grammar_dirs(
+  mut grammar_paths: Vec<GrammarDir>,
+  query_search_paths: &[PathBuf],
+  lib_dir: Option<PathBuf>,
+  cache_dir: Option<&Path>,
+  compiler: &GrammarCompilerConfig,
+  languages_filter: Option<&[String]>,
+) -> Result<Grammars> {
+  let lib_dir = lib_dir.unwrap_or_else(default_lib_dir);
   let mut languages = HashMap::new();
-  grammar_paths.sort();
+  grammar_paths.sort_by(|a, b| a.path.cmp(&b.path));
 
-  let results = grammar_paths
-    .par_iter()
-    .map(|path| load_grammars_from_path(path, query_search_paths, &lib_dir))
-    .collect::<Result<Vec<_>>>()?;
+  let manifest = cache_dir.map(read_build_manifest);
+  let mut built = BuildManifest::new();
+
+  // Each pass loads the languages injected by those loaded in the pass before, until no new
+  // languages are found. Without pending languages, every grammar not yet loaded is.
+  let mut pending = languages_filter.map(|filter| filter.iter().cloned().collect::<HashSet<_>>());
+  let mut requested = HashSet::new();
+  loop {
+    let wanted = |language: &str| match &pending {
+      Some(pending) => pending.contains(language),
+      None => !languages.contains_key(language),
+    };
+    let results = grammar_paths
+      .par_iter()
+      .map(|path| {
+        load_grammars_from_path(
+          path,
+          query_search_paths,
+          &lib_dir,
+          manifest.as_ref(),
+          compiler,
+          &wanted,
+        )
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    let mut loaded = Vec::new();
+    for (result, hashes) in results {
+      loaded.extend(result.keys().cloned());
+      languages.extend(result);
+      built.extend(hashes);
+    }
 
-  for result in results {
-    languages.extend(result);
+    let captured = loaded.iter().any(|name| captures_injected_languages(&languages[name]));
+    if pending.is_some() && captured {
+      log::debug!("Loading every grammar, as languages injected by name could be any of them");
+      pending = None;
+      continue;
+    }
+    let Some(pending) = &mut pending else {
+      break;
+    };
+    requested.extend(pending.drain());
+    pending.extend(
+      loaded
+        .iter()
+        .flat_map(|name| injected_languages(&languages[name]))
+        .filter(|name| !requested.contains(name)),
+    );
+    if pending.is_empty() {
+      break;
+    }
+  }
+
+  if let (Some(cache_dir), Some(mut manifest)) = (cache_dir, manifest) {
+    manifest.extend(built);
+    write_build_manifest(cache_dir, &manifest)?;
   }
 
   Ok(languages)
 }
+
+/// Clone any configured grammars which are not yet present and load every grammar available to
+/// `config`. Relative directories are resolved against `cwd`.
+///
+/// When a `lockfile` is configured it is updated with the commit each grammar resolved to, unless
+/// `frozen` is set in which case it is an error for the lockfile to change.
+///
+/// Only the grammars needed for `languages_filter` are loaded when one is given, see
+/// [`load_grammars`].
+pub fn load_configured_grammars(
+  config: &Config,
+  cwd: &Path,
+  frozen: bool,
+  languages_filter: Option<&[String]>,
+) -> Result<Grammars> {
+  let repos_dir = cwd.join(&config.grammar_download_dir);
+  let lib_dir = cwd.join(&config.grammar_build_dir);
+
+  fs::create_dir_all(&repos_dir)?;
+  fs::create_dir_all(&lib_dir)?;
+
+  let start = Instant::now();
+  let locked = config.lockfile.as_deref().map(lock::Lockfile::read).transpose()?;
+  super::git::clone_all_grammars(
+    &repos_dir,
+    &config.grammars,
+    locked.as_ref(),
+    config.grammar_clone_depth,
+  )?;
+  // Pinned grammars are verified while resolving the lockfile. Without one there is nothing to keep
+  // reproducible, so the clones are not inspected with git on every run
+  match (&config.lockfile, locked) {
+    (Some(path), Some(locked)) => {
+      let resolved = lock::resolve(&repos_dir, &config.grammars)?;
+      lock::update(path, &locked, &resolved, frozen)?;
+    }
+    _ if frozen => anyhow::bail!("--frozen requires a lockfile to be configured"),
+    _ => {}
+  }
+  log::debug!(
+    "Grammar clone duration: {:?}",
+    Instant::now().duration_since(start)
+  );
+
+  let mut grammar_search_paths = config.grammar_paths.clone();
+  grammar_search_paths.push(repos_dir.clone());
+  // Cloned grammars are loaded from the directory their spec points to within the repo
+  let grammar_paths = find_grammar_dirs(&grammar_search_paths)?
+    .into_iter()
+    .map(|dir| {
+      let spec = dir
+        .strip_prefix(&repos_dir)
+        .ok()
+        .and_then(|lang| config.grammars.get(lang.to_str()?));
+      let path = match spec.and_then(GrammarSpec::path) {
+        Some(spec_path) => dir.join(spec_path),
+        None => dir,
+      };
+      GrammarDir {
+        scanner: spec
+          .and_then(GrammarSpec::scanner)
+          .map(|scanner| path.join(scanner)),
+        path,
+      }
+    })
+    .collect();
+
+  let start = Instant::now();
+  let grammars = load_grammar_dirs(
+    grammar_paths,
+    &config.query_paths,
+    Some(lib_dir),
+    Some(&config.cache_dir),
+    &config.grammar_compiler,
+    languages_filter,
+  )
+    .context("Failed to load grammars")?;
+  log::debug!(
+    "Grammar load duration: {:?}",
+    Instant::now().duration_since(start)
+  );
+
+  Ok(grammars)
+}
+
+/// Returns the names of all languages configured in `languages` which have no corresponding grammar
+/// in `grammars`. The result is sorted to keep error output stable.
+pub fn missing_grammars(languages: &LanguageFormatters, grammars: &Grammars) -> Vec<String> {
+  let mut missing = languages
+    .keys()
+    .filter(|lang| !grammars.contains_key(*lang))
+    .cloned()
+    .collect::<Vec<_>>();
+  missing.sort();
+  missing
+}
+
+pub fn ensure_grammars(languages: &LanguageFormatters, grammars: &Grammars) -> Result<()> {
+  let missing = missing_grammars(languages, grammars);
+  if !missing.is_empty() {
+    anyhow::bail!(
+      "Missing grammars for configured languages: {}",
+      missing.join(", ")
+    );
+  }
+  Ok(())
+}
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+use tree_sitter::Language;
+
+use crate::config::GrammarCompilerConfig;
+
+/// The C sources of the grammar in `src_path`, which are its parser and optional external scanner.
+fn source_files(src_path: &Path) -> Vec<PathBuf> {
+  let mut files = vec![src_path.join("parser.c")];
+  let scanner = src_path.join("scanner.c");
+  if scanner.exists() {
+    files.push(scanner);
+  }
+  files
+}
+
+fn mtime(path: &Path) -> Result<SystemTime> {
+  fs::metadata(path)
+    .and_then(|metadata| metadata.modified())
+    .with_context(|| format!("Failed to read mtime of {path:?}"))
+}
+
+/// Whether the library at `lib_path` is missing or older than any of the grammar's sources.
+pub fn is_stale(src_path: &Path, lib_path: &Path) -> Result<bool> {
+  if !lib_path.exists() {
+    return Ok(true);
+  }
+  let lib_mtime = mtime(lib_path)?;
+  for file in source_files(src_path) {
+    if mtime(&file)? > lib_mtime {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+/// Compile the grammar in `src_path` into a shared library at `lib_path`.
+///
+/// The compiler, flags and defines from `compiler` are given to [`cc::Build`] rather than through
+/// the environment, so they only apply to the compiler process. The library is written to a
+/// temporary path first so that a failed or concurrent build never leaves a partial one behind.
+pub fn compile(
+  src_path: &Path,
+  header_paths: &[PathBuf],
+  lib_path: &Path,
+  compiler: &GrammarCompilerConfig,
+) -> Result<()> {
+  let mut build = cc::Build::new();
+  build
+    .cargo_metadata(false)
+    .cargo_warnings(false)
+    .target(env!("BUILD_TARGET"))
+    .host(env!("BUILD_HOST"))
+    .debug(false)
+    .opt_level(2)
+    .extra_warnings(false)
+    .std("c11")
+    .include(src_path)
+    .includes(header_paths)
+    .files(source_files(src_path));
+  if let Some(cc) = &compiler.cc {
+    build.compiler(cc);
+  }
+  for flag in compiler.cflags.iter().flatten() {
+    build.flag(flag);
+  }
+  for define in compiler.defines.iter().flatten() {
+    build.define(define, None);
+  }
+
+  let tool = build
+    .try_get_compiler()
+    .context("Failed to find a C compiler")?;
+  let tmp_path = lib_path.with_extension(format!("{}.tmp", std::process::id()));
+  let mut command = tool.to_command();
+  if tool.is_like_msvc() {
+    command
+      .args(["-LD", "-utf-8"])
+      .args(build.get_files())
+      .arg("-link")
+      .arg(format!("-out:{}", tmp_path.display()));
+  } else {
+    command.arg("-Werror=implicit-function-declaration");
+    if cfg!(target_os = "macos") {
+      command.arg("-dynamiclib");
+    } else {
+      command.arg("-shared");
+    }
+    command.args(build.get_files()).arg("-o").arg(&tmp_path);
+  }
+
+  if let Some(parent) = lib_path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let output = command
+    .output()
+    .with_context(|| format!("Failed to run the C compiler: {command:?}"))?;
+  if !output.status.success() {
+    let _ = fs::remove_file(&tmp_path);
+    anyhow::bail!(
+      "Grammar compilation failed:\n{}{}",
+      String::from_utf8_lossy(&output.stdout),
+      String::from_utf8_lossy(&output.stderr)
+    );
+  }
+  fs::rename(&tmp_path, lib_path).with_context(|| format!("Failed to write {lib_path:?}"))
+}
+
+/// Load the language exported by the library at `lib_path`, which was built from the grammar
+/// `name`.
+pub fn load_language(lib_path: &Path, name: &str) -> Result<Language> {
+  let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+  // SAFETY: The library was compiled from a tree-sitter grammar, whose only initialisation is
+  // static data.
+  let library = unsafe { libloading::Library::new(lib_path) }
+    .with_context(|| format!("Failed to open grammar library {lib_path:?}"))?;
+  // SAFETY: Tree-sitter grammars export `tree_sitter_<name>` as a function returning their
+  // language.
+  let language = unsafe {
+    let language_fn = library
+      .get::<libloading::Symbol<unsafe extern "C" fn() -> Language>>(symbol.as_bytes())
+      .with_context(|| format!("Failed to load symbol {symbol} from {lib_path:?}"))?;
+    language_fn()
+  };
+  // The language points into the library, so it must stay loaded for the rest of the process
+  std::mem::forget(library);
+  Ok(language)
+}
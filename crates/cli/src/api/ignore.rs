@@ -4,44 +4,139 @@ fn is_comment_node(node: Node) -> bool {
   node.kind().contains("comment")
 }
 
+/// How a `pruner-ignore` marker applies to the nodes around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Marker {
+  /// Ignores this many of the following nodes which are not comments.
+  Next(usize),
+  /// Ignores everything up to the matching [`Marker::End`].
+  Start,
+  End,
+}
+
+/// Classify a marker by its text. `pruner-ignore-next N` ignores the next `N` nodes, while a plain
+/// `pruner-ignore` or a marker without a count ignores one.
+fn marker_kind(node: Node, source: &[u8]) -> Marker {
+  let Ok(text) = node.utf8_text(source) else {
+    return Marker::Next(1);
+  };
+  if text.contains("pruner-ignore-start") {
+    return Marker::Start;
+  }
+  if text.contains("pruner-ignore-end") {
+    return Marker::End;
+  }
+
+  let count = text
+    .split_once("pruner-ignore-next")
+    .and_then(|(_, rest)| rest.split_whitespace().next())
+    .and_then(|count| count.parse().ok());
+  Marker::Next(count.unwrap_or(1))
+}
+
+/// Pair each start marker with the next end marker, ignoring the range spanning both. Markers may
+/// nest, and a start marker without an end ignores the rest of the document.
+fn block_ranges(root: Node, mut markers: Vec<(Marker, Range)>) -> Vec<Range> {
+  // The same marker may be found both as a comment and by the ignore query
+  markers.sort_by_key(|(marker, range)| (range.start_byte, range.end_byte, *marker));
+  markers.dedup();
+
+  let mut ranges = Vec::new();
+  let mut open = Vec::new();
+  for (marker, range) in markers {
+    match marker {
+      Marker::Start => open.push(range),
+      Marker::End => match open.pop() {
+        Some(start) => ranges.push(Range {
+          start_byte: start.start_byte,
+          start_point: start.start_point,
+          end_byte: range.end_byte,
+          end_point: range.end_point,
+        }),
+        None => log::warn!(
+          "Ignoring pruner-ignore-end on line {} without a matching start",
+          range.start_point.row + 1
+        ),
+      },
+      Marker::Next(_) => {}
+    }
+  }
+
+  for start in open {
+    log::warn!(
+      "pruner-ignore-start on line {} has no matching end, ignoring the rest of the document",
+      start.start_point.row + 1
+    );
+    ranges.push(Range {
+      start_byte: start.start_byte,
+      start_point: start.start_point,
+      end_byte: root.end_byte(),
+      end_point: root.end_position(),
+    });
+  }
+
+  ranges
+}
+
 pub(crate) fn collect_ignore_ranges(
   root: Node,
   source: &[u8],
   ignore_query: Option<&Query>,
 ) -> Vec<Range> {
-  fn add_marker(ignore_ranges: &mut Vec<Range>, marker: Node) {
+  fn add_marker(
+    ignore_ranges: &mut Vec<Range>,
+    block_markers: &mut Vec<(Marker, Range)>,
+    marker: Node,
+    kind: Marker,
+  ) {
+    let Marker::Next(count) = kind else {
+      block_markers.push((kind, marker.range()));
+      return;
+    };
+
     ignore_ranges.push(marker.range());
 
-    let mut target = marker.next_named_sibling();
-    while let Some(candidate) = target {
-      if is_comment_node(candidate) {
-        target = candidate.next_named_sibling();
-      } else {
-        break;
+    let mut previous = marker;
+    for _ in 0..count {
+      let mut target = previous.next_named_sibling();
+      while let Some(candidate) = target {
+        if is_comment_node(candidate) {
+          target = candidate.next_named_sibling();
+        } else {
+          break;
+        }
       }
-    }
 
-    if let Some(target) = target {
+      let Some(target) = target else {
+        break;
+      };
       ignore_ranges.push(target.range());
+      previous = target;
     }
   }
 
-  fn visit(node: Node, source: &[u8], ignore_ranges: &mut Vec<Range>) {
+  fn visit(
+    node: Node,
+    source: &[u8],
+    ignore_ranges: &mut Vec<Range>,
+    block_markers: &mut Vec<(Marker, Range)>,
+  ) {
     if is_comment_node(node)
       && let Ok(text) = node.utf8_text(source)
       && text.contains("pruner-ignore")
     {
-      add_marker(ignore_ranges, node);
+      add_marker(ignore_ranges, block_markers, node, marker_kind(node, source));
     }
 
     let mut cursor = node.walk();
     for child in node.named_children(&mut cursor) {
-      visit(child, source, ignore_ranges);
+      visit(child, source, ignore_ranges, block_markers);
     }
   }
 
   let mut ignore_ranges = Vec::new();
-  visit(root, source, &mut ignore_ranges);
+  let mut block_markers = Vec::new();
+  visit(root, source, &mut ignore_ranges, &mut block_markers);
 
   if let Some(ignore_query) = ignore_query {
     let mut cursor = QueryCursor::new();
@@ -49,24 +144,31 @@ pub(crate) fn collect_ignore_ranges(
     let ignore_target_capture = ignore_query.capture_index_for_name("pruner.ignore");
     let ignore_marker_capture =
       ignore_query.capture_index_for_name("pruner.ignore.marker");
-
-    if ignore_target_capture.is_none() && ignore_marker_capture.is_none() {
-      return ignore_ranges;
-    }
+    let ignore_start_capture = ignore_query.capture_index_for_name("pruner.ignore.start");
+    let ignore_end_capture = ignore_query.capture_index_for_name("pruner.ignore.end");
 
     while let Some(query_match) = matches.next() {
       for capture in query_match.captures {
-        if Some(capture.index) == ignore_target_capture {
+        let index = Some(capture.index);
+        if index == ignore_target_capture {
           ignore_ranges.push(capture.node.range());
         }
 
-        if Some(capture.index) == ignore_marker_capture {
-          add_marker(&mut ignore_ranges, capture.node);
-        }
+        let kind = if index == ignore_marker_capture {
+          marker_kind(capture.node, source)
+        } else if index == ignore_start_capture {
+          Marker::Start
+        } else if index == ignore_end_capture {
+          Marker::End
+        } else {
+          continue;
+        };
+        add_marker(&mut ignore_ranges, &mut block_markers, capture.node, kind);
       }
     }
   }
 
+  ignore_ranges.extend(block_ranges(root, block_markers));
   ignore_ranges
 }
 
@@ -75,3 +177,15 @@ pub(crate) fn is_ignored(range: &Range, ignore_ranges: &[Range]) -> bool {
     .iter()
     .any(|ignore| range.start_byte >= ignore.start_byte && range.end_byte <= ignore.end_byte)
 }
+
+/// The first ignore range which crosses a boundary of `range`. Such ranges only cover part of an
+/// injected region, so it is ambiguous whether the region was meant to be ignored. Ranges wholly
+/// within the region are not ambiguous, as they are left for the region's own document to handle.
+pub(crate) fn partial_overlap<'a>(range: &Range, ignore_ranges: &'a [Range]) -> Option<&'a Range> {
+  ignore_ranges.iter().find(|ignore| {
+    let overlaps = ignore.start_byte < range.end_byte && range.start_byte < ignore.end_byte;
+    let contains = range.start_byte >= ignore.start_byte && range.end_byte <= ignore.end_byte;
+    let within = ignore.start_byte >= range.start_byte && ignore.end_byte <= range.end_byte;
+    overlaps && !contains && !within
+  })
+}
@@ -2,11 +2,12 @@ use anyhow::Result;
 use std::{
   borrow::Cow,
   collections::{HashMap, HashSet},
+  ops,
 };
 use tree_sitter::{Node, Parser, Point, QueryCursor, QueryProperty, Range, StreamingIterator};
 
 use super::{
-  directives::{escape, gsub, indented, offset, trim},
+  directives::{case, escape, gsub, indented, offset, printwidth, trim},
   ignore,
   grammar::Grammar,
 };
@@ -82,6 +83,13 @@ fn remap_range_for_appended_newline(range: Range, original_endpoint: &Option<End
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct InjectionOpts {
   pub escape_chars: HashSet<String>,
+  /// A fixed print width set with `printwidth!`, used in place of the width inherited from the
+  /// parent document.
+  pub printwidth: Option<u32>,
+  /// The byte ranges between the captures of an `injection.combined` region. These belong to the
+  /// surrounding document, such as string interpolations, and are masked with placeholders while
+  /// the captures are formatted together so that they can be split back unchanged.
+  pub gaps: Vec<ops::Range<usize>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -91,8 +99,9 @@ pub struct InjectedRegion {
   pub opts: InjectionOpts,
 }
 
-fn trim_indented_range(source: &[u8], mut range: Range) -> Range {
-  let (start_byte, end_byte) = indented::trim_bytes(source, range.start_byte, range.end_byte);
+fn trim_indented_range(source: &[u8], mut range: Range, spec: indented::IndentedTrim) -> Range {
+  let (start_byte, end_byte) =
+    indented::trim_bytes(source, range.start_byte, range.end_byte, spec);
 
   range.start_byte = start_byte;
   range.end_byte = end_byte;
@@ -129,17 +138,97 @@ struct InjectedRegionFragment {
   start_byte: usize,
   end_byte: usize,
   escape_chars: HashSet<String>,
+  printwidth: Option<u32>,
+  captures: Vec<ops::Range<usize>>,
 }
 
+/// The byte ranges within `range` which none of the `captures` cover.
+fn gaps_between(mut captures: Vec<ops::Range<usize>>, range: &Range) -> Vec<ops::Range<usize>> {
+  captures.sort_by_key(|capture| capture.start);
+
+  let mut gaps = Vec::new();
+  let mut covered_until = range.start_byte;
+  for capture in captures {
+    if capture.start > covered_until && capture.start <= range.end_byte {
+      gaps.push(covered_until..capture.start);
+    }
+    covered_until = covered_until.max(capture.end);
+  }
+  gaps
+}
+
+type PatternDirectives = (
+  HashMap<u32, offset::RangeOffset>,
+  HashMap<u32, HashSet<String>>,
+  HashMap<u32, Vec<gsub::GsubRule>>,
+  HashMap<u32, trim::TrimSpec>,
+  HashMap<u32, case::CaseTransform>,
+  HashMap<u32, u32>,
+);
+
+/// Describe the directives of a pattern which apply to a region, for tracing.
+fn describe_directives(
+  directives: &PatternDirectives,
+  content_capture: u32,
+  lang_capture: Option<u32>,
+) -> String {
+  let (offsets, escapes, gsubs, trims, cases, printwidths) = directives;
+
+  let mut applied = Vec::new();
+  if let Some(offset) = offsets.get(&content_capture) {
+    applied.push(format!("offset! {offset:?}"));
+  }
+  if let Some(escape_chars) = escapes.get(&content_capture) {
+    applied.push(format!("escape! {escape_chars:?}"));
+  }
+  if let Some(spec) = trims.get(&content_capture) {
+    applied.push(format!("trim! {spec:?}"));
+  }
+  if let Some(printwidth) = printwidths.get(&content_capture) {
+    applied.push(format!("printwidth! {printwidth}"));
+  }
+  if let Some(lang_capture) = lang_capture {
+    for rule in gsubs.get(&lang_capture).into_iter().flatten() {
+      applied.push(format!("gsub! {:?} {:?}", rule.regex.as_str(), rule.replacement));
+    }
+    if let Some(transform) = cases.get(&lang_capture) {
+      applied.push(format!("{transform:?} case"));
+    }
+  }
+
+  if applied.is_empty() {
+    "no directives".to_string()
+  } else {
+    applied.join(", ")
+  }
+}
+
+/// How [`extract_language_injections`] treats content it cannot cleanly turn into a region.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExtractOpts {
+  /// Content that would otherwise be silently skipped, such as invalid directive predicates,
+  /// undecodable language captures, or out of bounds offsets, results in an error instead.
+  pub strict: bool,
+  /// Regions which a `pruner-ignore` range crosses the boundary of are skipped like fully ignored
+  /// regions, rather than extracted with a warning.
+  pub strict_ignore: bool,
+}
+
+/// Extract all injected language regions from `source`.
 pub fn extract_language_injections(
   parser: &mut Parser,
   grammar: &Grammar,
   source: &[u8],
+  opts: &ExtractOpts,
 ) -> Result<Vec<InjectedRegion>> {
+  let ExtractOpts {
+    strict,
+    strict_ignore,
+  } = *opts;
   let (source_with_newline, original_endpoint) = with_newline(source);
   let source_str = String::from_utf8(Vec::from(source_with_newline.as_ref()))?;
 
-  parser.set_language(&grammar.lang)?;
+  grammar.configure_parser(parser)?;
   let tree = parser
     .parse(source_with_newline.as_ref(), None)
     .ok_or_else(|| anyhow::anyhow!("Parse returned None"))?;
@@ -164,15 +253,7 @@ pub fn extract_language_injections(
     return Ok(Vec::new());
   };
 
-  let mut directives_cache: HashMap<
-    usize,
-    (
-      HashMap<u32, offset::RangeOffset>,
-      HashMap<u32, HashSet<String>>,
-      HashMap<u32, Vec<gsub::GsubRule>>,
-      HashMap<u32, trim::TrimSpec>,
-    ),
-  > = HashMap::new();
+  let mut directives_cache: HashMap<usize, PatternDirectives> = HashMap::new();
 
   while let Some(query_match) = matches.next() {
     let pattern_properties = query.property_settings(query_match.pattern_index);
@@ -197,39 +278,73 @@ pub fn extract_language_injections(
       continue;
     };
 
-    let (offset_modifiers, escape_modifiers, gsub_modifiers, trim_modifiers) = directives_cache
-      .entry(query_match.pattern_index)
-      .or_insert_with(|| {
+    let directives = match directives_cache.entry(query_match.pattern_index) {
+      std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+      std::collections::hash_map::Entry::Vacant(entry) => {
         let predicates = query.general_predicates(query_match.pattern_index);
-        (
-          offset::collect(predicates),
-          escape::collect(predicates),
-          gsub::collect(predicates),
-          trim::collect(predicates),
-        )
-      });
+        entry.insert((
+          offset::collect(predicates, strict)?,
+          escape::collect(predicates, strict)?,
+          gsub::collect(predicates, strict)?,
+          trim::collect(predicates, strict)?,
+          case::collect(predicates, strict)?,
+          printwidth::collect(predicates, strict)?,
+        ))
+      }
+    };
+    let (
+      offset_modifiers,
+      escape_modifiers,
+      gsub_modifiers,
+      trim_modifiers,
+      case_modifiers,
+      printwidth_modifiers,
+    ) = &*directives;
 
     let lang_capture_index = lang_capture.as_ref().map(|c| c.index);
-    let Some(mut lang_name) = harcoded_lang_name.or_else(|| {
-      lang_capture.and_then(|capture| {
-        capture
-          .node
-          .utf8_text(source_with_newline.as_ref())
-          .ok()
-          .map(String::from)
-      })
-    }) else {
+    let captured_lang_name = match lang_capture {
+      Some(capture) if !is_hardcoded_lang => {
+        match capture.node.utf8_text(source_with_newline.as_ref()) {
+          Ok(text) => Some(String::from(text)),
+          Err(err) if strict => {
+            return Err(anyhow::Error::from(err).context(format!(
+              "Failed to read injection.language capture at byte {}",
+              capture.node.start_byte()
+            )));
+          }
+          Err(_) => None,
+        }
+      }
+      _ => None,
+    };
+    let Some(mut lang_name) = harcoded_lang_name.or(captured_lang_name) else {
       continue;
     };
 
     if !is_hardcoded_lang && let Some(lang_capture_index) = lang_capture_index {
       lang_name = gsub::apply_gsub(gsub_modifiers, lang_capture_index, &lang_name);
+      lang_name = case::apply_case(case_modifiers, lang_capture_index, &lang_name);
     }
 
     for content_capture in content_captures {
       let base_range = content_capture.node.range();
       let mut range = if let Some(offset) = offset_modifiers.get(&content_capture.index) {
-        offset::apply_offset_to_range(&source_str, &base_range, offset).unwrap_or(base_range)
+        match offset::apply_offset_to_range(&source_str, &base_range, offset) {
+          Some(range) => range,
+          None if strict => anyhow::bail!(
+            "offset! {offset:?} moves capture at bytes {}..{} out of bounds",
+            base_range.start_byte,
+            base_range.end_byte
+          ),
+          None => {
+            log::warn!(
+              "Skipping injected region at bytes {}..{}: offset! {offset:?} moves it out of bounds",
+              base_range.start_byte,
+              base_range.end_byte
+            );
+            continue;
+          }
+        }
       } else {
         base_range
       };
@@ -239,13 +354,29 @@ pub fn extract_language_injections(
           source_with_newline.as_ref(),
           range.start_byte,
           range.end_byte,
-          *trim_spec,
+          trim_spec,
         );
         range.start_byte = start_byte;
         range.end_byte = end_byte;
       }
 
       let escape_chars = escape::escape_chars(escape_modifiers, content_capture.index);
+      let printwidth = printwidth_modifiers.get(&content_capture.index).copied();
+
+      // Regions from several files may be extracted in parallel, so each line names its range
+      if log::log_enabled!(log::Level::Trace) {
+        log::trace!(
+          "{} injections pattern {} captured a {lang_name} region at bytes {}..{} (from {}..{}) \
+           with {}",
+          grammar.name,
+          query_match.pattern_index,
+          range.start_byte,
+          range.end_byte,
+          base_range.start_byte,
+          base_range.end_byte,
+          describe_directives(directives, content_capture.index, lang_capture_index)
+        );
+      }
 
       let key = if is_combined {
         let container_range = container_range_for_content(content_capture.node);
@@ -261,12 +392,14 @@ pub fn extract_language_injections(
         key
       };
 
-      match fragments.entry(key.clone()) {
-        std::collections::hash_map::Entry::Occupied(mut entry) => {
-          let fragment = entry.get_mut();
+      let fragment = match fragments.entry(key.clone()) {
+        std::collections::hash_map::Entry::Occupied(entry) => {
+          let fragment = entry.into_mut();
           fragment.start_byte = fragment.start_byte.min(range.start_byte);
           fragment.end_byte = fragment.end_byte.max(range.end_byte);
           fragment.escape_chars.extend(escape_chars.iter().cloned());
+          fragment.printwidth = fragment.printwidth.or(printwidth);
+          fragment
         }
         std::collections::hash_map::Entry::Vacant(entry) => {
           fragment_key_order.push(key);
@@ -276,9 +409,12 @@ pub fn extract_language_injections(
             start_byte: range.start_byte,
             end_byte: range.end_byte,
             escape_chars,
-          });
+            printwidth,
+            captures: Vec::new(),
+          })
         }
-      }
+      };
+      fragment.captures.push(range.start_byte..range.end_byte);
     }
   }
 
@@ -295,22 +431,111 @@ pub fn extract_language_injections(
     };
 
     let props = query.property_settings(fragment.pattern_index);
-    if indented::is_indented(props) {
-      range = trim_indented_range(source_with_newline.as_ref(), range);
+    if let Some(spec) = indented::trim_spec(props) {
+      range = trim_indented_range(source_with_newline.as_ref(), range, spec);
     }
 
     if ignore::is_ignored(&range, &ignore_ranges) {
       continue;
     }
 
+    if let Some(ignore) = ignore::partial_overlap(&range, &ignore_ranges) {
+      if strict_ignore {
+        continue;
+      }
+      log::warn!(
+        "pruner-ignore range on lines {}-{} partially overlaps a {} region on lines {}-{}, the \
+         region is formatted anyway. Set strict_ignore to ignore it",
+        ignore.start_point.row + 1,
+        ignore.end_point.row + 1,
+        fragment.lang,
+        range.start_point.row + 1,
+        range.end_point.row + 1,
+      );
+    }
+
+    let gaps = gaps_between(fragment.captures, &range);
     injected_regions.push(InjectedRegion {
       lang: fragment.lang,
       range: remap_range_for_appended_newline(range, &original_endpoint),
       opts: InjectionOpts {
         escape_chars: fragment.escape_chars,
+        printwidth: fragment.printwidth,
+        gaps,
       },
     });
   }
 
-  Ok(injected_regions)
+  remove_overlapping_regions(injected_regions, strict)
+}
+
+fn overlaps(a: &Range, b: &Range) -> bool {
+  a.start_byte < b.end_byte && b.start_byte < a.end_byte
+}
+
+fn encloses(outer: &Range, inner: &Range) -> bool {
+  outer.start_byte <= inner.start_byte && inner.end_byte <= outer.end_byte
+}
+
+/// Drop regions overlapping another region, which would otherwise be formatted twice and spliced
+/// back over each other. The smallest of the overlapping regions is kept as the most specific, with
+/// ties going to the region matched first. Regions which overlap without one enclosing the other
+/// are an error when `strict` is set.
+fn remove_overlapping_regions(
+  regions: Vec<InjectedRegion>,
+  strict: bool,
+) -> Result<Vec<InjectedRegion>> {
+  let mut by_size = (0..regions.len()).collect::<Vec<_>>();
+  by_size.sort_by_key(|&i| regions[i].range.end_byte - regions[i].range.start_byte);
+
+  let mut kept: Vec<usize> = Vec::with_capacity(regions.len());
+  for i in by_size {
+    let region = &regions[i];
+    let Some(&overlapping) = kept
+      .iter()
+      .find(|&&j| overlaps(&regions[j].range, &region.range))
+    else {
+      kept.push(i);
+      continue;
+    };
+
+    let kept_region = &regions[overlapping];
+    let describe = |region: &InjectedRegion| {
+      format!(
+        "{} region at bytes {}..{}",
+        region.lang, region.range.start_byte, region.range.end_byte
+      )
+    };
+    if encloses(&region.range, &kept_region.range) {
+      log::debug!(
+        "Skipping {} which encloses the {}",
+        describe(region),
+        describe(kept_region)
+      );
+    } else if strict {
+      anyhow::bail!(
+        "Injected {} partially overlaps the {}",
+        describe(region),
+        describe(kept_region)
+      );
+    } else {
+      log::warn!(
+        "Skipping injected {} which partially overlaps the {}",
+        describe(region),
+        describe(kept_region)
+      );
+    }
+  }
+
+  let mut keep = vec![false; regions.len()];
+  for i in kept {
+    keep[i] = true;
+  }
+  Ok(
+    regions
+      .into_iter()
+      .zip(keep)
+      .filter_map(|(region, keep)| keep.then_some(region))
+      .collect(),
+  )
 }
@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap},
+  fs,
+  path::Path,
+};
+
+use super::git;
+use crate::config::GrammarSpec;
+
+const HEADER: &str = "# Generated by pruner to record the commit each grammar resolved to.\n\n";
+
+/// The commit each configured grammar was resolved to, as recorded in the configured `lockfile`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Lockfile {
+  #[serde(default)]
+  pub grammars: BTreeMap<String, LockedGrammar>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedGrammar {
+  pub url: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rev: Option<String>,
+  pub commit: String,
+}
+
+impl Lockfile {
+  /// Read the lockfile at `path`, which is empty if it does not exist yet.
+  pub fn read(path: &Path) -> Result<Self> {
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let content =
+      fs::read_to_string(path).with_context(|| format!("Failed to read lockfile {:?}", path))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse lockfile {:?}", path))
+  }
+
+  pub fn write(&self, path: &Path) -> Result<()> {
+    let content = format!("{HEADER}{}", toml::to_string(self)?);
+    super::atomic::write(path, content.as_bytes())
+  }
+
+  /// The commit recorded for `lang`. Recorded commits are only used while the grammar's url and rev
+  /// are unchanged, so that editing either resolves the grammar afresh.
+  pub fn commit(&self, lang: &str, spec: &GrammarSpec) -> Option<&str> {
+    self
+      .grammars
+      .get(lang)
+      .filter(|locked| locked.url == spec.url().as_str() && locked.rev.as_deref() == spec.rev())
+      .map(|locked| locked.commit.as_str())
+  }
+}
+
+/// The commit checked out for a grammar, failing if it is pinned to a different rev.
+fn checked_out_commit(repo_dir: &Path, spec: &GrammarSpec) -> Result<String> {
+  let commit = git::head_commit(repo_dir)?;
+  if let Some(rev) = spec.rev() {
+    git::verify_rev(repo_dir, rev, &commit)?;
+  }
+  Ok(commit)
+}
+
+/// Check that every grammar cloned into `clone_path` with a pinned `rev` is checked out at it.
+#[allow(dead_code)]
+pub fn verify_pinned(clone_path: &Path, grammars: &HashMap<String, GrammarSpec>) -> Result<()> {
+  for (lang, spec) in grammars.iter().filter(|(_, spec)| spec.rev().is_some()) {
+    checked_out_commit(&clone_path.join(lang), spec)?;
+  }
+  Ok(())
+}
+
+/// Record the commit each grammar cloned into `clone_path` is checked out at, verifying pinned
+/// grammars along the way.
+pub fn resolve(clone_path: &Path, grammars: &HashMap<String, GrammarSpec>) -> Result<Lockfile> {
+  let mut lockfile = Lockfile::default();
+  for (lang, spec) in grammars {
+    let commit = checked_out_commit(&clone_path.join(lang), spec)?;
+    lockfile.grammars.insert(
+      lang.clone(),
+      LockedGrammar {
+        url: spec.url().to_string(),
+        rev: spec.rev().map(String::from),
+        commit,
+      },
+    );
+  }
+  Ok(lockfile)
+}
+
+/// Write `resolved` to `path` if it differs from the `existing` lockfile. When `frozen` is set the
+/// lockfile must already be up to date, and an error naming the grammars which changed is returned
+/// instead.
+pub fn update(path: &Path, existing: &Lockfile, resolved: &Lockfile, frozen: bool) -> Result<()> {
+  if existing == resolved {
+    return Ok(());
+  }
+
+  if frozen {
+    let changed = existing
+      .grammars
+      .keys()
+      .chain(resolved.grammars.keys())
+      .filter(|lang| existing.grammars.get(*lang) != resolved.grammars.get(*lang))
+      .map(String::as_str)
+      .collect::<BTreeSet<_>>();
+    anyhow::bail!(
+      "Lockfile {:?} is out of date for grammars: {}",
+      path,
+      changed.into_iter().collect::<Vec<_>>().join(", ")
+    );
+  }
+
+  log::info!("Updating lockfile {:?}", path);
+  resolved.write(path)
+}
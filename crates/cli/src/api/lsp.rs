@@ -0,0 +1,120 @@
+use anyhow::Result;
+
+/// A position within a document, as defined by the Language Server Protocol. `character` is
+/// measured in UTF-16 code units, which is the default position encoding for LSP.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+  pub line: u32,
+  pub character: u32,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextRange {
+  pub start: Position,
+  pub end: Position,
+}
+
+/// An LSP `TextEdit`. Serializes to the shape expected by LSP clients.
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+  pub range: TextRange,
+  pub new_text: String,
+}
+
+/// Replacement of the bytes `start_byte..end_byte` of a source document with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splice {
+  pub start_byte: usize,
+  pub end_byte: usize,
+  pub replacement: Vec<u8>,
+}
+
+/// Compute the LSP position of `byte_index` within `source`. Lines are delimited by `\n`, and a
+/// `\r` preceding it is treated as part of the line.
+pub fn position_for_byte(source: &str, byte_index: usize) -> Result<Position> {
+  if byte_index > source.len() || !source.is_char_boundary(byte_index) {
+    anyhow::bail!("Byte index {byte_index} is not a char boundary of the source");
+  }
+
+  let prefix = &source[..byte_index];
+  let line_start = prefix.rfind('\n').map(|index| index + 1).unwrap_or(0);
+  let line = prefix.matches('\n').count();
+  let character = prefix[line_start..].encode_utf16().count();
+
+  Ok(Position {
+    line: line.try_into()?,
+    character: character.try_into()?,
+  })
+}
+
+/// Convert byte splices into LSP text edits. All splices are interpreted relative to the original
+/// `source`, matching LSP's semantics for multiple edits in a single response, and therefore must
+/// not overlap.
+pub fn text_edits(source: &[u8], splices: &[Splice]) -> Result<Vec<TextEdit>> {
+  let source = std::str::from_utf8(source)?;
+
+  let mut sorted = splices.iter().collect::<Vec<_>>();
+  sorted.sort_by_key(|splice| (splice.start_byte, splice.end_byte));
+  for pair in sorted.windows(2) {
+    if pair[0].end_byte > pair[1].start_byte {
+      anyhow::bail!(
+        "Overlapping splices {}..{} and {}..{}",
+        pair[0].start_byte,
+        pair[0].end_byte,
+        pair[1].start_byte,
+        pair[1].end_byte
+      );
+    }
+  }
+
+  splices
+    .iter()
+    .map(|splice| {
+      Ok(TextEdit {
+        range: TextRange {
+          start: position_for_byte(source, splice.start_byte)?,
+          end: position_for_byte(source, splice.end_byte)?,
+        },
+        new_text: String::from_utf8(splice.replacement.clone())?,
+      })
+    })
+    .collect()
+}
+
+/// Compute the smallest single splice which transforms `original` into `formatted`, or `None` if
+/// they are identical. Splice boundaries always fall on char boundaries.
+pub fn splice_between(original: &str, formatted: &str) -> Option<Splice> {
+  if original == formatted {
+    return None;
+  }
+
+  let mut prefix = original
+    .bytes()
+    .zip(formatted.bytes())
+    .take_while(|(a, b)| a == b)
+    .count();
+  while !original.is_char_boundary(prefix) || !formatted.is_char_boundary(prefix) {
+    prefix -= 1;
+  }
+
+  let max_suffix = original.len().min(formatted.len()) - prefix;
+  let mut suffix = original
+    .bytes()
+    .rev()
+    .zip(formatted.bytes().rev())
+    .take(max_suffix)
+    .take_while(|(a, b)| a == b)
+    .count();
+  while !original.is_char_boundary(original.len() - suffix)
+    || !formatted.is_char_boundary(formatted.len() - suffix)
+  {
+    suffix -= 1;
+  }
+
+  Some(Splice {
+    start_byte: prefix,
+    end_byte: original.len() - suffix,
+    replacement: formatted.as_bytes()[prefix..formatted.len() - suffix].to_vec(),
+  })
+}
@@ -1,8 +1,23 @@
+pub mod atomic;
+pub mod cache;
+pub mod diff;
 pub mod directives;
+// Only consumed by library users, the binary formats whole files.
+#[allow(dead_code)]
+pub mod embed;
 pub mod format;
 pub mod git;
 pub mod grammar;
 pub mod ignore;
 pub mod injections;
+pub mod lock;
+// Only consumed by library users, the binary does not speak LSP.
+#[allow(dead_code)]
+pub mod lsp;
 pub mod queries;
+pub mod stats;
 pub mod text;
+
+// The entrypoint for library users, see `embed`.
+#[allow(unused_imports)]
+pub use embed::{FormatStrOpts, format_str};
@@ -1,60 +1,193 @@
 use anyhow::Result;
-use std::{fs, path::PathBuf};
-use tree_sitter::{Language, Query};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+use tree_sitter::{Language, Query, QueryError};
 
-fn read_files(paths: &[PathBuf]) -> Result<String> {
-  let mut out = String::new();
-  for (i, p) in paths.iter().enumerate() {
-    let contents = fs::read_to_string(p)
-      .map_err(|e| anyhow::format_err!("Failed to read {}: {e}", p.display()))?;
-    if i > 0 {
-      out.push('\n');
+/// Set this environment variable to annotate merged queries with the file each part came from. The
+/// markers are comments, so they do not change the meaning of the query.
+const PROVENANCE_ENV: &str = "PRUNER_QUERY_PROVENANCE";
+
+fn provenance_enabled() -> bool {
+  std::env::var_os(PROVENANCE_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+fn read_query_file(path: &Path) -> Result<String> {
+  fs::read_to_string(path).map_err(|e| anyhow::format_err!("Failed to read {}: {e}", path.display()))
+}
+
+/// A query merged from one or more files, remembering where the contents of each file begin so
+/// that errors can be reported against the file they came from.
+#[derive(Default)]
+struct QuerySource {
+  text: String,
+  parts: Vec<QueryPart>,
+}
+
+struct QueryPart {
+  path: PathBuf,
+  start: usize,
+}
+
+impl QuerySource {
+  fn from_file(path: &Path, contents: String, provenance: bool) -> Self {
+    let mut text = String::new();
+    if provenance {
+      text = format!(";; --- from {} ---\n", path.display());
+    }
+    let start = text.len();
+    text.push_str(&contents);
+    Self {
+      text,
+      parts: vec![QueryPart {
+        path: path.to_owned(),
+        start,
+      }],
     }
-    out.push_str(&contents);
   }
-  Ok(out)
+
+  fn append(&mut self, other: QuerySource, newline: bool) {
+    if newline {
+      self.text.push('\n');
+    }
+    let offset = self.text.len();
+    self.parts.extend(other.parts.into_iter().map(|part| QueryPart {
+      start: part.start + offset,
+      ..part
+    }));
+    self.text.push_str(&other.text);
+  }
+
+  fn compile(&self, lang: &Language, description: &str) -> Result<Query> {
+    Query::new(lang, &self.text)
+      .map_err(|err| anyhow::format_err!("Invalid {description}: {}", self.describe_error(&err)))
+  }
+
+  /// Describe `err` by the file, line and column it occurred at, followed by the offending line.
+  fn describe_error(&self, err: &QueryError) -> String {
+    let offset = err.offset.min(self.text.len());
+    let located = self
+      .parts
+      .iter()
+      .rev()
+      .find(|part| part.start <= offset)
+      .and_then(|part| Some((part, self.text.get(part.start..offset)?)));
+    let Some((part, before)) = located else {
+      return err.to_string();
+    };
+
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    let snippet = self.text[part.start + line_start..]
+      .lines()
+      .next()
+      .unwrap_or_default();
+
+    let path = part.path.display();
+    let mut description = format!("{:?} error at {path}:{line}:{column}", err.kind);
+    if !err.message.is_empty() {
+      description.push_str(&format!(": {}", err.message.trim()));
+    }
+    format!("{description}\n  {snippet}\n  {}^", " ".repeat(column - 1))
+  }
 }
 
-fn merge_queries(base: &str, overlay: &str) -> String {
-  if base.is_empty() {
-    return overlay.to_owned();
+fn read_files(paths: &[PathBuf], provenance: bool) -> Result<QuerySource> {
+  let mut out = QuerySource::default();
+  for (i, p) in paths.iter().enumerate() {
+    out.append(QuerySource::from_file(p, read_query_file(p)?, provenance), i > 0);
   }
+  Ok(out)
+}
 
-  if overlay.is_empty() {
-    return base.to_owned();
+fn merge_queries(base: QuerySource, overlay: QuerySource) -> QuerySource {
+  if base.text.is_empty() {
+    return overlay;
   }
 
-  let mut merged = String::with_capacity(base.len() + overlay.len() + 1);
-  merged.push_str(base);
-  if !base.ends_with('\n') {
-    merged.push('\n');
+  if overlay.text.is_empty() {
+    return base;
   }
-  merged.push_str(overlay);
 
+  let newline = !base.text.ends_with('\n');
+  let mut merged = base;
+  merged.append(overlay, newline);
   merged
 }
 
-fn is_extending(contents: &str) -> bool {
+/// The directives given by the leading `;;` comments of a query file, such as `extends`.
+fn directives(contents: &str) -> impl Iterator<Item = &str> {
   contents
     .lines()
-    .next()
-    .map(|line| line.trim_start().starts_with(";; extends"))
-    .unwrap_or(false)
+    .map(str::trim_start)
+    .take_while(|line| line.starts_with(";;"))
+    .map(|line| line.trim_start_matches(';').trim())
+}
+
+fn is_extending(contents: &str) -> bool {
+  directives(contents).any(|directive| directive.starts_with("extends"))
+}
+
+/// The languages named by `;; inherits: a,b` directives, whose queries are included first.
+fn inherited_languages(contents: &str) -> Vec<String> {
+  directives(contents)
+    .filter_map(|directive| directive.strip_prefix("inherits:"))
+    .flat_map(|languages| languages.split(','))
+    .map(str::trim)
+    .filter(|language| !language.is_empty())
+    .map(String::from)
+    .collect()
+}
+
+fn read_query(
+  queries_dirs: &[PathBuf],
+  name: &str,
+  filename: &str,
+  base: QuerySource,
+  provenance: bool,
+) -> Result<QuerySource> {
+  resolve_query(
+    queries_dirs,
+    name,
+    filename,
+    base,
+    provenance,
+    &mut vec![name.to_owned()],
+  )
 }
 
-fn read_query(queries_dirs: &[PathBuf], name: &str, filename: &str, base: &str) -> Result<String> {
-  let mut result = base.to_owned();
+/// Resolve the query `filename` for the language `name`, where `inheriting` is the chain of
+/// languages whose files inherited it.
+fn resolve_query(
+  queries_dirs: &[PathBuf],
+  name: &str,
+  filename: &str,
+  base: QuerySource,
+  provenance: bool,
+  inheriting: &mut Vec<String>,
+) -> Result<QuerySource> {
+  let mut result = base;
 
   for dir in queries_dirs {
     let path = dir.join(name).join(filename);
     if path.is_file() {
-      let contents = fs::read_to_string(&path)
-        .map_err(|e| anyhow::format_err!("Failed to read {}: {e}", path.display()))?;
+      let contents = read_query_file(&path)?;
+      let extending = is_extending(&contents);
 
-      if is_extending(&contents) {
-        result = merge_queries(&result, &contents);
+      let mut query = QuerySource::default();
+      for language in inherited_languages(&contents) {
+        let inherited =
+          inherited_query(queries_dirs, &path, &language, filename, provenance, inheriting)?;
+        query = merge_queries(query, inherited);
+      }
+      query = merge_queries(query, QuerySource::from_file(&path, contents, provenance));
+
+      if extending {
+        result = merge_queries(result, query);
       } else {
-        result = contents;
+        result = query;
       }
     }
   }
@@ -62,15 +195,90 @@ fn read_query(queries_dirs: &[PathBuf], name: &str, filename: &str, base: &str)
   Ok(result)
 }
 
+fn inherited_query(
+  queries_dirs: &[PathBuf],
+  path: &Path,
+  language: &str,
+  filename: &str,
+  provenance: bool,
+  inheriting: &mut Vec<String>,
+) -> Result<QuerySource> {
+  if inheriting.iter().any(|name| name == language) {
+    anyhow::bail!(
+      "Query inheritance cycle in {}: {} -> {language}",
+      path.display(),
+      inheriting.join(" -> ")
+    );
+  }
+
+  inheriting.push(language.to_owned());
+  let query = resolve_query(
+    queries_dirs,
+    language,
+    filename,
+    QuerySource::default(),
+    provenance,
+    inheriting,
+  )?;
+  inheriting.pop();
+
+  anyhow::ensure!(
+    !query.parts.is_empty(),
+    "{} inherits from {language}, which has no {filename} in the query paths",
+    path.display()
+  );
+  Ok(query)
+}
+
+fn injections_query(
+  name: &str,
+  base_files: &[PathBuf],
+  search_paths: &[PathBuf],
+  provenance: bool,
+) -> Result<QuerySource> {
+  let base_queries = read_files(base_files, provenance)?;
+  read_query(
+    search_paths,
+    name,
+    "injections.scm",
+    base_queries,
+    provenance,
+  )
+}
+
+/// Resolve the source of the injections query for the language `name`, merging the grammar's own
+/// `base_files` with any `injections.scm` found in `search_paths`. When `provenance` is set each
+/// merged part is preceded by a comment naming the file it came from.
+pub fn injections_query_source(
+  name: &str,
+  base_files: &[PathBuf],
+  search_paths: &[PathBuf],
+  provenance: bool,
+) -> Result<String> {
+  Ok(injections_query(name, base_files, search_paths, provenance)?.text)
+}
+
+/// Load the injections query for the language `name`. Errors in the query name the file and
+/// position they occurred at.
 pub fn load_injections_query(
   lang: &Language,
   name: &str,
   base_files: &[PathBuf],
   search_paths: &[PathBuf],
 ) -> Result<Query> {
-  let base_queries = read_files(base_files)?;
-  let query_content = read_query(search_paths, name, "injections.scm", &base_queries)?;
-  Query::new(lang, &query_content).map_err(|err| anyhow::format_err!("{err:?}"))
+  injections_query(name, base_files, search_paths, provenance_enabled())?
+    .compile(lang, &format!("injections query for {name}"))
+}
+
+/// Resolve the source of a query which only comes from `search_paths`, such as
+/// `pruner/ignore.scm`. The source is empty when no file defines it.
+pub fn optional_query_source(
+  name: &str,
+  filename: &str,
+  search_paths: &[PathBuf],
+  provenance: bool,
+) -> Result<String> {
+  Ok(read_query(search_paths, name, filename, QuerySource::default(), provenance)?.text)
 }
 
 pub fn load_optional_query(
@@ -79,11 +287,16 @@ pub fn load_optional_query(
   filename: &str,
   search_paths: &[PathBuf],
 ) -> Result<Option<Query>> {
-  let query_content = read_query(search_paths, name, filename, "")?;
-  if query_content.trim().is_empty() {
+  let query = read_query(
+    search_paths,
+    name,
+    filename,
+    QuerySource::default(),
+    provenance_enabled(),
+  )?;
+  if query.text.trim().is_empty() {
     return Ok(None);
   }
 
-  let query = Query::new(lang, &query_content).map_err(|err| anyhow::format_err!("{err:?}"))?;
-  Ok(Some(query))
+  Ok(Some(query.compile(lang, &format!("{filename} query for {name}"))?))
 }
@@ -0,0 +1,69 @@
+use std::{collections::BTreeMap, fmt};
+
+/// How many injected regions of one language were visited while formatting, and how many of those
+/// the formatting changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegionCount {
+  pub visited: usize,
+  pub changed: usize,
+}
+
+/// Per-language [`RegionCount`]s, keyed by the language each region resolved to after aliasing.
+/// Collected for a single document, including regions nested within other regions, when
+/// [`crate::api::format::FormatContext::document_stats`] is set, and tallied across all files of a
+/// run through [`crate::api::format::FormatContext::region_stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RegionStats {
+  pub regions: BTreeMap<String, RegionCount>,
+}
+
+impl RegionStats {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record(&mut self, language: &str, changed: bool) {
+    let count = self.regions.entry(language.to_string()).or_default();
+    count.visited += 1;
+    count.changed += usize::from(changed);
+  }
+
+  pub fn merge(&mut self, other: RegionStats) {
+    for (language, other) in other.regions {
+      let count = self.regions.entry(language).or_default();
+      count.visited += other.visited;
+      count.changed += other.changed;
+    }
+  }
+
+  /// The number of regions visited per language, sorted by language name.
+  pub fn counts(&self) -> BTreeMap<String, usize> {
+    self
+      .regions
+      .iter()
+      .map(|(language, count)| (language.clone(), count.visited))
+      .collect()
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(&self.counts()).unwrap_or_default()
+  }
+}
+
+/// Summarizes the counts as `2 clojure regions (1 changed), 1 sql region (0 changed)`.
+impl fmt::Display for RegionStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.regions.is_empty() {
+      return write!(f, "no regions");
+    }
+    let summary = self
+      .regions
+      .iter()
+      .map(|(language, count)| {
+        let noun = if count.visited == 1 { "region" } else { "regions" };
+        format!("{} {language} {noun} ({} changed)", count.visited, count.changed)
+      })
+      .collect::<Vec<_>>();
+    write!(f, "{}", summary.join(", "))
+  }
+}
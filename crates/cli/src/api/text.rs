@@ -1,7 +1,78 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, ops};
 
-pub fn offset_lines(data: &mut Vec<u8>, offset: usize) {
-  if offset == 0 {
+/// The number of columns a tab is assumed to occupy when adjusting the print width of tab-indented
+/// regions.
+const TAB_WIDTH: usize = 4;
+
+/// The character used to indent the lines of an injected region.
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentUnit {
+  #[default]
+  Space,
+  Tab,
+}
+
+impl IndentUnit {
+  pub fn char(self) -> char {
+    match self {
+      Self::Space => ' ',
+      Self::Tab => '\t',
+    }
+  }
+
+  /// The number of columns occupied by a single unit of indentation.
+  pub fn width(self) -> usize {
+    match self {
+      Self::Space => 1,
+      Self::Tab => TAB_WIDTH,
+    }
+  }
+
+  /// The unit used by the majority of indented lines in `text`, or `None` if no line is indented.
+  pub fn detect(text: &str) -> Option<Self> {
+    let mut spaces = 0;
+    let mut tabs = 0;
+    for line in text.lines() {
+      match line.chars().next() {
+        Some(' ') => spaces += 1,
+        Some('\t') => tabs += 1,
+        _ => {}
+      }
+    }
+
+    match (spaces, tabs) {
+      (0, 0) => None,
+      (spaces, tabs) if tabs > spaces => Some(Self::Tab),
+      _ => Some(Self::Space),
+    }
+  }
+}
+
+/// How the lines of a formatted region are re-indented to match their position in the host.
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReindentMode {
+  /// Indent every non-blank line.
+  #[default]
+  Always,
+  /// Leave a region alone when its lines are all already indented by at least the target amount,
+  /// and otherwise indent every non-blank line. This keeps formatters which preserve their input
+  /// indentation idempotent. Deciding once for the whole region keeps nested lines indented
+  /// relative to each other.
+  IfNeeded,
+}
+
+/// The number of leading `unit` characters in `line`.
+pub fn leading_indent(line: &[u8], unit: IndentUnit) -> usize {
+  line
+    .iter()
+    .take_while(|byte| **byte == unit.char() as u8)
+    .count()
+}
+
+pub fn offset_lines(data: &mut Vec<u8>, offset: usize, unit: IndentUnit, mode: ReindentMode) {
+  if offset == 0 || (mode == ReindentMode::IfNeeded && lines_indented_by(data, offset, unit)) {
     return;
   }
 
@@ -13,8 +84,8 @@ pub fn offset_lines(data: &mut Vec<u8>, offset: usize) {
         i += 1;
         continue;
       }
-      let spaces = vec![b' '; offset];
-      data.splice(i + 1..i + 1, spaces);
+      let indent = vec![unit.char() as u8; offset];
+      data.splice(i + 1..i + 1, indent);
       i += offset + 1;
     } else {
       i += 1;
@@ -22,12 +93,95 @@ pub fn offset_lines(data: &mut Vec<u8>, offset: usize) {
   }
 }
 
+/// Whether every non-blank line of `data` after the first, which are the lines [`offset_lines`]
+/// indents, is already indented by at least `offset`.
+fn lines_indented_by(data: &[u8], offset: usize, unit: IndentUnit) -> bool {
+  data
+    .split(|byte| *byte == b'\n')
+    .skip(1)
+    .filter(|line| !matches!(line, [] | [b'\r']))
+    .all(|line| leading_indent(line, unit) >= offset)
+}
+
 pub fn strip_trailing_newlines(data: &mut Vec<u8>) {
   while data.last() == Some(&b'\n') || data.last() == Some(&b'\r') {
     data.pop();
   }
 }
 
+/// `data` without its final line ending, if it has one.
+pub fn without_final_newline(data: &[u8]) -> &[u8] {
+  data
+    .strip_suffix(b"\r\n")
+    .or_else(|| data.strip_suffix(b"\n"))
+    .unwrap_or(data)
+}
+
+/// Replace each of `gaps`, sorted and non-overlapping byte ranges of `source`, with a placeholder
+/// identifier which does not otherwise occur in `source`. Returns the masked source along with the
+/// placeholder standing in for each gap.
+pub fn mask_gaps(source: &[u8], gaps: &[ops::Range<usize>]) -> (Vec<u8>, Vec<String>) {
+  let mut prefix = "pruner_gap".to_string();
+  while contains(source, prefix.as_bytes()) {
+    prefix.insert(0, '_');
+  }
+
+  let mut masked = Vec::with_capacity(source.len());
+  let mut placeholders = Vec::with_capacity(gaps.len());
+  let mut copied_until = 0;
+  for (index, gap) in gaps.iter().enumerate() {
+    // The trailing `_` keeps `_1_` from matching the start of `_10_`
+    let placeholder = format!("{prefix}_{index}_");
+    masked.extend_from_slice(&source[copied_until..gap.start]);
+    masked.extend_from_slice(placeholder.as_bytes());
+    placeholders.push(placeholder);
+    copied_until = gap.end;
+  }
+  masked.extend_from_slice(&source[copied_until..]);
+  (masked, placeholders)
+}
+
+/// Put the text of each gap back in place of its placeholder in `data`, reversing [`mask_gaps`].
+/// Returns `None` unless every placeholder is found exactly once and in their original order.
+pub fn unmask_gaps(data: &[u8], placeholders: &[String], gaps: &[&[u8]]) -> Option<Vec<u8>> {
+  let mut unmasked = Vec::with_capacity(data.len());
+  let mut rest = data;
+  for (placeholder, gap) in placeholders.iter().zip(gaps) {
+    let index = find(rest, placeholder.as_bytes())?;
+    unmasked.extend_from_slice(&rest[..index]);
+    unmasked.extend_from_slice(gap);
+    rest = &rest[index + placeholder.len()..];
+  }
+  unmasked.extend_from_slice(rest);
+
+  // The gaps never contain a placeholder, so any left over was duplicated or moved out of order
+  let leftover = placeholders
+    .iter()
+    .any(|placeholder| contains(&unmasked, placeholder.as_bytes()));
+  (!leftover).then_some(unmasked)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack
+    .windows(needle.len())
+    .position(|window| window == needle)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  find(haystack, needle).is_some()
+}
+
+/// The UTF-8 byte order mark which some editors write at the start of files.
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The number of leading bytes inspected by [`is_binary`].
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// A quick heuristic for binary content: text files practically never contain a NUL byte.
+pub fn is_binary(data: &[u8]) -> bool {
+  data[..data.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
 pub fn trailing_newlines(data: &[u8]) -> Vec<u8> {
   let mut index = data.len();
   while index > 0 {
@@ -40,44 +194,130 @@ pub fn trailing_newlines(data: &[u8]) -> Vec<u8> {
   data[index..].to_vec()
 }
 
-pub fn column_for_byte(source: &[u8], byte_index: usize) -> usize {
+/// Build a run of exactly `count` newlines, using `\r\n` if the `original` trailing newlines did.
+pub fn normalized_trailing_newlines(original: &[u8], count: usize) -> Vec<u8> {
+  let newline: &[u8] = if original.windows(2).any(|pair| pair == b"\r\n") {
+    b"\r\n"
+  } else {
+    b"\n"
+  };
+  newline.repeat(count)
+}
+
+/// The style of line ending used within a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+  Lf,
+  CrLf,
+}
+
+impl LineEnding {
+  /// The line ending used by the majority of lines in `data`, or `None` if it contains no line
+  /// endings at all. Ties are resolved in favour of `\n`.
+  pub fn detect(data: &[u8]) -> Option<Self> {
+    let mut lf = 0;
+    let mut crlf = 0;
+    for (index, byte) in data.iter().enumerate() {
+      if *byte == b'\n' {
+        if index > 0 && data[index - 1] == b'\r' {
+          crlf += 1;
+        } else {
+          lf += 1;
+        }
+      }
+    }
+
+    match (lf, crlf) {
+      (0, 0) => None,
+      (lf, crlf) if crlf > lf => Some(Self::CrLf),
+      _ => Some(Self::Lf),
+    }
+  }
+}
+
+/// Rewrite every `\n` and `\r\n` line ending in `data` to `ending`. Lone `\r` bytes are left as is.
+pub fn normalize_line_endings(data: &mut Vec<u8>, ending: LineEnding) {
+  let needs_rewrite = data.iter().enumerate().any(|(index, byte)| {
+    let after_cr = index > 0 && data[index - 1] == b'\r';
+    *byte == b'\n' && after_cr != (ending == LineEnding::CrLf)
+  });
+  if !needs_rewrite {
+    return;
+  }
+
+  let mut normalized = Vec::with_capacity(data.len());
+  for (index, byte) in data.iter().enumerate() {
+    match byte {
+      b'\r' if data.get(index + 1) == Some(&b'\n') => {}
+      b'\n' => {
+        if ending == LineEnding::CrLf {
+          normalized.push(b'\r');
+        }
+        normalized.push(b'\n');
+      }
+      _ => normalized.push(*byte),
+    }
+  }
+  *data = normalized;
+}
+
+/// The byte index at which the line containing `byte_index` starts.
+pub fn line_start(source: &[u8], byte_index: usize) -> usize {
   let target = byte_index.min(source.len());
-  let line_start = source[..target]
+  source[..target]
     .iter()
     .rposition(|byte| *byte == b'\n')
     .map(|index| index + 1)
-    .unwrap_or(0);
+    .unwrap_or(0)
+}
 
-  target - line_start
+/// The column of `byte_index` within its line, counted in characters so that it matches the number
+/// of indent characters needed to line up with it. A tab counts as a single column, as indentation
+/// is measured in units of [`IndentUnit`]. A `\r` immediately before the position is part of the
+/// preceding `\r\n` line ending rather than the line content, so is not counted.
+pub fn column_for_byte(source: &[u8], byte_index: usize) -> usize {
+  let target = byte_index.min(source.len());
+  let line_start = line_start(source, target);
+
+  let mut line_end = target;
+  if line_end > line_start && source[line_end - 1] == b'\r' && source.get(target) == Some(&b'\n') {
+    line_end -= 1;
+  }
+
+  // UTF-8 continuation bytes belong to the character before them
+  source[line_start..line_end]
+    .iter()
+    .filter(|byte| **byte & 0xC0 != 0x80)
+    .count()
 }
 
-pub fn min_leading_indent(text: &str) -> usize {
+pub fn min_leading_indent(text: &str, unit: IndentUnit) -> usize {
   let mut min_indent: Option<usize> = None;
   for line in text.lines() {
     if line.trim().is_empty() {
       continue;
     }
-    let indent = line.chars().take_while(|ch| *ch == ' ').count();
+    let indent = line.chars().take_while(|ch| *ch == unit.char()).count();
     min_indent = Some(min_indent.map_or(indent, |current| current.min(indent)));
   }
 
   min_indent.unwrap_or(0)
 }
 
-pub fn strip_leading_indent(text: &str, indent: usize) -> String {
+pub fn strip_leading_indent(text: &str, indent: usize, unit: IndentUnit) -> String {
   if indent == 0 {
     return text.to_string();
   }
 
   let mut result = String::with_capacity(text.len());
   for segment in text.split_inclusive('\n') {
-    let (line, newline) = if segment.ends_with('\n') {
-      (&segment[..segment.len() - 1], "\n")
+    let (line, newline) = if let Some(line) = segment.strip_suffix('\n') {
+      (line, "\n")
     } else {
       (segment, "")
     };
-    let leading_spaces = line.chars().take_while(|ch| *ch == ' ').count();
-    let trim_count = indent.min(leading_spaces);
+    let leading = line.chars().take_while(|ch| *ch == unit.char()).count();
+    let trim_count = indent.min(leading);
     let trimmed = if trim_count > 0 {
       &line[trim_count..]
     } else {
@@ -90,6 +330,10 @@ pub fn strip_leading_indent(text: &str, indent: usize) -> String {
   result
 }
 
+/// Order escape characters for [`escape_text`] and [`unescape_text`], which use the first one that
+/// matches. Escape characters may be any non-empty string, so longer ones come first and win over
+/// any shorter one they start with: given `'` and `''`, a doubled quote is escaped as `\''` rather
+/// than `\'\'`. Ties are broken alphabetically so that the order is stable.
 pub fn sort_escape_chars(escape_chars: &HashSet<String>) -> Vec<String> {
   let mut chars: Vec<String> = escape_chars.iter().cloned().collect();
   chars.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
@@ -98,10 +342,13 @@ pub fn sort_escape_chars(escape_chars: &HashSet<String>) -> Vec<String> {
 
 // Unescape injected text before passing it to a nested formatter.
 //
-// We scan left-to-right and treat `\\` as a literal backslash so double-escaped sequences survive.
+// We scan left-to-right, and when a backslash directly prefixes one of the configured escape
+// characters we drop the backslash and emit the raw character. Otherwise `\\` is treated as a
+// literal backslash so double-escaped sequences survive.
 //
-// Only when a backslash directly prefixes one of the configured escape characters do we drop the
-// backslash and emit the raw character.
+// Escape characters are matched before `\\` so that one beginning with a backslash is unescaped
+// the same way [`escape_text`] escapes it. Otherwise a backslash produced by unescaping would be
+// escaped a second time on the way back out.
 pub fn unescape_text(text: &str, escape_chars: &[String]) -> String {
   let mut result = String::with_capacity(text.len());
   let escape_bytes: Vec<&[u8]> = escape_chars.iter().map(|s| s.as_bytes()).collect();
@@ -110,25 +357,15 @@ pub fn unescape_text(text: &str, escape_chars: &[String]) -> String {
   while index < text.len() {
     let remaining = &text[index..];
     if remaining.as_bytes().first() == Some(&b'\\') {
-      if remaining.as_bytes().get(1) == Some(&b'\\') {
-        result.push('\\');
-        index += 2;
+      let rest = &remaining.as_bytes()[1..];
+      if let Some(escape) = escape_bytes.iter().find(|escape| rest.starts_with(escape)) {
+        result.push_str(std::str::from_utf8(escape).unwrap());
+        index += 1 + escape.len();
         continue;
       }
-      let mut matched = false;
-      for escape in &escape_bytes {
-        if remaining
-          .as_bytes()
-          .get(1..)
-          .is_some_and(|rest| rest.starts_with(escape))
-        {
-          result.push_str(std::str::from_utf8(escape).unwrap());
-          index += 1 + escape.len();
-          matched = true;
-          break;
-        }
-      }
-      if matched {
+      if rest.first() == Some(&b'\\') {
+        result.push('\\');
+        index += 2;
         continue;
       }
     }
@@ -143,8 +380,8 @@ pub fn unescape_text(text: &str, escape_chars: &[String]) -> String {
 
 // Re-escape injected text before reinserting it into the outer document.
 //
-// We scan left-to-right and always escape literal backslashes, then prefix any configured escape
-// character with a backslash.
+// We scan left-to-right and prefix any configured escape character with a backslash, then escape
+// any other literal backslash. As in [`unescape_text`], escape characters are matched first.
 pub fn escape_text(text: &str, escape_chars: &[String]) -> String {
   let mut result = String::with_capacity(text.len());
   let escape_bytes: Vec<&[u8]> = escape_chars.iter().map(|s| s.as_bytes()).collect();
@@ -152,24 +389,20 @@ pub fn escape_text(text: &str, escape_chars: &[String]) -> String {
   let mut index = 0;
   while index < text.len() {
     let remaining = &text[index..];
+    if let Some(escape) = escape_bytes
+      .iter()
+      .find(|escape| remaining.as_bytes().starts_with(escape))
+    {
+      result.push('\\');
+      result.push_str(std::str::from_utf8(escape).unwrap());
+      index += escape.len();
+      continue;
+    }
     if remaining.as_bytes().first() == Some(&b'\\') {
       result.push_str("\\\\");
       index += 1;
       continue;
     }
-    let mut matched = false;
-    for escape in &escape_bytes {
-      if remaining.as_bytes().starts_with(escape) {
-        result.push('\\');
-        result.push_str(std::str::from_utf8(escape).unwrap());
-        index += escape.len();
-        matched = true;
-        break;
-      }
-    }
-    if matched {
-      continue;
-    }
 
     let ch = remaining.chars().next().unwrap();
     result.push(ch);
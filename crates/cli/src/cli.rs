@@ -1,6 +1,10 @@
 use std::path::PathBuf;
 
-use crate::commands::format::FormatArgs;
+use crate::commands::{
+  check_config::CheckConfigArgs, clean::CleanArgs, dump_injections::DumpInjectionsArgs,
+  dump_query::DumpQueryArgs, format::FormatArgs, list_languages::ListLanguagesArgs,
+  resolve_lang::ResolveLangArgs,
+};
 
 #[derive(Debug, clap::Args)]
 pub struct GlobalOpts {
@@ -11,9 +15,26 @@ pub struct GlobalOpts {
   pub config: Option<PathBuf>,
 
   /// Use named profiles from the config file. Can be specified multiple times;
-  /// profiles are applied in order.
+  /// profiles are applied in order, after any selected by the `PRUNER_PROFILE` env var.
   #[arg(long, global = true)]
   pub profile: Vec<String>,
+
+  /// Fail instead of updating the configured `lockfile` when the commits grammars resolve to have
+  /// changed. Useful in CI to ensure every machine formats with the same grammars.
+  #[arg(
+    long,
+    global = true,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  pub frozen: bool,
+
+  /// Download configs included by url again, instead of using the copies cached when they were
+  /// first fetched.
+  #[arg(long, global = true, default_value_t = false)]
+  pub refresh_includes: bool,
 }
 
 #[derive(clap::Parser, Debug)]
@@ -27,7 +48,26 @@ pub struct Cli {
 }
 
 #[derive(clap::Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
   /// Format one or more files
   Format(FormatArgs),
+
+  /// Remove cached data such as compiled grammars and plugins
+  Clean(CleanArgs),
+
+  /// Print the canonical language a language name resolves to after applying `language_aliases`
+  ResolveLang(ResolveLangArgs),
+
+  /// List every known language along with its grammar, injections query and formatters
+  ListLanguages(ListLanguagesArgs),
+
+  /// Validate the config, reporting every problem found
+  CheckConfig(CheckConfigArgs),
+
+  /// Print the merged source of a language's query, annotated with the file each part came from
+  DumpQuery(DumpQueryArgs),
+
+  /// Print the injected regions found in a document read from stdin, without formatting it
+  DumpInjections(DumpInjectionsArgs),
 }
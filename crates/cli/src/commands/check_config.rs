@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::process::exit;
+
+use crate::{
+  cli::GlobalOpts,
+  config::{self, Config, LoadOpts},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CheckConfigArgs {}
+
+/// Cross-check a loaded config, returning every problem found. Problems are sorted so that the
+/// output is stable.
+pub fn diagnose(config: &Config) -> Vec<String> {
+  let mut diagnostics = Vec::new();
+
+  for (language, specs) in &config.languages {
+    for spec in specs {
+      let formatter = spec.formatter();
+      if !config.formatters.contains_key(formatter) && !config.plugins.contains_key(formatter) {
+        diagnostics.push(format!(
+          "Language '{language}' uses formatter '{formatter}' which is not defined in formatters \
+           or plugins"
+        ));
+      }
+    }
+  }
+
+  for (kind, paths) in [
+    ("grammar_paths", &config.grammar_paths),
+    ("query_paths", &config.query_paths),
+  ] {
+    for path in paths {
+      if !path.is_dir() {
+        diagnostics.push(format!("{kind} entry {path:?} is not a readable directory"));
+      }
+    }
+  }
+
+  diagnostics.sort();
+  diagnostics
+}
+
+pub fn handle(_args: CheckConfigArgs, global: GlobalOpts) -> Result<()> {
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  let diagnostics = diagnose(&config);
+  if diagnostics.is_empty() {
+    log::info!("Config is valid");
+    return Ok(());
+  }
+
+  for diagnostic in &diagnostics {
+    log::error!("{diagnostic}");
+  }
+  log::error!("{} config problems found", diagnostics.len());
+  exit(1);
+}
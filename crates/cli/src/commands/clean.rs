@@ -0,0 +1,56 @@
+use anyhow::Result;
+
+use crate::{
+  api,
+  cli::GlobalOpts,
+  config::{self, LoadOpts},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CleanArgs {
+  /// Only print the paths which would be removed, without removing anything.
+  #[arg(long, default_value_t = false)]
+  dry_run: bool,
+
+  /// Remove compiled grammar libraries from the grammar build dir. They will be rebuilt on the next
+  /// run.
+  #[arg(long, default_value_t = false)]
+  grammars: bool,
+
+  /// Remove cached config includes and compiled wasm plugins from the cache dir. They are rebuilt
+  /// on the next run, while downloaded plugins are kept.
+  #[arg(long, default_value_t = false)]
+  format_cache: bool,
+}
+
+pub fn handle(args: CleanArgs, global: GlobalOpts) -> Result<()> {
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  // Without a targeted flag everything within the cache dir is removed
+  let mut dirs = Vec::new();
+  if !args.format_cache && !args.grammars {
+    dirs.push(config.cache_dir.clone());
+  }
+  if args.format_cache {
+    dirs.extend(api::cache::format_cache_dirs(&config.cache_dir)?);
+  }
+  if args.grammars {
+    dirs.push(config.grammar_build_dir.clone());
+  }
+
+  for dir in dirs {
+    for path in api::cache::clear_dir(&dir, args.dry_run)? {
+      if args.dry_run {
+        println!("{}", path.to_string_lossy());
+      } else {
+        log::info!("Removed {}", path.to_string_lossy());
+      }
+    }
+  }
+
+  Ok(())
+}
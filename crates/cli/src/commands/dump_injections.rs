@@ -0,0 +1,93 @@
+use anyhow::Result;
+use std::io::Read;
+
+use crate::{
+  api::{self, grammar::Grammar, injections::InjectedRegion},
+  cli::GlobalOpts,
+  config::{self, LoadOpts},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DumpInjectionsArgs {
+  /// The language of the document read from stdin. Language aliases are resolved.
+  #[arg(long)]
+  lang: String,
+}
+
+fn describe(region: &InjectedRegion) -> String {
+  let range = &region.range;
+  let mut line = format!(
+    "{} @ {}..{} ({},{})",
+    region.lang,
+    range.start_byte,
+    range.end_byte,
+    range.start_point.row + 1,
+    range.start_point.column + 1
+  );
+
+  if !region.opts.escape_chars.is_empty() {
+    let mut escape_chars = region.opts.escape_chars.iter().collect::<Vec<_>>();
+    escape_chars.sort();
+    line.push_str(&format!(" escape={escape_chars:?}"));
+  }
+  if let Some(printwidth) = region.opts.printwidth {
+    line.push_str(&format!(" printwidth={printwidth}"));
+  }
+  if !region.opts.gaps.is_empty() {
+    line.push_str(&format!(" gaps={:?}", region.opts.gaps));
+  }
+  line
+}
+
+/// List the regions which `grammar`'s injections query finds in `source`, one per line as
+/// `lang @ start..end (row,col)` followed by the options its directives set. Rows and columns are
+/// 1-based.
+pub fn dump(grammar: &Grammar, source: &[u8], strict_ignore: bool) -> Result<String> {
+  let mut parser = tree_sitter::Parser::new();
+  let regions = api::injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source,
+    &api::injections::ExtractOpts {
+      strict_ignore,
+      ..Default::default()
+    },
+  )?;
+
+  Ok(
+    regions
+      .iter()
+      .map(|region| format!("{}\n", describe(region)))
+      .collect(),
+  )
+}
+
+pub fn handle(args: DumpInjectionsArgs, global: GlobalOpts) -> Result<()> {
+  let cwd = std::env::current_dir()?;
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  let lang = config
+    .language_aliases
+    .get(&args.lang)
+    .unwrap_or(&args.lang);
+  let grammars = api::grammar::load_configured_grammars(
+    &config,
+    &cwd,
+    global.frozen,
+    Some(std::slice::from_ref(lang)),
+  )?;
+  let Some(grammar) = grammars.get(lang) else {
+    anyhow::bail!("No grammar found for language '{lang}'");
+  };
+
+  let mut source = Vec::new();
+  std::io::stdin().read_to_end(&mut source)?;
+
+  print!("{}", dump(grammar, &source, config.strict_ignore)?);
+
+  Ok(())
+}
@@ -0,0 +1,65 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::{
+  api::{self, grammar::Grammar, queries},
+  cli::GlobalOpts,
+  config::{self, LoadOpts},
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum QueryKind {
+  /// The injections query used to find embedded regions
+  Injections,
+  /// The `pruner/ignore.scm` query used to skip regions
+  Ignore,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DumpQueryArgs {
+  /// The language whose query should be printed. Language aliases are resolved.
+  #[arg(long)]
+  lang: String,
+
+  #[arg(long, value_enum, default_value_t = QueryKind::Injections)]
+  kind: QueryKind,
+}
+
+/// Resolve the source of the `kind` query for `grammar` the same way it is resolved when the
+/// grammar is loaded, with each merged part preceded by a comment naming the file it came from.
+pub fn dump(grammar: &Grammar, kind: QueryKind, query_paths: &[PathBuf]) -> Result<String> {
+  match kind {
+    QueryKind::Injections => queries::injections_query_source(
+      &grammar.name,
+      &grammar.injections_files,
+      query_paths,
+      true,
+    ),
+    QueryKind::Ignore => {
+      queries::optional_query_source(&grammar.name, "pruner/ignore.scm", query_paths, true)
+    }
+  }
+}
+
+pub fn handle(args: DumpQueryArgs, global: GlobalOpts) -> Result<()> {
+  let cwd = std::env::current_dir()?;
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  let grammars = api::grammar::load_configured_grammars(&config, &cwd, global.frozen, None)?;
+
+  let lang = config
+    .language_aliases
+    .get(&args.lang)
+    .unwrap_or(&args.lang);
+  let Some(grammar) = grammars.get(lang) else {
+    anyhow::bail!("No grammar found for language '{lang}'");
+  };
+
+  print!("{}", dump(grammar, args.kind, &config.query_paths)?);
+
+  Ok(())
+}
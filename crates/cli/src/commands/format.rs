@@ -1,28 +1,86 @@
 use anyhow::{Context, Result};
-use std::{fs, io::Read, path::PathBuf, process::exit, time::Instant};
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::Read,
+  path::{Path, PathBuf},
+  process::exit,
+  sync::Mutex,
+  time::Instant,
+};
 
 use crate::{
   api::{
     self,
-    format::{self, FormatContext, FormatOpts},
+    format::{self, DiscoveredFile, FileResults, FormatContext, FormatOpts, WalkOpts},
+    stats::RegionStats,
   },
   cli::GlobalOpts,
-  config::{self, LoadOpts},
+  config::{self, Config, DirConfigs, LoadOpts},
   wasm::formatter::WasmFormatter,
 };
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+  Text,
+  Json,
+}
+
+/// Printed by `--output-format json` after formatting stdin.
+#[derive(serde::Serialize)]
+struct StdinReport<'a> {
+  formatted: &'a str,
+  changed: bool,
+}
+
+/// Printed by `--output-format json` after formatting files.
+#[derive(serde::Serialize)]
+struct FilesReport<'a> {
+  /// Files which were formatted, or which are dirty when checking.
+  changed: &'a [String],
+  errors: Vec<FileError>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  region_stats: Option<BTreeMap<String, usize>>,
+}
+
+#[derive(serde::Serialize)]
+struct FileError {
+  path: String,
+  error: String,
+}
+
 #[derive(clap::Args, Debug)]
 pub struct FormatArgs {
   /// The language name of the root document. Regions containing injected languages will be
   /// dynamically discovered from queries.
+  ///
+  /// This is required when formatting stdin, unless it can be inferred from --stdin-filepath. When
+  /// formatting files it is used for any file whose extension does not map to a language via
+  /// `language_extensions`.
+  #[arg(long)]
+  lang: Option<String>,
+
+  /// The path of the document being formatted over stdin. The root language is inferred from its
+  /// extension using `language_extensions`. If --lang is also given then --lang takes precedence.
+  #[arg(long)]
+  stdin_filepath: Option<PathBuf>,
+
+  /// Format stdin and atomically write the result to this path rather than to stdout. The root
+  /// language is inferred from the path's extension when --lang and --stdin-filepath are not set.
   #[arg(long)]
-  lang: String,
+  write_stdin_to: Option<PathBuf>,
 
   /// The desired print-width of the document after which text should wrap. This value specifies the
-  /// starting point and will be dynamically adjusted for injected language regions.
+  /// starting point and will be dynamically adjusted for injected language regions. A width of `0`
+  /// disables wrapping.
   #[arg(long, short('w'), default_value_t = 80)]
   print_width: u32,
 
+  /// The maximum number of files to format at once, overriding `max_file_jobs` from the config.
+  /// Injected regions are formatted on the same threads as their file unless `max_region_jobs` is
+  /// configured, so `--jobs 1` formats everything on a single thread in a deterministic order.
+  #[arg(long, short('j'))]
+  jobs: Option<usize>,
+
   /// Specifying this will skip formatting the document root. This means only regions within the
   /// document containing language injections will be formatted. If you only want to use pruner to
   /// format injected regions, then this is the option to use.
@@ -39,6 +97,11 @@ pub struct FormatArgs {
   )]
   skip_root: bool,
 
+  /// Only format files which have changed since this git ref, as well as untracked files. Files
+  /// must still match the include and exclude globs. Requires the files to be in a git repository.
+  #[arg(long)]
+  since: Option<String>,
+
   /// The current working directory. Only used when formatting files.
   #[arg(long, short('d'))]
   dir: Option<PathBuf>,
@@ -48,6 +111,11 @@ pub struct FormatArgs {
   #[arg(long, short('e'))]
   exclude: Option<Vec<String>>,
 
+  /// Leave injected regions of this language untouched, as if it had no formatters or grammar. Can
+  /// be specified multiple times.
+  #[arg(long)]
+  exclude_language: Vec<String>,
+
   /// Setting this to true will result in no files being modified on disk. If any files are
   /// considered 'dirty' meaning, meaning they are not correctly formatted, then pruner will exit
   /// with a non-0 exit code.
@@ -61,17 +129,180 @@ pub struct FormatArgs {
   )]
   check: bool,
 
-  /// A file pattern, in glob format, describing files on disk to be formatted.
+  /// Fail if any language configured under `languages` does not have a loadable grammar. By default
+  /// languages without a grammar are still formatted, but no injected regions will be discovered
+  /// within them.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  require_grammars: bool,
+
+  /// Fail instead of silently skipping content that cannot be processed, such as invalid query
+  /// directives, undecodable language captures, or unreadable files. Useful in CI to guarantee that
+  /// nothing was skipped.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  strict: bool,
+
+  /// Also format hidden files (dotfiles) and files within hidden directories which match the
+  /// include glob. These are skipped by default.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  include_hidden: bool,
+
+  /// Format files even if they are excluded by `.gitignore`, `.ignore` or other ignore files. This
+  /// also includes hidden files.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  no_ignore: bool,
+
+  /// Exit with an error if the include glob did not match any files. Useful in CI to catch typos
+  /// in the glob or running from the wrong directory.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  fail_if_empty: bool,
+
+  /// Do not run any formatters. Injected regions are still unescaped, re-indented and re-escaped,
+  /// normalizing how they are embedded without needing the formatters to be installed.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  no_format: bool,
+
+  /// Print the files which would be formatted, one per line, without formatting them. Files are
+  /// selected exactly as they would be when formatting, including any `[[format_targets]]`.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  list_files: bool,
+
+  /// Format each file with the config nearest to it, rather than every file with the config of the
+  /// working directory. The nearest `pruner.toml` above each file is merged with the global config,
+  /// or the file given with --config, so that subprojects can configure their own formatters.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  per_file_config: bool,
+
+  /// Re-parse each formatted document, failing it if formatting introduced syntax errors. This
+  /// guards against formatters whose output breaks the document their region is embedded in.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  verify_roundtrip: bool,
+
+  /// Only log the summary and any errors, rather than also the path of every file which was
+  /// formatted or found to be dirty. The paths are still logged at the debug level.
+  #[arg(
+    long,
+    short('q'),
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  quiet: bool,
+
+  /// Report how many injected regions of each language were formatted across all files. Text is
+  /// logged alongside the file summary, JSON is printed to stdout.
+  #[arg(long, value_enum)]
+  region_stats: Option<OutputFormat>,
+
+  /// How the result is reported. With `json` a single object is printed to stdout instead of the
+  /// formatted text or the logged file summary. For stdin it holds the `formatted` text and whether
+  /// it `changed`, and for files the paths which `changed` along with any `errors`. JSON region
+  /// stats are included in the object as `region_stats`.
+  #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+  output_format: OutputFormat,
+
+  /// Format exactly the files listed in this file, one path per line, or in stdin when `-`.
+  /// Relative paths are resolved against the cwd (or --dir if set). Excludes still apply, but the
+  /// directory is not walked, so ignore files, hidden files and --since are not considered.
+  #[arg(long, conflicts_with = "include_glob")]
+  files_from: Option<PathBuf>,
+
+  /// File patterns, in glob format, describing files on disk to be formatted. Can be specified
+  /// multiple times.
   ///
   /// If this is specified then pruner will recursively format all files in the cwd (or --dir if
-  /// set) that match this pattern.
+  /// set) that match any of these patterns and no exclude pattern.
   ///
   /// If this is _not_ set then pruner will expect source code to be provided via stdin and the
   /// formatted result will be outputted over stdout.
-  include_glob: Option<String>,
+  ///
+  /// If neither this nor --lang are set then pruner will instead format all `[[format_targets]]`
+  /// declared by `pruner.toml` files found within the cwd (or --dir if set).
+  include_glob: Vec<String>,
 }
 
-fn format_stdin(args: &FormatArgs, context: &FormatContext) -> Result<()> {
+fn stdin_language<'a>(
+  args: &'a FormatArgs,
+  language_extensions: &'a HashMap<String, String>,
+) -> Result<&'a str> {
+  if let Some(lang) = args.lang.as_deref() {
+    return Ok(lang);
+  }
+
+  let Some(path) = args.stdin_filepath.as_ref().or(args.write_stdin_to.as_ref()) else {
+    anyhow::bail!("--lang or --stdin-filepath is required when formatting stdin");
+  };
+
+  format::language_for_path(path, language_extensions).ok_or_else(|| {
+    anyhow::anyhow!(
+      "Unable to infer the language of {:?} from its extension. Specify --lang or add the \
+       extension to language_extensions",
+      path
+    )
+  })
+}
+
+fn format_stdin(
+  args: &FormatArgs,
+  language_extensions: &HashMap<String, String>,
+  context: &FormatContext,
+) -> Result<()> {
+  let lang = stdin_language(args, language_extensions)?;
+
   let input = {
     let mut buf = Vec::new();
     std::io::stdin().read_to_end(&mut buf)?;
@@ -83,7 +314,8 @@ fn format_stdin(args: &FormatArgs, context: &FormatContext) -> Result<()> {
     &input,
     &FormatOpts {
       printwidth: args.print_width,
-      language: &args.lang,
+      language: lang,
+      indent: 0,
     },
     !args.skip_root,
     true,
@@ -94,26 +326,294 @@ fn format_stdin(args: &FormatArgs, context: &FormatContext) -> Result<()> {
     Instant::now().duration_since(start)
   );
 
-  print!("{}", String::from_utf8(result).unwrap());
+  if let Some(path) = &args.write_stdin_to {
+    api::atomic::write(path, &result)?;
+  }
+  match args.output_format {
+    OutputFormat::Json => {
+      let report = StdinReport {
+        formatted: std::str::from_utf8(&result)?,
+        changed: result != input,
+      };
+      println!("{}", serde_json::to_string(&report)?);
+    }
+    OutputFormat::Text if args.write_stdin_to.is_none() => {
+      print!("{}", String::from_utf8(result).unwrap())
+    }
+    OutputFormat::Text => {}
+  }
 
   Ok(())
 }
 
-fn format_files(args: &FormatArgs, context: &FormatContext) -> Result<()> {
-  let cwd = std::env::current_dir()?;
+struct FilesTarget<'a> {
+  dir: &'a Path,
+  include_globs: Vec<&'a str>,
+  exclude: Option<Vec<String>>,
+  /// The language of files whose extension is not mapped to one, which are otherwise skipped.
+  lang: Option<&'a str>,
+  /// The paths given with --files-from, which are formatted instead of walking `dir`.
+  listed: Option<&'a [PathBuf]>,
+}
+
+fn walk_opts(args: &FormatArgs) -> WalkOpts {
+  WalkOpts {
+    include_hidden: args.include_hidden,
+    no_ignore: args.no_ignore,
+    fail_if_empty: args.fail_if_empty,
+    since: args.since.clone(),
+  }
+}
+
+fn target_files<'a>(
+  args: &FormatArgs,
+  target: &FilesTarget<'a>,
+  language_extensions: &'a HashMap<String, String>,
+) -> Result<Vec<DiscoveredFile<'a>>> {
+  match target.listed {
+    Some(paths) => format::listed_files(
+      target.dir,
+      paths,
+      target.exclude.clone(),
+      target.lang,
+      language_extensions,
+      args.strict,
+    ),
+    None => format::discover_files(
+      target.dir,
+      &target.include_globs,
+      target.exclude.clone(),
+      &walk_opts(args),
+      target.lang,
+      language_extensions,
+      args.strict,
+    ),
+  }
+}
+
+/// The files of `target`, as found by [`target_files`] before their languages are resolved.
+fn target_paths(args: &FormatArgs, target: &FilesTarget) -> Result<Vec<PathBuf>> {
+  match target.listed {
+    Some(paths) => format::listed_paths(target.dir, paths, target.exclude.clone(), args.strict),
+    None => format::discover_paths(
+      target.dir,
+      &target.include_globs,
+      target.exclude.clone(),
+      &walk_opts(args),
+      args.strict,
+    ),
+  }
+}
+
+/// Read the newline separated paths given with --files-from, from stdin when `-`.
+fn read_file_list(path: &Path) -> Result<Vec<PathBuf>> {
+  let contents = if path == Path::new("-") {
+    let mut buf = String::new();
+    std::io::stdin().read_to_string(&mut buf)?;
+    buf
+  } else {
+    std::fs::read_to_string(path)
+      .with_context(|| format!("Failed to read the file list {:?}", path))?
+  };
+  Ok(
+    contents
+      .lines()
+      .map(|line| line.trim_end_matches('\r'))
+      .filter(|line| !line.is_empty())
+      .map(PathBuf::from)
+      .collect(),
+  )
+}
 
-  let paths = format::format_files(
-    &args.dir.clone().unwrap_or(cwd),
-    &args.include_glob.clone().unwrap(),
-    args.exclude.clone(),
+fn list_files(
+  args: &FormatArgs,
+  target: FilesTarget,
+  language_extensions: &HashMap<String, String>,
+) -> Result<()> {
+  let files = target_files(args, &target, language_extensions)?;
+  for file in files {
+    println!("{}", file.path.to_string_lossy());
+  }
+  Ok(())
+}
+
+fn format_files(
+  args: &FormatArgs,
+  target: FilesTarget,
+  language_extensions: &HashMap<String, String>,
+  context: &FormatContext,
+) -> Result<FileResults> {
+  let files = target_files(args, &target, language_extensions)?;
+  Ok(format::format_discovered_file_results(
+    &files,
     !args.check,
     &FormatOpts {
       printwidth: args.print_width,
-      language: &args.lang,
+      language: target.lang.unwrap_or_default(),
+      indent: 0,
     },
     args.skip_root,
     context,
-  )?;
+  ))
+}
+
+/// The format state shared by every config files are formatted with.
+struct Shared<'a> {
+  cwd: &'a Path,
+  frozen: bool,
+  file_pool: Option<&'a rayon::ThreadPool>,
+  region_pool: Option<&'a rayon::ThreadPool>,
+  region_stats: &'a Mutex<RegionStats>,
+}
+
+/// Load the plugins and grammars used by `config`, then run `f` with a context formatting with it.
+fn with_context<R>(
+  args: &FormatArgs,
+  config: &Config,
+  shared: &Shared,
+  f: impl FnOnce(&FormatContext) -> Result<R>,
+) -> Result<R> {
+  let wasm_formatter = WasmFormatter::from_config(config)?;
+
+  let mut languages = api::grammar::referenced_languages(config);
+  languages.extend(args.lang.clone());
+  let grammars =
+    api::grammar::load_configured_grammars(config, shared.cwd, shared.frozen, Some(&languages))?;
+
+  if args.require_grammars {
+    api::grammar::ensure_grammars(&config.languages, &grammars)?;
+  }
+
+  let context = FormatContext {
+    grammars: &grammars,
+    languages: &config.formatter_languages,
+    language_aliases: &config.language_aliases,
+    formatters: &config.formatters,
+    wasm_formatter: &wasm_formatter,
+    formatter_kinds: &config.formatter_kinds,
+    formatter_environment: &config.formatter_environment,
+    strict: args.strict,
+    injection_trailing_newlines: &config.injection_trailing_newlines,
+    injection_indent: &config.injection_indent,
+    injection_reindent: &config.injection_reindent,
+    print_width: &config.print_width,
+    region_stats: args.region_stats.is_some().then_some(shared.region_stats),
+    file_pool: shared.file_pool,
+    region_pool: shared.region_pool,
+    skip_formatters: args.no_format,
+    max_depth: None,
+    strict_ignore: config.strict_ignore,
+    grammar_language_map: &config.grammar_language_map,
+    formatter_language_map: &config.formatter_language_map,
+    exclude_languages: &args.exclude_language,
+    document_stats: false,
+    verify_roundtrip: args.verify_roundtrip,
+  };
+  let formatter_kinds = api::format::resolve_formatter_kinds(&context)?;
+  f(&FormatContext {
+    formatter_kinds: &formatter_kinds,
+    ..context
+  })
+}
+
+/// Format the files of `target`, each with the config nearest to it. Files are grouped by the
+/// `pruner.toml` which applies to them, and their languages resolved with that config, so a
+/// `pruner.toml` can map extensions which the config of the working directory does not.
+fn format_files_per_config(
+  args: &FormatArgs,
+  target: FilesTarget,
+  dir_configs: &mut DirConfigs,
+  shared: &Shared,
+) -> Result<FileResults> {
+  let mut groups: BTreeMap<Option<PathBuf>, Vec<PathBuf>> = BTreeMap::new();
+  for path in target_paths(args, &target)? {
+    let dir = path.parent().unwrap_or(target.dir);
+    groups
+      .entry(dir_configs.config_path(dir))
+      .or_default()
+      .push(path);
+  }
+
+  let mut results = FileResults::default();
+  for (config_path, files) in groups {
+    let config = dir_configs.load(config_path.as_deref())?;
+    let files = format::with_languages(files, target.lang, &config.language_extensions);
+
+    results.append(with_context(args, &config, shared, |context| {
+      Ok(format::format_discovered_file_results(
+        &files,
+        !args.check,
+        &FormatOpts {
+          printwidth: args.print_width,
+          language: target.lang.unwrap_or_default(),
+          indent: 0,
+        },
+        args.skip_root,
+        context,
+      ))
+    })?);
+  }
+  Ok(results)
+}
+
+fn report_region_stats(format: OutputFormat, stats: &RegionStats) {
+  match format {
+    OutputFormat::Text => {
+      for (language, count) in stats.counts() {
+        log::info!("{language}: {count} regions");
+      }
+    }
+    OutputFormat::Json => println!("{}", stats.to_json()),
+  }
+}
+
+fn report_json(args: &FormatArgs, results: FileResults, stats: &RegionStats) -> Result<()> {
+  if args.region_stats == Some(OutputFormat::Text) {
+    report_region_stats(OutputFormat::Text, stats);
+  }
+
+  let report = FilesReport {
+    changed: &results.changed,
+    errors: results
+      .errors
+      .iter()
+      .map(|(path, err)| FileError {
+        path: path.clone(),
+        error: format!("{err:#}"),
+      })
+      .collect(),
+    region_stats: (args.region_stats == Some(OutputFormat::Json)).then(|| stats.counts()),
+  };
+  println!("{}", serde_json::to_string(&report)?);
+
+  if !results.errors.is_empty() {
+    anyhow::bail!("Failed to format {} files", results.errors.len());
+  }
+  if args.check && !results.changed.is_empty() {
+    log::error!("{} dirty files", results.changed.len());
+    exit(1);
+  }
+  Ok(())
+}
+
+fn report(args: &FormatArgs, results: FileResults, stats: &RegionStats) -> Result<()> {
+  let level = if args.quiet {
+    log::Level::Debug
+  } else {
+    log::Level::Info
+  };
+  for path in &results.changed {
+    log::log!(level, "{path}");
+  }
+
+  if args.output_format == OutputFormat::Json {
+    return report_json(args, results, stats);
+  }
+
+  let paths = results.into_result()?;
+  if let Some(format) = args.region_stats {
+    report_region_stats(format, stats);
+  }
 
   if args.check {
     if !paths.is_empty() {
@@ -123,56 +623,126 @@ fn format_files(args: &FormatArgs, context: &FormatContext) -> Result<()> {
   } else {
     log::info!("formatted {} files", paths.len());
   }
-
   Ok(())
 }
 
 pub fn handle(args: FormatArgs, global: GlobalOpts) -> Result<()> {
   let cwd = std::env::current_dir()?;
   let config = config::load(LoadOpts {
-    config_path: global.config,
-    profiles: global.profile,
+    config_path: global.config.clone(),
+    profiles: global.profile.clone(),
+    refresh_includes: global.refresh_includes,
   })?;
 
-  let wasm_formatter = WasmFormatter::from_config(&config)?;
-
-  let repos_dir = cwd.join(&config.grammar_download_dir);
-  let lib_dir = cwd.join(&config.grammar_build_dir);
+  let dir = args.dir.clone().unwrap_or_else(|| cwd.clone());
+  let listed = args.files_from.as_deref().map(read_file_list).transpose()?;
+  let format_stdin_only = args.include_glob.is_empty()
+    && listed.is_none()
+    && (args.lang.is_some() || args.stdin_filepath.is_some() || args.write_stdin_to.is_some());
 
-  fs::create_dir_all(&repos_dir)?;
-  fs::create_dir_all(&lib_dir)?;
+  let format_targets = if args.include_glob.is_empty() && listed.is_none() && !format_stdin_only {
+    let targets = config::discover_format_targets(&dir)?;
+    if targets.is_empty() {
+      anyhow::bail!(
+        "No format targets found in {:?}. Specify --lang to format stdin, or an include glob",
+        dir
+      );
+    }
+    targets
+  } else {
+    Vec::new()
+  };
 
-  let start = Instant::now();
-  api::git::clone_all_grammars(&repos_dir, &config.grammars)?;
-  log::debug!(
-    "Grammar clone duration: {:?}",
-    Instant::now().duration_since(start)
-  );
+  let files_targets = if let Some(listed) = &listed {
+    vec![FilesTarget {
+      dir: &dir,
+      include_globs: Vec::new(),
+      exclude: args.exclude.clone(),
+      lang: args.lang.as_deref(),
+      listed: Some(listed),
+    }]
+  } else if !args.include_glob.is_empty() {
+    vec![FilesTarget {
+      dir: &dir,
+      include_globs: args.include_glob.iter().map(String::as_str).collect(),
+      exclude: args.exclude.clone(),
+      lang: args.lang.as_deref(),
+      listed: None,
+    }]
+  } else {
+    format_targets
+      .iter()
+      .map(|resolved| {
+        let mut exclude = resolved.target.exclude.clone().unwrap_or_default();
+        exclude.extend(args.exclude.clone().unwrap_or_default());
 
-  let mut grammar_paths = config.grammar_paths.clone();
-  grammar_paths.push(repos_dir);
+        FilesTarget {
+          dir: &resolved.dir,
+          include_globs: vec![&resolved.target.glob],
+          exclude: Some(exclude),
+          lang: Some(&resolved.target.lang),
+          listed: None,
+        }
+      })
+      .collect()
+  };
 
-  let start = Instant::now();
-  let grammars = api::grammar::load_grammars(&grammar_paths, &config.query_paths, Some(lib_dir))
-    .context("Failed to load grammars")?;
-  log::debug!(
-    "Grammar load duration: {:?}",
-    Instant::now().duration_since(start)
-  );
+  if args.list_files {
+    if format_stdin_only {
+      anyhow::bail!("--list-files requires an include glob or `[[format_targets]]`");
+    }
+    for target in files_targets {
+      list_files(&args, target, &config.language_extensions)?;
+    }
+    return Ok(());
+  }
 
-  let context = FormatContext {
-    grammars: &grammars,
-    languages: &config.languages,
-    language_aliases: &config.language_aliases,
-    formatters: &config.formatters,
-    wasm_formatter: &wasm_formatter,
+  let file_pool = format::thread_pool(args.jobs.or(config.max_file_jobs))?;
+  let region_pool = format::thread_pool(config.max_region_jobs)?;
+  let region_stats = Mutex::new(RegionStats::new());
+  let shared = Shared {
+    cwd: &cwd,
+    frozen: global.frozen,
+    file_pool: file_pool.as_ref(),
+    region_pool: region_pool.as_ref(),
+    region_stats: &region_stats,
   };
 
-  if args.include_glob.is_some() {
-    format_files(&args, &context)?;
-  } else {
-    format_stdin(&args, &context)?;
+  if format_stdin_only {
+    return with_context(&args, &config, &shared, |context| {
+      let format = || format_stdin(&args, &config.language_extensions, context);
+      match &file_pool {
+        Some(pool) => pool.install(format),
+        None => format(),
+      }
+    });
   }
 
-  Ok(())
+  let mut results = FileResults::default();
+  if args.per_file_config {
+    let mut dir_configs = DirConfigs::new(LoadOpts {
+      config_path: global.config,
+      profiles: global.profile,
+      refresh_includes: global.refresh_includes,
+    })?;
+    for target in files_targets {
+      results.append(format_files_per_config(&args, target, &mut dir_configs, &shared)?);
+    }
+  } else {
+    with_context(&args, &config, &shared, |context| {
+      for target in files_targets {
+        results.append(format_files(
+          &args,
+          target,
+          &config.language_extensions,
+          context,
+        )?);
+      }
+      Ok(())
+    })?;
+  }
+  let region_stats = region_stats
+    .into_inner()
+    .unwrap_or_else(|err| err.into_inner());
+  report(&args, results, &region_stats)
 }
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+use crate::{
+  api::{self, grammar::Grammars},
+  cli::GlobalOpts,
+  config::{self, LanguageFormatters, LoadOpts},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ListLanguagesArgs {}
+
+fn yes_no(value: bool) -> &'static str {
+  if value { "yes" } else { "no" }
+}
+
+/// Describe every language which has either a grammar or configured formatters, one line per
+/// language sorted by name. Each line holds tab separated `key=value` fields.
+pub fn describe(languages: &LanguageFormatters, grammars: &Grammars) -> Vec<String> {
+  let names = languages
+    .keys()
+    .chain(grammars.keys())
+    .collect::<BTreeSet<_>>();
+
+  names
+    .into_iter()
+    .map(|name| {
+      let grammar = grammars.get(name);
+      let formatters = languages
+        .get(name)
+        .map(|specs| {
+          specs
+            .iter()
+            .map(|spec| spec.formatter())
+            .collect::<Vec<_>>()
+            .join(",")
+        })
+        .unwrap_or_default();
+      format!(
+        "{name}\tgrammar={}\tinjections={}\tformatters={formatters}",
+        yes_no(grammar.is_some()),
+        yes_no(grammar.is_some_and(|grammar| grammar.injections.pattern_count() > 0)),
+      )
+    })
+    .collect()
+}
+
+pub fn handle(_args: ListLanguagesArgs, global: GlobalOpts) -> Result<()> {
+  let cwd = std::env::current_dir()?;
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  let grammars = api::grammar::load_configured_grammars(&config, &cwd, global.frozen, None)?;
+
+  for line in describe(&config.languages, &grammars) {
+    println!("{line}");
+  }
+
+  Ok(())
+}
@@ -1 +1,7 @@
+pub mod check_config;
+pub mod clean;
+pub mod dump_injections;
+pub mod dump_query;
 pub mod format;
+pub mod list_languages;
+pub mod resolve_lang;
@@ -0,0 +1,48 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::{
+  api::directives::gsub::{self, GsubRule},
+  cli::GlobalOpts,
+  config::{self, LoadOpts},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ResolveLangArgs {
+  /// The language name to resolve, as it would be captured by an injection query.
+  name: String,
+
+  /// A `gsub!` rule, given as a Lua pattern followed by its replacement, to apply to the name
+  /// before aliases are resolved. Can be specified multiple times, rules are applied in order.
+  #[arg(long, num_args = 2, value_names = ["PATTERN", "REPLACEMENT"])]
+  gsub: Vec<String>,
+}
+
+/// Apply `rules` to `name` and then map the result to its canonical language, mirroring how the
+/// language of an injected region is resolved.
+pub fn resolve(
+  name: &str,
+  rules: &[GsubRule],
+  language_aliases: &HashMap<String, String>,
+) -> String {
+  let name = gsub::apply(name, rules);
+  language_aliases.get(&name).cloned().unwrap_or(name)
+}
+
+pub fn handle(args: ResolveLangArgs, global: GlobalOpts) -> Result<()> {
+  let config = config::load(LoadOpts {
+    config_path: global.config,
+    profiles: global.profile,
+    refresh_includes: global.refresh_includes,
+  })?;
+
+  let rules = args
+    .gsub
+    .chunks(2)
+    .map(|rule| gsub::compile_gsub_rule(&rule[0], &rule[1]))
+    .collect::<Result<Vec<_>>>()?;
+
+  println!("{}", resolve(&args.name, &rules, &config.language_aliases));
+
+  Ok(())
+}
@@ -1,16 +1,34 @@
 use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
 use std::{
   collections::HashMap,
+  fs,
   hash::Hash,
   path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
 };
 use url::Url;
 
-#[derive(serde::Deserialize, Debug, Clone)]
+use crate::api::{
+  cache,
+  text::{IndentUnit, ReindentMode},
+};
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum GrammarSpec {
   Url(Url),
-  Table { url: Url, rev: Option<String> },
+  Table {
+    url: Url,
+    rev: Option<String>,
+    /// The directory within the repo holding the grammar, for repos which provide several such as
+    /// `typescript` and `tsx`.
+    path: Option<PathBuf>,
+    /// An external scanner kept outside the grammar's `src` directory, relative to the grammar.
+    /// Grammars are compiled as C, so this must be a `.c` file.
+    scanner: Option<PathBuf>,
+  },
 }
 
 impl GrammarSpec {
@@ -30,36 +48,174 @@ impl GrammarSpec {
       },
     }
   }
+
+  pub fn path(&self) -> Option<&Path> {
+    match self {
+      GrammarSpec::Url(_) => None,
+      GrammarSpec::Table { path, .. } => path.as_deref(),
+    }
+  }
+
+  pub fn scanner(&self) -> Option<&Path> {
+    match self {
+      GrammarSpec::Url(_) => None,
+      GrammarSpec::Table { scanner, .. } => scanner.as_deref(),
+    }
+  }
 }
 
-#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct FormatterSpec {
   pub cmd: String,
   pub args: Vec<String>,
   pub stdin: Option<bool>,
   pub fail_on_stderr: Option<bool>,
+  /// Exit codes which count as success, for check-style tools which exit non-zero when they
+  /// reformat their input. Defaults to `[0]`.
+  pub success_codes: Option<Vec<i32>>,
+  /// A regex matched against each line of stderr when `fail_on_stderr` is set. Matching lines,
+  /// such as deprecation warnings, do not fail the run.
+  pub stderr_ignore: Option<String>,
+  /// Kill the formatter and fail if it has not exited after this many milliseconds.
+  pub timeout_ms: Option<u64>,
+  /// Kill the formatter and fail if it writes more than this many bytes to stdout or stderr,
+  /// protecting against formatters stuck printing in a loop.
+  pub max_output_bytes: Option<u64>,
+  /// Environment variables set for the formatter process. These are added on top of the inherited
+  /// environment and support the same `$textwidth`, `$language`, `$indent` and `$file`
+  /// substitutions as `args`.
+  pub env: Option<HashMap<String, String>>,
+  /// Append a newline to the source written to stdin if it does not already end with one.
+  pub stdin_append_newline: Option<bool>,
+  /// The encoding the formatter reads and writes, such as `shift_jis` or `windows-1252`. Sources
+  /// are transcoded from UTF-8 before being passed to the formatter, and its output is transcoded
+  /// back. Defaults to UTF-8.
+  pub encoding: Option<String>,
+  /// Run the formatter again up to this many times if it fails transiently, meaning it could not
+  /// be spawned or communicated with, or it exited with `EX_TEMPFAIL` (75). Other failures are
+  /// never retried.
+  pub retries: Option<u32>,
+  /// The delay before the first retry in milliseconds, doubling after each attempt. Defaults to
+  /// 100ms.
+  pub retry_backoff_ms: Option<u64>,
+  /// When `stdin` is false, pass the source to the formatter through a named pipe at `$file`
+  /// instead of a temp file, and read the result from stdout. Large documents are then streamed
+  /// rather than written to disk. Falls back to a temp file where named pipes are unsupported.
+  pub fifo: Option<bool>,
+  /// Substituted for `$textwidth` instead of a very large width when wrapping is disabled with a
+  /// print width of `0`, for formatters with their own way of saying so.
+  pub unlimited_textwidth: Option<String>,
 }
 
-#[derive(serde::Deserialize, Debug, Clone)]
+/// A wasm formatter, given as a `file://` or `https://` url or as a path to a `.wasm` component
+/// relative to the config file.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum PluginSpec {
   Url(Url),
   Table { url: Url },
+  Path(PathBuf),
 }
 
 impl PluginSpec {
-  pub fn url(&self) -> &Url {
+  pub fn url(&self) -> Result<Url> {
+    match self {
+      Self::Url(url) => Ok(url.clone()),
+      Self::Table { url, .. } => Ok(url.clone()),
+      Self::Path(path) => Url::from_file_path(path)
+        .map_err(|_| anyhow::anyhow!("Plugin path {:?} must be absolute", path)),
+    }
+  }
+
+  fn absolutize_path(self, base_dir: &Path) -> Self {
     match self {
-      Self::Url(url) => url,
-      Self::Table { url, .. } => url,
+      Self::Path(path) => Self::Path(absolutize_path(path, base_dir)),
+      spec => spec,
     }
   }
 }
 
+fn absolutize_plugins(plugins: PluginSpecs, base_dir: &Path) -> PluginSpecs {
+  plugins
+    .into_iter()
+    .map(|(name, spec)| (name, spec.absolutize_path(base_dir)))
+    .collect()
+}
+
+/// Those of `paths`, along with any plugin paths in `plugins`, which are relative.
+fn relative_paths<'a>(
+  paths: impl IntoIterator<Item = &'a PathBuf>,
+  plugins: &'a Option<PluginSpecs>,
+) -> Vec<&'a Path> {
+  let plugin_paths = plugins.iter().flatten().filter_map(|(_, spec)| match spec {
+    PluginSpec::Path(path) => Some(path),
+    _ => None,
+  });
+  paths
+    .into_iter()
+    .chain(plugin_paths)
+    .filter(|path| path.is_relative())
+    .map(PathBuf::as_path)
+    .collect()
+}
+
+/// Environment applied to every spawned formatter process, allowing formatter output to be made
+/// independent of the ambient locale and timezone.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FormatterEnvironment {
+  /// Sets `LC_ALL` and `LANG` for formatter processes.
+  pub locale: Option<String>,
+  /// Sets `TZ` for formatter processes.
+  pub timezone: Option<String>,
+  /// When set, formatter processes do not inherit the environment of pruner. Only `PATH` and `HOME`
+  /// are passed through, in addition to the locale and timezone.
+  #[serde(default)]
+  pub isolated: bool,
+}
+
+/// Controls how grammar parsers are compiled, for cross-compilation or toolchains the platform
+/// defaults do not cover.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct GrammarCompilerConfig {
+  /// The C compiler used to build grammars, taking precedence over the `CC` environment variable.
+  pub cc: Option<String>,
+  /// Extra flags passed to the C compiler, ahead of any from the `CFLAGS` environment variable.
+  pub cflags: Option<Vec<String>>,
+  /// Preprocessor symbols defined when compiling grammars, each passed to the compiler as
+  /// `-D<define>`.
+  pub defines: Option<Vec<String>>,
+}
+
+/// A set of files to format, declared via `[[format_targets]]` in a `pruner.toml`. The glob is
+/// matched against files within the directory containing the declaring config file.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct FormatTarget {
+  pub glob: String,
+  pub lang: String,
+  pub exclude: Option<Vec<String>>,
+}
+
+/// A [`FormatTarget`] along with the directory it was declared in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedFormatTarget {
+  pub dir: PathBuf,
+  pub target: FormatTarget,
+}
+
 pub type FormatterSpecs = HashMap<String, FormatterSpec>;
 pub type PluginSpecs = HashMap<String, PluginSpec>;
 pub type GrammarSpecs = HashMap<String, GrammarSpec>;
 
+/// Which kind of formatter a name used in `languages` refers to.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatterKind {
+  /// A command configured in `formatters`.
+  Shell,
+  /// A component configured in `plugins`.
+  Wasm,
+}
+
 fn default_resource() -> bool {
   true
 }
@@ -75,6 +231,14 @@ pub enum LanguageFormatSpec {
     run_in_root: bool,
     #[serde(default = "default_resource")]
     run_in_injections: bool,
+    /// Only run this formatter on injections whose parent language is in this list. When unset or
+    /// empty the formatter runs in injections within any language.
+    #[serde(default)]
+    only_when_injected_in: Option<Vec<String>>,
+    /// Skip the formatters following this one when it leaves the document unchanged, for chains
+    /// whose later formatters are expensive and only needed after this one made changes.
+    #[serde(default)]
+    stop_if_unchanged: bool,
   },
 }
 impl LanguageFormatSpec {
@@ -98,6 +262,31 @@ impl LanguageFormatSpec {
       } => *run_in_injections,
     }
   }
+  fn is_inherited(&self) -> bool {
+    matches!(self, Self::String(formatter) if formatter == INHERITED_FORMATTERS)
+  }
+  pub fn stop_if_unchanged(&self) -> bool {
+    match self {
+      Self::String(_) => false,
+      Self::Table {
+        stop_if_unchanged, ..
+      } => *stop_if_unchanged,
+    }
+  }
+  /// Whether this formatter should run on a document injected within `parent_language`. Root
+  /// documents, which have no parent, are always allowed.
+  pub fn runs_within(&self, parent_language: Option<&str>) -> bool {
+    match (parent_language, self) {
+      (
+        Some(parent_language),
+        Self::Table {
+          only_when_injected_in: Some(languages),
+          ..
+        },
+      ) => languages.is_empty() || languages.iter().any(|language| language == parent_language),
+      _ => true,
+    }
+  }
 }
 
 impl From<String> for LanguageFormatSpec {
@@ -112,28 +301,97 @@ impl From<&str> for LanguageFormatSpec {
   }
 }
 
+/// Stands in for the formatters a language inherits from the config or profile being overlaid, so
+/// that `markdown = ["...", "extra"]` appends to the inherited chain rather than replacing it.
+pub const INHERITED_FORMATTERS: &str = "...";
+
 pub type LanguageFormatSpecs = Vec<LanguageFormatSpec>;
 pub type LanguageFormatters = HashMap<String, LanguageFormatSpecs>;
 pub type LanguageAliasSpecs = HashMap<String, Vec<String>>;
+pub type LanguageExtensionSpecs = HashMap<String, Vec<String>>;
 
 /// Profile-specific configuration overrides.
 /// Has the same fields as ConfigFile (except profiles) to allow full override capability.
 #[derive(serde::Deserialize, Debug, Default, Clone)]
 pub struct ProfileConfig {
+  /// The name of a profile applied before this one whenever this profile is, so that a profile can
+  /// build on another and override parts of it.
+  pub extends: Option<String>,
+
   pub query_paths: Option<Vec<PathBuf>>,
   pub grammar_paths: Option<Vec<PathBuf>>,
 
   pub grammar_download_dir: Option<PathBuf>,
   pub grammar_build_dir: Option<PathBuf>,
+  pub grammar_compiler: Option<GrammarCompilerConfig>,
+  /// Where to record the commit each grammar was resolved to, such as `pruner.lock`. Missing
+  /// grammars are cloned at their recorded commit, so every machine sharing the lockfile uses the
+  /// same grammar sources. Relative to the config file.
+  pub lockfile: Option<PathBuf>,
+  /// How many commits of history to fetch when cloning grammars. Defaults to `1`, set to `0` to
+  /// fetch the full history.
+  pub grammar_clone_depth: Option<u32>,
 
   pub grammars: Option<GrammarSpecs>,
+  /// The formatters run on each language, in order. A language's chain replaces the one it would
+  /// inherit from the global config or, within a profile, from the config the profile is applied
+  /// to. Include [`INHERITED_FORMATTERS`] to splice the inherited chain in instead.
   pub languages: Option<LanguageFormatters>,
   pub language_aliases: Option<LanguageAliasSpecs>,
+  pub language_extensions: Option<LanguageExtensionSpecs>,
   pub formatters: Option<FormatterSpecs>,
+  pub formatter_environment: Option<FormatterEnvironment>,
+  /// The number of trailing newlines kept by injected regions, keyed by the language of the host
+  /// document. Regions within hosts that are not listed keep their original trailing newlines.
+  pub injection_trailing_newlines: Option<HashMap<String, usize>>,
+  /// The unit, `space` or `tab`, used to indent injected regions, keyed by the language of the host
+  /// document. Regions within hosts that are not listed infer it from their content.
+  pub injection_indent: Option<HashMap<String, IndentUnit>>,
+  /// How injected regions are re-indented, keyed by the language of the host document. `always`
+  /// indents every line, `if_needed` skips lines the formatter already indented.
+  pub injection_reindent: Option<HashMap<String, ReindentMode>>,
+  /// Print widths keyed by language, overriding the width given on the command line for root
+  /// documents and the width inherited by injected regions of that language. `0` disables wrapping.
+  pub print_width: Option<HashMap<String, u32>>,
+  /// Maps a language, after aliases are resolved, to the name of the grammar used to parse it.
+  pub grammar_language_map: Option<HashMap<String, String>>,
+  /// Maps a language, after aliases are resolved, to the language used to select its formatters
+  /// and substituted for `$language`. For example `javascript = "babel"` parses with the
+  /// `javascript` grammar but formats with the formatters configured for `babel`.
+  pub formatter_language_map: Option<HashMap<String, String>>,
+  /// The maximum number of files formatted at once. Defaults to one per CPU.
+  pub max_file_jobs: Option<usize>,
+  /// The maximum number of injected regions formatted at once, shared by every file being
+  /// formatted. Defaults to one per CPU.
+  pub max_region_jobs: Option<usize>,
   pub plugins: Option<PluginSpecs>,
+  /// The kind of formatter a name resolves to, keyed by formatter name. Names configured as both a
+  /// formatter and a plugin otherwise resolve to the formatter, so `wasm` forces the plugin.
+  pub formatter_kinds: Option<HashMap<String, FormatterKind>>,
+
+  /// When enabled, any formatter or plugin whose name matches a language is used to format that
+  /// language without needing an explicit `languages` entry.
+  pub auto_map_formatters: Option<bool>,
+
+  /// Ignore injected regions which a `pruner-ignore` range only partially overlaps, instead of
+  /// formatting them with a warning.
+  pub strict_ignore: Option<bool>,
 }
 
 impl ProfileConfig {
+  fn relative_paths(&self) -> Vec<&Path> {
+    let dirs = [&self.query_paths, &self.grammar_paths];
+    let paths = [
+      &self.grammar_download_dir,
+      &self.grammar_build_dir,
+      &self.lockfile,
+    ];
+    relative_paths(
+      dirs.into_iter().flatten().flatten().chain(paths.into_iter().flatten()),
+      &self.plugins,
+    )
+  }
+
   fn absolutize_paths(mut self, base_dir: &Path) -> Self {
     self.query_paths = self
       .query_paths
@@ -147,6 +405,10 @@ impl ProfileConfig {
     self.grammar_build_dir = self
       .grammar_build_dir
       .map(|path| absolutize_path(path, base_dir));
+    self.lockfile = self.lockfile.map(|path| absolutize_path(path, base_dir));
+    self.plugins = self
+      .plugins
+      .map(|plugins| absolutize_plugins(plugins, base_dir));
 
     self
   }
@@ -161,12 +423,71 @@ pub struct ConfigFile {
 
   pub grammar_download_dir: Option<PathBuf>,
   pub grammar_build_dir: Option<PathBuf>,
+  pub grammar_compiler: Option<GrammarCompilerConfig>,
+  /// Where to record the commit each grammar was resolved to, such as `pruner.lock`. Missing
+  /// grammars are cloned at their recorded commit, so every machine sharing the lockfile uses the
+  /// same grammar sources. Relative to the config file.
+  pub lockfile: Option<PathBuf>,
+  /// How many commits of history to fetch when cloning grammars. Defaults to `1`, set to `0` to
+  /// fetch the full history.
+  pub grammar_clone_depth: Option<u32>,
 
   pub grammars: Option<GrammarSpecs>,
+  /// The formatters run on each language, in order. A language's chain replaces the one it would
+  /// inherit from the global config or, within a profile, from the config the profile is applied
+  /// to. Include [`INHERITED_FORMATTERS`] to splice the inherited chain in instead.
   pub languages: Option<LanguageFormatters>,
   pub language_aliases: Option<LanguageAliasSpecs>,
+  pub language_extensions: Option<LanguageExtensionSpecs>,
   pub formatters: Option<FormatterSpecs>,
+  pub formatter_environment: Option<FormatterEnvironment>,
+  /// The number of trailing newlines kept by injected regions, keyed by the language of the host
+  /// document. Regions within hosts that are not listed keep their original trailing newlines.
+  pub injection_trailing_newlines: Option<HashMap<String, usize>>,
+  /// The unit, `space` or `tab`, used to indent injected regions, keyed by the language of the host
+  /// document. Regions within hosts that are not listed infer it from their content.
+  pub injection_indent: Option<HashMap<String, IndentUnit>>,
+  /// How injected regions are re-indented, keyed by the language of the host document. `always`
+  /// indents every line, `if_needed` skips lines the formatter already indented.
+  pub injection_reindent: Option<HashMap<String, ReindentMode>>,
+  /// Print widths keyed by language, overriding the width given on the command line for root
+  /// documents and the width inherited by injected regions of that language. `0` disables wrapping.
+  pub print_width: Option<HashMap<String, u32>>,
+  /// Maps a language, after aliases are resolved, to the name of the grammar used to parse it.
+  pub grammar_language_map: Option<HashMap<String, String>>,
+  /// Maps a language, after aliases are resolved, to the language used to select its formatters
+  /// and substituted for `$language`. For example `javascript = "babel"` parses with the
+  /// `javascript` grammar but formats with the formatters configured for `babel`.
+  pub formatter_language_map: Option<HashMap<String, String>>,
+  /// The maximum number of files formatted at once. Defaults to one per CPU.
+  pub max_file_jobs: Option<usize>,
+  /// The maximum number of injected regions formatted at once, shared by every file being
+  /// formatted. Defaults to one per CPU.
+  pub max_region_jobs: Option<usize>,
   pub plugins: Option<PluginSpecs>,
+  /// The kind of formatter a name resolves to, keyed by formatter name. Names configured as both a
+  /// formatter and a plugin otherwise resolve to the formatter, so `wasm` forces the plugin.
+  pub formatter_kinds: Option<HashMap<String, FormatterKind>>,
+
+  /// When enabled, any formatter or plugin whose name matches a language is used to format that
+  /// language without needing an explicit `languages` entry.
+  pub auto_map_formatters: Option<bool>,
+
+  /// Ignore injected regions which a `pruner-ignore` range only partially overlaps, instead of
+  /// formatting them with a warning.
+  pub strict_ignore: Option<bool>,
+
+  pub format_targets: Option<Vec<FormatTarget>>,
+
+  /// Configs this one builds on, given as paths relative to this file or as `https://` urls. They
+  /// are merged beneath this file in order, so its own settings take precedence. The
+  /// `format_targets` of included configs are ignored, as they belong to the directory of the file
+  /// declaring them.
+  ///
+  /// Remote configs are downloaded once and cached, only being fetched again when
+  /// `--refresh-includes` is given. As they have no directory of their own, any paths they set must
+  /// be absolute.
+  pub include: Option<Vec<String>>,
 
   pub profiles: Option<HashMap<String, ProfileConfig>>,
 }
@@ -180,13 +501,35 @@ pub struct Config {
 
   pub grammar_download_dir: PathBuf,
   pub grammar_build_dir: PathBuf,
+  pub grammar_compiler: GrammarCompilerConfig,
+  pub lockfile: Option<PathBuf>,
+  pub grammar_clone_depth: u32,
   pub cache_dir: PathBuf,
 
   pub grammars: GrammarSpecs,
   pub languages: LanguageFormatters,
+  /// The formatters run on documents of each language, used when resolving which formatters to
+  /// run. These are `languages` plus, with `auto_map_formatters`, every formatter and plugin under
+  /// its own name. The auto mapped names are kept out of `languages`, as they are not languages
+  /// which grammars or extensions are configured for.
+  pub formatter_languages: LanguageFormatters,
   pub language_aliases: HashMap<String, String>,
+  /// Maps a file extension (without the leading `.`) to the language of documents with that
+  /// extension.
+  pub language_extensions: HashMap<String, String>,
   pub formatters: FormatterSpecs,
+  pub formatter_environment: FormatterEnvironment,
+  pub injection_trailing_newlines: HashMap<String, usize>,
+  pub injection_indent: HashMap<String, IndentUnit>,
+  pub injection_reindent: HashMap<String, ReindentMode>,
+  pub print_width: HashMap<String, u32>,
+  pub grammar_language_map: HashMap<String, String>,
+  pub formatter_language_map: HashMap<String, String>,
+  pub max_file_jobs: Option<usize>,
+  pub max_region_jobs: Option<usize>,
+  pub strict_ignore: bool,
   pub plugins: PluginSpecs,
+  pub formatter_kinds: HashMap<String, FormatterKind>,
 }
 
 fn absolutize_vec(paths: Vec<PathBuf>, base_dir: &Path) -> Vec<PathBuf> {
@@ -231,11 +574,167 @@ fn merge_maps<K: Eq + Hash + Clone, V: Clone>(
   }
 }
 
+/// Merge language formatters like [`merge_maps`], except that [`INHERITED_FORMATTERS`] within an
+/// overlaid chain is replaced by the chain it overlays.
+fn merge_languages(
+  base: &Option<LanguageFormatters>,
+  overlay: &Option<LanguageFormatters>,
+) -> Option<LanguageFormatters> {
+  let Some(overlay_values) = overlay else {
+    return base.clone();
+  };
+
+  let mut merged = base.clone().unwrap_or_default();
+  for (language, specs) in overlay_values {
+    let inherited = merged.remove(language).unwrap_or_default();
+    let chain = specs
+      .iter()
+      .flat_map(|spec| {
+        if spec.is_inherited() {
+          inherited.clone()
+        } else {
+          vec![spec.clone()]
+        }
+      })
+      .collect();
+    merged.insert(language.clone(), chain);
+  }
+  Some(merged)
+}
+
+/// Where a config file is read from, which the configs it includes are resolved relative to.
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigSource {
+  Path(PathBuf),
+  Url(Url),
+}
+
+impl std::fmt::Display for ConfigSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Path(path) => write!(f, "{}", path.display()),
+      Self::Url(url) => write!(f, "{url}"),
+    }
+  }
+}
+
+/// How long to wait on a remote config before giving up.
+const REMOTE_CONFIG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Read the config at `url` from the cache, downloading it first when it is not cached yet or
+/// `refresh` is set.
+fn read_remote_config(url: &Url, refresh: bool) -> Result<String> {
+  let cache_dir = cache::include_cache_dir(&default_cache_dir()?);
+  let cache_path = cache_dir.join(format!(
+    "{:x}.toml",
+    Sha256::digest(url.as_str().as_bytes())
+  ));
+  if !refresh && let Ok(content) = fs::read_to_string(&cache_path) {
+    log::debug!("Using cached config {cache_path:?} for {url}");
+    return Ok(content);
+  }
+
+  log::info!("Downloading config {url}");
+  let content = ureq::get(url.as_str())
+    .timeout(REMOTE_CONFIG_TIMEOUT)
+    .call()?
+    .into_string()?;
+  fs::create_dir_all(&cache_dir)?;
+  let tmp_path = cache_path.with_extension(format!("{}.tmp", std::process::id()));
+  fs::write(&tmp_path, &content)?;
+  fs::rename(&tmp_path, &cache_path).context("Failed to cache downloaded config")?;
+  Ok(content)
+}
+
+impl ConfigSource {
+  fn read(&self, refresh: bool) -> Result<String> {
+    match self {
+      Self::Path(path) => Ok(fs::read_to_string(path)?),
+      Self::Url(url) => read_remote_config(url, refresh),
+    }
+  }
+
+  fn base_dir(&self) -> Option<&Path> {
+    match self {
+      Self::Path(path) => path.parent(),
+      Self::Url(_) => None,
+    }
+  }
+
+  fn include(&self, include: &str) -> Result<ConfigSource> {
+    if let Ok(url) = Url::parse(include) {
+      match url.scheme() {
+        "https" => return Ok(Self::Url(url)),
+        "http" => anyhow::bail!("Config includes must use https, not {url}"),
+        "file" => {
+          let path = url
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid file url {url}"))?;
+          return Ok(Self::Path(path));
+        }
+        _ => {}
+      }
+    }
+
+    match self {
+      Self::Path(path) => Ok(Self::Path(absolutize_path(
+        PathBuf::from(include),
+        path.parent().unwrap_or(Path::new("")),
+      ))),
+      Self::Url(url) => Ok(Self::Url(url.join(include)?)),
+    }
+  }
+}
+
 impl ConfigFile {
-  pub fn from_file(path: &Path) -> Result<Self> {
-    let content = std::fs::read_to_string(path)?;
+  /// Read the config at `path` merged over everything it includes. Remote includes are downloaded
+  /// again rather than read from the cache when `refresh_includes` is set.
+  pub fn from_file(path: &Path, refresh_includes: bool) -> Result<Self> {
+    Self::from_source(
+      &ConfigSource::Path(path.to_owned()),
+      &mut Vec::new(),
+      refresh_includes,
+    )
+  }
+
+  /// Read the config at `source` merged over everything it includes, where `including` is the
+  /// chain of configs which included it.
+  fn from_source(
+    source: &ConfigSource,
+    including: &mut Vec<ConfigSource>,
+    refresh: bool,
+  ) -> Result<Self> {
+    if including.contains(source) {
+      let chain = including.iter().chain([source]).map(ToString::to_string);
+      anyhow::bail!(
+        "Config includes form a cycle: {}",
+        chain.collect::<Vec<_>>().join(" -> ")
+      );
+    }
+
+    let content = source.read(refresh)?;
     let config: ConfigFile = toml::from_str(&content)?;
-    Ok(config.absolutize_paths(path.parent()))
+    if let (ConfigSource::Url(_), Some(path)) = (source, config.relative_paths().first()) {
+      anyhow::bail!("Remote configs must only use absolute paths, found {path:?}");
+    }
+    let mut config = config.absolutize_paths(source.base_dir());
+    let Some(includes) = config.include.take() else {
+      return Ok(config);
+    };
+
+    including.push(source.clone());
+    let mut base = ConfigFile::default();
+    for include in &includes {
+      let mut included = source
+        .include(include)
+        .and_then(|included| Self::from_source(&included, including, refresh))
+        .with_context(|| format!("Failed to include {include:?} from {source}"))?;
+      included.format_targets = None;
+      base = ConfigFile::merge(&base, &included);
+    }
+    including.pop();
+
+    Ok(ConfigFile::merge(&base, &config))
   }
 
   pub fn merge(base: &ConfigFile, overlay: &ConfigFile) -> ConfigFile {
@@ -250,11 +749,41 @@ impl ConfigFile {
         .grammar_build_dir
         .clone()
         .or_else(|| base.grammar_build_dir.clone()),
+      grammar_compiler: overlay
+        .grammar_compiler
+        .clone()
+        .or_else(|| base.grammar_compiler.clone()),
+      lockfile: overlay.lockfile.clone().or_else(|| base.lockfile.clone()),
+      grammar_clone_depth: overlay.grammar_clone_depth.or(base.grammar_clone_depth),
       grammars: merge_maps(&base.grammars, &overlay.grammars),
-      languages: merge_maps(&base.languages, &overlay.languages),
+      languages: merge_languages(&base.languages, &overlay.languages),
       language_aliases: merge_maps(&base.language_aliases, &overlay.language_aliases),
+      language_extensions: merge_maps(&base.language_extensions, &overlay.language_extensions),
       formatters: merge_maps(&base.formatters, &overlay.formatters),
+      formatter_environment: overlay
+        .formatter_environment
+        .clone()
+        .or_else(|| base.formatter_environment.clone()),
+      injection_trailing_newlines: merge_maps(
+        &base.injection_trailing_newlines,
+        &overlay.injection_trailing_newlines,
+      ),
+      injection_indent: merge_maps(&base.injection_indent, &overlay.injection_indent),
+      injection_reindent: merge_maps(&base.injection_reindent, &overlay.injection_reindent),
+      print_width: merge_maps(&base.print_width, &overlay.print_width),
+      grammar_language_map: merge_maps(&base.grammar_language_map, &overlay.grammar_language_map),
+      formatter_language_map: merge_maps(
+        &base.formatter_language_map,
+        &overlay.formatter_language_map,
+      ),
+      max_file_jobs: overlay.max_file_jobs.or(base.max_file_jobs),
+      max_region_jobs: overlay.max_region_jobs.or(base.max_region_jobs),
       plugins: merge_maps(&base.plugins, &overlay.plugins),
+      formatter_kinds: merge_maps(&base.formatter_kinds, &overlay.formatter_kinds),
+      auto_map_formatters: overlay.auto_map_formatters.or(base.auto_map_formatters),
+      strict_ignore: overlay.strict_ignore.or(base.strict_ignore),
+      format_targets: merge_vecs(&base.format_targets, &overlay.format_targets),
+      include: merge_vecs(&base.include, &overlay.include),
       profiles: merge_maps(&base.profiles, &overlay.profiles),
     }
   }
@@ -268,15 +797,63 @@ impl ConfigFile {
         .clone()
         .or(self.grammar_download_dir),
       grammar_build_dir: profile.grammar_build_dir.clone().or(self.grammar_build_dir),
+      grammar_compiler: profile.grammar_compiler.clone().or(self.grammar_compiler),
+      lockfile: profile.lockfile.clone().or(self.lockfile),
+      grammar_clone_depth: profile.grammar_clone_depth.or(self.grammar_clone_depth),
       grammars: merge_maps(&self.grammars, &profile.grammars),
-      languages: merge_maps(&self.languages, &profile.languages),
+      languages: merge_languages(&self.languages, &profile.languages),
       language_aliases: merge_maps(&self.language_aliases, &profile.language_aliases),
+      language_extensions: merge_maps(&self.language_extensions, &profile.language_extensions),
       formatters: merge_maps(&self.formatters, &profile.formatters),
+      formatter_environment: profile
+        .formatter_environment
+        .clone()
+        .or(self.formatter_environment),
+      injection_trailing_newlines: merge_maps(
+        &self.injection_trailing_newlines,
+        &profile.injection_trailing_newlines,
+      ),
+      injection_indent: merge_maps(&self.injection_indent, &profile.injection_indent),
+      injection_reindent: merge_maps(&self.injection_reindent, &profile.injection_reindent),
+      print_width: merge_maps(&self.print_width, &profile.print_width),
+      grammar_language_map: merge_maps(&self.grammar_language_map, &profile.grammar_language_map),
+      formatter_language_map: merge_maps(
+        &self.formatter_language_map,
+        &profile.formatter_language_map,
+      ),
+      max_file_jobs: profile.max_file_jobs.or(self.max_file_jobs),
+      max_region_jobs: profile.max_region_jobs.or(self.max_region_jobs),
       plugins: merge_maps(&self.plugins, &profile.plugins),
+      formatter_kinds: merge_maps(&self.formatter_kinds, &profile.formatter_kinds),
+      auto_map_formatters: profile.auto_map_formatters.or(self.auto_map_formatters),
+      strict_ignore: profile.strict_ignore.or(self.strict_ignore),
+      format_targets: self.format_targets,
+      include: self.include,
       profiles: self.profiles,
     }
   }
 
+  fn relative_paths(&self) -> Vec<&Path> {
+    let dirs = [&self.query_paths, &self.grammar_paths];
+    let paths = [
+      &self.grammar_download_dir,
+      &self.grammar_build_dir,
+      &self.lockfile,
+    ];
+    let mut relative = relative_paths(
+      dirs.into_iter().flatten().flatten().chain(paths.into_iter().flatten()),
+      &self.plugins,
+    );
+    relative.extend(
+      self
+        .profiles
+        .iter()
+        .flat_map(|profiles| profiles.values())
+        .flat_map(ProfileConfig::relative_paths),
+    );
+    relative
+  }
+
   fn absolutize_paths(mut self, base_dir: Option<&Path>) -> Self {
     let Some(base_dir) = base_dir else {
       return self;
@@ -294,6 +871,10 @@ impl ConfigFile {
     self.grammar_build_dir = self
       .grammar_build_dir
       .map(|path| absolutize_path(path, base_dir));
+    self.lockfile = self.lockfile.map(|path| absolutize_path(path, base_dir));
+    self.plugins = self
+      .plugins
+      .map(|plugins| absolutize_plugins(plugins, base_dir));
     self.profiles = self.profiles.map(|profiles| {
       profiles
         .into_iter()
@@ -315,48 +896,225 @@ fn find_local_config(start_dir: &Path) -> Option<PathBuf> {
   None
 }
 
-fn load_config_file(config_path: Option<PathBuf>) -> Result<ConfigFile> {
-  let cwd = std::env::current_dir()?;
+/// Discover all `pruner.toml` files within `root` (respecting ignore files) and collect the
+/// `format_targets` they declare. Targets are returned ordered by the directory they were declared
+/// in.
+pub fn discover_format_targets(root: &Path) -> Result<Vec<ResolvedFormatTarget>> {
+  let mut config_paths = ignore::WalkBuilder::new(root)
+    .build()
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.into_path())
+    .filter(|path| path.is_file() && path.file_name() == Some("pruner.toml".as_ref()))
+    .collect::<Vec<_>>();
+  config_paths.sort();
 
+  let mut targets = Vec::new();
+  for config_path in config_paths {
+    let config_file = ConfigFile::from_file(&config_path, false)
+      .with_context(|| format!("Failed to load config {:?}", config_path))?;
+    let Some(dir) = config_path.parent() else {
+      continue;
+    };
+
+    for target in config_file.format_targets.unwrap_or_default() {
+      targets.push(ResolvedFormatTarget {
+        dir: dir.to_path_buf(),
+        target,
+      });
+    }
+  }
+
+  Ok(targets)
+}
+
+fn read_config_file(path: Option<&Path>, refresh_includes: bool) -> Result<ConfigFile> {
+  match path {
+    Some(path) => ConfigFile::from_file(path, refresh_includes)
+      .with_context(|| format!("Failed to load config {:?}", path)),
+    None => Ok(ConfigFile::default()),
+  }
+}
+
+/// The config every `pruner.toml` is merged on top of, which is the file given by `config_path` or
+/// otherwise the global config.
+fn base_config_file(
+  config_path: Option<&Path>,
+  cwd: &Path,
+  refresh_includes: bool,
+) -> Result<ConfigFile> {
   if let Some(path) = config_path {
-    return ConfigFile::from_file(&cwd.join(path));
+    return ConfigFile::from_file(&cwd.join(path), refresh_includes);
   }
 
   let xdg_dirs = xdg::BaseDirectories::with_prefix("pruner");
-  let config_path = xdg_dirs.find_config_file("config.toml");
-  let global_config = match config_path.as_deref() {
-    Some(config_path) => ConfigFile::from_file(config_path)
-      .with_context(|| format!("Failed to load config {:?}", config_path))?,
-    None => ConfigFile::default(),
-  };
+  read_config_file(
+    xdg_dirs.find_config_file("config.toml").as_deref(),
+    refresh_includes,
+  )
+}
 
-  let local_config_path = find_local_config(&cwd);
-  let local_config = match local_config_path.as_deref() {
-    Some(local_config_path) => ConfigFile::from_file(local_config_path)
-      .with_context(|| format!("Failed to load config {:?}", local_config_path))?,
-    None => ConfigFile::default(),
-  };
+fn load_config_file(config_path: Option<PathBuf>, refresh_includes: bool) -> Result<ConfigFile> {
+  let cwd = std::env::current_dir()?;
+
+  if config_path.is_some() {
+    return base_config_file(config_path.as_deref(), &cwd, refresh_includes);
+  }
 
+  let global_config = base_config_file(None, &cwd, refresh_includes)?;
+  let local_config = read_config_file(find_local_config(&cwd).as_deref(), refresh_includes)?;
   Ok(ConfigFile::merge(&global_config, &local_config))
 }
 
+/// Follow aliases which map to another alias, such that every alias maps directly to its final
+/// canonical language.
+fn resolve_alias_chains(aliases: HashMap<String, String>) -> Result<HashMap<String, String>> {
+  let mut resolved = HashMap::with_capacity(aliases.len());
+  for alias in aliases.keys() {
+    let mut chain = vec![alias.as_str()];
+    let mut canonical = &aliases[alias];
+    while let Some(next) = aliases.get(canonical)
+      && next != canonical
+    {
+      chain.push(canonical);
+      if chain.contains(&next.as_str()) {
+        chain.push(next);
+        anyhow::bail!("Language aliases form a cycle: {}", chain.join(" -> "));
+      }
+      canonical = next;
+    }
+    resolved.insert(alias.clone(), canonical.clone());
+  }
+  Ok(resolved)
+}
+
 pub struct LoadOpts {
   pub config_path: Option<PathBuf>,
   pub profiles: Vec<String>,
+  /// Download remote includes again instead of using their cached copies.
+  pub refresh_includes: bool,
 }
 
 pub fn load(opts: LoadOpts) -> Result<Config> {
+  resolve(
+    load_config_file(opts.config_path, opts.refresh_includes)?,
+    &opts.profiles,
+  )
+}
+
+/// Resolves the config of each directory when files are formatted with the config nearest to them.
+/// This is the base config, being the file given with `--config` or otherwise the global config,
+/// merged with the nearest `pruner.toml` above the directory. Lookups are cached by directory and
+/// configs by the `pruner.toml` they were read from, so each is only searched for and read once.
+pub struct DirConfigs {
+  base: ConfigFile,
+  profiles: Vec<String>,
+  refresh_includes: bool,
+  nearest: HashMap<PathBuf, Option<PathBuf>>,
+  configs: HashMap<Option<PathBuf>, Arc<Config>>,
+}
+
+impl DirConfigs {
+  pub fn new(opts: LoadOpts) -> Result<Self> {
+    let cwd = std::env::current_dir()?;
+    Ok(Self {
+      base: base_config_file(opts.config_path.as_deref(), &cwd, opts.refresh_includes)?,
+      profiles: opts.profiles,
+      refresh_includes: opts.refresh_includes,
+      nearest: HashMap::new(),
+      configs: HashMap::new(),
+    })
+  }
+
+  /// The `pruner.toml` nearest to `dir`, searching `dir` and then each of its ancestors.
+  pub fn config_path(&mut self, dir: &Path) -> Option<PathBuf> {
+    self
+      .nearest
+      .entry(dir.to_owned())
+      .or_insert_with(|| find_local_config(dir))
+      .clone()
+  }
+
+  /// The base config merged with the `pruner.toml` at `config_path`, or just the base config when
+  /// there is none.
+  pub fn load(&mut self, config_path: Option<&Path>) -> Result<Arc<Config>> {
+    let key = config_path.map(Path::to_path_buf);
+    if let Some(config) = self.configs.get(&key) {
+      return Ok(config.clone());
+    }
+
+    let local_config = read_config_file(config_path, self.refresh_includes)?;
+    let config = Arc::new(resolve(
+      ConfigFile::merge(&self.base, &local_config),
+      &self.profiles,
+    )?);
+    self.configs.insert(key, config.clone());
+    Ok(config)
+  }
+}
+
+/// The env var selecting profiles without changing the command line, such as `PRUNER_PROFILE=ci`.
+/// Comma-separated profiles are applied in order.
+pub const PROFILE_ENV: &str = "PRUNER_PROFILE";
+
+/// The profiles named by [`PROFILE_ENV`] followed by `profiles`. Profiles applied later take
+/// precedence, so those given explicitly win, and a profile named by both is only applied in its
+/// explicit position.
+fn selected_profiles(profiles: &[String]) -> Vec<String> {
+  let env_profiles = std::env::var(PROFILE_ENV).unwrap_or_default();
+  let mut selected = env_profiles
+    .split(',')
+    .map(str::trim)
+    .filter(|profile| !profile.is_empty() && !profiles.iter().any(|explicit| explicit == profile))
+    .map(String::from)
+    .collect::<Vec<_>>();
+  selected.extend(profiles.iter().cloned());
+  selected
+}
+
+/// The profile `name` preceded by each profile it `extends`, in the order they are applied.
+fn profile_chain<'a>(
+  profiles: Option<&'a HashMap<String, ProfileConfig>>,
+  name: &'a str,
+) -> Result<Vec<&'a ProfileConfig>> {
+  let mut names = Vec::new();
+  let mut chain = Vec::new();
+  let mut next = Some(name);
+  while let Some(name) = next {
+    if names.contains(&name) {
+      names.push(name);
+      anyhow::bail!("Profiles form an inheritance cycle: {}", names.join(" -> "));
+    }
+
+    let profile = profiles
+      .and_then(|p| p.get(name))
+      .ok_or_else(|| anyhow::anyhow!("Profile '{}' not found", name))?;
+    names.push(name);
+    chain.push(profile);
+    next = profile.extends.as_deref();
+  }
+
+  chain.reverse();
+  Ok(chain)
+}
+
+/// Where pruner caches downloaded and compiled data, such as plugins and remote configs.
+fn default_cache_dir() -> Result<PathBuf> {
+  Ok(xdg::BaseDirectories::with_prefix("pruner").place_data_file("cache")?)
+}
+
+/// Apply `profiles`, along with any selected by [`PROFILE_ENV`], to `config_file` and resolve the
+/// result.
+fn resolve(mut config_file: ConfigFile, profiles: &[String]) -> Result<Config> {
   let xdg_dirs = xdg::BaseDirectories::with_prefix("pruner");
-  let mut config_file = load_config_file(opts.config_path)?;
 
-  for profile_name in &opts.profiles {
-    let profile = config_file
-      .profiles
-      .as_ref()
-      .and_then(|p| p.get(profile_name))
-      .ok_or_else(|| anyhow::anyhow!("Profile '{}' not found", profile_name))?
-      .clone();
-    config_file = config_file.apply_profile(&profile);
+  for profile_name in &selected_profiles(profiles) {
+    let chain = profile_chain(config_file.profiles.as_ref(), profile_name)?
+      .into_iter()
+      .cloned()
+      .collect::<Vec<_>>();
+    for profile in &chain {
+      config_file = config_file.apply_profile(profile);
+    }
   }
 
   let mut alias_to_canonical: HashMap<String, String> = HashMap::new();
@@ -376,6 +1134,55 @@ pub fn load(opts: LoadOpts) -> Result<Config> {
     }
   }
 
+  let alias_to_canonical = resolve_alias_chains(alias_to_canonical)?;
+
+  let mut extension_to_language: HashMap<String, String> = HashMap::new();
+  for (language, extensions) in config_file.language_extensions.clone().unwrap_or_default() {
+    for extension in extensions {
+      let extension = extension.trim_start_matches('.').to_string();
+      if let Some(existing) = extension_to_language.get(&extension)
+        && existing != &language
+      {
+        anyhow::bail!(
+          "Language extension '{}' conflicts: maps to '{}' and '{}'",
+          extension,
+          existing,
+          language
+        );
+      }
+      extension_to_language.insert(extension, language.clone());
+    }
+  }
+
+  let grammars = config_file.grammars.unwrap_or_default();
+  for (name, spec) in &grammars {
+    if let Some(scanner) = spec.scanner()
+      && scanner.extension().is_none_or(|extension| extension != "c")
+    {
+      anyhow::bail!(
+        "Grammar '{}' has scanner {:?}, but only C scanners ending in .c are supported",
+        name,
+        scanner
+      );
+    }
+  }
+
+  let formatters = config_file.formatters.unwrap_or_default();
+  let plugins = config_file.plugins.unwrap_or_default();
+  let mut languages = config_file.languages.unwrap_or_default();
+  // Chains with nothing to inherit inherit nothing
+  for specs in languages.values_mut() {
+    specs.retain(|spec| !spec.is_inherited());
+  }
+  let mut formatter_languages = languages.clone();
+  if config_file.auto_map_formatters.unwrap_or(false) {
+    for name in formatters.keys().chain(plugins.keys()) {
+      formatter_languages
+        .entry(name.clone())
+        .or_insert_with(|| vec![name.as_str().into()]);
+    }
+  }
+
   Ok(Config {
     query_paths: config_file.query_paths.unwrap_or_default(),
     grammar_paths: config_file.grammar_paths.unwrap_or_default(),
@@ -385,11 +1192,27 @@ pub fn load(opts: LoadOpts) -> Result<Config> {
     grammar_build_dir: config_file
       .grammar_build_dir
       .unwrap_or(xdg_dirs.place_data_file("build")?),
-    cache_dir: xdg_dirs.place_data_file("cache")?,
-    grammars: config_file.grammars.unwrap_or_default(),
-    languages: config_file.languages.unwrap_or_default(),
+    grammar_compiler: config_file.grammar_compiler.unwrap_or_default(),
+    lockfile: config_file.lockfile,
+    grammar_clone_depth: config_file.grammar_clone_depth.unwrap_or(1),
+    cache_dir: default_cache_dir()?,
+    grammars,
+    languages,
+    formatter_languages,
     language_aliases: alias_to_canonical,
-    formatters: config_file.formatters.unwrap_or_default(),
-    plugins: config_file.plugins.unwrap_or_default(),
+    language_extensions: extension_to_language,
+    formatters,
+    formatter_environment: config_file.formatter_environment.unwrap_or_default(),
+    injection_trailing_newlines: config_file.injection_trailing_newlines.unwrap_or_default(),
+    injection_indent: config_file.injection_indent.unwrap_or_default(),
+    injection_reindent: config_file.injection_reindent.unwrap_or_default(),
+    print_width: config_file.print_width.unwrap_or_default(),
+    grammar_language_map: config_file.grammar_language_map.unwrap_or_default(),
+    formatter_language_map: config_file.formatter_language_map.unwrap_or_default(),
+    max_file_jobs: config_file.max_file_jobs,
+    max_region_jobs: config_file.max_region_jobs,
+    strict_ignore: config_file.strict_ignore.unwrap_or(false),
+    plugins,
+    formatter_kinds: config_file.formatter_kinds.unwrap_or_default(),
   })
 }
@@ -26,6 +26,24 @@ fn main() -> Result<()> {
     cli::Commands::Format(args) => {
       commands::format::handle(args, cli.global_opts)?;
     }
+    cli::Commands::Clean(args) => {
+      commands::clean::handle(args, cli.global_opts)?;
+    }
+    cli::Commands::ResolveLang(args) => {
+      commands::resolve_lang::handle(args, cli.global_opts)?;
+    }
+    cli::Commands::ListLanguages(args) => {
+      commands::list_languages::handle(args, cli.global_opts)?;
+    }
+    cli::Commands::CheckConfig(args) => {
+      commands::check_config::handle(args, cli.global_opts)?;
+    }
+    cli::Commands::DumpQuery(args) => {
+      commands::dump_query::handle(args, cli.global_opts)?;
+    }
+    cli::Commands::DumpInjections(args) => {
+      commands::dump_injections::handle(args, cli.global_opts)?;
+    }
   }
 
   Ok(())
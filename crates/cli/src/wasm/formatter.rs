@@ -59,7 +59,7 @@ impl WasmFormatter {
   pub fn from_config(config: &Config) -> Result<Self> {
     let mut formatter = Self::new(config.cache_dir.clone())?;
     for (name, spec) in &config.plugins {
-      formatter.registry.load_component(name, spec.url())?;
+      formatter.registry.load_component(name, &spec.url()?)?;
     }
     Ok(formatter)
   }
@@ -88,7 +88,7 @@ impl WasmFormatter {
         &mut store,
         source,
         &plugin_api::formatter::FormatOpts {
-          print_width: opts.printwidth,
+          print_width: opts.effective_printwidth(),
           lang: opts.language.into(),
         },
       )?
@@ -11,6 +11,8 @@ use std::{
 use url::Url;
 use wasmtime::{Engine, component::Component};
 
+use crate::api::cache;
+
 pub struct ComponentRegistry {
   engine: Engine,
   components: HashMap<String, Component>,
@@ -117,12 +119,7 @@ impl ComponentRegistry {
   }
 
   fn compile_component(&mut self, name: &str, path: &Path, hash: &str) -> Result<Component> {
-    let cache_path = self
-      .cache_dir
-      .join("wasm")
-      .join(name)
-      .join("compiled")
-      .join(format!("{hash}.cwasm"));
+    let cache_path = cache::compiled_plugin_dir(&self.cache_dir, name).join(format!("{hash}.cwasm"));
 
     if std::fs::exists(&cache_path)? {
       return unsafe { Component::deserialize_file(&self.engine, cache_path) };
@@ -174,16 +171,17 @@ impl ComponentRegistry {
   }
 
   fn resolve_remote_component(&self, name: &str, url: &Url) -> Result<(PathBuf, String)> {
-    let component_dir = self.cache_dir.join("wasm").join(name);
+    let component_dir = cache::plugin_cache_dir(&self.cache_dir, name);
     fs::create_dir_all(&component_dir).context("Failed to ensure wasm cache dir")?;
 
     let metadata_path = component_dir.join("metadata.toml");
     let download_path = component_dir.join("component.wasm");
 
-    if let Some(metadata) = read_metadata(&metadata_path)? {
-      if metadata.url == *url && download_path.is_file() {
-        return Ok((download_path, metadata.hash));
-      }
+    if let Some(metadata) = read_metadata(&metadata_path)?
+      && metadata.url == *url
+      && download_path.is_file()
+    {
+      return Ok((download_path, metadata.hash));
     }
 
     let hash = download_to_path(url, &download_path)?;
@@ -0,0 +1,54 @@
+#![cfg(unix)]
+
+use std::{fs, io::Write, os::unix::fs::PermissionsExt};
+
+use pruner::api::atomic;
+
+mod common;
+
+#[test]
+fn atomic_write_keeps_permissions() {
+  let temp_dir = common::unique_temp_dir("pruner-atomic-write");
+  let target = temp_dir.join("script.sh");
+  fs::write(&target, "old").expect("should write target");
+  fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).expect("should set mode");
+
+  atomic::write(&target, b"new").expect("should write atomically");
+
+  assert_eq!(fs::read_to_string(&target).expect("should read target"), "new");
+  let mode = fs::metadata(&target).expect("should stat").permissions().mode();
+  assert_eq!(mode & 0o777, 0o750);
+  assert_eq!(
+    fs::read_dir(&temp_dir).expect("should list dir").count(),
+    1,
+    "temporary files should not be left behind"
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[test]
+fn failed_atomic_writes_leave_the_original_intact() {
+  let temp_dir = common::unique_temp_dir("pruner-atomic-write-failure");
+  let target = temp_dir.join("notes.txt");
+  fs::write(&target, "original").expect("should write target");
+
+  let err = atomic::write_with(&target, |file| {
+    file.write_all(b"partial")?;
+    anyhow::bail!("simulated crash")
+  })
+  .expect_err("write should fail");
+
+  assert!(format!("{err:#}").contains("simulated crash"));
+  assert_eq!(
+    fs::read_to_string(&target).expect("should read target"),
+    "original"
+  );
+  assert_eq!(
+    fs::read_dir(&temp_dir).expect("should list dir").count(),
+    1,
+    "temporary files should not be left behind"
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{self, LoadOpts},
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn formatter_is_applied_by_language_name() -> Result<()> {
+  let config_path = common::write_config(
+    &common::unique_temp_dir("pruner-auto-map"),
+    r#"
+auto_map_formatters = true
+
+[formatters]
+upper = { cmd = "tr", args = ["a-z", "A-Z"] }
+rev = { cmd = "rev", args = [] }
+
+[languages]
+rev = ["upper"]
+"#,
+  );
+
+  let config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+
+  assert_eq!(config.formatter_languages.get("upper"), Some(&vec!["upper".into()]));
+  assert_eq!(
+    config.formatter_languages.get("rev"),
+    Some(&vec!["upper".into()]),
+    "explicit language entries should take precedence"
+  );
+  // Formatter names are not languages, and so are not reported by list-languages and the like
+  assert_eq!(config.languages.keys().collect::<Vec<_>>(), vec!["rev"]);
+
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let result = format::format(
+    b"hello\n",
+    &FormatOpts {
+      printwidth: 80,
+      language: "upper",
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      language_aliases: &config.language_aliases,
+      formatter_environment: &config.formatter_environment,
+      ..common::format_context(
+        &grammars,
+        &config.formatter_languages,
+        &config.formatters,
+        &wasm_formatter,
+      )
+    },
+  )?;
+
+  assert_eq!(String::from_utf8(result)?, "HELLO\n");
+
+  Ok(())
+}
+
+#[test]
+fn formatters_are_not_mapped_by_default() -> Result<()> {
+  let config_path = common::write_config(
+    &common::unique_temp_dir("pruner-auto-map"),
+    r#"
+[formatters]
+upper = { cmd = "tr", args = ["a-z", "A-Z"] }
+"#,
+  );
+
+  let config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+
+  assert!(config.languages.is_empty());
+  assert!(config.formatter_languages.is_empty());
+
+  Ok(())
+}
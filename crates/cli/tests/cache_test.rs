@@ -0,0 +1,87 @@
+use std::fs;
+
+use pruner::api::cache;
+
+mod common;
+
+#[test]
+fn clear_dir_empties_the_cache_dir() {
+  let cache_dir = common::unique_temp_dir("pruner-cache-test");
+  fs::create_dir_all(cache_dir.join("wasm/plugin/compiled")).unwrap();
+  fs::write(cache_dir.join("wasm/plugin/compiled/abc.cwasm"), "").unwrap();
+  fs::write(cache_dir.join("manifest.toml"), "").unwrap();
+
+  let removed = cache::clear_dir(&cache_dir, false).expect("should clear cache dir");
+
+  assert_eq!(
+    removed,
+    vec![cache_dir.join("manifest.toml"), cache_dir.join("wasm")]
+  );
+  assert!(cache_dir.is_dir(), "the cache dir itself should be kept");
+  assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 0);
+
+  let _ = fs::remove_dir_all(&cache_dir);
+}
+
+#[test]
+fn clear_dir_dry_run_removes_nothing() {
+  let cache_dir = common::unique_temp_dir("pruner-cache-test");
+  fs::write(cache_dir.join("markdown.so"), "").unwrap();
+
+  let removed = cache::clear_dir(&cache_dir, true).expect("should list cache dir");
+
+  assert_eq!(removed, vec![cache_dir.join("markdown.so")]);
+  assert!(cache_dir.join("markdown.so").is_file());
+
+  let _ = fs::remove_dir_all(&cache_dir);
+}
+
+#[test]
+fn clear_dir_ignores_missing_dirs() {
+  let temp_dir = common::unique_temp_dir("pruner-cache-test");
+
+  let removed =
+    cache::clear_dir(&temp_dir.join("missing"), false).expect("should ignore missing dir");
+
+  assert!(removed.is_empty());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[test]
+fn clean_format_cache_removes_compiled_plugins() {
+  let temp_dir = common::unique_temp_dir("pruner-cache-test");
+  fs::copy("tests/fixtures/wasm/upper.wat", temp_dir.join("upper.wat")).unwrap();
+  common::write_config(
+    &temp_dir,
+    r#"
+[plugins]
+upper = "upper.wat"
+
+[languages]
+text = ["upper"]
+"#,
+  );
+  // The cache dir lives in the data dir, which is pointed at the temp dir
+  let compiled_dir = cache::compiled_plugin_dir(&temp_dir.join("pruner/cache"), "upper");
+
+  let output = common::run_pruner_with_stdin(&temp_dir, &["format", "--lang", "text"], b"text\n");
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert_eq!(String::from_utf8_lossy(&output.stdout), "TEXT\n");
+  assert_eq!(fs::read_dir(&compiled_dir).unwrap().count(), 1);
+
+  let output = common::run_pruner(&temp_dir, &["clean", "--format-cache"]);
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert!(compiled_dir.is_dir());
+  assert_eq!(fs::read_dir(&compiled_dir).unwrap().count(), 0);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
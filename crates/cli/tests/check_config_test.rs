@@ -0,0 +1,52 @@
+use std::fs;
+
+use pruner::{
+  commands::check_config,
+  config::{self, LoadOpts},
+};
+
+mod common;
+
+#[test]
+fn reports_every_config_problem() {
+  let temp_dir = common::unique_temp_dir("pruner-check-config");
+  fs::create_dir_all(temp_dir.join("queries")).expect("should create queries dir");
+  let config_path = common::write_config(
+    &temp_dir,
+    r#"
+grammar_paths = ["missing-grammars"]
+query_paths = ["queries"]
+
+[formatters]
+sqlfmt = { cmd = "sqlfmt", args = [] }
+
+[plugins]
+wasmfmt = "https://example.com/wasmfmt.wasm"
+
+[languages]
+sql = ["sqlfmt", "missing"]
+markdown = ["wasmfmt", { formatter = "prettier" }]
+"#,
+  );
+
+  let config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load config");
+
+  let missing_grammars = temp_dir.join("missing-grammars");
+  assert_eq!(
+    check_config::diagnose(&config),
+    vec![
+      "Language 'markdown' uses formatter 'prettier' which is not defined in formatters or plugins"
+        .to_string(),
+      "Language 'sql' uses formatter 'missing' which is not defined in formatters or plugins"
+        .to_string(),
+      format!("grammar_paths entry {missing_grammars:?} is not a readable directory"),
+    ]
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
@@ -1,10 +1,23 @@
 use anyhow::Result;
 use fslock::LockFile;
-use std::{collections::HashMap, fs::File, io::Read, path::PathBuf};
+use std::{
+  collections::HashMap,
+  fs::{self, File},
+  io::{Read, Write},
+  path::{Path, PathBuf},
+  process::{Command, Output, Stdio},
+  sync::LazyLock,
+  time::{SystemTime, UNIX_EPOCH},
+};
 
 use pruner::{
-  api::grammar::{self, Grammars},
-  config::{FormatterSpecs, LanguageFormatters},
+  api::{
+    format::FormatContext,
+    grammar::{self, Grammars},
+    text::{IndentUnit, ReindentMode},
+  },
+  config::{FormatterEnvironment, FormatterKind, FormatterSpecs, LanguageFormatters},
+  wasm::formatter::WasmFormatter,
 };
 
 #[allow(dead_code)]
@@ -19,8 +32,7 @@ pub fn formatters() -> FormatterSpecs {
           "--print-width=$textwidth".into(),
           "--parser=$language".into(),
         ]),
-        stdin: None,
-        fail_on_stderr: None,
+        ..Default::default()
       },
     ),
     (
@@ -33,7 +45,7 @@ pub fn formatters() -> FormatterSpecs {
           "--remove-multiple-non-indenting-spaces".into(),
         ]),
         stdin: Some(true),
-        fail_on_stderr: None,
+        ..Default::default()
       },
     ),
   ])
@@ -46,6 +58,16 @@ pub fn grammars() -> Result<Grammars> {
 
 #[allow(dead_code)]
 pub fn grammars_with_queries(query_paths: &[PathBuf]) -> Result<Grammars> {
+  load_grammars(query_paths, None)
+}
+
+/// The fixture grammars needed to format `languages`, as loaded for a config referencing them.
+#[allow(dead_code)]
+pub fn grammars_for(languages: &[String]) -> Result<Grammars> {
+  load_grammars(&["tests/fixtures/queries".into()], Some(languages))
+}
+
+fn load_grammars(query_paths: &[PathBuf], languages: Option<&[String]>) -> Result<Grammars> {
   let mut file = LockFile::open("tests/fixtures/.build.lock")?;
   file.lock()?;
 
@@ -53,6 +75,9 @@ pub fn grammars_with_queries(query_paths: &[PathBuf]) -> Result<Grammars> {
     &["tests/fixtures/grammars".into()],
     query_paths,
     Some("tests/fixtures/.build".into()),
+    None,
+    &Default::default(),
+    languages,
   )
 }
 
@@ -70,6 +95,115 @@ pub fn language_aliases() -> HashMap<String, String> {
   HashMap::new()
 }
 
+static EMPTY_STRINGS: LazyLock<HashMap<String, String>> = LazyLock::new(HashMap::new);
+static EMPTY_KINDS: LazyLock<HashMap<String, FormatterKind>> = LazyLock::new(HashMap::new);
+static EMPTY_COUNTS: LazyLock<HashMap<String, usize>> = LazyLock::new(HashMap::new);
+static EMPTY_INDENTS: LazyLock<HashMap<String, IndentUnit>> = LazyLock::new(HashMap::new);
+static EMPTY_REINDENTS: LazyLock<HashMap<String, ReindentMode>> = LazyLock::new(HashMap::new);
+static EMPTY_WIDTHS: LazyLock<HashMap<String, u32>> = LazyLock::new(HashMap::new);
+static EMPTY_ENVIRONMENT: LazyLock<FormatterEnvironment> = LazyLock::new(Default::default);
+
+/// A [`FormatContext`] with every option left at its default, for tests to override with struct
+/// update syntax.
+#[allow(dead_code)]
+pub fn format_context<'a>(
+  grammars: &'a Grammars,
+  languages: &'a LanguageFormatters,
+  formatters: &'a FormatterSpecs,
+  wasm_formatter: &'a WasmFormatter,
+) -> FormatContext<'a> {
+  FormatContext {
+    grammars,
+    languages,
+    language_aliases: &EMPTY_STRINGS,
+    formatters,
+    wasm_formatter,
+    formatter_kinds: &EMPTY_KINDS,
+    formatter_environment: &EMPTY_ENVIRONMENT,
+    strict: false,
+    injection_trailing_newlines: &EMPTY_COUNTS,
+    injection_indent: &EMPTY_INDENTS,
+    injection_reindent: &EMPTY_REINDENTS,
+    print_width: &EMPTY_WIDTHS,
+    region_stats: None,
+    file_pool: None,
+    region_pool: None,
+    skip_formatters: false,
+    max_depth: None,
+    strict_ignore: false,
+    grammar_language_map: &EMPTY_STRINGS,
+    formatter_language_map: &EMPTY_STRINGS,
+    exclude_languages: &[],
+    document_stats: false,
+    verify_roundtrip: false,
+  }
+}
+
+/// A config which upper-cases `text` documents, and maps `.txt` files to them.
+#[allow(dead_code)]
+pub const UPPER_CONFIG: &str = r#"
+[formatters]
+upper = { cmd = "tr", args = ["a-z", "A-Z"] }
+
+[languages]
+text = ["upper"]
+
+[language_extensions]
+text = ["txt"]
+"#;
+
+/// Create an empty directory within the system temp dir, unique to this process and call.
+#[allow(dead_code)]
+pub fn unique_temp_dir(prefix: &str) -> PathBuf {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("time should be available")
+    .as_nanos();
+  let dir = std::env::temp_dir().join(format!("{prefix}-{}-{nanos}", std::process::id()));
+  fs::create_dir_all(&dir).expect("should create temp dir");
+  dir
+}
+
+/// Write `contents` to `config.toml` within `dir`, returning its path.
+#[allow(dead_code)]
+pub fn write_config(dir: &Path, contents: &str) -> PathBuf {
+  let config_path = dir.join("config.toml");
+  fs::write(&config_path, contents).expect("should write config file");
+  config_path
+}
+
+/// Run the pruner binary from `dir` with `args`, using the `config.toml` written there.
+///
+/// The data dir is pointed into `dir`, so grammars and caches are neither shared with other tests
+/// nor with the user's own.
+#[allow(dead_code)]
+pub fn run_pruner(dir: &Path, args: &[&str]) -> Output {
+  run_pruner_with_stdin(dir, args, b"")
+}
+
+/// [`run_pruner`], with `stdin` written to the process.
+#[allow(dead_code)]
+pub fn run_pruner_with_stdin(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_pruner"))
+    .current_dir(dir)
+    .env("XDG_DATA_HOME", dir)
+    .arg("--config")
+    .arg(dir.join("config.toml"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("should run pruner");
+  child
+    .stdin
+    .take()
+    .expect("stdin should be piped")
+    .write_all(stdin)
+    .expect("should write stdin");
+  child.wait_with_output().expect("should wait for pruner")
+}
+
 #[allow(dead_code)]
 pub fn load_file(path: &str) -> String {
   let filepath = PathBuf::from("tests/fixtures/tests/").join(path);
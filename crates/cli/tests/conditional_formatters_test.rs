@@ -3,8 +3,11 @@ use std::collections::HashMap;
 use anyhow::Result;
 
 use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
-  config::LanguageFormatSpec,
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{FormatterSpec, LanguageFormatSpec},
   wasm::formatter::WasmFormatter,
 };
 
@@ -14,7 +17,6 @@ mod common;
 fn injections_only_pipeline_condition_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let languages = HashMap::from([(
@@ -23,6 +25,8 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
       formatter: "cljfmt".into(),
       run_in_root: false,
       run_in_injections: true,
+      only_when_injected_in: None,
+      stop_if_unchanged: false,
     }],
   )]);
 
@@ -33,16 +37,11 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -58,16 +57,11 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -86,7 +80,6 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
 fn root_only_pipeline_condition_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let languages = HashMap::from([(
@@ -95,6 +88,8 @@ fn root_only_pipeline_condition_test() -> Result<()> {
       formatter: "cljfmt".into(),
       run_in_root: true,
       run_in_injections: false,
+      only_when_injected_in: None,
+      stop_if_unchanged: false,
     }],
   )]);
 
@@ -105,16 +100,11 @@ fn root_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -130,16 +120,11 @@ fn root_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -147,3 +132,188 @@ fn root_only_pipeline_condition_test() -> Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn root_pipeline_runs_all_formatters_in_order() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  let append = |suffix: &str| FormatterSpec {
+    cmd: "sh".into(),
+    args: vec!["-c".into(), format!("cat; printf '{suffix}'")],
+    ..Default::default()
+  };
+  let formatters = HashMap::from([
+    ("first".to_string(), append("1")),
+    ("skipped".to_string(), append("x")),
+    ("second".to_string(), append("2")),
+  ]);
+  let languages = HashMap::from([(
+    "text".to_string(),
+    vec![
+      "first".into(),
+      LanguageFormatSpec::Table {
+        formatter: "skipped".into(),
+        run_in_root: false,
+        run_in_injections: true,
+        only_when_injected_in: None,
+        stop_if_unchanged: false,
+      },
+      "second".into(),
+    ],
+  )]);
+
+  let result = format::format(
+    b"source:",
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert_eq!(String::from_utf8(result)?, "source:12");
+
+  Ok(())
+}
+
+#[test]
+fn only_when_injected_in_parses_from_toml() -> Result<()> {
+  let spec: LanguageFormatSpec = toml::from_str(
+    r#"
+formatter = "cljfmt"
+only_when_injected_in = ["markdown"]
+"#,
+  )?;
+
+  assert!(spec.runs_within(None));
+  assert!(spec.runs_within(Some("markdown")));
+  assert!(!spec.runs_within(Some("nix")));
+
+  let spec: LanguageFormatSpec = toml::from_str(r#"formatter = "cljfmt""#)?;
+  assert!(spec.runs_within(Some("nix")));
+
+  Ok(())
+}
+
+#[test]
+fn only_when_injected_in_gates_by_parent_language() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+
+  let format_markdown = |parents: Vec<String>| -> Result<String> {
+    let languages = HashMap::from([(
+      "clojure".to_string(),
+      vec![LanguageFormatSpec::Table {
+        formatter: "upper".into(),
+        run_in_root: true,
+        run_in_injections: true,
+        only_when_injected_in: Some(parents),
+        stop_if_unchanged: false,
+      }],
+    )]);
+
+    let result = format::format(
+      b"```clojure\n(println 1)\n```\n",
+      &FormatOpts {
+        printwidth: 80,
+        language: "markdown",
+        indent: 0,
+      },
+      true,
+      true,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )?;
+    Ok(String::from_utf8(result)?)
+  };
+
+  assert_eq!(
+    format_markdown(vec!["markdown".into()])?,
+    "```clojure\n(PRINTLN 1)\n```\n"
+  );
+  assert_eq!(
+    format_markdown(vec!["nix".into()])?,
+    "```clojure\n(println 1)\n```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn stop_if_unchanged_skips_the_rest_of_the_pipeline() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([
+    (
+      "underscore".to_string(),
+      FormatterSpec {
+        cmd: "tr".into(),
+        args: vec![" ".into(), "_".into()],
+        ..Default::default()
+      },
+    ),
+    (
+      "upper".to_string(),
+      FormatterSpec {
+        cmd: "tr".into(),
+        args: vec!["a-z".into(), "A-Z".into()],
+        ..Default::default()
+      },
+    ),
+  ]);
+
+  let format_text = |source: &str| -> Result<String> {
+    let languages = HashMap::from([(
+      "text".to_string(),
+      vec![
+        LanguageFormatSpec::Table {
+          formatter: "underscore".into(),
+          run_in_root: true,
+          run_in_injections: true,
+          only_when_injected_in: None,
+          stop_if_unchanged: true,
+        },
+        "upper".into(),
+      ],
+    )]);
+
+    let result = format::format(
+      source.as_bytes(),
+      &FormatOpts {
+        printwidth: 80,
+        language: "text",
+        indent: 0,
+      },
+      true,
+      true,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )?;
+    Ok(String::from_utf8(result)?)
+  };
+
+  assert_eq!(format_text("two words")?, "TWO_WORDS");
+  // The first formatter has nothing to change, so the second never runs
+  assert_eq!(format_text("one_word")?, "one_word");
+
+  let spec: LanguageFormatSpec = toml::from_str(
+    r#"
+formatter = "underscore"
+stop_if_unchanged = true
+"#,
+  )?;
+  assert!(spec.stop_if_unchanged());
+  assert!(!LanguageFormatSpec::from("underscore").stop_if_unchanged());
+
+  Ok(())
+}
@@ -1,25 +1,16 @@
-use pruner::config::{ConfigFile, ProfileConfig};
+use pruner::config::{ConfigFile, FormatTarget, ProfileConfig, ResolvedFormatTarget};
 use std::{
   collections::HashMap,
   fs::{self, File},
   io::Write,
   path::PathBuf,
-  time::{SystemTime, UNIX_EPOCH},
 };
 
-fn unique_temp_dir() -> PathBuf {
-  let nanos = SystemTime::now()
-    .duration_since(UNIX_EPOCH)
-    .expect("time should be available")
-    .as_nanos();
-  let temp_dir = std::env::temp_dir().join(format!("pruner-test-{nanos}"));
-  fs::create_dir_all(&temp_dir).expect("should create temp dir");
-  temp_dir
-}
+mod common;
 
 #[test]
 fn loads_config_and_absolutizes_paths() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
   let config_path = temp_dir.join("config.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -34,7 +25,7 @@ grammar_build_dir = "build"
   )
   .expect("should write config file");
 
-  let config = ConfigFile::from_file(&config_path).expect("should load config");
+  let config = ConfigFile::from_file(&config_path, false).expect("should load config");
 
   let query_paths = config.query_paths.expect("query_paths should be set");
   let grammar_paths = config.grammar_paths.expect("grammar_paths should be set");
@@ -75,8 +66,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "a".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
       (
@@ -84,8 +74,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "base".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
     ])),
@@ -106,8 +95,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "overlay".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
       (
@@ -115,8 +103,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "b".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
     ])),
@@ -153,8 +140,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "a".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
       (
@@ -162,8 +148,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "overlay".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
       (
@@ -171,8 +156,7 @@ fn merges_configs_with_overlay_priority() {
         pruner::config::FormatterSpec {
           cmd: "b".to_string(),
           args: Vec::new(),
-          stdin: None,
-          fail_on_stderr: None,
+          ..Default::default()
         },
       ),
     ]),
@@ -206,8 +190,7 @@ fn applies_profile_overrides() {
       pruner::config::FormatterSpec {
         cmd: "base_cmd".to_string(),
         args: Vec::new(),
-        stdin: None,
-        fail_on_stderr: None,
+        ..Default::default()
       },
     )])),
     ..Default::default()
@@ -258,8 +241,7 @@ fn applies_profile_overrides() {
       pruner::config::FormatterSpec {
         cmd: "base_cmd".to_string(),
         args: Vec::new(),
-        stdin: None,
-        fail_on_stderr: None,
+        ..Default::default()
       },
     )]),
     formatters
@@ -268,7 +250,7 @@ fn applies_profile_overrides() {
 
 #[test]
 fn loads_config_with_profiles_from_toml() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
   let config_path = temp_dir.join("config.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -291,7 +273,7 @@ rust = ["rustfmt"]
   )
   .expect("should write config file");
 
-  let config = ConfigFile::from_file(&config_path).expect("should load config");
+  let config = ConfigFile::from_file(&config_path, false).expect("should load config");
 
   assert!(config.profiles.is_some());
   let profiles = config.profiles.unwrap();
@@ -313,7 +295,7 @@ rust = ["rustfmt"]
 
 #[test]
 fn loads_and_normalizes_language_aliases() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
   let config_path = temp_dir.join("config.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -329,6 +311,7 @@ typescript = ["ts", "tsx"]
   let config = pruner::config::load(pruner::config::LoadOpts {
     config_path: Some(config_path),
     profiles: Vec::new(),
+    refresh_includes: false,
   })
   .expect("should load config");
 
@@ -343,7 +326,7 @@ typescript = ["ts", "tsx"]
 
 #[test]
 fn language_alias_conflict_is_an_error() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
   let config_path = temp_dir.join("config.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -360,6 +343,7 @@ rust = ["ts"]
   let err = pruner::config::load(pruner::config::LoadOpts {
     config_path: Some(config_path),
     profiles: Vec::new(),
+    refresh_includes: false,
   })
   .unwrap_err();
 
@@ -368,3 +352,340 @@ rust = ["ts"]
     "Unexpected error: {err}"
   );
 }
+
+#[test]
+fn chained_language_aliases_resolve_to_canonical() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  let config_path = temp_dir.join("config.toml");
+
+  fs::write(
+    &config_path,
+    r#"
+[language_aliases]
+typescript = ["typescript", "ts"]
+ts = ["tsx"]
+"#,
+  )
+  .expect("should write config file");
+
+  let config = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path.clone()),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load config");
+
+  assert_eq!(config.language_aliases.get("tsx"), Some(&"typescript".to_string()));
+  assert_eq!(config.language_aliases.get("ts"), Some(&"typescript".to_string()));
+
+  fs::write(
+    &config_path,
+    r#"
+[language_aliases]
+typescript = ["ts"]
+ts = ["typescript"]
+"#,
+  )
+  .expect("should write config file");
+
+  let err = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .unwrap_err();
+
+  assert!(
+    err.to_string().contains("Language aliases form a cycle"),
+    "Unexpected error: {err}"
+  );
+}
+
+#[test]
+fn loads_and_normalizes_language_extensions() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[language_extensions]
+markdown = ["md", ".mdx"]
+clojure = ["clj"]
+"#
+  )
+  .expect("should write config file");
+
+  let config = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load config");
+
+  assert_eq!(
+    config.language_extensions,
+    HashMap::from([
+      ("md".to_string(), "markdown".to_string()),
+      ("mdx".to_string(), "markdown".to_string()),
+      ("clj".to_string(), "clojure".to_string()),
+    ])
+  );
+}
+
+#[test]
+fn language_extension_conflict_is_an_error() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[language_extensions]
+markdown = ["md"]
+text = ["md"]
+"#
+  )
+  .expect("should write config file");
+
+  let err = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .unwrap_err();
+
+  assert!(
+    err.to_string().contains("Language extension 'md' conflicts"),
+    "Unexpected error: {err}"
+  );
+}
+
+#[test]
+fn non_c_grammar_scanners_are_an_error() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[grammars.markdown]
+url = "https://github.com/tree-sitter-grammars/tree-sitter-markdown"
+scanner = "x/scanner.cc"
+"#
+  )
+  .expect("should write config file");
+
+  let err = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .unwrap_err();
+
+  assert!(
+    err
+      .to_string()
+      .contains("Grammar 'markdown' has scanner \"x/scanner.cc\", but only C scanners"),
+    "Unexpected error: {err}"
+  );
+}
+
+#[test]
+fn discovers_format_targets_in_subtrees() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  fs::create_dir_all(temp_dir.join("docs")).expect("should create docs dir");
+  fs::create_dir_all(temp_dir.join("src/clj")).expect("should create src dir");
+
+  fs::write(
+    temp_dir.join("docs/pruner.toml"),
+    r#"
+[[format_targets]]
+glob = "**/*.md"
+lang = "markdown"
+exclude = ["**/CHANGELOG.md"]
+"#,
+  )
+  .expect("should write docs config");
+  fs::write(
+    temp_dir.join("src/clj/pruner.toml"),
+    r#"
+[[format_targets]]
+glob = "**/*.clj"
+lang = "clojure"
+"#,
+  )
+  .expect("should write src config");
+
+  let targets =
+    pruner::config::discover_format_targets(&temp_dir).expect("should discover targets");
+
+  assert_eq!(
+    targets,
+    vec![
+      ResolvedFormatTarget {
+        dir: temp_dir.join("docs"),
+        target: FormatTarget {
+          glob: "**/*.md".into(),
+          lang: "markdown".into(),
+          exclude: Some(vec!["**/CHANGELOG.md".into()]),
+        },
+      },
+      ResolvedFormatTarget {
+        dir: temp_dir.join("src/clj"),
+        target: FormatTarget {
+          glob: "**/*.clj".into(),
+          lang: "clojure".into(),
+          exclude: None,
+        },
+      },
+    ]
+  );
+}
+
+#[test]
+fn language_chains_replace_unless_they_inherit() {
+  let base = ConfigFile {
+    languages: Some(HashMap::from([
+      ("markdown".to_string(), vec!["prettier".into()]),
+      ("rust".to_string(), vec!["rustfmt".into()]),
+      ("sql".to_string(), vec!["sqlfmt".into()]),
+    ])),
+    ..Default::default()
+  };
+  let overlay = ConfigFile {
+    languages: Some(HashMap::from([
+      ("markdown".to_string(), vec!["...".into(), "markdownlint".into()]),
+      ("rust".to_string(), vec!["leptosfmt".into()]),
+      ("nix".to_string(), vec!["...".into(), "nixfmt".into()]),
+    ])),
+    ..Default::default()
+  };
+
+  let merged = ConfigFile::merge(&base, &overlay);
+  assert_eq!(
+    merged.languages.clone().unwrap(),
+    HashMap::from([
+      ("markdown".to_string(), vec!["prettier".into(), "markdownlint".into()]),
+      ("rust".to_string(), vec!["leptosfmt".into()]),
+      ("sql".to_string(), vec!["sqlfmt".into()]),
+      ("nix".to_string(), vec!["nixfmt".into()]),
+    ])
+  );
+
+  // Profiles inherit from the config they are applied to, and may prepend as well as append
+  let profile = ProfileConfig {
+    languages: Some(HashMap::from([(
+      "sql".to_string(),
+      vec!["sqlfluff".into(), "...".into()],
+    )])),
+    ..Default::default()
+  };
+  let languages = merged.apply_profile(&profile).languages.unwrap();
+  assert_eq!(languages["sql"], vec!["sqlfluff".into(), "sqlfmt".into()]);
+}
+
+#[test]
+fn inherited_formatters_are_dropped_without_a_base() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(&config_path, "[languages]\nmarkdown = [\"...\", \"prettier\"]\n")
+    .expect("should write config file");
+
+  let config = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load config");
+  assert_eq!(config.languages["markdown"], vec!["prettier".into()]);
+}
+
+#[test]
+fn included_configs_are_merged_beneath_the_including_file() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  fs::create_dir_all(temp_dir.join("shared")).expect("should create shared dir");
+  fs::create_dir_all(temp_dir.join("repo")).expect("should create repo dir");
+  fs::write(
+    temp_dir.join("shared/pruner.toml"),
+    r#"
+query_paths = ["queries"]
+
+[formatters.shared]
+cmd = "shared"
+args = []
+
+[formatters.prettier]
+cmd = "prettier"
+args = []
+"#,
+  )
+  .expect("should write shared config");
+  let config_path = temp_dir.join("repo/pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+include = ["../shared/pruner.toml"]
+
+[formatters.prettier]
+cmd = "local-prettier"
+args = []
+"#,
+  )
+  .expect("should write local config");
+
+  let config = pruner::config::load(pruner::config::LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load config");
+
+  assert_eq!(config.formatters["shared"].cmd, "shared");
+  assert_eq!(config.formatters["prettier"].cmd, "local-prettier");
+  // Paths in an included config are relative to that config
+  assert!(config.query_paths.contains(&temp_dir.join("repo/../shared/queries")));
+}
+
+#[test]
+fn include_cycles_and_missing_includes_are_errors() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  fs::write(temp_dir.join("a.toml"), "include = [\"b.toml\"]\n").expect("should write a");
+  fs::write(temp_dir.join("b.toml"), "include = [\"a.toml\"]\n").expect("should write b");
+  fs::write(temp_dir.join("c.toml"), "include = [\"missing.toml\"]\n").expect("should write c");
+
+  let err = ConfigFile::from_file(&temp_dir.join("a.toml"), false).expect_err("cycle should fail");
+  let a = temp_dir.join("a.toml").display().to_string();
+  let b = temp_dir.join("b.toml").display().to_string();
+  assert!(
+    format!("{err:#}").contains(&format!("Config includes form a cycle: {a} -> {b} -> {a}")),
+    "unexpected error: {err:#}"
+  );
+
+  let err =
+    ConfigFile::from_file(&temp_dir.join("c.toml"), false).expect_err("missing should fail");
+  assert!(
+    format!("{err:#}").contains("Failed to include \"missing.toml\""),
+    "unexpected error: {err:#}"
+  );
+}
+
+#[test]
+fn plain_http_includes_are_refused() {
+  let temp_dir = common::unique_temp_dir("pruner-config-test");
+  fs::write(
+    temp_dir.join("pruner.toml"),
+    "include = [\"http://example.com/pruner.toml\"]\n",
+  )
+  .expect("should write config");
+
+  let err = ConfigFile::from_file(&temp_dir.join("pruner.toml"), false)
+    .expect_err("http include should fail");
+  assert!(
+    format!("{err:#}").contains("Config includes must use https, not http://example.com/"),
+    "unexpected error: {err:#}"
+  );
+}
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+};
+
+use pruner::{
+  api::{
+    diff,
+    format::{self, FormatOpts},
+    grammar::Grammars,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn identical_text_has_no_diff() {
+  assert_eq!(diff::unified_diff("a\nb\n", "a\nb\n", "a", "b"), None);
+}
+
+#[test]
+fn diff_hunks_include_context() {
+  let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+  let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\nthirteen\n";
+
+  assert_eq!(
+    diff::unified_diff(old, new, "a/file", "b/file").unwrap(),
+    "--- a/file
++++ b/file
+@@ -1,6 +1,6 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+ 6
+@@ -10,3 +10,4 @@
+ 10
+ 11
+ 12
++thirteen
+"
+  );
+}
+
+#[test]
+fn diff_handles_missing_trailing_newline() {
+  assert_eq!(
+    diff::unified_diff("a\nb", "a\nb\n", "old", "new").unwrap(),
+    "--- old
++++ new
+@@ -1,2 +1,2 @@
+ a
+-b
+\\ No newline at end of file
++b
+"
+  );
+
+  assert_eq!(
+    diff::unified_diff("", "a", "old", "new").unwrap(),
+    "--- old
++++ new
+@@ -0,0 +1 @@
++a
+\\ No newline at end of file
+"
+  );
+}
+
+#[test]
+fn format_file_diff_does_not_modify_file() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-diff-test");
+  let file = temp_dir.join("file.txt");
+  fs::write(&file, "keep\nupper\n")?;
+
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "fmt".to_string(),
+    FormatterSpec {
+      cmd: "sed".into(),
+      args: vec!["s/upper/UPPER/".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["fmt".into()])]);
+  let context = common::format_context(&grammars, &languages, &formatters, &wasm_formatter);
+  let opts = FormatOpts {
+    printwidth: 80,
+    language: "text",
+    indent: 0,
+  };
+
+  let name = file.to_string_lossy();
+  assert_eq!(
+    format::format_file_diff(&file, &opts, false, &context)?,
+    Some(format!(
+      "--- a/{name}\n+++ b/{name}\n@@ -1,2 +1,2 @@\n keep\n-upper\n+UPPER\n"
+    ))
+  );
+  assert_eq!(fs::read_to_string(&file)?, "keep\nupper\n");
+
+  fs::write(&file, "keep\nUPPER\n")?;
+  assert_eq!(format::format_file_diff(&file, &opts, false, &context)?, None);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
@@ -1,8 +1,17 @@
 use anyhow::Result;
 use std::collections::HashSet;
-use tree_sitter::{Point, Range};
+use tree_sitter::{Point, QueryPredicate, QueryPredicateArg, Range};
 
-use pruner::api::injections::{self, InjectedRegion, InjectionOpts};
+use pruner::api::{
+  directives::{
+    case::{self, CaseTransform},
+    gsub,
+    indented::{self, IndentedTrim},
+    offset::{self, RangeOffset},
+    printwidth, trim,
+  },
+  injections::{self, ExtractOpts, InjectedRegion, InjectionOpts},
+};
 
 mod common;
 
@@ -22,8 +31,12 @@ fn gsub_directive_test() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -36,7 +49,9 @@ fn gsub_directive_test() -> Result<()> {
       },
       lang: "javascript".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
@@ -62,8 +77,12 @@ fn pruner_indented_property_test() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -76,7 +95,9 @@ fn pruner_indented_property_test() -> Result<()> {
       },
       lang: "javascript".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
@@ -107,8 +128,12 @@ fn trim_directive_test() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -121,10 +146,287 @@ fn trim_directive_test() -> Result<()> {
       },
       lang: "javascript".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
 
   Ok(())
 }
+
+#[test]
+fn case_directive_test() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_case".into()])?;
+
+  let grammar = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  let source = "```SQL\nselect 1\n```\n\n```JavaScript\nconsole.log(1)\n```\n";
+
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  assert_eq!(
+    injected_regions
+      .iter()
+      .map(|region| region.lang.as_str())
+      .collect::<Vec<_>>(),
+    vec!["sql", "javascript"]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn case_predicates_are_collected() -> Result<()> {
+  let predicates = [
+    QueryPredicate {
+      operator: "upper!".into(),
+      args: vec![QueryPredicateArg::Capture(0)].into(),
+    },
+    QueryPredicate {
+      operator: "lower!".into(),
+      args: vec![QueryPredicateArg::Capture(1)].into(),
+    },
+  ];
+
+  let cases = case::collect(&predicates, true)?;
+  assert_eq!(cases.get(&0), Some(&CaseTransform::Upper));
+  assert_eq!(case::apply_case(&cases, 0, "Sql"), "SQL");
+  assert_eq!(case::apply_case(&cases, 1, "JavaScript"), "javascript");
+  assert_eq!(case::apply_case(&cases, 2, "Unchanged"), "Unchanged");
+
+  let invalid = [QueryPredicate {
+    operator: "lower!".into(),
+    args: vec![QueryPredicateArg::String("sql".into())].into(),
+  }];
+  assert!(case::collect(&invalid, true).is_err());
+  assert!(case::collect(&invalid, false)?.is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn printwidth_predicates_are_collected() -> Result<()> {
+  let printwidth_predicate = |value: &str| QueryPredicate {
+    operator: "printwidth!".into(),
+    args: vec![
+      QueryPredicateArg::Capture(0),
+      QueryPredicateArg::String(value.into()),
+    ]
+    .into(),
+  };
+
+  let widths = printwidth::collect(&[printwidth_predicate("120")], true)?;
+  assert_eq!(widths.get(&0), Some(&120));
+
+  let invalid = [printwidth_predicate("wide")];
+  assert!(printwidth::collect(&invalid, false)?.is_empty());
+  let err = printwidth::collect(&invalid, true).unwrap_err();
+  assert!(format!("{err:#}").contains("Invalid printwidth! predicate"));
+
+  Ok(())
+}
+
+#[test]
+fn trim_directive_strips_delimiter_lines() -> Result<()> {
+  let predicates = [QueryPredicate {
+    operator: "trim!".into(),
+    args: vec![
+      QueryPredicateArg::Capture(0),
+      QueryPredicateArg::String("<<EOF".into()),
+      QueryPredicateArg::String("EOF".into()),
+    ]
+    .into(),
+  }];
+  let specs = trim::collect(&predicates, true)?;
+  let spec = &specs[&0];
+  let trimmed = |source: &'static str| {
+    let (start, end) = trim::apply_trim(source.as_bytes(), 0, source.len(), spec);
+    &source[start..end]
+  };
+
+  assert_eq!(trimmed("<<EOF\nselect 1;\n  EOF\n"), "select 1;\n");
+  assert_eq!(trimmed("<<EOF\nselect 1;\nEOF"), "select 1;\n");
+  // Lines which only contain the delimiter as part of their content are kept
+  assert_eq!(trimmed("<<EOFX\nselect 'EOF';\n"), "<<EOFX\nselect 'EOF';\n");
+
+  Ok(())
+}
+
+fn offset_predicate(capture: u32, offsets: [&str; 4]) -> QueryPredicate {
+  let mut args = vec![QueryPredicateArg::Capture(capture)];
+  args.extend(offsets.map(|value| QueryPredicateArg::String(value.into())));
+  QueryPredicate {
+    operator: "offset!".into(),
+    args: args.into(),
+  }
+}
+
+#[test]
+fn strict_rejects_invalid_directives() -> Result<()> {
+  let invalid_offset = [offset_predicate(0, ["0", "x", "0", "0"])];
+  assert!(offset::collect(&invalid_offset, false)?.is_empty());
+  let err = offset::collect(&invalid_offset, true).unwrap_err();
+  assert!(format!("{err:#}").contains("Invalid offset! predicate"));
+
+  let invalid_gsub = [QueryPredicate {
+    operator: "gsub!".into(),
+    args: vec![
+      QueryPredicateArg::Capture(0),
+      QueryPredicateArg::String("(unclosed".into()),
+      QueryPredicateArg::String("".into()),
+    ]
+    .into(),
+  }];
+  assert!(gsub::collect(&invalid_gsub, false)?.is_empty());
+  assert!(gsub::collect(&invalid_gsub, true).is_err());
+
+  Ok(())
+}
+
+#[test]
+fn multiple_offset_directives_compose() -> Result<()> {
+  let predicates = [
+    offset_predicate(0, ["0", "6", "0", "0"]),
+    offset_predicate(1, ["1", "0", "0", "0"]),
+    offset_predicate(0, ["0", "1", "0", "-1"]),
+  ];
+
+  let offsets = offset::collect(&predicates, false)?;
+
+  assert_eq!(
+    offsets.get(&0),
+    Some(&RangeOffset {
+      start_row: 0,
+      start_col: 7,
+      end_row: 0,
+      end_col: -1,
+    })
+  );
+  assert_eq!(
+    offsets.get(&1),
+    Some(&RangeOffset {
+      start_row: 1,
+      start_col: 0,
+      end_row: 0,
+      end_col: 0,
+    })
+  );
+
+  let source = "style=\"color: red\"\n";
+  let range = Range {
+    start_byte: 0,
+    end_byte: 18,
+    start_point: Point { row: 0, column: 0 },
+    end_point: Point { row: 0, column: 18 },
+  };
+  let offset_range = offset::apply_offset_to_range(source, &range, offsets.get(&0).unwrap())
+    .expect("offset should be in bounds");
+  assert_eq!(
+    &source[offset_range.start_byte..offset_range.end_byte],
+    "color: red"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn out_of_bounds_offsets_do_not_panic() {
+  let source = "ab\ncd\n";
+  let range = Range {
+    start_byte: 0,
+    end_byte: 2,
+    start_point: Point { row: 0, column: 0 },
+    end_point: Point { row: 0, column: 2 },
+  };
+  let offset_by = |start_row, start_col, end_row, end_col| {
+    offset::apply_offset_to_range(
+      source,
+      &range,
+      &RangeOffset {
+        start_row,
+        start_col,
+        end_row,
+        end_col,
+      },
+    )
+  };
+
+  // Start pushed past the end of the content
+  assert_eq!(offset_by(0, 5, 0, 0), None);
+  // Rows beyond the end of the source
+  assert_eq!(offset_by(0, 0, 5, 0), None);
+  // Negative points
+  assert_eq!(offset_by(-1, 0, 0, 0), None);
+  assert_eq!(offset_by(0, -3, 0, 0), None);
+
+  // Columns past the end of a line are clamped to the line
+  let clamped = offset_by(0, 0, 0, 5).expect("column should be clamped");
+  assert_eq!(&source[clamped.start_byte..clamped.end_byte], "ab\n");
+
+  // The position just past a trailing newline is the end of the source
+  let to_end = offset_by(1, 0, 2, -2).expect("end of source should be in bounds");
+  assert_eq!(&source[to_end.start_byte..to_end.end_byte], "cd\n");
+}
+
+fn indented_trim(source: &str, spec: IndentedTrim) -> &str {
+  let (start, end) = indented::trim_bytes(source.as_bytes(), 0, source.len(), spec);
+  &source[start..end]
+}
+
+#[test]
+fn indented_trim_next_line_delimiter() {
+  let source = "\n  foo\n  bar\n";
+  assert_eq!(
+    indented_trim(source, IndentedTrim::default()),
+    "  foo\n  bar\n"
+  );
+
+  let source = "\n  foo\n  bar\n  ";
+  assert_eq!(
+    indented_trim(source, IndentedTrim::default()),
+    "  foo\n  bar\n"
+  );
+  assert_eq!(
+    indented_trim(
+      source,
+      IndentedTrim {
+        leading_line: true,
+        trailing_indent: false,
+      }
+    ),
+    "  foo\n  bar\n  "
+  );
+  assert_eq!(
+    indented_trim(
+      source,
+      IndentedTrim {
+        leading_line: false,
+        trailing_indent: true,
+      }
+    ),
+    "\n  foo\n  bar\n"
+  );
+}
+
+#[test]
+fn indented_trim_same_line_delimiter() {
+  let source = "foo\n  bar\n";
+  assert_eq!(indented_trim(source, IndentedTrim::default()), source);
+
+  // Trailing whitespace before a closing delimiter on the same line is content
+  let source = "foo  ";
+  assert_eq!(indented_trim(source, IndentedTrim::default()), source);
+
+  let source = "foo\n  bar  ";
+  assert_eq!(indented_trim(source, IndentedTrim::default()), source);
+}
@@ -0,0 +1,19 @@
+use anyhow::Result;
+
+use pruner::commands::dump_injections;
+
+mod common;
+
+#[test]
+fn dumped_injections_list_each_region() -> Result<()> {
+  let grammars = common::grammars()?;
+  let markdown = grammars.get("markdown").expect("markdown grammar should load");
+
+  let source = "# Title\n\n```sql\nselect 1\n```\n";
+  let dump = dump_injections::dump(markdown, source.as_bytes(), false)?;
+
+  let lines = dump.lines().collect::<Vec<_>>();
+  assert!(lines.contains(&"sql @ 16..25 (4,1)"), "{dump}");
+
+  Ok(())
+}
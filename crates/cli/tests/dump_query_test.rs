@@ -0,0 +1,23 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use tree_sitter::Query;
+
+use pruner::commands::dump_query::{self, QueryKind};
+
+mod common;
+
+#[test]
+fn dumped_injections_query_matches_compiled_query() -> Result<()> {
+  let query_paths: Vec<PathBuf> = vec!["tests/fixtures/queries".into()];
+  let grammars = common::grammars_with_queries(&query_paths)?;
+  let markdown = grammars.get("markdown").expect("markdown grammar should load");
+
+  let source = dump_query::dump(markdown, QueryKind::Injections, &query_paths)?;
+  assert!(source.contains(";; --- from "));
+
+  let query = Query::new(&markdown.lang, &source)?;
+  assert_eq!(query.pattern_count(), markdown.injections.pattern_count());
+  assert_eq!(query.capture_names(), markdown.injections.capture_names());
+
+  Ok(())
+}
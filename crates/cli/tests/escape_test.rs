@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use pruner::api::text;
+
+/// Unescape `source` as an injected region with `escape_chars`, pass the result through `inner` as
+/// a formatter would, then escape it again for the outer document.
+fn round_trip(source: &str, escape_chars: &[&str], inner: impl Fn(&str) -> String) -> String {
+  let escape_chars = text::sort_escape_chars(
+    &escape_chars
+      .iter()
+      .map(|escape| escape.to_string())
+      .collect::<HashSet<_>>(),
+  );
+  let unescaped = text::unescape_text(source, &escape_chars);
+  text::escape_text(&inner(&unescaped), &escape_chars)
+}
+
+/// Replace the text between the first `start` and the following `end` in `text` with `inner`
+/// applied to it, as formatting a nested region would.
+fn map_region(text: &str, start: &str, end: &str, inner: impl Fn(&str) -> String) -> String {
+  let from = text.find(start).expect("region start should exist") + start.len();
+  let to = from + text[from..].find(end).expect("region end should exist");
+  format!("{}{}{}", &text[..from], inner(&text[from..to]), &text[to..])
+}
+
+#[test]
+fn escaped_regions_round_trip() {
+  for source in [
+    r#"plain text"#,
+    r#"say \"hi\""#,
+    r#"a literal \\ backslash"#,
+    r#"{:a \\\"hi\\\"}"#,
+    r#"(println \\newline)"#,
+  ] {
+    assert_eq!(round_trip(source, &["\""], str::to_string), source);
+  }
+}
+
+#[test]
+fn clojure_in_markdown_in_clojure_round_trips() {
+  // The content of a clojure docstring containing markdown, which contains a clojure code block,
+  // which contains a string with an escaped quote
+  let docstring = r#"Usage:
+
+   ```clojure
+   (exec \"SELECT \\\"name\\\" FROM users\")
+   ```"#;
+
+  let result = round_trip(docstring, &["\""], |markdown| {
+    map_region(markdown, "```clojure\n", "```", |clojure| {
+      map_region(clojure, "(exec \"", "\")", |string| {
+        round_trip(string, &["\""], |sql| {
+          // The innermost region sees the fully unescaped string
+          assert_eq!(sql, r#"SELECT "name" FROM users"#);
+          sql.to_string()
+        })
+      })
+    })
+  });
+  assert_eq!(result, docstring);
+}
+
+#[test]
+fn escape_chars_starting_with_a_backslash_round_trip() {
+  // `\"` takes precedence over the generic `\\` rule, so the backslash it yields is not escaped a
+  // second time on the way back out
+  let source = r#"a \\" b \" c"#;
+  let unescaped = text::unescape_text(source, &["\\\"".to_string(), "\"".to_string()]);
+  assert_eq!(unescaped, r#"a \" b " c"#);
+  assert_eq!(round_trip(source, &["\\\"", "\""], str::to_string), source);
+}
+
+#[test]
+fn longer_escape_chars_take_precedence() {
+  let escape_chars = text::sort_escape_chars(&HashSet::from(["'".to_string(), "''".to_string()]));
+  assert_eq!(escape_chars, vec!["''".to_string(), "'".to_string()]);
+
+  // The doubled quote is matched as a single token rather than as two single quotes
+  assert_eq!(text::escape_text("it''s 'x'", &escape_chars), r"it\''s \'x\'");
+  assert_eq!(text::unescape_text(r"it\''s \'x\'", &escape_chars), "it''s 'x'");
+  assert_eq!(text::escape_text("'''", &escape_chars), r"\''\'");
+
+  // `\'\'` is not included as it unescapes to a doubled quote, which is escaped as one token
+  for source in [r"it\''s", r"\'x\'", r"\''\'"] {
+    assert_eq!(round_trip(source, &["'", "''"], str::to_string), source);
+  }
+}
+
+#[test]
+fn multi_byte_escape_chars() {
+  let escape_chars = text::sort_escape_chars(&HashSet::from(["»".to_string(), "»»".to_string()]));
+  assert_eq!(text::escape_text("a »» b » c", &escape_chars), r"a \»» b \» c");
+  assert_eq!(text::unescape_text(r"a \»» b \» c", &escape_chars), "a »» b » c");
+}
@@ -0,0 +1,4 @@
+((html_block) @pruner.ignore.start
+  (#match? @pruner.ignore.start "format-off"))
+((html_block) @pruner.ignore.end
+  (#match? @pruner.ignore.end "format-on"))
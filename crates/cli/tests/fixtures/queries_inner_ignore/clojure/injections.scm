@@ -0,0 +1,4 @@
+; The region extends past both ends of the string, so the ignored string lies wholly within it
+((str_lit) @injection.content
+  (#offset! @injection.content 0 -1 0 1)
+  (#set! injection.language "sql"))
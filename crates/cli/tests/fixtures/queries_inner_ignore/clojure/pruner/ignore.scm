@@ -0,0 +1 @@
+((str_lit) @pruner.ignore)
@@ -0,0 +1,3 @@
+((indented_string_expression
+  (string_fragment) @injection.content)
+  (#set! injection.language "sql"))
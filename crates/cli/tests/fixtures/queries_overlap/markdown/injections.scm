@@ -0,0 +1,5 @@
+;; extends
+
+; A generic rule enclosing the content the info string rule injects
+((fenced_code_block) @injection.content
+  (#set! injection.language "text"))
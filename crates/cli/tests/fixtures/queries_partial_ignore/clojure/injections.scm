@@ -0,0 +1,4 @@
+; The region starts before the string, so it is only partially covered by the ignored string
+((str_lit) @injection.content
+  (#offset! @injection.content 0 -2 0 -1)
+  (#set! injection.language "sql"))
@@ -0,0 +1,3 @@
+; Every document injects itself, recursing forever without a depth limit
+((document) @injection.content
+  (#set! injection.language "markdown"))
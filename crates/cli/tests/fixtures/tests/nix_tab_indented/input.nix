@@ -0,0 +1,7 @@
+{}: let
+	embeddedSql =
+		# sql
+		''
+			select a from b
+		'';
+in embeddedSql
@@ -0,0 +1,8 @@
+{}: let
+	embeddedSql =
+		# sql
+		''
+			SELECT A
+			  FROM B
+		'';
+in embeddedSql
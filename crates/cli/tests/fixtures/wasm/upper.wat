@@ -0,0 +1,69 @@
+;; A minimal formatter component which upper-cases ASCII letters in place.
+(component
+  (core module $m
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "cabi_realloc")
+      (param $old i32) (param $old_size i32) (param $align i32) (param $new_size i32)
+      (result i32)
+      (local $ptr i32)
+      (local.set $ptr
+        (i32.and
+          (i32.add (global.get $heap) (i32.sub (local.get $align) (i32.const 1)))
+          (i32.sub (i32.const 0) (local.get $align))))
+      (global.set $heap (i32.add (local.get $ptr) (local.get $new_size)))
+      (local.get $ptr))
+    (func (export "format")
+      (param $src i32) (param $len i32) (param $width i32) (param $lang i32) (param $lang_len i32)
+      (result i32)
+      (local $i i32)
+      (local $b i32)
+      (block $done
+        (loop $next
+          (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+          (local.set $b (i32.load8_u (i32.add (local.get $src) (local.get $i))))
+          (if (i32.and
+                (i32.ge_u (local.get $b) (i32.const 97))
+                (i32.le_u (local.get $b) (i32.const 122)))
+            (then
+              (i32.store8
+                (i32.add (local.get $src) (local.get $i))
+                (i32.sub (local.get $b) (i32.const 32)))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $next)))
+      ;; result::ok(list) at a fixed return area
+      (i32.store8 (i32.const 16) (i32.const 0))
+      (i32.store (i32.const 20) (local.get $src))
+      (i32.store (i32.const 24) (local.get $len))
+      (i32.const 16)))
+  (core instance $i (instantiate $m))
+
+  (type $format-error (variant (case "error" string)))
+  (type $format-opts (record (field "print-width" u32) (field "lang" string)))
+  (type $source (list u8))
+  (type $format-result (result $source (error $format-error)))
+  (func $format
+    (param "source" $source) (param "opts" $format-opts) (result $format-result)
+    (canon lift (core func $i "format") (memory $i "memory") (realloc (func $i "cabi_realloc"))))
+
+  ;; Exports the function alongside the types in its signature, as an interface instance must
+  (component $interface
+    (type $e (variant (case "error" string)))
+    (import "import-type-format-error" (type $error (eq $e)))
+    (type $o (record (field "print-width" u32) (field "lang" string)))
+    (import "import-type-format-opts" (type $opts (eq $o)))
+    (type $l (list u8))
+    (type $r (result $l (error $error)))
+    (type $f (func (param "source" $l) (param "opts" $opts) (result $r)))
+    (import "import-func-format" (func $format (type $f)))
+    (export $error-export "format-error" (type $error))
+    (export $opts-export "format-opts" (type $opts))
+    (type $l2 (list u8))
+    (type $r2 (result $l2 (error $error-export)))
+    (type $f2 (func (param "source" $l2) (param "opts" $opts-export) (result $r2)))
+    (export "format" (func $format) (func (type $f2))))
+  (instance $formatter (instantiate $interface
+    (with "import-func-format" (func $format))
+    (with "import-type-format-error" (type $format-error))
+    (with "import-type-format-opts" (type $format-opts))))
+  (export "pruner:plugin-api/formatter@1.0.0" (instance $formatter)))
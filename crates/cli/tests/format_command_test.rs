@@ -1,7 +1,9 @@
 use anyhow::Result;
+use std::{fs, path::Path, process::Output, sync::Arc};
 
 use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
+  api::format::{self, FormatOpts},
+  config::{DirConfigs, LoadOpts},
   wasm::formatter::WasmFormatter,
 };
 
@@ -12,7 +14,6 @@ fn format_command() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_command/input.clj");
@@ -22,16 +23,11 @@ fn format_command() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -47,7 +43,6 @@ fn fail_on_empty_stdout() -> Result<()> {
   let grammars = common::grammars()?;
   let mut formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   formatters.insert(
@@ -55,8 +50,7 @@ fn fail_on_empty_stdout() -> Result<()> {
     pruner::config::FormatterSpec {
       cmd: "echo".into(),
       args: vec!["-n".into()],
-      stdin: None,
-      fail_on_stderr: None,
+      ..Default::default()
     },
   );
 
@@ -67,16 +61,11 @@ fn fail_on_empty_stdout() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   );
 
   match result {
@@ -99,7 +88,6 @@ fn format_escaped() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_escaped/input.clj");
@@ -109,16 +97,11 @@ fn format_escaped() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -134,7 +117,6 @@ fn markdown_with_escape_characters() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("markdown_with_escape_characters/input.md");
@@ -144,16 +126,11 @@ fn markdown_with_escape_characters() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -169,7 +146,6 @@ fn format_double_escaped() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("double_escaped/input.clj");
@@ -179,16 +155,11 @@ fn format_double_escaped() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -204,7 +175,6 @@ fn format_injections_only() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_injections_only/input.clj");
@@ -214,16 +184,11 @@ fn format_injections_only() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     false,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -239,7 +204,6 @@ fn offset_dependent_printwidth() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("offset_dependent_printwidth/input.clj");
@@ -249,16 +213,11 @@ fn offset_dependent_printwidth() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     false,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -274,7 +233,6 @@ fn format_fixes_indent() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_fixes_indent/input.clj");
@@ -284,16 +242,11 @@ fn format_fixes_indent() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -309,7 +262,6 @@ fn markdown_with_html() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("markdown_with_html/input.md");
@@ -319,16 +271,11 @@ fn markdown_with_html() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -344,7 +291,6 @@ fn utf8_docstring() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("utf8_docstring/input.clj");
@@ -354,16 +300,11 @@ fn utf8_docstring() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -379,7 +320,6 @@ fn nix_embeddings_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("nix_embeddings/input.nix");
@@ -389,16 +329,11 @@ fn nix_embeddings_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "nix",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -418,7 +353,6 @@ fn nix_embeddings_trim_directive_test() -> Result<()> {
 
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("nix_embeddings/input.nix");
@@ -428,16 +362,11 @@ fn nix_embeddings_trim_directive_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "nix",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )?;
 
   let expected = common::load_file("nix_embeddings/output.nix");
@@ -452,7 +381,6 @@ fn nix_templated_embeddings_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("nix_templated_embeddings/input.nix");
@@ -462,16 +390,11 @@ fn nix_templated_embeddings_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "nix",
+      indent: 0,
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )
   .unwrap();
 
@@ -481,3 +404,501 @@ fn nix_templated_embeddings_test() -> Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn nix_tab_indented_test() -> Result<()> {
+  let grammars = common::grammars()?;
+  let formatters = std::collections::HashMap::from([(
+    "sql".to_string(),
+    pruner::config::FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "tr a-z A-Z | sed 's/ FROM /\\n  FROM /'".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = std::collections::HashMap::from([("sql".to_string(), vec!["sql".into()])]);
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  let source = common::load_file("nix_tab_indented/input.nix");
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "nix",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  let expected = common::load_file("nix_tab_indented/output.nix");
+
+  assert_eq!(String::from_utf8(result)?, expected);
+
+  Ok(())
+}
+
+/// Text files are upper-cased, while formatting `.bad` files always fails.
+#[cfg(unix)]
+const FAILING_CONFIG: &str = r#"
+[formatters]
+upper = { cmd = "tr", args = ["a-z", "A-Z"] }
+fail = { cmd = "false", args = [] }
+
+[languages]
+text = ["upper"]
+bad = ["fail"]
+
+[language_extensions]
+text = ["txt"]
+bad = ["bad"]
+"#;
+
+/// Run `pruner format` with `args`, reporting as json.
+#[cfg(unix)]
+fn format_json(dir: &Path, args: &[&str], stdin: &[u8]) -> (Output, serde_json::Value) {
+  let args = [&["format"], args, &["--output-format", "json"]].concat();
+  let output = common::run_pruner_with_stdin(dir, &args, stdin);
+  let report = serde_json::from_slice(&output.stdout).unwrap_or_else(|err| {
+    panic!(
+      "stdout should be json ({err}): {}\n{}",
+      String::from_utf8_lossy(&output.stdout),
+      String::from_utf8_lossy(&output.stderr)
+    )
+  });
+  (output, report)
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_results_are_reported_as_json() {
+  let temp_dir = common::unique_temp_dir("pruner-json-stdin");
+  common::write_config(&temp_dir, FAILING_CONFIG);
+
+  let (output, report) = format_json(&temp_dir, &["--lang", "text"], b"some text\n");
+  assert!(output.status.success());
+  assert_eq!(
+    report,
+    serde_json::json!({ "formatted": "SOME TEXT\n", "changed": true })
+  );
+
+  let (_, report) = format_json(&temp_dir, &["--lang", "text"], b"SOME TEXT\n");
+  assert_eq!(report["changed"], false);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn file_results_are_reported_as_json() {
+  let temp_dir = common::unique_temp_dir("pruner-json-files");
+  common::write_config(&temp_dir, FAILING_CONFIG);
+  fs::write(temp_dir.join("a.txt"), "lower\n").expect("should write file");
+  fs::write(temp_dir.join("b.txt"), "UPPER\n").expect("should write file");
+
+  // Dirty files are reported and still fail the check
+  let path = |file: &str| temp_dir.join(file).to_string_lossy().to_string();
+  let (output, report) = format_json(&temp_dir, &["*.txt", "--check"], b"");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(
+    report,
+    serde_json::json!({ "changed": [path("a.txt")], "errors": [] })
+  );
+  assert_eq!(
+    fs::read_to_string(temp_dir.join("a.txt")).expect("should read file"),
+    "lower\n"
+  );
+
+  // Files which fail are reported alongside those which were formatted
+  fs::write(temp_dir.join("c.bad"), "anything\n").expect("should write file");
+  let (output, report) = format_json(&temp_dir, &["*"], b"");
+  assert!(!output.status.success());
+  assert_eq!(report["changed"], serde_json::json!([path("a.txt")]));
+  let errors = report["errors"].as_array().expect("errors should be a list");
+  assert_eq!(errors.len(), 1);
+  assert_eq!(errors[0]["path"], path("c.bad").as_str());
+  assert_eq!(
+    fs::read_to_string(temp_dir.join("a.txt")).expect("should read file"),
+    "LOWER\n"
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_only_logs_the_summary() {
+  let temp_dir = common::unique_temp_dir("pruner-quiet");
+  common::write_config(&temp_dir, common::UPPER_CONFIG);
+  fs::write(temp_dir.join("dirty.txt"), "lower\n").expect("should write file");
+  fs::write(temp_dir.join("clean.txt"), "UPPER\n").expect("should write file");
+  let check = |args: &[&str]| {
+    common::run_pruner(&temp_dir, &[&["format", "*.txt", "--check"], args].concat())
+  };
+
+  let output = check(&[]);
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("dirty.txt"), "{stderr}");
+
+  let output = check(&["--quiet"]);
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert_eq!(output.status.code(), Some(1));
+  assert!(!stderr.contains("dirty.txt"), "{stderr}");
+  assert!(stderr.contains("1 dirty files"), "{stderr}");
+
+  // The dirty set is unaffected, and paths are still logged when asked for
+  let output = check(&["--quiet", "--output-format", "json"]);
+  let report: serde_json::Value =
+    serde_json::from_slice(&output.stdout).expect("stdout should be json");
+  let dirty = temp_dir.join("dirty.txt").to_string_lossy().to_string();
+  assert_eq!(report["changed"], serde_json::json!([dirty]));
+
+  let output = check(&["--quiet", "--log-level", "debug"]);
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("dirty.txt"), "{stderr}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// Run `pruner format` over 6 files with `extra_args`, using a formatter which records how many
+/// copies of itself are running. Returns the highest count seen.
+#[cfg(unix)]
+fn max_concurrent_formatters(temp_dir: &Path, extra_args: &[&str]) -> usize {
+  let running = temp_dir.join("running");
+  fs::create_dir_all(&running).expect("should create running dir");
+  let counts = temp_dir.join("running.counts");
+  let _ = fs::remove_file(&counts);
+  for i in 0..6 {
+    fs::write(temp_dir.join(format!("{i}.txt")), "text\n").expect("should write file");
+  }
+
+  common::write_config(
+    temp_dir,
+    &format!(
+      r#"
+max_file_jobs = 3
+
+[formatters.count]
+cmd = "sh"
+args = [
+  "-c",
+  "touch \"$0/$$\"; ls \"$0\" | wc -l >> \"$0.counts\"; sleep 0.2; rm \"$0/$$\"; cat",
+  "{}",
+]
+
+[languages]
+text = ["count"]
+
+[language_extensions]
+text = ["txt"]
+"#,
+      running.display()
+    ),
+  );
+
+  let output = common::run_pruner(temp_dir, &[&["format"], extra_args, &["*.txt"]].concat());
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  fs::read_to_string(&counts)
+    .expect("formatter should have run")
+    .lines()
+    .map(|line| line.trim().parse::<usize>().expect("count should be a number"))
+    .max()
+    .unwrap_or_default()
+}
+
+#[cfg(unix)]
+#[test]
+fn jobs_flag_overrides_config() {
+  let temp_dir = common::unique_temp_dir("pruner-jobs");
+
+  assert_eq!(max_concurrent_formatters(&temp_dir, &["--jobs", "1"]), 1);
+  assert!(max_concurrent_formatters(&temp_dir, &[]) <= 3);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn list_files_prints_matches_without_formatting() {
+  let temp_dir = common::unique_temp_dir("pruner-list-files");
+  fs::create_dir_all(temp_dir.join("sub")).expect("should create sub dir");
+  fs::write(temp_dir.join("b.txt"), "b\n").expect("should write file");
+  fs::write(temp_dir.join("sub/a.txt"), "a\n").expect("should write file");
+  fs::write(temp_dir.join("skipped.txt"), "skipped\n").expect("should write file");
+  common::write_config(
+    &temp_dir,
+    r#"
+[formatters.failing]
+cmd = "false"
+args = []
+
+[languages]
+text = ["failing"]
+
+[language_extensions]
+text = ["txt"]
+"#,
+  );
+
+  let output = common::run_pruner(
+    &temp_dir,
+    &["format", "--list-files", "--exclude", "**/skipped.txt", "**/*.txt"],
+  );
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  let listed = String::from_utf8(output.stdout).expect("output should be utf8");
+  let expected = [temp_dir.join("b.txt"), temp_dir.join("sub/a.txt")]
+    .map(|path| format!("{}\n", path.display()))
+    .concat();
+  assert_eq!(listed, expected);
+  // The failing formatter was never run
+  assert_eq!(fs::read_to_string(temp_dir.join("b.txt")).unwrap(), "b\n");
+  assert!(!temp_dir.join("pruner/grammars").exists());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn formats_exactly_the_listed_files() {
+  let temp_dir = common::unique_temp_dir("pruner-files-from");
+  common::write_config(&temp_dir, common::UPPER_CONFIG);
+  fs::create_dir_all(temp_dir.join("sub")).expect("should create sub dir");
+  for file in ["a.md", "b.md", "sub/c.md", "sub/skip.md"] {
+    fs::write(temp_dir.join(file), "text\n").expect("should write file");
+  }
+
+  let output = common::run_pruner_with_stdin(
+    &temp_dir,
+    &["format", "--lang", "text", "--files-from", "-", "--exclude", "**/skip.md"],
+    b"a.md\nsub/c.md\r\n\nsub/skip.md\n",
+  );
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  let read = |file: &str| fs::read_to_string(temp_dir.join(file)).expect("should read file");
+  assert_eq!(read("a.md"), "TEXT\n");
+  assert_eq!(read("b.md"), "text\n");
+  assert_eq!(read("sub/c.md"), "TEXT\n");
+  assert_eq!(read("sub/skip.md"), "text\n");
+
+  // Lists can also be read from a file, and are selected just like when formatting
+  fs::write(temp_dir.join("files"), "b.md\n").expect("should write file list");
+  let output = common::run_pruner(
+    &temp_dir,
+    &["format", "--lang", "text", "--files-from", "files", "--list-files"],
+  );
+  assert_eq!(
+    String::from_utf8_lossy(&output.stdout),
+    format!("{}\n", temp_dir.join("b.md").display())
+  );
+
+  let output =
+    common::run_pruner_with_stdin(&temp_dir, &["format", "*.md", "--files-from", "-"], b"a.md\n");
+  assert!(!output.status.success());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("cannot be used with"), "{stderr}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn formats_stdin_into_file() {
+  let temp_dir = common::unique_temp_dir("pruner-write-stdin-to");
+  common::write_config(&temp_dir, common::UPPER_CONFIG);
+  let target = temp_dir.join("notes.txt");
+  fs::write(&target, "stale\n").expect("should write target");
+
+  let output = common::run_pruner_with_stdin(
+    &temp_dir,
+    &["format", "--write-stdin-to", "notes.txt"],
+    b"buffer contents\n",
+  );
+
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert!(output.stdout.is_empty());
+  assert_eq!(
+    fs::read_to_string(&target).expect("should read target"),
+    "BUFFER CONTENTS\n"
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// Format `input` from stdin as though it were read from `stdin_filepath`.
+#[cfg(unix)]
+fn format_stdin(temp_dir: &Path, stdin_filepath: &str, input: &str) -> Output {
+  common::write_config(temp_dir, common::UPPER_CONFIG);
+  common::run_pruner_with_stdin(
+    temp_dir,
+    &["format", "--stdin-filepath", stdin_filepath],
+    input.as_bytes(),
+  )
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_language_is_resolved_from_the_filepath_extension() {
+  let temp_dir = common::unique_temp_dir("pruner-stdin-filepath");
+
+  // The file does not need to exist, only its extension is used
+  let output = format_stdin(&temp_dir, "docs/notes.txt", "buffer contents\n");
+
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert_eq!(String::from_utf8_lossy(&output.stdout), "BUFFER CONTENTS\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_filepath_with_an_unmapped_extension_is_an_error() {
+  let temp_dir = common::unique_temp_dir("pruner-stdin-filepath-unmapped");
+
+  let output = format_stdin(&temp_dir, "notes.unknown", "buffer contents\n");
+
+  assert!(!output.status.success());
+  assert!(output.stdout.is_empty());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(
+    stderr.contains("Unable to infer the language of \"notes.unknown\""),
+    "{stderr}"
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+/// A workspace whose root upper-cases text files, except within `sub` which lower-cases them.
+#[cfg(unix)]
+fn create_workspace(dir: &Path) {
+  fs::create_dir_all(dir.join("sub/deeper")).expect("should create sub dirs");
+  for file in ["a.txt", "sub/b.txt", "sub/deeper/c.txt"] {
+    fs::write(dir.join(file), "Mixed Case\n").expect("should write file");
+  }
+
+  common::write_config(dir, common::UPPER_CONFIG);
+  fs::write(
+    dir.join("sub/pruner.toml"),
+    r#"
+[formatters.lower]
+cmd = "tr"
+args = ["A-Z", "a-z"]
+
+[languages]
+text = ["lower"]
+"#,
+  )
+  .expect("should write config file");
+}
+
+#[cfg(unix)]
+#[test]
+fn files_are_formatted_with_their_nearest_config() {
+  let temp_dir = common::unique_temp_dir("pruner-per-file-config");
+  create_workspace(&temp_dir);
+
+  let output = common::run_pruner(&temp_dir, &["format", "**/*.txt", "--per-file-config"]);
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  let read = |file: &str| fs::read_to_string(temp_dir.join(file)).expect("should read file");
+  assert_eq!(read("a.txt"), "MIXED CASE\n");
+  assert_eq!(read("sub/b.txt"), "mixed case\n");
+  assert_eq!(read("sub/deeper/c.txt"), "mixed case\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn extensions_mapped_by_the_nearest_config_are_formatted() {
+  let temp_dir = common::unique_temp_dir("pruner-per-file-config-extensions");
+  create_workspace(&temp_dir);
+  fs::write(
+    temp_dir.join("sub/pruner.toml"),
+    r#"
+[formatters.lower]
+cmd = "tr"
+args = ["A-Z", "a-z"]
+
+[languages]
+text = ["lower"]
+
+[language_extensions]
+text = ["notes"]
+"#,
+  )
+  .expect("should write config file");
+  for file in ["d.notes", "sub/e.notes"] {
+    fs::write(temp_dir.join(file), "Mixed Case\n").expect("should write file");
+  }
+
+  let output = common::run_pruner(&temp_dir, &["format", "**/*.notes", "--per-file-config"]);
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  let read = |file: &str| fs::read_to_string(temp_dir.join(file)).expect("should read file");
+  // Only `sub` maps the extension, so the file outside it has no language and is skipped
+  assert_eq!(read("d.notes"), "Mixed Case\n");
+  assert_eq!(read("sub/e.notes"), "mixed case\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn dir_configs_are_shared_by_directories_with_the_same_config() {
+  let temp_dir = common::unique_temp_dir("pruner-dir-configs");
+  create_workspace(&temp_dir);
+
+  let mut dir_configs = DirConfigs::new(LoadOpts {
+    config_path: Some(temp_dir.join("config.toml")),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+  .expect("should load base config");
+
+  let sub_config = Some(temp_dir.join("sub/pruner.toml"));
+  assert_eq!(dir_configs.config_path(&temp_dir.join("sub")), sub_config);
+  assert_eq!(dir_configs.config_path(&temp_dir.join("sub/deeper")), sub_config);
+
+  let sub = dir_configs.load(sub_config.as_deref()).expect("should load sub config");
+  assert!(Arc::ptr_eq(
+    &sub,
+    &dir_configs.load(sub_config.as_deref()).expect("should load sub config")
+  ));
+  // The nearest config is merged over the base config rather than replacing it
+  assert!(sub.formatters.contains_key("upper"));
+  assert_eq!(sub.languages["text"], vec!["lower".into()]);
+  assert_eq!(sub.language_extensions["txt"], "text");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+}
@@ -1,13 +1,18 @@
 use anyhow::Result;
 use std::{
-  collections::BTreeMap,
+  collections::{BTreeMap, HashMap},
   fs,
   path::{Path, PathBuf},
-  time::{SystemTime, UNIX_EPOCH},
+  sync::Mutex,
 };
 
 use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
+  api::{
+    format::{self, FormatContext, FormatOpts, WalkOpts},
+    grammar::Grammars,
+    stats::RegionStats,
+  },
+  config::{self, FormatterSpec, LoadOpts},
   wasm::formatter::WasmFormatter,
 };
 
@@ -18,32 +23,28 @@ fn format_files() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let language_aliases = common::language_aliases();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let input_dir = PathBuf::from("tests/fixtures/tests/format_files/input");
   let output_dir = PathBuf::from("tests/fixtures/tests/format_files/output");
-  let temp_dir = create_temp_dir("pruner-format-files")?;
+  let temp_dir = common::unique_temp_dir("pruner-format-files");
 
   copy_dir_recursive(&input_dir, &temp_dir)?;
 
   format::format_files(
     &temp_dir,
-    "**/*.clj",
+    &["**/*.clj"],
     None,
+    &WalkOpts::default(),
     true,
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      indent: 0,
     },
+    &HashMap::new(),
     false,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
   )?;
 
   let actual_files = collect_files(&temp_dir)?;
@@ -55,11 +56,750 @@ fn format_files() -> Result<()> {
   Ok(())
 }
 
-fn create_temp_dir(prefix: &str) -> Result<PathBuf> {
-  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
-  let dir = std::env::temp_dir().join(format!("{prefix}-{}-{nanos}", std::process::id()));
-  fs::create_dir_all(&dir)?;
-  Ok(dir)
+#[test]
+fn format_files_with_language_per_extension() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  // Prefixes the document with the language it was formatted as
+  let formatters = HashMap::from([(
+    "tag".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "printf '$language:'; cat".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("markdown".to_string(), vec!["tag".into()]),
+    ("clojure".to_string(), vec!["tag".into()]),
+    ("text".to_string(), vec!["tag".into()]),
+  ]);
+  let language_extensions = HashMap::from([
+    ("md".to_string(), "markdown".to_string()),
+    ("clj".to_string(), "clojure".to_string()),
+  ]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-mixed");
+  fs::create_dir_all(temp_dir.join("nested"))?;
+  fs::write(temp_dir.join("a.md"), "a\n")?;
+  fs::write(temp_dir.join("nested/b.clj"), "b\n")?;
+  fs::write(temp_dir.join("c.txt"), "c\n")?;
+
+  let mut paths = format::format_files(
+    &temp_dir,
+    &["**/*.{md,clj,txt}"],
+    None,
+    &WalkOpts::default(),
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    &language_extensions,
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+  paths.sort();
+
+  assert_eq!(paths.len(), 3);
+  assert_eq!(
+    collect_files(&temp_dir)?,
+    BTreeMap::from([
+      (PathBuf::from("a.md"), "markdown:a\n".to_string()),
+      (PathBuf::from("c.txt"), "text:c\n".to_string()),
+      (PathBuf::from("nested/b.clj"), "clojure:b\n".to_string()),
+    ])
+  );
+
+  // Without a fallback language, files with unmapped extensions are skipped
+  let paths = format::format_files(
+    &temp_dir,
+    &["**/*.{md,txt}"],
+    None,
+    &WalkOpts::default(),
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "",
+      indent: 0,
+    },
+    &language_extensions,
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read_to_string(temp_dir.join("a.md"))?, "markdown:markdown:a\n");
+  assert_eq!(fs::read_to_string(temp_dir.join("c.txt"))?, "text:c\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn hidden_files_are_only_formatted_when_included() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("markdown".to_string(), vec!["upper".into()])]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-hidden");
+  fs::write(temp_dir.join(".hidden.md"), "hidden\n")?;
+  fs::write(temp_dir.join("visible.md"), "visible\n")?;
+
+  let format_files = |walk_opts: &WalkOpts| {
+    format::format_files(
+      &temp_dir,
+      &["**/*.md"],
+      None,
+      walk_opts,
+      true,
+      &FormatOpts {
+        printwidth: 80,
+        language: "markdown",
+        indent: 0,
+      },
+      &HashMap::new(),
+      false,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )
+  };
+
+  let paths = format_files(&WalkOpts::default())?;
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read_to_string(temp_dir.join(".hidden.md"))?, "hidden\n");
+
+  let paths = format_files(&WalkOpts {
+    include_hidden: true,
+    ..Default::default()
+  })?;
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read_to_string(temp_dir.join(".hidden.md"))?, "HIDDEN\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn region_stats_are_tallied_across_files() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "cat".to_string(),
+    FormatterSpec {
+      cmd: "cat".into(),
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("sql".to_string(), vec!["cat".into()]),
+    ("clojure".to_string(), vec!["cat".into()]),
+  ]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-stats");
+  fs::write(
+    temp_dir.join("a.md"),
+    "```sql\nselect 1\n```\n\n```sql\nselect 2\n```\n",
+  )?;
+  fs::write(
+    temp_dir.join("b.md"),
+    "```sql\nselect 3\n```\n\n```clojure\n(def a 1)\n```\n",
+  )?;
+
+  let stats = Mutex::new(RegionStats::new());
+  format::format_files(
+    &temp_dir,
+    &["**/*.md"],
+    None,
+    &WalkOpts::default(),
+    false,
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    &HashMap::new(),
+    true,
+    &FormatContext {
+      region_stats: Some(&stats),
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  let counts = stats.into_inner().unwrap().counts();
+  assert_eq!(counts.get("sql"), Some(&3));
+  assert_eq!(counts.get("clojure"), Some(&1));
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn region_stats_json() {
+  let mut stats = RegionStats::new();
+  stats.record("sql", true);
+  stats.record("markdown", false);
+  stats.record("sql", false);
+
+  assert_eq!(stats.to_json(), r#"{"markdown":1,"sql":2}"#);
+}
+
+#[test]
+fn ignored_files_are_only_formatted_with_no_ignore() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("markdown".to_string(), vec!["upper".into()])]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-no-ignore");
+  fs::write(temp_dir.join(".ignore"), "generated.md\n")?;
+  fs::write(temp_dir.join("generated.md"), "generated\n")?;
+  fs::write(temp_dir.join("source.md"), "source\n")?;
+
+  let format_files = |walk_opts: &WalkOpts| {
+    format::format_files(
+      &temp_dir,
+      &["**/*.md"],
+      None,
+      walk_opts,
+      true,
+      &FormatOpts {
+        printwidth: 80,
+        language: "markdown",
+        indent: 0,
+      },
+      &HashMap::new(),
+      false,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )
+  };
+
+  let paths = format_files(&WalkOpts::default())?;
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read_to_string(temp_dir.join("generated.md"))?, "generated\n");
+
+  let paths = format_files(&WalkOpts {
+    no_ignore: true,
+    ..Default::default()
+  })?;
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read_to_string(temp_dir.join("generated.md"))?, "GENERATED\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn binary_files_are_skipped() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["upper".into()])]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-binary");
+  let binary = b"image\0data\xff\xfe".to_vec();
+  fs::write(temp_dir.join("image.png"), &binary)?;
+  fs::write(temp_dir.join("notes.txt"), "notes\n")?;
+
+  let paths = format::format_files(
+    &temp_dir,
+    &["**/*"],
+    None,
+    &WalkOpts::default(),
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    &HashMap::new(),
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert_eq!(paths.len(), 1);
+  assert_eq!(fs::read(temp_dir.join("image.png"))?, binary);
+  assert_eq!(fs::read_to_string(temp_dir.join("notes.txt"))?, "NOTES\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn fail_if_empty_errors_when_nothing_matches() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::new();
+  let languages = HashMap::new();
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-empty");
+  fs::write(temp_dir.join("notes.txt"), "notes\n")?;
+
+  let format_files = |include_glob: &str| {
+    format::format_files(
+      &temp_dir,
+      &[include_glob],
+      None,
+      &WalkOpts {
+        fail_if_empty: true,
+        ..Default::default()
+      },
+      true,
+      &FormatOpts {
+        printwidth: 80,
+        language: "text",
+        indent: 0,
+      },
+      &HashMap::new(),
+      false,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )
+  };
+
+  let err = format_files("**/*.clj").unwrap_err();
+  assert!(err.to_string().contains("matched \"**/*.clj\""));
+
+  // Matching files that are already formatted is not an error
+  assert!(format_files("**/*.txt")?.is_empty());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn files_matching_any_include_glob_are_formatted() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("markdown".to_string(), vec!["upper".into()])]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-includes");
+  fs::write(temp_dir.join("readme.md"), "readme\n")?;
+  fs::write(temp_dir.join("page.mdx"), "page\n")?;
+  fs::write(temp_dir.join("notes.txt"), "notes\n")?;
+  fs::write(temp_dir.join("skipped.md"), "skipped\n")?;
+
+  let mut paths = format::format_files(
+    &temp_dir,
+    &["**/*.md", "**/*.mdx"],
+    Some(vec!["**/skipped.md".into()]),
+    &WalkOpts::default(),
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    &HashMap::new(),
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+  paths.sort();
+
+  assert_eq!(paths.len(), 2);
+  assert_eq!(fs::read_to_string(temp_dir.join("readme.md"))?, "README\n");
+  assert_eq!(fs::read_to_string(temp_dir.join("page.mdx"))?, "PAGE\n");
+  assert_eq!(fs::read_to_string(temp_dir.join("notes.txt"))?, "notes\n");
+  assert_eq!(fs::read_to_string(temp_dir.join("skipped.md"))?, "skipped\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn discovered_files_are_the_files_formatted() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("markdown".to_string(), vec!["upper".into()])]);
+  let language_extensions = HashMap::from([("md".to_string(), "markdown".to_string())]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-discover");
+  fs::create_dir_all(temp_dir.join("sub"))?;
+  fs::write(temp_dir.join("readme.md"), "readme\n")?;
+  fs::write(temp_dir.join("sub/page.md"), "page\n")?;
+  fs::write(temp_dir.join("notes.txt"), "notes\n")?;
+  fs::write(temp_dir.join("skipped.md"), "skipped\n")?;
+
+  let exclude = Some(vec!["**/skipped.md".to_string()]);
+  let discovered = format::discover_files(
+    &temp_dir,
+    &["**/*"],
+    exclude.clone(),
+    &WalkOpts::default(),
+    None,
+    &language_extensions,
+    false,
+  )?;
+  // notes.txt matches the glob but has no language, so it is not formatted
+  assert_eq!(
+    discovered
+      .iter()
+      .map(|file| (file.path.clone(), file.language))
+      .collect::<Vec<_>>(),
+    vec![
+      (temp_dir.join("readme.md"), "markdown"),
+      (temp_dir.join("sub/page.md"), "markdown"),
+    ]
+  );
+
+  let paths = format::format_files(
+    &temp_dir,
+    &["**/*"],
+    exclude,
+    &WalkOpts::default(),
+    false,
+    &FormatOpts {
+      printwidth: 80,
+      language: "",
+      indent: 0,
+    },
+    &language_extensions,
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+  assert_eq!(
+    paths,
+    discovered
+      .iter()
+      .map(|file| file.path.to_string_lossy().to_string())
+      .collect::<Vec<_>>()
+  );
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn format_files_reports_in_path_order() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  // Uppercases its input, failing on documents containing "fail"
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "tr a-z A-Z | sed '/FAIL/q1'".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["upper".into()])]);
+  let file_pool = format::thread_pool(Some(4))?;
+
+  let temp_dir = common::unique_temp_dir("pruner-format-files-order");
+  for i in 0..12 {
+    let content = if i % 3 == 0 { "CLEAN\n" } else { "dirty\n" };
+    fs::write(temp_dir.join(format!("{i:02}.txt")), content)?;
+  }
+
+  let format_files = || {
+    format::format_files(
+      &temp_dir,
+      &["**/*.txt"],
+      None,
+      &WalkOpts::default(),
+      false,
+      &FormatOpts {
+        printwidth: 80,
+        language: "text",
+        indent: 0,
+      },
+      &HashMap::new(),
+      false,
+      &FormatContext {
+        file_pool: file_pool.as_ref(),
+        ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+      },
+    )
+  };
+
+  let first = format_files()?;
+  let mut sorted = first.clone();
+  sorted.sort();
+  assert_eq!(first, sorted);
+  assert_eq!(first.len(), 8);
+  for _ in 0..3 {
+    assert_eq!(format_files()?, first);
+  }
+
+  fs::write(temp_dir.join("07.txt"), "fail\n")?;
+  fs::write(temp_dir.join("04.txt"), "fail\n")?;
+  for _ in 0..3 {
+    let err = format_files().expect_err("failing files should be reported");
+    let failed = temp_dir.join("04.txt");
+    assert!(
+      format!("{err:#}").contains(&failed.to_string_lossy().to_string()),
+      "{err:#}"
+    );
+  }
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+/// Format a file containing `content` with a `sh -c` formatter `script`, returning whether it was
+/// changed along with its new contents.
+fn format_text_file(script: &str, content: &str) -> Result<(bool, String)> {
+  let temp_dir = common::unique_temp_dir("pruner-format-file-newline");
+  let file = temp_dir.join("file.txt");
+  fs::write(&file, content)?;
+
+  let changed = format_text_file_at(&file, script)?;
+  let result = fs::read_to_string(&file)?;
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok((changed, result))
+}
+
+/// Format `file` as text with a `sh -c` formatter `script`, returning whether it was changed.
+fn format_text_file_at(file: &Path, script: &str) -> Result<bool> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "fmt".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), script.into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["fmt".into()])]);
+
+  format::format_file(
+    file,
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    false,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )
+}
+
+#[test]
+fn format_file_keeps_trailing_newline_policy() -> Result<()> {
+  let add_newline = "cat; echo";
+  let drop_newline = "printf %s \"$(cat)\"";
+
+  // Formatters which only change the final newline leave the file untouched
+  assert_eq!(
+    format_text_file(add_newline, "no newline")?,
+    (false, "no newline".into())
+  );
+  assert_eq!(
+    format_text_file(drop_newline, "newline\n")?,
+    (false, "newline\n".into())
+  );
+  assert_eq!(
+    format_text_file("cat; printf '\\r\\n'", "crlf")?,
+    (false, "crlf".into())
+  );
+
+  // Other changes are kept as the formatter produced them
+  assert_eq!(
+    format_text_file("tr a-z A-Z; echo", "changed")?,
+    (true, "CHANGED\n".into())
+  );
+  assert_eq!(
+    format_text_file("tr a-z A-Z", "changed\n")?,
+    (true, "CHANGED\n".into())
+  );
+
+  Ok(())
+}
+
+#[test]
+fn format_file_restores_byte_order_mark() -> Result<()> {
+  let prefix_first_line = "sed '1s/^/> /'";
+
+  // The formatter never sees the BOM, but it is kept at the start of the file
+  assert_eq!(
+    format_text_file(prefix_first_line, "\u{feff}quoted\n")?,
+    (true, "\u{feff}> quoted\n".into())
+  );
+  assert_eq!(
+    format_text_file("cat", "\u{feff}unchanged\n")?,
+    (false, "\u{feff}unchanged\n".into())
+  );
+  assert_eq!(
+    format_text_file(prefix_first_line, "no bom\n")?,
+    (true, "> no bom\n".into())
+  );
+
+  Ok(())
+}
+
+#[test]
+fn byte_order_marks_do_not_shift_injected_regions() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["upper".into()])]);
+
+  let temp_dir = common::unique_temp_dir("pruner-format-file-bom");
+  let file = temp_dir.join("README.md");
+  fs::write(&file, "\u{feff}```sql\nselect 1\n```\n")?;
+
+  let changed = format::format_file(
+    &file,
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert!(changed);
+  assert_eq!(fs::read_to_string(&file)?, "\u{feff}```sql\nSELECT 1\n```\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+/// Formatted files are replaced by a newly created temporary file, which would otherwise be created
+/// with the default mode and leave scripts unexecutable.
+#[cfg(unix)]
+#[test]
+fn format_file_keeps_permissions() -> Result<()> {
+  use std::os::unix::fs::PermissionsExt;
+
+  let temp_dir = common::unique_temp_dir("pruner-format-file-permissions");
+  let file = temp_dir.join("script.sh");
+  fs::write(&file, "echo hello\n")?;
+  fs::set_permissions(&file, fs::Permissions::from_mode(0o754))?;
+
+  assert!(format_text_file_at(&file, "tr a-z A-Z")?);
+  assert_eq!(fs::read_to_string(&file)?, "ECHO HELLO\n");
+  assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o777, 0o754);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+/// Format every file in a directory of 6 with a formatter which records how many copies of itself
+/// are running, returning the highest count seen.
+fn max_concurrent_formatters(config_toml: &str) -> Result<usize> {
+  let temp_dir = common::unique_temp_dir("pruner-format-files-jobs");
+  let running = temp_dir.join("running");
+  fs::create_dir_all(&running)?;
+  let files = temp_dir.join("files");
+  fs::create_dir_all(&files)?;
+  for i in 0..6 {
+    fs::write(files.join(format!("{i}.txt")), "text\n")?;
+  }
+
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(&config_path, config_toml)?;
+  let config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+  let file_pool = format::thread_pool(config.max_file_jobs)?;
+
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "count".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec![
+        "-c".into(),
+        "touch \"$0/$$\"; ls \"$0\" | wc -l >> \"$0.counts\"; sleep 0.2; rm \"$0/$$\"; cat"
+          .into(),
+        running.to_string_lossy().to_string(),
+      ],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["count".into()])]);
+
+  format::format_files(
+    &files,
+    &["**/*.txt"],
+    None,
+    &WalkOpts::default(),
+    true,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    &HashMap::new(),
+    false,
+    &FormatContext {
+      file_pool: file_pool.as_ref(),
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  let counts = fs::read_to_string(temp_dir.join("running.counts"))?;
+  let max = counts
+    .lines()
+    .map(|line| line.trim().parse::<usize>())
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .max()
+    .unwrap_or_default();
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(max)
+}
+
+#[test]
+fn max_file_jobs_bounds_parallelism() -> Result<()> {
+  assert_eq!(max_concurrent_formatters("max_file_jobs = 1\n")?, 1);
+  assert!(max_concurrent_formatters("max_file_jobs = 2\n")? <= 2);
+  Ok(())
 }
 
 fn copy_dir_recursive(from: &Path, to: &Path) -> Result<()> {
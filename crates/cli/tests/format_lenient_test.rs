@@ -0,0 +1,157 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts, FormatOutcome},
+    grammar::Grammars,
+    stats::{RegionStats, RegionCount},
+  },
+  config::{FormatterSpec, FormatterSpecs, LanguageFormatters},
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+fn formatters() -> FormatterSpecs {
+  HashMap::from([
+    (
+      "upper".to_string(),
+      FormatterSpec {
+        cmd: "tr".into(),
+        args: vec!["a-z".into(), "A-Z".into()],
+        ..Default::default()
+      },
+    ),
+    (
+      "fail".to_string(),
+      FormatterSpec {
+        cmd: "false".into(),
+        ..Default::default()
+      },
+    ),
+  ])
+}
+
+fn format_lenient(
+  grammars: &Grammars,
+  languages: &LanguageFormatters,
+  source: &str,
+  language: &str,
+) -> Result<FormatOutcome> {
+  let formatters = formatters();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  format::format_lenient(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(grammars, languages, &formatters, &wasm_formatter),
+  )
+}
+
+#[test]
+fn failed_regions_are_reported_and_left_unformatted() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([
+    ("sql".to_string(), vec!["upper".into()]),
+    ("broken".to_string(), vec!["fail".into()]),
+  ]);
+
+  let source = "```sql\nselect 1\n```\n\n```broken\nkeep me\n```\n";
+  let outcome = format_lenient(&grammars, &languages, source, "markdown")?;
+
+  assert_eq!(
+    String::from_utf8(outcome.formatted)?,
+    "```sql\nSELECT 1\n```\n\n```broken\nkeep me\n```\n"
+  );
+  assert_eq!(outcome.errors.len(), 1);
+  let error = &outcome.errors[0];
+  assert_eq!(error.language, "broken");
+  assert_eq!(&source[error.range.start_byte..error.range.end_byte], "keep me\n");
+  assert!(error.error.contains("Failed to run formatter: fail"));
+
+  Ok(())
+}
+
+#[test]
+fn root_failures_are_still_errors() -> Result<()> {
+  let grammars = Grammars::new();
+  let languages = HashMap::from([
+    ("text".to_string(), vec!["upper".into()]),
+    ("broken".to_string(), vec!["fail".into()]),
+  ]);
+
+  let outcome = format_lenient(&grammars, &languages, "text", "text")?;
+  assert_eq!(
+    outcome,
+    FormatOutcome {
+      formatted: b"TEXT".to_vec(),
+      errors: Vec::new(),
+      stats: None,
+    }
+  );
+
+  assert!(format_lenient(&grammars, &languages, "text", "broken").is_err());
+
+  Ok(())
+}
+
+#[test]
+fn document_stats_count_visited_and_changed_regions() -> Result<()> {
+  let grammars = common::grammars()?;
+  let formatters = formatters();
+  let languages = HashMap::from([
+    ("sql".to_string(), vec!["upper".into()]),
+    ("broken".to_string(), vec!["fail".into()]),
+  ]);
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  let source = "```sql\nselect 1\n```\n\n```sql\nSELECT 2\n```\n\n```broken\nkeep me\n```\n";
+  let outcome = format::format_lenient(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    false,
+    true,
+    &FormatContext {
+      document_stats: true,
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  // Failed regions are visited but left unchanged
+  let stats = outcome.stats.expect("stats should be collected");
+  assert_eq!(stats.to_string(), "1 broken region (0 changed), 2 sql regions (1 changed)");
+
+  Ok(())
+}
+
+#[test]
+fn document_stats_merge_nested_counts() {
+  let mut stats = RegionStats::default();
+  stats.record("markdown", true);
+
+  let mut nested = RegionStats::default();
+  nested.record("sql", false);
+  nested.record("markdown", false);
+  stats.merge(nested);
+
+  assert_eq!(
+    stats.regions["markdown"],
+    RegionCount {
+      visited: 2,
+      changed: 1
+    }
+  );
+  assert_eq!(stats.to_string(), "2 markdown regions (1 changed), 1 sql region (0 changed)");
+  assert_eq!(RegionStats::default().to_string(), "no regions");
+}
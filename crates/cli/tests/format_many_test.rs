@@ -0,0 +1,74 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, BatchOpts},
+    grammar::Grammars,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn formats_batch_of_languages() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([
+    (
+      "upper".to_string(),
+      FormatterSpec {
+        cmd: "tr".into(),
+        args: vec!["a-z".into(), "A-Z".into()],
+        ..Default::default()
+      },
+    ),
+    (
+      "reverse".to_string(),
+      FormatterSpec {
+        cmd: "rev".into(),
+        ..Default::default()
+      },
+    ),
+    (
+      "fail".to_string(),
+      FormatterSpec {
+        cmd: "false".into(),
+        ..Default::default()
+      },
+    ),
+  ]);
+  let languages = HashMap::from([
+    ("upper".to_string(), vec!["upper".into()]),
+    ("reverse".to_string(), vec!["reverse".into()]),
+    ("fail".to_string(), vec!["fail".into()]),
+  ]);
+
+  let inputs = vec![
+    ("upper".to_string(), b"one\n".to_vec()),
+    ("reverse".to_string(), b"two\n".to_vec()),
+    ("fail".to_string(), b"three\n".to_vec()),
+    ("unconfigured".to_string(), b"four\n".to_vec()),
+    ("upper".to_string(), b"five\n".to_vec()),
+  ];
+
+  let results = format::format_many(
+    &inputs,
+    &BatchOpts {
+      printwidth: 80,
+      format_root: true,
+    },
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  );
+
+  assert_eq!(results.len(), inputs.len());
+  assert_eq!(results[0].as_ref().unwrap(), b"ONE\n");
+  assert_eq!(results[1].as_ref().unwrap(), b"owt\n");
+  assert!(results[2].is_err());
+  assert_eq!(results[3].as_ref().unwrap(), b"four\n");
+  assert_eq!(results[4].as_ref().unwrap(), b"FIVE\n");
+
+  Ok(())
+}
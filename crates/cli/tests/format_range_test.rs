@@ -0,0 +1,69 @@
+use anyhow::Result;
+use std::{collections::HashMap, ops};
+
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammars,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+/// Format the regions of a markdown `source` within `range`, upper-casing sql and markdown alike.
+fn format_range(grammars: &Grammars, source: &str, range: ops::Range<usize>) -> Result<String> {
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("markdown".to_string(), vec!["upper".into()]),
+    ("sql".to_string(), vec!["upper".into()]),
+  ]);
+
+  let result = format::format_range(
+    source.as_bytes(),
+    range,
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    &common::format_context(grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn only_regions_overlapping_the_range_are_formatted() -> Result<()> {
+  let grammars = common::grammars()?;
+  let source = "# title\n\n```sql\nselect 1\nfrom a\n```\n\n```sql\nselect 2\n```\n";
+
+  // The selection starts before the first region and ends part way through it
+  let start = source.find("# title").unwrap() + 2;
+  let end = source.find("from a").unwrap();
+  assert_eq!(
+    format_range(&grammars, source, start..end)?,
+    "# title\n\n```sql\nSELECT 1\nFROM A\n```\n\n```sql\nselect 2\n```\n"
+  );
+
+  // An empty range formats the region containing it
+  let cursor = source.find("select 2").unwrap() + 3;
+  assert_eq!(
+    format_range(&grammars, source, cursor..cursor)?,
+    "# title\n\n```sql\nselect 1\nfrom a\n```\n\n```sql\nSELECT 2\n```\n"
+  );
+
+  // Regions outside of the range are left untouched
+  assert_eq!(format_range(&grammars, source, 0..1)?, source);
+
+  Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+
+use pruner::{
+  api::{self, FormatStrOpts},
+  config::{self, LoadOpts},
+};
+
+mod common;
+
+#[test]
+fn format_str_formats_using_config() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-format-str-test");
+  let config_path = common::write_config(
+    &temp_dir,
+    &format!(
+      "grammar_download_dir = \"grammars\"\ngrammar_build_dir = \"build\"\n{}",
+      common::UPPER_CONFIG
+    ),
+  );
+
+  let config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+
+  let result = api::format_str(&config, "hello\n", "text", &FormatStrOpts::default())?;
+  assert_eq!(result, "HELLO\n");
+
+  // Subsequent calls reuse the cached grammars and plugins
+  let result = api::format_str(&config, "again\n", "text", &FormatStrOpts::default())?;
+  assert_eq!(result, "AGAIN\n");
+
+  let result = api::format_str(
+    &config,
+    "skipped\n",
+    "text",
+    &FormatStrOpts {
+      skip_root: true,
+      ..Default::default()
+    },
+  )?;
+  assert_eq!(result, "skipped\n");
+
+  Ok(())
+}
@@ -0,0 +1,409 @@
+#![cfg(unix)]
+
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  os::unix::fs::PermissionsExt,
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammars,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+fn write_script(path: &Path, contents: &str) -> Result<()> {
+  fs::write(path, contents)?;
+  fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+  Ok(())
+}
+
+#[test]
+fn cmd_substitutes_language() -> Result<()> {
+  // The `$dir` in the path is not a known variable and must be left untouched
+  let bin_dir = common::unique_temp_dir("pruner-cmd-test").join("bin$dir");
+  fs::create_dir_all(&bin_dir)?;
+  write_script(
+    &bin_dir.join("format-markdown"),
+    "#!/bin/sh\nprintf 'markdown:'\ncat\n",
+  )?;
+  write_script(
+    &bin_dir.join("format-clojure"),
+    "#!/bin/sh\nprintf 'clojure:'\ncat\n",
+  )?;
+
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "dispatch".to_string(),
+    FormatterSpec {
+      cmd: format!("{}/format-$language", bin_dir.to_string_lossy()),
+      args: Vec::new(),
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("markdown".to_string(), vec!["dispatch".into()]),
+    ("clojure".to_string(), vec!["dispatch".into()]),
+  ]);
+
+  for language in ["markdown", "clojure"] {
+    let result = format::format(
+      b"source",
+      &FormatOpts {
+        printwidth: 80,
+        language,
+        indent: 0,
+      },
+      true,
+      true,
+      &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+    )?;
+
+    assert_eq!(String::from_utf8(result)?, format!("{language}:source"));
+  }
+
+  Ok(())
+}
+
+#[test]
+fn env_is_passed_to_formatter() -> Result<()> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "env".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec![
+        "-c".into(),
+        "cat > /dev/null; printf '%s|%s|%s' \"$PRUNER_TEST_OPTS\" \"$PRUNER_TEST_LANG\" \"$PATH\"".into(),
+      ],
+      env: Some(HashMap::from([
+        ("PRUNER_TEST_OPTS".into(), "--width=$textwidth".into()),
+        ("PRUNER_TEST_LANG".into(), "$language".into()),
+      ])),
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["env".into()])]);
+
+  let result = format::format(
+    b"source",
+    &FormatOpts {
+      printwidth: 42,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  // The inherited environment is preserved alongside the configured variables
+  let path = std::env::var("PATH")?;
+  assert_eq!(
+    String::from_utf8(result)?,
+    format!("--width=42|text|{path}")
+  );
+
+  Ok(())
+}
+
+fn format_text(formatter: FormatterSpec, source: &str) -> Result<String> {
+  format_text_with_width(formatter, source, 80)
+}
+
+fn format_text_with_width(
+  formatter: FormatterSpec,
+  source: &str,
+  printwidth: u32,
+) -> Result<String> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([("fmt".to_string(), formatter)]);
+  let languages = HashMap::from([("text".to_string(), vec!["fmt".into()])]);
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn stdin_append_newline() -> Result<()> {
+  // `read` fails when the final line is not terminated by a newline
+  let formatter = FormatterSpec {
+    cmd: "sh".into(),
+    args: vec![
+      "-c".into(),
+      "read -r line && printf '%s\\n' \"$line\" | tr a-z A-Z".into(),
+    ],
+    ..Default::default()
+  };
+
+  assert!(format_text(formatter.clone(), "source").is_err());
+
+  let formatter = FormatterSpec {
+    stdin_append_newline: Some(true),
+    ..formatter
+  };
+  assert_eq!(format_text(formatter.clone(), "source")?, "SOURCE\n");
+  // Sources which already end with a newline are passed through as-is
+  assert_eq!(format_text(formatter, "source\n")?, "SOURCE\n");
+
+  Ok(())
+}
+
+#[test]
+fn formatter_encoding() -> Result<()> {
+  let formatter = FormatterSpec {
+    cmd: "cat".into(),
+    encoding: Some("windows-1252".into()),
+    ..Default::default()
+  };
+  assert_eq!(format_text(formatter, "café")?, "café");
+
+  // Dump the bytes received by the formatter to check they were transcoded
+  let formatter = FormatterSpec {
+    cmd: "od".into(),
+    args: vec!["-An".into(), "-tx1".into()],
+    encoding: Some("windows-1252".into()),
+    ..Default::default()
+  };
+  assert_eq!(format_text(formatter, "é")?.trim(), "e9");
+
+  let formatter = FormatterSpec {
+    cmd: "cat".into(),
+    encoding: Some("not-an-encoding".into()),
+    ..Default::default()
+  };
+  assert!(format_text(formatter, "text").is_err());
+
+  Ok(())
+}
+
+/// A formatter which exits with `status` on its first `failures` runs, counting attempts in
+/// `counter`.
+fn flaky_formatter(counter: &Path, failures: u32, status: u32, retries: u32) -> FormatterSpec {
+  FormatterSpec {
+    cmd: "sh".into(),
+    args: vec![
+      "-c".into(),
+      format!(
+        "n=$(cat \"$0\" 2>/dev/null || echo 0); echo $((n + 1)) > \"$0\"; \
+         [ \"$n\" -ge {failures} ] || exit {status}; tr a-z A-Z"
+      ),
+      counter.to_string_lossy().to_string(),
+    ],
+    retries: Some(retries),
+    retry_backoff_ms: Some(1),
+    ..Default::default()
+  }
+}
+
+#[test]
+fn formatter_retries_transient_failures() -> Result<()> {
+  let dir = common::unique_temp_dir("pruner-retries");
+
+  let counter = dir.join("succeeds");
+  assert_eq!(
+    format_text(flaky_formatter(&counter, 2, 75, 3), "text")?,
+    "TEXT"
+  );
+  assert_eq!(fs::read_to_string(&counter)?.trim(), "3");
+
+  let counter = dir.join("exhausted");
+  assert!(format_text(flaky_formatter(&counter, 5, 75, 2), "text").is_err());
+  assert_eq!(fs::read_to_string(&counter)?.trim(), "3");
+
+  // Ordinary failures are deterministic and are not retried
+  let counter = dir.join("deterministic");
+  assert!(format_text(flaky_formatter(&counter, 2, 1, 3), "text").is_err());
+  assert_eq!(fs::read_to_string(&counter)?.trim(), "1");
+
+  // Neither is a command which does not exist, which would otherwise back off for seconds
+  let missing = FormatterSpec {
+    cmd: "pruner-missing-formatter".into(),
+    retries: Some(3),
+    retry_backoff_ms: Some(2_000),
+    ..Default::default()
+  };
+  let start = Instant::now();
+  assert!(format_text(missing, "text").is_err());
+  assert!(start.elapsed() < Duration::from_secs(2));
+
+  let _ = fs::remove_dir_all(&dir);
+  Ok(())
+}
+
+#[test]
+fn formatter_fifo_input() -> Result<()> {
+  let stdin = FormatterSpec {
+    cmd: "tr".into(),
+    args: vec!["a-z".into(), "A-Z".into()],
+    ..Default::default()
+  };
+  let fifo = FormatterSpec {
+    cmd: "sh".into(),
+    args: vec!["-c".into(), "tr a-z A-Z < \"$0\"".into(), "$file".into()],
+    stdin: Some(false),
+    fifo: Some(true),
+    ..Default::default()
+  };
+
+  // Larger than a pipe buffer so the writer has to stream it
+  let source = "some text\n".repeat(20_000);
+  assert_eq!(
+    format_text(fifo.clone(), &source)?,
+    format_text(stdin, &source)?
+  );
+
+  // Formatters which never read their input should not block the writer
+  let ignores_input = FormatterSpec {
+    args: vec!["-c".into(), "echo formatted".into(), "$file".into()],
+    ..fifo
+  };
+  assert_eq!(format_text(ignores_input, &source)?, "formatted\n");
+
+  Ok(())
+}
+
+#[test]
+fn fail_on_stderr_ignore_pattern() -> Result<()> {
+  let formatter = |stderr: &str| FormatterSpec {
+    cmd: "sh".into(),
+    args: vec!["-c".into(), format!("printf '{stderr}' >&2; tr a-z A-Z")],
+    fail_on_stderr: Some(true),
+    stderr_ignore: Some("^\\[warn\\] .*deprecated".into()),
+    ..Default::default()
+  };
+
+  assert_eq!(
+    format_text(formatter("[warn] option is deprecated\\n"), "text")?,
+    "TEXT"
+  );
+  let stderr = "[warn] option is deprecated\\nerror: bad input\\n";
+  assert!(format_text(formatter(stderr), "text").is_err());
+
+  // Without a pattern any stderr output fails the run, even if it is only whitespace
+  let formatter = FormatterSpec {
+    stderr_ignore: None,
+    ..formatter("[warn] option is deprecated\\n")
+  };
+  assert!(format_text(formatter.clone(), "text").is_err());
+  let blank = FormatterSpec {
+    args: vec!["-c".into(), "echo >&2; tr a-z A-Z".into()],
+    ..formatter
+  };
+  assert!(format_text(blank, "text").is_err());
+
+  Ok(())
+}
+
+#[test]
+fn formatter_success_codes() -> Result<()> {
+  let formatter = FormatterSpec {
+    cmd: "sh".into(),
+    args: vec!["-c".into(), "tr a-z A-Z; exit 1".into()],
+    ..Default::default()
+  };
+  assert!(format_text(formatter.clone(), "text").is_err());
+
+  let formatter = FormatterSpec {
+    success_codes: Some(vec![0, 1]),
+    ..formatter
+  };
+  assert_eq!(format_text(formatter.clone(), "text")?, "TEXT");
+
+  // Codes outside of the list still fail, including 0 when it is not listed
+  let formatter = FormatterSpec {
+    success_codes: Some(vec![1]),
+    args: vec!["-c".into(), "tr a-z A-Z".into()],
+    ..formatter
+  };
+  assert!(format_text(formatter, "text").is_err());
+
+  Ok(())
+}
+
+#[test]
+fn formatter_max_output_bytes() -> Result<()> {
+  // Prints forever, so this only finishes if the formatter is killed
+  let runaway = FormatterSpec {
+    cmd: "yes".into(),
+    max_output_bytes: Some(1024),
+    ..Default::default()
+  };
+  let err = format_text(runaway, "text").expect_err("runaway formatter should fail");
+  assert!(
+    format!("{err:#}").contains("produced more than 1024 bytes of output"),
+    "{err:#}"
+  );
+
+  let formatter = FormatterSpec {
+    cmd: "cat".into(),
+    max_output_bytes: Some(4),
+    ..Default::default()
+  };
+  assert_eq!(format_text(formatter.clone(), "text")?, "text");
+  assert!(format_text(formatter, "longer text").is_err());
+
+  Ok(())
+}
+
+#[test]
+fn indent_is_zero_for_root_documents() -> Result<()> {
+  let formatter = FormatterSpec {
+    cmd: "sed".into(),
+    args: vec!["s/^/[$indent]/".into()],
+    ..Default::default()
+  };
+  assert_eq!(format_text(formatter, "source\n")?, "[0]source\n");
+
+  Ok(())
+}
+
+#[test]
+fn print_width_zero_disables_wrapping() -> Result<()> {
+  let fold = |unlimited_textwidth: Option<&str>| FormatterSpec {
+    cmd: "fold".into(),
+    args: vec!["-s".into(), "-w".into(), "$textwidth".into()],
+    unlimited_textwidth: unlimited_textwidth.map(String::from),
+    ..Default::default()
+  };
+  let source = "a line long enough that it would wrap at any reasonable print width\n";
+
+  assert_eq!(
+    format_text_with_width(fold(None), source, 20)?,
+    "a line long enough \nthat it would wrap \nat any reasonable \nprint width\n"
+  );
+  assert_eq!(format_text_with_width(fold(None), source, 0)?, source);
+
+  let echo = FormatterSpec {
+    cmd: "echo".into(),
+    args: vec!["$textwidth".into()],
+    unlimited_textwidth: Some("none".into()),
+    ..Default::default()
+  };
+  assert_eq!(format_text_with_width(echo.clone(), "", 0)?, "none\n");
+  assert_eq!(format_text_with_width(echo, "", 20)?, "20\n");
+
+  Ok(())
+}
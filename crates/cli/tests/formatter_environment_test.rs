@@ -0,0 +1,70 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{FormatterEnvironment, FormatterSpec},
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+fn format_with_environment(environment: &FormatterEnvironment) -> Result<String> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  let formatters = HashMap::from([(
+    "env".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec![
+        "-c".into(),
+        "cat > /dev/null; printf '%s|%s|%s' \"$LC_ALL\" \"$TZ\" \"$PRUNER_TEST_AMBIENT\"".into(),
+      ],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("text".to_string(), vec!["env".into()])]);
+
+  let result = format::format(
+    b"text",
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      formatter_environment: environment,
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn formatter_environment_is_applied_to_formatters() -> Result<()> {
+  // SAFETY: No other test in this binary reads or writes this variable.
+  unsafe { std::env::set_var("PRUNER_TEST_AMBIENT", "ambient") };
+
+  let result = format_with_environment(&FormatterEnvironment {
+    locale: Some("C".into()),
+    timezone: Some("UTC".into()),
+    isolated: false,
+  })?;
+  assert_eq!(result, "C|UTC|ambient");
+
+  let result = format_with_environment(&FormatterEnvironment {
+    locale: Some("C".into()),
+    timezone: Some("UTC".into()),
+    isolated: true,
+  })?;
+  assert_eq!(result, "C|UTC|");
+
+  Ok(())
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{FormatterSpec, FormatterSpecs},
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+fn format_text(formatters: &FormatterSpecs, source: &[u8]) -> Result<Vec<u8>> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let languages = HashMap::from([("text".to_string(), vec!["fmt".into()])]);
+
+  format::format(
+    source,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, formatters, &wasm_formatter),
+  )
+}
+
+#[test]
+fn hung_formatter_times_out() -> Result<()> {
+  let formatters = HashMap::from([(
+    "fmt".to_string(),
+    FormatterSpec {
+      cmd: "sleep".into(),
+      args: vec!["10".into()],
+      timeout_ms: Some(100),
+      ..Default::default()
+    },
+  )]);
+
+  let start = Instant::now();
+  let err = format_text(&formatters, b"text").unwrap_err();
+
+  assert!(Instant::now().duration_since(start) < Duration::from_secs(5));
+  assert_eq!("Failed to run formatter: fmt", err.to_string());
+  assert_eq!(
+    "Formatter sleep timed out after 100ms",
+    err.root_cause().to_string()
+  );
+
+  Ok(())
+}
+
+#[test]
+fn formatter_within_timeout_succeeds() -> Result<()> {
+  let formatters = HashMap::from([(
+    "fmt".to_string(),
+    FormatterSpec {
+      cmd: "cat".into(),
+      args: Vec::new(),
+      timeout_ms: Some(10_000),
+      ..Default::default()
+    },
+  )]);
+
+  // Larger than a pipe buffer, so stdin and stdout have to be serviced concurrently
+  let source = "text\n".repeat(100_000);
+  let result = format_text(&formatters, source.as_bytes())?;
+
+  assert_eq!(String::from_utf8(result)?, source);
+
+  Ok(())
+}
@@ -0,0 +1,148 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+  process::Command,
+};
+use url::Url;
+
+use pruner::api::{
+  format::{self, WalkOpts},
+  git::{self, CloneArgs},
+};
+
+mod common;
+
+fn git_output(dir: &Path, args: &[&str]) -> Result<String> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(dir)
+    .args(["-c", "user.name=pruner", "-c", "user.email=pruner@example.com"])
+    .args(args)
+    .output()?;
+  anyhow::ensure!(output.status.success(), "git {args:?} failed");
+  Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Create a repo with `commits` commits, returning the SHA of each.
+fn create_source_repo(dir: &Path, commits: usize) -> Result<Vec<String>> {
+  fs::create_dir_all(dir)?;
+  git_output(dir, &["init", "--quiet"])?;
+  (0..commits)
+    .map(|i| {
+      fs::write(dir.join("grammar.js"), format!("// {i}\n"))?;
+      git_output(dir, &["add", "."])?;
+      git_output(dir, &["commit", "--quiet", "-m", &format!("Commit {i}")])?;
+      git_output(dir, &["rev-parse", "HEAD"])
+    })
+    .collect()
+}
+
+#[test]
+fn unpinned_grammars_are_cloned_shallow_unless_configured() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-git-depth");
+  create_source_repo(&temp_dir.join("source"), 3)?;
+  let repo = Url::from_directory_path(temp_dir.join("source")).unwrap();
+
+  for (depth, shallow) in [(1, "true"), (0, "false")] {
+    let target_dir = temp_dir.join(format!("depth-{depth}"));
+    git::clone(CloneArgs {
+      repo: &repo,
+      target_dir: &target_dir,
+      rev: None,
+      depth,
+    })?;
+    assert_eq!(
+      git_output(&target_dir, &["rev-parse", "--is-shallow-repository"])?,
+      shallow
+    );
+  }
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn existing_checkouts_move_to_the_pinned_rev() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-git-pinned");
+  let commits = create_source_repo(&temp_dir.join("source"), 3)?;
+  let repo = Url::from_directory_path(temp_dir.join("source")).unwrap();
+  let target_dir = temp_dir.join("grammar");
+
+  let clone = |rev: &str| {
+    git::clone(CloneArgs {
+      repo: &repo,
+      target_dir: &target_dir,
+      rev: Some(rev),
+      depth: 1,
+    })
+  };
+
+  clone(&commits[0])?;
+  assert_eq!(git::head_commit(&target_dir)?, commits[0]);
+  // Only the pinned commit was fetched
+  assert_eq!(git_output(&target_dir, &["rev-list", "--count", "HEAD"])?, "1");
+
+  // Anything left in the checkout shows that it was updated in place rather than recloned
+  fs::write(target_dir.join(".git/marker"), "")?;
+  clone(&commits[1])?;
+  assert_eq!(git::head_commit(&target_dir)?, commits[1]);
+  assert!(target_dir.join(".git/marker").exists());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn since_only_discovers_changed_files() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-git-since");
+  let repo_dir = temp_dir.join("repo");
+  fs::create_dir_all(repo_dir.join("docs"))?;
+  git_output(&repo_dir, &["init", "--quiet"])?;
+  for name in ["a.md", "b.md", "docs/c.md"] {
+    fs::write(repo_dir.join(name), "text\n")?;
+  }
+  git_output(&repo_dir, &["add", "."])?;
+  git_output(&repo_dir, &["commit", "--quiet", "-m", "Add files"])?;
+
+  fs::write(repo_dir.join("b.md"), "changed\n")?;
+  fs::write(repo_dir.join("docs/d.md"), "untracked\n")?;
+  fs::write(repo_dir.join("docs/e.txt"), "untracked\n")?;
+
+  let language_extensions = HashMap::from([("md".to_string(), "markdown".to_string())]);
+  let discover = |dir: &Path, since: &str| {
+    format::discover_files(
+      dir,
+      &["**/*.md"],
+      None,
+      &WalkOpts {
+        since: Some(since.into()),
+        ..Default::default()
+      },
+      None,
+      &language_extensions,
+      false,
+    )
+    .map(|files| {
+      files
+        .into_iter()
+        .map(|file| file.path.strip_prefix(dir).unwrap().to_path_buf())
+        .collect::<Vec<_>>()
+    })
+  };
+
+  assert_eq!(
+    discover(&repo_dir, "HEAD")?,
+    vec![PathBuf::from("b.md"), PathBuf::from("docs/d.md")]
+  );
+  // Changes are found across the whole repo, but only files within the dir are visited
+  assert_eq!(discover(&repo_dir.join("docs"), "HEAD")?, vec![PathBuf::from("d.md")]);
+
+  fs::create_dir_all(temp_dir.join("not-a-repo"))?;
+  let err = discover(&temp_dir.join("not-a-repo"), "HEAD").unwrap_err();
+  assert!(format!("{err:#}").contains("to be within a git repository"), "{err:#}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
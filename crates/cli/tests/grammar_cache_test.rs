@@ -0,0 +1,255 @@
+#![cfg(unix)]
+
+use anyhow::Result;
+use std::{
+  fs,
+  os::unix::fs::MetadataExt,
+  path::Path,
+  time::{Duration, SystemTime},
+};
+
+use pruner::{
+  api::grammar,
+  config::{self, GrammarCompilerConfig, LoadOpts},
+};
+
+mod common;
+
+fn copy_dir_recursive(from: &Path, to: &Path) -> Result<()> {
+  fs::create_dir_all(to)?;
+  for entry in fs::read_dir(from)? {
+    let entry = entry?;
+    if entry.file_name() == ".git" {
+      continue;
+    }
+    let target = to.join(entry.file_name());
+    if entry.file_type()?.is_dir() {
+      copy_dir_recursive(&entry.path(), &target)?;
+    } else {
+      fs::copy(entry.path(), &target)?;
+    }
+  }
+  Ok(())
+}
+
+#[test]
+fn unchanged_grammars_are_not_recompiled() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-cache-test");
+  let grammars_dir = temp_dir.join("grammars");
+  let lib_dir = temp_dir.join("build");
+  let cache_dir = temp_dir.join("cache");
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/clojure"),
+    &grammars_dir.join("clojure"),
+  )?;
+
+  let load = || {
+    grammar::load_grammars(
+      std::slice::from_ref(&grammars_dir),
+      &["tests/fixtures/queries".into()],
+      Some(lib_dir.clone()),
+      Some(&cache_dir),
+      &Default::default(),
+      None,
+    )
+  };
+
+  let grammars = load()?;
+  assert!(grammars.contains_key("clojure"));
+  assert!(cache_dir.join("grammar-manifest.json").exists());
+
+  let lib_path = lib_dir
+    .join("clojure")
+    .with_extension(std::env::consts::DLL_EXTENSION);
+  let inode = fs::metadata(&lib_path)?.ino();
+
+  // Simulate a fresh checkout, where sources are newer than the library but their content has not
+  // changed. The library should be reused rather than rebuilt.
+  fs::File::options()
+    .write(true)
+    .open(grammars_dir.join("clojure/src/parser.c"))?
+    .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+
+  let grammars = load()?;
+  assert!(grammars.contains_key("clojure"));
+  assert_eq!(fs::metadata(&lib_path)?.ino(), inode);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn grammars_are_recompiled_when_the_compiler_config_changes() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-compiler-cache-test");
+  let grammars_dir = temp_dir.join("grammars");
+  let lib_dir = temp_dir.join("build");
+  let cache_dir = temp_dir.join("cache");
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/clojure"),
+    &grammars_dir.join("clojure"),
+  )?;
+
+  let load = |compiler: &GrammarCompilerConfig| {
+    grammar::load_grammars(
+      std::slice::from_ref(&grammars_dir),
+      &["tests/fixtures/queries".into()],
+      Some(lib_dir.clone()),
+      Some(&cache_dir),
+      compiler,
+      None,
+    )
+  };
+  let lib_path = lib_dir
+    .join("clojure")
+    .with_extension(std::env::consts::DLL_EXTENSION);
+
+  load(&Default::default())?;
+  let inode = fs::metadata(&lib_path)?.ino();
+
+  let grammars = load(&GrammarCompilerConfig {
+    defines: Some(vec!["PRUNER_TEST_DEFINE".into()]),
+    ..Default::default()
+  })?;
+  assert!(grammars.contains_key("clojure"));
+  assert_ne!(fs::metadata(&lib_path)?.ino(), inode);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn missing_grammar_paths_are_skipped() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-paths-test");
+  let grammars_dir = temp_dir.join("grammars");
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/clojure"),
+    &grammars_dir.join("clojure"),
+  )?;
+
+  let grammars = grammar::load_grammars(
+    &[grammars_dir, temp_dir.join("missing")],
+    &["tests/fixtures/queries".into()],
+    Some(temp_dir.join("build")),
+    None,
+    &Default::default(),
+    None,
+  )?;
+  assert!(grammars.contains_key("clojure"));
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn unreferenced_grammars_are_not_compiled() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-filter-test");
+  let grammars_dir = temp_dir.join("grammars");
+  let lib_dir = temp_dir.join("build");
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/clojure"),
+    &grammars_dir.join("clojure"),
+  )?;
+
+  let load = |languages: &[String]| {
+    grammar::load_grammars(
+      std::slice::from_ref(&grammars_dir),
+      &["tests/fixtures/queries".into()],
+      Some(lib_dir.clone()),
+      None,
+      &Default::default(),
+      Some(languages),
+    )
+  };
+  let lib_path = lib_dir
+    .join("clojure")
+    .with_extension(std::env::consts::DLL_EXTENSION);
+
+  let grammars = load(&["markdown".into()])?;
+  assert!(grammars.is_empty());
+  assert!(!lib_path.exists());
+
+  let grammars = load(&["clojure".into()])?;
+  assert!(grammars.contains_key("clojure"));
+  assert!(lib_path.exists());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn grammars_are_loaded_from_their_configured_repo_subdirectory() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-subdir-test");
+  // The markdown repo provides both the block and inline grammars, each in its own directory. An
+  // existing checkout stands in for the clone.
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/markdown"),
+    &temp_dir.join("grammars/markdown_inline"),
+  )?;
+
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+grammar_download_dir = "grammars"
+grammar_build_dir = "build"
+
+[grammars.markdown_inline]
+url = "https://github.com/tree-sitter-grammars/tree-sitter-markdown"
+path = "tree-sitter-markdown-inline"
+"#,
+  )?;
+  let mut config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+  config.cache_dir = temp_dir.join("cache");
+
+  let grammars = grammar::load_configured_grammars(&config, &temp_dir, false, None)?;
+  let mut languages = grammars.keys().collect::<Vec<_>>();
+  languages.sort();
+  assert_eq!(languages, vec!["markdown_inline"]);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn external_scanners_outside_src_are_compiled() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-scanner-test");
+  let grammar_dir = temp_dir.join("grammars/markdown/tree-sitter-markdown");
+  copy_dir_recursive(
+    Path::new("tests/fixtures/grammars/markdown"),
+    &temp_dir.join("grammars/markdown"),
+  )?;
+  fs::create_dir_all(grammar_dir.join("scanners"))?;
+  fs::rename(grammar_dir.join("src/scanner.c"), grammar_dir.join("scanners/markdown.c"))?;
+
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+grammar_download_dir = "grammars"
+grammar_build_dir = "build"
+
+[grammars.markdown]
+url = "https://github.com/tree-sitter-grammars/tree-sitter-markdown"
+path = "tree-sitter-markdown"
+scanner = "scanners/markdown.c"
+"#,
+  )?;
+  let mut config = config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })?;
+  config.cache_dir = temp_dir.join("cache");
+
+  // Without its scanner the grammar would fail to load, missing the external scanner symbols
+  let grammars = grammar::load_configured_grammars(&config, &temp_dir, false, None)?;
+  assert!(grammars.contains_key("markdown"));
+  assert!(!grammar_dir.join("src/scanner.c").exists());
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
@@ -0,0 +1,57 @@
+#![cfg(unix)]
+
+use anyhow::Result;
+use std::{
+  fs,
+  os::unix::fs::PermissionsExt,
+};
+
+use pruner::{api::grammar, config::GrammarCompilerConfig};
+
+mod common;
+
+#[test]
+fn grammars_are_compiled_with_configured_compiler() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-grammar-compiler-test");
+  let grammar_dir = temp_dir.join("grammars/fake");
+  fs::create_dir_all(grammar_dir.join("src"))?;
+  fs::write(
+    grammar_dir.join("tree-sitter.json"),
+    r#"{
+  "grammars": [{ "name": "fake", "scope": "source.fake", "path": "." }],
+  "metadata": { "version": "0.1.0" }
+}"#,
+  )?;
+  fs::write(grammar_dir.join("src/grammar.json"), r#"{ "name": "fake" }"#)?;
+  fs::write(grammar_dir.join("src/parser.c"), "")?;
+
+  // A compiler which records its arguments and fails, so nothing needs to be built
+  let args_file = temp_dir.join("args");
+  let cc = temp_dir.join("cc");
+  fs::write(
+    &cc,
+    format!("#!/bin/sh\necho \"$@\" > '{}'\nexit 1\n", args_file.display()),
+  )?;
+  fs::set_permissions(&cc, fs::Permissions::from_mode(0o755))?;
+
+  let result = grammar::load_grammars(
+    &[temp_dir.join("grammars")],
+    &[],
+    Some(temp_dir.join("build")),
+    None,
+    &GrammarCompilerConfig {
+      cc: Some(cc.to_string_lossy().to_string()),
+      cflags: Some(vec!["-fno-pruner-test".into()]),
+      defines: Some(vec!["PRUNER_TEST_DEFINE".into()]),
+    },
+    None,
+  );
+  assert!(result.is_err());
+
+  let args = fs::read_to_string(&args_file)?;
+  assert!(args.contains("-fno-pruner-test"), "{args}");
+  assert!(args.contains("-DPRUNER_TEST_DEFINE"), "{args}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use rayon::prelude::*;
+
+use pruner::api::directives::gsub;
+
+// The compilation counter is global, so this is the only test in its binary
+#[test]
+fn patterns_are_compiled_once() -> Result<()> {
+  let patterns = ["^%s*(%w+)", "%-%-+", "([%a_][%w_]*)%s*="];
+  let before = gsub::compilations();
+
+  let replaced = (0..3000)
+    .into_par_iter()
+    .map(|i| {
+      let rule = gsub::compile_gsub_rule(patterns[i % patterns.len()], "<%1>")?;
+      Ok(gsub::apply("  name = value", &[rule]))
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  assert_eq!(gsub::compilations() - before, patterns.len());
+  assert_eq!(replaced[0], "<name> = value");
+  assert_eq!(replaced[2], "  <name> value");
+
+  Ok(())
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use std::collections::HashSet;
 use tree_sitter::{Point, Range};
 
-use pruner::api::injections::{self, InjectedRegion, InjectionOpts};
+use pruner::api::injections::{self, ExtractOpts, InjectedRegion, InjectionOpts};
 
 mod common;
 
@@ -23,8 +23,12 @@ fn pruner_ignore_annotation_test() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(injected_regions, vec![]);
 
@@ -40,8 +44,12 @@ fn pruner_ignore_annotation_test() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -54,7 +62,9 @@ fn pruner_ignore_annotation_test() -> Result<()> {
       },
       lang: "typescript".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
@@ -84,8 +94,12 @@ console.log(1)
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, markdown, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    markdown,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -98,7 +112,9 @@ console.log(1)
       },
       lang: "markdown_inline".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
@@ -126,8 +142,12 @@ console.log(1)
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, markdown, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    markdown,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -140,7 +160,9 @@ console.log(1)
       },
       lang: "markdown_inline".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::new()
+        escape_chars: HashSet::new(),
+        printwidth: None,
+        gaps: Vec::new()
       }
     },]
   );
@@ -165,7 +187,12 @@ fn pruner_ignore_indirect() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions = injections::extract_language_injections(&mut parser, nix, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    nix,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(injected_regions, vec![]);
 
@@ -182,10 +209,253 @@ fn pruner_ignore_indirect() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, clojure, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    clojure,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(injected_regions, vec![]);
 
   Ok(())
 }
+
+#[test]
+fn pruner_ignore_partial_overlap() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_partial_ignore".into()])?;
+
+  let clojure = grammars
+    .get("clojure")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = "(f  \"select 1\")\n";
+  let source_bytes = source.as_bytes();
+
+  // Partially ignored regions are still formatted by default
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    clojure,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
+  assert_eq!(injected_regions.len(), 1);
+  assert_eq!(
+    (
+      injected_regions[0].range.start_byte,
+      injected_regions[0].range.end_byte
+    ),
+    (2, 13)
+  );
+
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    clojure,
+    source_bytes,
+    &ExtractOpts {
+      strict_ignore: true,
+      ..Default::default()
+    },
+  )?;
+  assert_eq!(injected_regions, vec![]);
+
+  Ok(())
+}
+
+#[test]
+fn pruner_ignore_within_a_region_is_not_a_partial_overlap() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_inner_ignore".into()])?;
+
+  let clojure = grammars
+    .get("clojure")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = "(f \"select 1\")\n";
+
+  // The ignored string does not reach either end of the region, so strict_ignore keeps it
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    clojure,
+    source.as_bytes(),
+    &ExtractOpts {
+      strict_ignore: true,
+      ..Default::default()
+    },
+  )?;
+  assert_eq!(
+    region_texts(&injected_regions, source),
+    vec![("sql", " \"select 1\")")]
+  );
+
+  Ok(())
+}
+
+/// The language and text of each region, as byte ranges are awkward to check by hand.
+fn region_texts<'a>(regions: &'a [InjectedRegion], source: &'a str) -> Vec<(&'a str, &'a str)> {
+  regions
+    .iter()
+    .map(|region| {
+      let text = &source[region.range.start_byte..region.range.end_byte];
+      (region.lang.as_str(), text.trim_end())
+    })
+    .collect()
+}
+
+#[test]
+fn pruner_ignore_block_comments() -> Result<()> {
+  let grammars = common::grammars()?;
+
+  let nix = grammars
+    .get("nix")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = r#"{}: let
+  # pruner-ignore-start
+  a =
+    # typescript
+    ''console.log("a")'';
+  b =
+    # typescript
+    ''console.log("b")'';
+  # pruner-ignore-end
+  c =
+    # typescript
+    ''console.log("c")'';
+in c
+"#;
+
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    nix,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  assert_eq!(region_texts(&injected_regions, source), vec![("typescript", r#"console.log("c")"#)]);
+
+  // Without an end marker the rest of the document is ignored
+  let source = source.replace("# pruner-ignore-end", "# end");
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    nix,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  assert_eq!(injected_regions, vec![]);
+
+  Ok(())
+}
+
+#[test]
+fn pruner_ignore_block_markdown() -> Result<()> {
+  let grammars = common::grammars()?;
+
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = r#"abc
+
+<!-- pruner-ignore-start -->
+```typescript
+console.log(1)
+```
+
+```typescript
+console.log(2)
+```
+<!-- pruner-ignore-end -->
+
+```typescript
+console.log(3)
+```
+"#;
+
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    markdown,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  // The end marker is not mistaken for a `pruner-ignore` of the block after it
+  assert_eq!(
+    region_texts(&injected_regions, source),
+    vec![("markdown_inline", "abc"), ("typescript", "console.log(3)")]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn pruner_ignore_block_captures() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_ignore_block".into()])?;
+
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = r#"<!-- format-off -->
+```typescript
+console.log(1)
+```
+<!-- format-on -->
+
+```typescript
+console.log(2)
+```
+"#;
+
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    markdown,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  assert_eq!(region_texts(&injected_regions, source), vec![("typescript", "console.log(2)")]);
+
+  Ok(())
+}
+
+#[test]
+fn pruner_ignore_next_count() -> Result<()> {
+  let grammars = common::grammars()?;
+
+  let nix = grammars
+    .get("nix")
+    .ok_or_else(|| anyhow::anyhow!("Missing grammar"))?;
+
+  let source = r#"{}: let
+  # pruner-ignore-next 2
+  a =
+    # typescript
+    ''console.log("a")'';
+  # Comments between the ignored bindings are skipped over
+  b =
+    # typescript
+    ''console.log("b")'';
+  c =
+    # typescript
+    ''console.log("c")'';
+in c
+"#;
+
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    nix,
+    source.as_bytes(),
+    &ExtractOpts::default(),
+  )?;
+
+  assert_eq!(region_texts(&injected_regions, source), vec![("typescript", r#"console.log("c")"#)]);
+
+  Ok(())
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    text::{self, IndentUnit, ReindentMode},
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn detects_indent_unit() {
+  assert_eq!(IndentUnit::detect("a\nb\n"), None);
+  assert_eq!(IndentUnit::detect("  a\n  b\n"), Some(IndentUnit::Space));
+  assert_eq!(IndentUnit::detect("\ta\n\t\tb\n  c\n"), Some(IndentUnit::Tab));
+}
+
+#[test]
+fn tab_indent_round_trips() {
+  let source = "\t\tif x:\n\t\t\treturn 1\n";
+  assert_eq!(text::min_leading_indent(source, IndentUnit::Tab), 2);
+  assert_eq!(text::min_leading_indent(source, IndentUnit::Space), 0);
+
+  let stripped = text::strip_leading_indent(source, 2, IndentUnit::Tab);
+  assert_eq!(stripped, "if x:\n\treturn 1\n");
+
+  let mut reindented = stripped.into_bytes();
+  text::offset_lines(&mut reindented, 2, IndentUnit::Tab, ReindentMode::Always);
+  assert_eq!(String::from_utf8(reindented).unwrap(), "if x:\n\t\t\treturn 1\n");
+}
+
+#[test]
+fn reindent_if_needed_is_idempotent() {
+  let mut data = b"a\nb\n    c\n\n  d\n".to_vec();
+  text::offset_lines(&mut data, 4, IndentUnit::Space, ReindentMode::IfNeeded);
+  assert_eq!(data, b"a\n    b\n        c\n\n      d\n");
+
+  let once = data.clone();
+  text::offset_lines(&mut data, 4, IndentUnit::Space, ReindentMode::IfNeeded);
+  assert_eq!(data, once);
+
+  text::offset_lines(&mut data, 4, IndentUnit::Space, ReindentMode::Always);
+  assert_eq!(data, b"a\n        b\n            c\n\n          d\n");
+}
+
+#[test]
+fn reindent_if_needed_keeps_nested_lines_relative() {
+  // The nested line is already indented past the offset, but not relative to its parent
+  let mut data = b"(a\n  (b\n    c))\n".to_vec();
+  text::offset_lines(&mut data, 4, IndentUnit::Space, ReindentMode::IfNeeded);
+  assert_eq!(data, b"(a\n      (b\n        c))\n");
+}
+
+#[test]
+fn reindent_if_needed_formats_indented_output_idempotently() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  // A formatter which indents its own output, as if it preserved the original indentation
+  let formatters = HashMap::from([(
+    "indenting".to_string(),
+    FormatterSpec {
+      cmd: "sed".into(),
+      args: vec!["s/^/      /".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["indenting".into()])]);
+  let injection_reindent = HashMap::from([("nix".to_string(), ReindentMode::IfNeeded)]);
+
+  let format = |source: &str| -> Result<String> {
+    let result = format::format(
+      source.as_bytes(),
+      &FormatOpts {
+        printwidth: 80,
+        language: "nix",
+        indent: 0,
+      },
+      true,
+      true,
+      &FormatContext {
+        injection_reindent: &injection_reindent,
+        ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+      },
+    )?;
+    Ok(String::from_utf8(result)?)
+  };
+
+  let source = r#"{}: let
+  embedded =
+    # sql
+    ''
+      select 1;
+      select 2;
+    '';
+in embedded
+"#;
+  let once = format(source)?;
+  assert_eq!(once, source);
+  assert_eq!(format(&once)?, source);
+
+  Ok(())
+}
+
+#[test]
+fn skip_formatters_only_normalizes_regions() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  // Fails if it is ever run
+  let formatters = HashMap::from([(
+    "failing".to_string(),
+    FormatterSpec {
+      cmd: "false".into(),
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["failing".into()])]);
+
+  let format = |source: &str, skip_formatters: bool| -> Result<String> {
+    let result = format::format(
+      source.as_bytes(),
+      &FormatOpts {
+        printwidth: 80,
+        language: "nix",
+        indent: 0,
+      },
+      true,
+      true,
+      &FormatContext {
+        skip_formatters,
+        ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+      },
+    )?;
+    Ok(String::from_utf8(result)?)
+  };
+
+  let source = common::load_file("nix_tab_indented/input.nix");
+  assert!(format(&source, false).is_err());
+  // The region is stripped of its tab indentation and re-indented without being formatted
+  assert_eq!(format(&source, true)?, source);
+
+  Ok(())
+}
+
+#[test]
+fn indent_is_substituted_for_injected_regions() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "tagging".to_string(),
+    FormatterSpec {
+      cmd: "sed".into(),
+      args: vec!["/./s/^/[$indent]/".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["tagging".into()])]);
+
+  let result = format::format(
+    "{}: let\n  embedded =\n    # sql\n    ''\n      select 1;\n    '';\nin embedded\n".as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "nix",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  // The region starts after the `''` six columns in
+  assert!(String::from_utf8(result)?.contains("      [6]select 1;\n"));
+
+  Ok(())
+}
+
+#[test]
+fn indent_columns_are_counted_in_characters() -> Result<()> {
+  let grammars =
+    common::grammars_with_queries(&["tests/fixtures/queries_multibyte_prefix".into()])?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "tagging".to_string(),
+    FormatterSpec {
+      cmd: "sed".into(),
+      args: vec!["/./s/^/[$indent]/".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["tagging".into()])]);
+
+  let result = format::format(
+    "{}: let\n  embedded = \"é\" + ''\n    select 1;\n  '';\nin embedded\n".as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "nix",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  // The `é` is two bytes but a single column
+  assert!(String::from_utf8(result)?.contains("[21]select 1;\n"));
+
+  Ok(())
+}
@@ -1,8 +1,16 @@
 use anyhow::Result;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use tree_sitter::{Point, Range};
 
-use pruner::api::injections::{self, InjectedRegion, InjectionOpts};
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    injections::{self, ExtractOpts, InjectedRegion, InjectionOpts},
+    text,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
 
 mod common;
 
@@ -26,8 +34,12 @@ fn injected_regions_markdown() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -41,6 +53,8 @@ fn injected_regions_markdown() -> Result<()> {
       lang: "markdown".into(),
       opts: InjectionOpts {
         escape_chars: HashSet::from(["\"".to_string()]),
+        printwidth: None,
+        gaps: Vec::new()
       }
     }]
   );
@@ -70,8 +84,12 @@ fn injected_regions_newline() -> Result<()> {
   let source_bytes = source.as_bytes();
 
   let mut parser = tree_sitter::Parser::new();
-  let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source_bytes,
+    &ExtractOpts::default(),
+  )?;
 
   assert_eq!(
     injected_regions,
@@ -86,6 +104,8 @@ fn injected_regions_newline() -> Result<()> {
         lang: "markdown_inline".into(),
         opts: InjectionOpts {
           escape_chars: HashSet::default(),
+          printwidth: None,
+          gaps: Vec::new()
         }
       },
       InjectedRegion {
@@ -98,6 +118,8 @@ fn injected_regions_newline() -> Result<()> {
         lang: "clojure".into(),
         opts: InjectionOpts {
           escape_chars: HashSet::default(),
+          printwidth: None,
+          gaps: Vec::new()
         }
       }
     ],
@@ -106,3 +128,129 @@ fn injected_regions_newline() -> Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn overlapping_regions_keep_the_most_specific() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_overlap".into()])?;
+  let grammar = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  let source = "# Title\n\n```sql\nselect 1\n```\n";
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source.as_bytes(),
+    &ExtractOpts {
+      strict: true,
+      ..Default::default()
+    },
+  )?;
+
+  let regions = injected_regions
+    .iter()
+    .map(|region| {
+      let text = &source[region.range.start_byte..region.range.end_byte];
+      (region.lang.as_str(), text)
+    })
+    .filter(|(lang, _)| *lang != "markdown_inline")
+    .collect::<Vec<_>>();
+  assert_eq!(regions, vec![("sql", "select 1\n")]);
+
+  Ok(())
+}
+
+#[test]
+fn combined_captures_form_one_region_with_gaps() -> Result<()> {
+  let grammars = common::grammars()?;
+  let grammar = grammars
+    .get("nix")
+    .ok_or_else(|| anyhow::anyhow!("Missing nix grammar"))?;
+
+  let source = "{}: let\n  query =\n    # sql\n    ''select ${column} from t'';\nin query\n";
+  let mut parser = tree_sitter::Parser::new();
+  let injected_regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source.as_bytes(),
+    &ExtractOpts {
+      strict: true,
+      ..Default::default()
+    },
+  )?;
+
+  // The string is split into fragments around the interpolation, which stays in the region
+  assert_eq!(injected_regions.len(), 1);
+  let region = &injected_regions[0];
+  assert_eq!(
+    &source[region.range.start_byte..region.range.end_byte],
+    "select ${column} from t"
+  );
+  let gaps = region
+    .opts
+    .gaps
+    .iter()
+    .map(|gap| &source[gap.clone()])
+    .collect::<Vec<_>>();
+  assert_eq!(gaps, vec!["${column}"]);
+
+  Ok(())
+}
+
+#[test]
+fn combined_captures_are_formatted_as_one_unit() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  // Squeezes runs of spaces, including the ones either side of the interpolation
+  let formatters = HashMap::from([(
+    "squeeze".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["-s".into(), " ".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["squeeze".into()])]);
+
+  let source = "{}: let\n  query =\n    # sql\n    ''select   ${column}   from t'';\nin query\n";
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "nix",
+      indent: 0,
+    },
+    false,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert_eq!(
+    String::from_utf8(result)?,
+    "{}: let\n  query =\n    # sql\n    ''select ${column} from t'';\nin query\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn gaps_are_masked_and_split_back_out() {
+  let source = b"select ${a} from ${b} where pruner_gap";
+  let (masked, placeholders) = text::mask_gaps(source, &[7..11, 17..21]);
+  assert_eq!(masked, b"select _pruner_gap_0_ from _pruner_gap_1_ where pruner_gap");
+
+  let gaps: [&[u8]; 2] = [b"${a}", b"${b}"];
+  let formatted = b"SELECT _pruner_gap_0_\nFROM _pruner_gap_1_\nWHERE pruner_gap";
+  assert_eq!(
+    text::unmask_gaps(formatted, &placeholders, &gaps).as_deref(),
+    Some(&b"SELECT ${a}\nFROM ${b}\nWHERE pruner_gap"[..])
+  );
+
+  // Placeholders which are dropped, reordered or duplicated cannot be split back
+  assert_eq!(text::unmask_gaps(b"SELECT _pruner_gap_0_", &placeholders, &gaps), None);
+  let reordered = b"SELECT _pruner_gap_1_ FROM _pruner_gap_0_";
+  assert_eq!(text::unmask_gaps(reordered, &placeholders, &gaps), None);
+  let duplicated = b"_pruner_gap_0_ _pruner_gap_0_ _pruner_gap_1_";
+  assert_eq!(text::unmask_gaps(duplicated, &placeholders, &gaps), None);
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 
 use pruner::{
   api::format::{self, FormatContext, FormatOpts},
+  config::FormatterSpec,
   wasm::formatter::WasmFormatter,
 };
 
@@ -15,23 +16,19 @@ fn normalizes_injected_language_via_aliases() -> Result<()> {
   let languages = common::languages();
   let wasm_formatter = WasmFormatter::new("cache".into())?;
 
-  let language_aliases = HashMap::from([("ts".to_string(), "typescript".to_string())]);
-
   let source = "```ts\nconsole.log(  1  )\n```\n";
   let result = format::format(
     source.as_bytes(),
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      indent: 0,
     },
     false,
     true,
     &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      language_aliases: &language_aliases,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
+      language_aliases: &HashMap::from([("ts".into(), "typescript".into())]),
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
     },
   )?;
 
@@ -42,3 +39,134 @@ fn normalizes_injected_language_via_aliases() -> Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn grammar_and_formatter_languages_can_diverge() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "tr a-z A-Z; echo \"-- $language\"".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("postgres".to_string(), vec!["upper".into()])]);
+  // `notes` documents are parsed as markdown, and `sql` regions are formatted as `postgres`
+  let grammar_language_map = HashMap::from([("notes".to_string(), "markdown".to_string())]);
+  let formatter_language_map = HashMap::from([("sql".to_string(), "postgres".to_string())]);
+
+  let source = "# Title\n\n```sql\nselect 1\n```\n";
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "notes",
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      grammar_language_map: &grammar_language_map,
+      formatter_language_map: &formatter_language_map,
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  assert_eq!(
+    String::from_utf8(result)?,
+    "# Title\n\n```sql\nSELECT 1\n-- postgres\n```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn excluded_languages_are_left_untouched() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("typescript".to_string(), vec!["upper".into()]),
+    ("sql".to_string(), vec!["upper".into()]),
+  ]);
+  let language_aliases = HashMap::from([("ts".to_string(), "typescript".to_string())]);
+  // Excluded by the name the alias resolves to
+  let exclude_languages = vec!["typescript".to_string()];
+
+  let source = "```ts\nconsole.log(  1  )\n```\n\n```sql\nselect 1\n```\n";
+  let format = |exclude_languages: &[String]| {
+    format::format(
+      source.as_bytes(),
+      &FormatOpts {
+        printwidth: 80,
+        language: "markdown",
+        indent: 0,
+      },
+      false,
+      true,
+      &FormatContext {
+        language_aliases: &language_aliases,
+        exclude_languages,
+        ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+      },
+    )
+  };
+
+  assert_eq!(
+    String::from_utf8(format(&exclude_languages)?)?,
+    "```ts\nconsole.log(  1  )\n```\n\n```sql\nSELECT 1\n```\n"
+  );
+  assert_eq!(
+    String::from_utf8(format(&[])?)?,
+    "```ts\nCONSOLE.LOG(  1  )\n```\n\n```sql\nSELECT 1\n```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn region_stats_are_keyed_by_the_aliased_language() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["upper".into()])]);
+  let language_aliases = HashMap::from([("psql".to_string(), "sql".to_string())]);
+
+  let source = "```psql\nselect 1\n```\n\n```sql\nselect 2\n```\n";
+  let outcome = format::format_lenient(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    false,
+    true,
+    &FormatContext {
+      language_aliases: &language_aliases,
+      document_stats: true,
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  let stats = outcome.stats.expect("stats should be collected");
+  assert_eq!(stats.to_string(), "2 sql regions (2 changed)");
+
+  Ok(())
+}
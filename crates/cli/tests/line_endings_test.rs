@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammars,
+    text::{self, IndentUnit, LineEnding},
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+/// Format `source` with a formatter which uppercases its input and always emits `\n` line endings.
+fn format_with_lf_formatter(grammars: &Grammars, source: &str, language: &str) -> Result<String> {
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "tr -d '\\r' | tr a-z A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("text".to_string(), vec!["upper".into()]),
+    ("sql".to_string(), vec!["upper".into()]),
+  ]);
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn detects_dominant_line_ending() {
+  assert_eq!(LineEnding::detect(b"no newline"), None);
+  assert_eq!(LineEnding::detect(b"a\nb\n"), Some(LineEnding::Lf));
+  assert_eq!(LineEnding::detect(b"a\r\nb\r\n"), Some(LineEnding::CrLf));
+  assert_eq!(LineEnding::detect(b"a\r\nb\r\nc\n"), Some(LineEnding::CrLf));
+  assert_eq!(LineEnding::detect(b"a\r\nb\n"), Some(LineEnding::Lf));
+}
+
+#[test]
+fn normalizes_line_endings() {
+  let mut data = b"a\nb\r\nc\rd\n".to_vec();
+  text::normalize_line_endings(&mut data, LineEnding::CrLf);
+  assert_eq!(data, b"a\r\nb\r\nc\rd\r\n");
+
+  text::normalize_line_endings(&mut data, LineEnding::Lf);
+  assert_eq!(data, b"a\nb\nc\rd\n");
+}
+
+#[test]
+fn column_ignores_carriage_return() {
+  let source = b"ab\r\n  cd\r\n";
+  assert_eq!(text::column_for_byte(source, 2), 2);
+  assert_eq!(text::column_for_byte(source, 3), 2);
+  assert_eq!(text::column_for_byte(source, 6), 2);
+  assert_eq!(text::min_leading_indent("  a\r\n    b\r\n", IndentUnit::Space), 2);
+  assert_eq!(
+    text::strip_leading_indent("  a\r\n    b\r\n", 2, IndentUnit::Space),
+    "a\r\n  b\r\n"
+  );
+}
+
+#[test]
+fn root_preserves_crlf() -> Result<()> {
+  let result = format_with_lf_formatter(&Grammars::new(), "one\r\ntwo\r\n", "text")?;
+  assert_eq!(result, "ONE\r\nTWO\r\n");
+
+  let result = format_with_lf_formatter(&Grammars::new(), "one\ntwo\n", "text")?;
+  assert_eq!(result, "ONE\nTWO\n");
+
+  Ok(())
+}
+
+#[test]
+fn injected_regions_preserve_crlf() -> Result<()> {
+  let grammars = common::grammars()?;
+  let source = "# Title\r\n\r\n- item\r\n\r\n  ```sql\r\n  select 1;\r\n  select 2;\r\n  ```\r\n";
+  let result = format_with_lf_formatter(&grammars, source, "markdown")?;
+  assert_eq!(
+    result,
+    "# Title\r\n\r\n- item\r\n\r\n  ```sql\r\n  SELECT 1;\r\n  SELECT 2;\r\n  ```\r\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn column_counts_characters() {
+  let source = "é = ''\n  ü\tx".as_bytes();
+  assert_eq!(text::line_start(source, 3), 0);
+  assert_eq!(text::column_for_byte(source, 7), 6);
+  assert_eq!(text::line_start(source, source.len()), 8);
+  assert_eq!(text::column_for_byte(source, source.len() - 1), 4);
+}
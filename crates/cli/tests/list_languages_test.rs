@@ -0,0 +1,34 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{api::grammar::Grammars, commands::list_languages};
+
+mod common;
+
+#[test]
+fn describes_configured_languages() {
+  let languages = HashMap::from([
+    ("sql".to_string(), vec!["sqlfmt".into()]),
+    ("markdown".to_string(), vec!["prettier".into(), "trim".into()]),
+  ]);
+
+  assert_eq!(
+    list_languages::describe(&languages, &Grammars::new()),
+    vec![
+      "markdown\tgrammar=no\tinjections=no\tformatters=prettier,trim",
+      "sql\tgrammar=no\tinjections=no\tformatters=sqlfmt",
+    ]
+  );
+}
+
+#[test]
+fn describes_languages_with_grammars() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([("markdown".to_string(), vec!["prettier".into()])]);
+
+  let lines = list_languages::describe(&languages, &grammars);
+  assert!(lines.contains(&"markdown\tgrammar=yes\tinjections=yes\tformatters=prettier".into()));
+  assert!(lines.contains(&"clojure\tgrammar=yes\tinjections=yes\tformatters=".into()));
+
+  Ok(())
+}
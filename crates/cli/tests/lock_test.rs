@@ -0,0 +1,95 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+  process::Command,
+};
+
+use pruner::{
+  api::{
+    git,
+    lock::{self, Lockfile},
+  },
+  config::GrammarSpec,
+};
+
+mod common;
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<()> {
+  let status = Command::new("git")
+    .arg("-C")
+    .arg(dir)
+    .args(["-c", "user.name=pruner", "-c", "user.email=pruner@example.com"])
+    .args(args)
+    .status()?;
+  anyhow::ensure!(status.success(), "git {args:?} failed");
+  Ok(())
+}
+
+/// Create a repo standing in for a cloned grammar, returning the commit it is checked out at.
+fn create_grammar_repo(dir: &Path) -> Result<String> {
+  fs::create_dir_all(dir)?;
+  run_git(dir, &["init", "--quiet"])?;
+  fs::write(dir.join("grammar.js"), "module.exports = {}\n")?;
+  run_git(dir, &["add", "."])?;
+  run_git(dir, &["commit", "--quiet", "-m", "Add grammar"])?;
+  git::head_commit(dir)
+}
+
+fn spec(rev: Option<&str>) -> Result<GrammarSpec> {
+  Ok(GrammarSpec::Table {
+    url: "https://example.com/tree-sitter-test".parse()?,
+    rev: rev.map(String::from),
+    path: None,
+    scanner: None,
+  })
+}
+
+#[test]
+fn resolved_commits_round_trip_through_the_lockfile() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-lock-round-trip");
+  let commit = create_grammar_repo(&temp_dir.join("grammars/test"))?;
+  let grammars = HashMap::from([("test".to_string(), spec(None)?)]);
+  let path = temp_dir.join("pruner.lock");
+
+  let resolved = lock::resolve(&temp_dir.join("grammars"), &grammars)?;
+  assert_eq!(resolved.grammars["test"].commit, commit);
+  assert_eq!(resolved.commit("test", &grammars["test"]), Some(commit.as_str()));
+  // A changed rev no longer uses the recorded commit
+  assert_eq!(resolved.commit("test", &spec(Some("v2"))?), None);
+
+  let existing = Lockfile::read(&path)?;
+  assert_eq!(existing, Lockfile::default());
+  lock::update(&path, &existing, &resolved, false)?;
+  assert_eq!(Lockfile::read(&path)?, resolved);
+
+  // Unchanged lockfiles pass when frozen, changed ones do not
+  lock::update(&path, &resolved, &resolved, true)?;
+  let err = lock::update(&path, &existing, &resolved, true).unwrap_err();
+  assert!(err.to_string().contains("out of date for grammars: test"), "{err}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn pinned_revs_must_match_the_checkout() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-lock-pinned");
+  let commit = create_grammar_repo(&temp_dir.join("test"))?;
+  run_git(&temp_dir.join("test"), &["tag", "v1"])?;
+
+  for rev in [&commit[..12], commit.as_str(), "v1"] {
+    let grammars = HashMap::from([("test".to_string(), spec(Some(rev))?)]);
+    lock::verify_pinned(&temp_dir, &grammars)?;
+  }
+
+  let grammars = HashMap::from([("test".to_string(), spec(Some("0123456789abcdef"))?)]);
+  let err = lock::verify_pinned(&temp_dir, &grammars).unwrap_err();
+  assert!(err.to_string().contains("pinned to 0123456789abcdef"), "{err}");
+  let err = lock::resolve(&temp_dir, &grammars).unwrap_err();
+  assert!(err.to_string().contains("pinned to 0123456789abcdef"), "{err}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
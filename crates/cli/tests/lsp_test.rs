@@ -0,0 +1,117 @@
+use anyhow::Result;
+
+use pruner::api::lsp::{self, Position, Splice, TextEdit, TextRange};
+
+fn position(line: u32, character: u32) -> Position {
+  Position { line, character }
+}
+
+#[test]
+fn positions_are_utf16_code_units() -> Result<()> {
+  let source = "a\n😀b\r\nç";
+
+  assert_eq!(lsp::position_for_byte(source, 0)?, position(0, 0));
+  assert_eq!(lsp::position_for_byte(source, 2)?, position(1, 0));
+  // The emoji is 4 bytes in UTF-8 but 2 code units in UTF-16
+  assert_eq!(lsp::position_for_byte(source, 6)?, position(1, 2));
+  assert_eq!(lsp::position_for_byte(source, 7)?, position(1, 3));
+  assert_eq!(lsp::position_for_byte(source, 9)?, position(2, 0));
+  assert_eq!(lsp::position_for_byte(source, 11)?, position(2, 1));
+
+  assert!(lsp::position_for_byte(source, 3).is_err());
+  assert!(lsp::position_for_byte(source, 12).is_err());
+
+  Ok(())
+}
+
+#[test]
+fn splices_convert_to_text_edits() -> Result<()> {
+  let source = "# Tïtle\n\n```js\nfoo(  1  )\n```\n";
+  let splices = [
+    Splice {
+      start_byte: 16,
+      end_byte: 27,
+      replacement: b"foo(1);\n".to_vec(),
+    },
+    Splice {
+      start_byte: 0,
+      end_byte: 0,
+      replacement: b"<!-- x -->\n".to_vec(),
+    },
+  ];
+
+  let edits = lsp::text_edits(source.as_bytes(), &splices)?;
+
+  assert_eq!(
+    edits,
+    vec![
+      TextEdit {
+        range: TextRange {
+          start: position(3, 0),
+          end: position(4, 0),
+        },
+        new_text: "foo(1);\n".into(),
+      },
+      TextEdit {
+        range: TextRange {
+          start: position(0, 0),
+          end: position(0, 0),
+        },
+        new_text: "<!-- x -->\n".into(),
+      },
+    ]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn overlapping_splices_are_an_error() {
+  let splices = [
+    Splice {
+      start_byte: 0,
+      end_byte: 4,
+      replacement: Vec::new(),
+    },
+    Splice {
+      start_byte: 2,
+      end_byte: 6,
+      replacement: Vec::new(),
+    },
+  ];
+
+  assert!(lsp::text_edits(b"abcdefgh", &splices).is_err());
+}
+
+#[test]
+fn splice_between_formatted_documents() -> Result<()> {
+  assert_eq!(lsp::splice_between("same", "same"), None);
+
+  let original = "let x = [ 1,2 ];\n";
+  let formatted = "let x = [1, 2];\n";
+  let splice = lsp::splice_between(original, formatted).unwrap();
+  assert_eq!(
+    splice,
+    Splice {
+      start_byte: 9,
+      end_byte: 14,
+      replacement: b"1, 2".to_vec(),
+    }
+  );
+
+  let edits = lsp::text_edits(original.as_bytes(), &[splice])?;
+  assert_eq!(
+    edits[0].range,
+    TextRange {
+      start: position(0, 9),
+      end: position(0, 14),
+    }
+  );
+
+  // Differences inside a multibyte char must not split it
+  let splice = lsp::splice_between("é", "è").unwrap();
+  assert_eq!(splice.start_byte, 0);
+  assert_eq!(splice.end_byte, 2);
+
+  Ok(())
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+};
+
+use pruner::{
+  api::format::{self, FormatContext, FormatOpts},
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn self_injecting_documents_stop_at_max_depth() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_self_injection".into()])?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+
+  let temp_dir = common::unique_temp_dir("pruner-max-depth");
+  let counter = temp_dir.join("runs");
+  let formatters = HashMap::from([(
+    "count".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec![
+        "-c".into(),
+        "echo run >> \"$0\"; cat".into(),
+        counter.to_string_lossy().to_string(),
+      ],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("markdown".to_string(), vec!["count".into()])]);
+
+  let source = "Some *text*\n";
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      max_depth: Some(3),
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+  assert_eq!(String::from_utf8(result)?, source);
+
+  // The root document and one region at each of the allowed depths
+  assert_eq!(fs::read_to_string(&counter)?.lines().count(), 4);
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
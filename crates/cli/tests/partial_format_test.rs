@@ -0,0 +1,95 @@
+use anyhow::Result;
+use std::{collections::HashMap, sync::Mutex};
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    stats::RegionStats,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+#[test]
+fn only_configured_regions_are_formatted() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["upper".into()])]);
+  let region_stats = Mutex::new(RegionStats::new());
+
+  let source = "```sql\nselect 1\n```\n\n- item\n\n  ```css\n  a {\n  color: red;\n    }\n  ```\n";
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      strict: true,
+      region_stats: Some(&region_stats),
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  assert_eq!(
+    String::from_utf8(result)?,
+    "```sql\nSELECT 1\n```\n\n- item\n\n  ```css\n  a {\n  color: red;\n    }\n  ```\n"
+  );
+  assert_eq!(
+    region_stats.into_inner().unwrap().counts(),
+    std::collections::BTreeMap::from([("sql".to_string(), 1)])
+  );
+
+  Ok(())
+}
+
+#[test]
+fn regions_nested_in_captured_languages_are_formatted() -> Result<()> {
+  // Clojure is only named by the code fence, so isn't referenced by the config
+  let grammars = common::grammars_for(&["markdown".into(), "sql".into()])?;
+  assert!(grammars.contains_key("clojure"));
+
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "tr".into(),
+      args: vec!["a-z".into(), "A-Z".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["upper".into()])]);
+
+  let result = format::format(
+    "```clojure\n(def q \"SELECT a\")\n```\n".as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      indent: 0,
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters, &wasm_formatter),
+  )?;
+
+  assert_eq!(
+    String::from_utf8(result)?,
+    "```clojure\n(def q \"SELECT A\")\n```\n"
+  );
+
+  Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    grammar::Grammars,
+  },
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+/// Format `source` with a formatter which replaces each document with the print width it was given.
+fn format_reporting_width(
+  grammars: &Grammars,
+  source: &str,
+  language: &str,
+  print_width: &HashMap<String, u32>,
+) -> Result<String> {
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "width".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "cat > /dev/null; echo $textwidth".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([
+    ("text".to_string(), vec!["width".into()]),
+    ("sql".to_string(), vec!["width".into()]),
+  ]);
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      print_width,
+      ..common::format_context(grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn root_language_print_width_override() -> Result<()> {
+  let grammars = Grammars::new();
+  assert_eq!(
+    format_reporting_width(&grammars, "text\n", "text", &HashMap::new())?,
+    "80\n"
+  );
+
+  let print_width = HashMap::from([("text".to_string(), 100)]);
+  assert_eq!(
+    format_reporting_width(&grammars, "text\n", "text", &print_width)?,
+    "100\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn injected_language_print_width_override() -> Result<()> {
+  let grammars = common::grammars()?;
+  let print_width = HashMap::from([("sql".to_string(), 100)]);
+  let source = "- item\n\n  ```sql\n  select 1\n  ```\n";
+
+  // The indentation of the region is still subtracted from the overridden width
+  assert_eq!(
+    format_reporting_width(&grammars, source, "markdown", &print_width)?,
+    "- item\n\n  ```sql\n  98\n  ```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn printwidth_directive_sets_an_absolute_width() -> Result<()> {
+  let grammars = common::grammars_with_queries(&["tests/fixtures/queries_printwidth".into()])?;
+  let print_width = HashMap::from([("sql".to_string(), 100)]);
+  let source = "- item\n\n  ```sql\n  select 1\n  ```\n";
+
+  // Neither the configured width nor the region's indentation affect the directive's width
+  assert_eq!(
+    format_reporting_width(&grammars, source, "markdown", &print_width)?,
+    "- item\n\n  ```sql\n  120\n  ```\n"
+  );
+
+  Ok(())
+}
@@ -0,0 +1,56 @@
+use pruner::config::{LoadOpts, PROFILE_ENV};
+use std::fs;
+
+mod common;
+
+// The env var is process wide, so this is the only test in its binary
+#[test]
+fn profiles_are_selected_from_the_environment() {
+  let temp_dir = common::unique_temp_dir("pruner-profile-env");
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+[languages]
+markdown = ["prettier"]
+rust = ["rustfmt"]
+sql = ["sqlfmt"]
+
+[profiles.ci.languages]
+markdown = ["ci_prettier"]
+rust = ["ci_rustfmt"]
+
+[profiles.lint.languages]
+sql = ["sqlfluff"]
+
+[profiles.local.languages]
+rust = ["local_rustfmt"]
+"#,
+  )
+  .expect("should write config file");
+
+  let load = |env: &str, profiles: &[&str]| {
+    unsafe { std::env::set_var(PROFILE_ENV, env) };
+    pruner::config::load(LoadOpts {
+      config_path: Some(config_path.clone()),
+      profiles: profiles.iter().map(|profile| profile.to_string()).collect(),
+      refresh_includes: false,
+    })
+  };
+
+  let config = load("ci, lint", &[]).expect("should load config");
+  assert_eq!(config.languages["markdown"], vec!["ci_prettier".into()]);
+  assert_eq!(config.languages["sql"], vec!["sqlfluff".into()]);
+
+  // Profiles given explicitly are applied last, so they win over those from the environment
+  let config = load("local", &["ci"]).expect("should load config");
+  assert_eq!(config.languages["rust"], vec!["ci_rustfmt".into()]);
+  let config = load("ci,local", &["local"]).expect("should load config");
+  assert_eq!(config.languages["rust"], vec!["local_rustfmt".into()]);
+
+  let err = load("missing", &[]).unwrap_err();
+  assert_eq!(err.to_string(), "Profile 'missing' not found");
+
+  unsafe { std::env::remove_var(PROFILE_ENV) };
+  let _ = fs::remove_dir_all(&temp_dir);
+}
@@ -4,18 +4,9 @@ use std::{
   fs::{self, File},
   io::Write,
   path::PathBuf,
-  time::{SystemTime, UNIX_EPOCH},
 };
 
-fn unique_temp_dir() -> PathBuf {
-  let nanos = SystemTime::now()
-    .duration_since(UNIX_EPOCH)
-    .expect("time should be available")
-    .as_nanos();
-  let temp_dir = std::env::temp_dir().join(format!("pruner-test-{nanos}"));
-  fs::create_dir_all(&temp_dir).expect("should create temp dir");
-  temp_dir
-}
+mod common;
 
 #[test]
 fn apply_single_profile() {
@@ -183,7 +174,7 @@ fn profile_with_empty_fields_does_not_override() {
 
 #[test]
 fn load_config_with_single_profile_from_toml() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-profiles-test");
   let config_path = temp_dir.join("pruner.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -210,6 +201,7 @@ markdown = ["ci_prettier"]
   let config = pruner::config::load(LoadOpts {
     config_path: Some(config_path),
     profiles: vec!["ci".into()],
+    refresh_includes: false,
   })
   .expect("should load config");
 
@@ -230,7 +222,7 @@ markdown = ["ci_prettier"]
 
 #[test]
 fn load_config_with_multiple_profiles_from_toml() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-profiles-test");
   let config_path = temp_dir.join("pruner.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -265,6 +257,7 @@ markdown = ["debug_prettier"]
   let config = pruner::config::load(LoadOpts {
     config_path: Some(config_path),
     profiles: vec!["ci".into(), "debug".into()],
+    refresh_includes: false,
   })
   .expect("should load config");
 
@@ -296,7 +289,7 @@ markdown = ["debug_prettier"]
 
 #[test]
 fn load_config_with_nonexistent_profile_fails() {
-  let temp_dir = unique_temp_dir();
+  let temp_dir = common::unique_temp_dir("pruner-profiles-test");
   let config_path = temp_dir.join("pruner.toml");
 
   let mut file = File::create(&config_path).expect("should create config file");
@@ -316,6 +309,7 @@ query_paths = ["ci_queries"]
   let result = pruner::config::load(LoadOpts {
     config_path: Some(config_path),
     profiles: vec!["nonexistent".into()],
+    refresh_includes: false,
   });
 
   assert!(result.is_err());
@@ -326,3 +320,92 @@ query_paths = ["ci_queries"]
     err
   );
 }
+
+#[test]
+fn profiles_apply_the_profiles_they_extend_first() {
+  let temp_dir = common::unique_temp_dir("pruner-profiles-test");
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+query_paths = ["queries"]
+
+[languages]
+markdown = ["prettier"]
+rust = ["rustfmt"]
+sql = ["sqlfmt"]
+
+[profiles.ci]
+query_paths = ["ci_queries"]
+
+[profiles.ci.languages]
+markdown = ["ci_prettier"]
+rust = ["ci_rustfmt"]
+
+[profiles.ci-strict]
+extends = "ci"
+query_paths = ["strict_queries"]
+strict_ignore = true
+
+[profiles.ci-strict.languages]
+rust = ["strict_rustfmt"]
+"#,
+  )
+  .expect("should write config file");
+
+  let config = pruner::config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: vec!["ci-strict".into()],
+    refresh_includes: false,
+  })
+  .expect("should load config");
+
+  assert_eq!(
+    config.query_paths,
+    vec![
+      temp_dir.join("queries"),
+      temp_dir.join("ci_queries"),
+      temp_dir.join("strict_queries")
+    ]
+  );
+  assert_eq!(config.languages["markdown"], vec!["ci_prettier".into()]);
+  assert_eq!(config.languages["rust"], vec!["strict_rustfmt".into()]);
+  assert_eq!(config.languages["sql"], vec!["sqlfmt".into()]);
+  assert!(config.strict_ignore);
+}
+
+#[test]
+fn profile_inheritance_cycles_are_errors() {
+  let temp_dir = common::unique_temp_dir("pruner-profiles-test");
+  let config_path = temp_dir.join("pruner.toml");
+  fs::write(
+    &config_path,
+    r#"
+[profiles.a]
+extends = "b"
+
+[profiles.b]
+extends = "c"
+
+[profiles.c]
+extends = "a"
+
+[profiles.orphan]
+extends = "missing"
+"#,
+  )
+  .expect("should write config file");
+
+  let load = |profile: &str| {
+    pruner::config::load(LoadOpts {
+      config_path: Some(config_path.clone()),
+      profiles: vec![profile.into()],
+      refresh_includes: false,
+    })
+  };
+
+  let err = load("a").unwrap_err();
+  assert_eq!(err.to_string(), "Profiles form an inheritance cycle: a -> b -> c -> a");
+  let err = load("orphan").unwrap_err();
+  assert_eq!(err.to_string(), "Profile 'missing' not found");
+}
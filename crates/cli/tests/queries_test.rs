@@ -0,0 +1,153 @@
+use anyhow::Result;
+use std::{
+  fs,
+  path::PathBuf,
+};
+use tree_sitter::Query;
+
+use pruner::api::queries;
+
+mod common;
+
+fn query_dirs() -> Result<(PathBuf, PathBuf)> {
+  let temp_dir = common::unique_temp_dir("pruner-queries-test");
+  let search_path = temp_dir.join("queries");
+  fs::create_dir_all(search_path.join("clojure"))?;
+
+  let base = temp_dir.join("base.scm");
+  fs::write(
+    &base,
+    "((str_lit) @injection.content\n  (#set! injection.language \"markdown\"))\n",
+  )?;
+  fs::write(
+    search_path.join("clojure/injections.scm"),
+    ";; extends\n\n((sym_lit) @injection.content\n  (#set! injection.language \"sql\"))\n",
+  )?;
+
+  Ok((base, search_path))
+}
+
+#[test]
+fn provenance_markers_are_added_to_merged_queries() -> Result<()> {
+  let (base, search_path) = query_dirs()?;
+  let extension = search_path.join("clojure/injections.scm");
+
+  let source = queries::injections_query_source(
+    "clojure",
+    std::slice::from_ref(&base),
+    std::slice::from_ref(&search_path),
+    true,
+  )?;
+  assert!(source.starts_with(&format!(";; --- from {} ---\n", base.display())));
+  assert!(source.contains(&format!(
+    ";; --- from {} ---\n;; extends\n",
+    extension.display()
+  )));
+
+  let source = queries::injections_query_source(
+    "clojure",
+    std::slice::from_ref(&base),
+    std::slice::from_ref(&search_path),
+    false,
+  )?;
+  assert!(!source.contains(";; --- from"));
+
+  Ok(())
+}
+
+#[test]
+fn query_with_provenance_markers_compiles() -> Result<()> {
+  let grammars = common::grammars()?;
+  let clojure = grammars.get("clojure").expect("clojure grammar should load");
+  let (base, search_path) = query_dirs()?;
+
+  let source = queries::injections_query_source("clojure", &[base], &[search_path], true)?;
+  let query = Query::new(&clojure.lang, &source)?;
+  assert_eq!(query.pattern_count(), 2);
+
+  Ok(())
+}
+
+#[test]
+fn query_errors_name_the_file_they_occur_in() -> Result<()> {
+  let grammars = common::grammars()?;
+  let clojure = grammars.get("clojure").expect("clojure grammar should load");
+  let (base, search_path) = query_dirs()?;
+  let extension = search_path.join("clojure/injections.scm");
+  fs::write(
+    &extension,
+    ";; extends\n\n((sym_lit) @injection.content\n  (#set! injection.language \"sql\"))\n\n\
+     (not_a_node) @injection.content\n",
+  )?;
+
+  // Positions are reported within the extending file rather than the merged query
+  for provenance in ["0", "1"] {
+    unsafe { std::env::set_var("PRUNER_QUERY_PROVENANCE", provenance) };
+    let err = queries::load_injections_query(
+      &clojure.lang,
+      "clojure",
+      std::slice::from_ref(&base),
+      std::slice::from_ref(&search_path),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("Invalid injections query for clojure"), "{err}");
+    assert!(err.contains(&format!("{}:6:2", extension.display())), "{err}");
+    assert!(err.contains("\n  (not_a_node) @injection.content\n   ^"), "{err}");
+  }
+  unsafe { std::env::remove_var("PRUNER_QUERY_PROVENANCE") };
+
+  Ok(())
+}
+
+fn write_queries(files: &[(&str, &str)]) -> Result<PathBuf> {
+  let search_path = common::unique_temp_dir("pruner-queries-inherits");
+  for (language, contents) in files {
+    fs::create_dir_all(search_path.join(language))?;
+    fs::write(search_path.join(language).join("injections.scm"), contents)?;
+  }
+  Ok(search_path)
+}
+
+#[test]
+fn inherited_queries_are_included_first() -> Result<()> {
+  let search_path = write_queries(&[
+    ("javascript", "((template_string) @injection.content)\n"),
+    ("typescript", ";; inherits: javascript\n((string) @injection.content)\n"),
+  ])?;
+
+  let search_paths = std::slice::from_ref(&search_path);
+  let source = queries::injections_query_source("typescript", &[], search_paths, false)?;
+  assert_eq!(
+    source,
+    "((template_string) @injection.content)\n\
+     ;; inherits: javascript\n((string) @injection.content)\n"
+  );
+
+  let _ = fs::remove_dir_all(&search_path);
+  Ok(())
+}
+
+#[test]
+fn inheritance_cycles_are_errors() -> Result<()> {
+  let search_path = write_queries(&[
+    ("a", ";; inherits: b\n((a) @injection.content)\n"),
+    ("b", ";; inherits: c\n((b) @injection.content)\n"),
+    ("c", ";; inherits: a\n((c) @injection.content)\n"),
+    ("d", ";; inherits: missing\n((d) @injection.content)\n"),
+  ])?;
+
+  let resolve = |language: &str| {
+    queries::injections_query_source(language, &[], std::slice::from_ref(&search_path), false)
+  };
+
+  let err = resolve("a").unwrap_err();
+  assert!(err.to_string().contains("inheritance cycle"), "{err}");
+  assert!(err.to_string().ends_with(": a -> b -> c -> a"), "{err}");
+
+  let err = resolve("d").unwrap_err();
+  assert!(err.to_string().contains("inherits from missing"), "{err}");
+
+  let _ = fs::remove_dir_all(&search_path);
+  Ok(())
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use pruner::api::grammar::{self, Grammars};
+
+#[test]
+fn configured_language_without_grammar_is_an_error() {
+  let grammars = Grammars::new();
+  let languages = HashMap::from([
+    ("rust".to_string(), vec!["rustfmt".into()]),
+    ("markdown".to_string(), vec!["prettier".into()]),
+  ]);
+
+  assert_eq!(
+    grammar::missing_grammars(&languages, &grammars),
+    vec!["markdown".to_string(), "rust".to_string()]
+  );
+
+  let err = grammar::ensure_grammars(&languages, &grammars).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Missing grammars for configured languages: markdown, rust"
+  );
+}
+
+#[test]
+fn no_configured_languages_is_ok() {
+  let grammars = Grammars::new();
+  let languages = HashMap::new();
+
+  assert!(grammar::ensure_grammars(&languages, &grammars).is_ok());
+}
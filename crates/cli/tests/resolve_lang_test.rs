@@ -0,0 +1,39 @@
+use std::fs;
+
+mod common;
+
+fn resolve_lang(args: &[&str]) -> String {
+  let temp_dir = common::unique_temp_dir("pruner-resolve-lang");
+  common::write_config(
+    &temp_dir,
+    r#"
+[language_aliases]
+typescript = ["ts"]
+ts = ["tsx", "typescriptreact"]
+"#,
+  );
+
+  let output = common::run_pruner(&temp_dir, &[&["resolve-lang"], args].concat());
+  let _ = fs::remove_dir_all(&temp_dir);
+
+  assert!(
+    output.status.success(),
+    "{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  String::from_utf8(output.stdout).expect("output should be utf8")
+}
+
+#[test]
+fn resolves_chained_alias() {
+  assert_eq!(resolve_lang(&["typescriptreact"]), "typescript\n");
+  assert_eq!(resolve_lang(&["rust"]), "rust\n");
+}
+
+#[test]
+fn applies_gsub_before_aliases() {
+  assert_eq!(
+    resolve_lang(&["--gsub", "^lang:(%w+)$", "%1", "lang:tsx"]),
+    "typescript\n"
+  );
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::format::{self, FormatContext, FormatOpts},
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+fn format_with_trailing_newlines(source: &str, language: &str) -> Result<String> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "cat".to_string(),
+    FormatterSpec {
+      cmd: "cat".into(),
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("sql".to_string(), vec!["cat".into()])]);
+  let injection_trailing_newlines = HashMap::from([("markdown".to_string(), 1)]);
+
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      indent: 0,
+    },
+    true,
+    true,
+    &FormatContext {
+      injection_trailing_newlines: &injection_trailing_newlines,
+      ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+    },
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn markdown_host_normalizes_trailing_newlines() -> Result<()> {
+  let result = format_with_trailing_newlines("```sql\nselect 1\n\n\n```\n", "markdown")?;
+  assert_eq!(result, "```sql\nselect 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn clojure_host_preserves_trailing_newlines() -> Result<()> {
+  let source = "(def query \"SELECT 1\n\n\")\n";
+  let result = format_with_trailing_newlines(source, "clojure")?;
+  assert_eq!(result, source);
+
+  Ok(())
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::format::{self, FormatContext, FormatOpts},
+  config::FormatterSpec,
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+const SOURCE: &str = "```clojure\n(def a 1)\n```\n";
+
+#[test]
+fn formatters_introducing_syntax_errors_are_caught() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  // Leaves an unclosed list behind, which no longer parses as clojure
+  let formatters = HashMap::from([(
+    "unbalance".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "cat; echo '('".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("clojure".to_string(), vec!["unbalance".into()])]);
+
+  let unverified = common::format_context(&grammars, &languages, &formatters, &wasm_formatter);
+  let verified = FormatContext {
+    verify_roundtrip: true,
+    ..unverified
+  };
+  let opts = FormatOpts {
+    printwidth: 80,
+    language: "markdown",
+    indent: 0,
+  };
+
+  let formatted = format::format(SOURCE.as_bytes(), &opts, true, true, &unverified)?;
+  assert_eq!(
+    String::from_utf8(formatted)?,
+    "```clojure\n(def a 1)\n(\n```\n"
+  );
+
+  let err = format::format(SOURCE.as_bytes(), &opts, true, true, &verified)
+    .expect_err("the corrupted region should fail verification");
+  assert!(
+    format!("{err:#}").contains("introduced syntax errors into clojure"),
+    "{err:#}"
+  );
+
+  // Leniently, the corrupted region is reverted
+  let outcome = format::format_lenient(SOURCE.as_bytes(), &opts, true, true, &verified)?;
+  assert_eq!(String::from_utf8(outcome.formatted)?, SOURCE);
+  assert_eq!(outcome.errors.len(), 1);
+  assert_eq!(outcome.errors[0].language, "clojure");
+
+  Ok(())
+}
+
+#[test]
+fn regions_which_already_had_syntax_errors_are_still_verified() -> Result<()> {
+  let grammars = common::grammars()?;
+  let wasm_formatter = WasmFormatter::new("cache".into())?;
+  let formatters = HashMap::from([(
+    "unbalance".to_string(),
+    FormatterSpec {
+      cmd: "sh".into(),
+      args: vec!["-c".into(), "cat; echo '('".into()],
+      ..Default::default()
+    },
+  )]);
+  let languages = HashMap::from([("clojure".to_string(), vec!["unbalance".into()])]);
+  let ctx = FormatContext {
+    verify_roundtrip: true,
+    ..common::format_context(&grammars, &languages, &formatters, &wasm_formatter)
+  };
+  let opts = FormatOpts {
+    printwidth: 80,
+    language: "markdown",
+    indent: 0,
+  };
+
+  // The stray closing paren is already an error, the formatter adds an unclosed list on top of it
+  let source = "```clojure\n(def a 1))\n```\n";
+  let err = format::format(source.as_bytes(), &opts, true, true, &ctx)
+    .expect_err("the additional error should fail verification");
+  assert!(
+    format!("{err:#}").contains("introduced syntax errors into clojure"),
+    "{err:#}"
+  );
+
+  Ok(())
+}
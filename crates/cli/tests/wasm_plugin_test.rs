@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+};
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    grammar::Grammars,
+  },
+  config::{self, Config, FormatterKind, LoadOpts},
+  wasm::formatter::WasmFormatter,
+};
+
+mod common;
+
+/// Load `config` from a directory alongside the `upper` plugin, which upper-cases its input.
+fn load_config(dir: &Path, config: &str) -> Result<Config> {
+  fs::copy("tests/fixtures/wasm/upper.wat", dir.join("upper.wat"))?;
+  let config_path = dir.join("pruner.toml");
+  fs::write(&config_path, config)?;
+  config::load(LoadOpts {
+    config_path: Some(config_path),
+    profiles: Vec::new(),
+    refresh_includes: false,
+  })
+}
+
+fn format_context<'a>(
+  config: &'a Config,
+  grammars: &'a Grammars,
+  wasm_formatter: &'a WasmFormatter,
+) -> FormatContext<'a> {
+  FormatContext {
+    language_aliases: &config.language_aliases,
+    formatter_environment: &config.formatter_environment,
+    injection_trailing_newlines: &config.injection_trailing_newlines,
+    injection_indent: &config.injection_indent,
+    print_width: &config.print_width,
+    injection_reindent: &config.injection_reindent,
+    grammar_language_map: &config.grammar_language_map,
+    formatter_language_map: &config.formatter_language_map,
+    formatter_kinds: &config.formatter_kinds,
+    ..common::format_context(
+      grammars,
+      &config.formatter_languages,
+      &config.formatters,
+      wasm_formatter,
+    )
+  }
+}
+
+fn format_text(config: &Config) -> Result<String> {
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::from_config(config)?;
+  let result = format::format(
+    b"Hello, Wasm\n",
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      indent: 0,
+    },
+    true,
+    true,
+    &format_context(config, &grammars, &wasm_formatter),
+  )?;
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn plugins_are_formatters_by_name() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-wasm-plugin");
+  let config = load_config(
+    &temp_dir,
+    r#"
+[plugins]
+upper = "upper.wat"
+
+[languages]
+text = ["upper"]
+"#,
+  )?;
+
+  assert_eq!(format_text(&config)?, "HELLO, WASM\n");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}
+
+#[test]
+fn formatter_kinds_resolve_name_collisions() -> Result<()> {
+  let temp_dir = common::unique_temp_dir("pruner-wasm-collision");
+  let collision = r#"
+[plugins]
+upper = "upper.wat"
+
+[formatters.upper]
+cmd = "tr"
+args = ["A-Z", "a-z"]
+
+[languages]
+text = ["upper"]
+"#;
+
+  // Formatters take precedence over plugins of the same name
+  let config = load_config(&temp_dir, collision)?;
+  let grammars = Grammars::new();
+  let wasm_formatter = WasmFormatter::from_config(&config)?;
+  let ctx = format_context(&config, &grammars, &wasm_formatter);
+  assert_eq!(format::resolve_formatter("upper", &ctx)?, Some(FormatterKind::Shell));
+  assert_eq!(format::resolve_formatter("missing", &ctx)?, None);
+  assert_eq!(
+    format::resolve_formatter_kinds(&ctx)?,
+    HashMap::from([("upper".to_string(), FormatterKind::Shell)])
+  );
+  assert_eq!(format_text(&config)?, "hello, wasm\n");
+
+  let config = load_config(
+    &temp_dir,
+    &format!("{collision}\n[formatter_kinds]\nupper = \"wasm\"\n"),
+  )?;
+  assert_eq!(format_text(&config)?, "HELLO, WASM\n");
+
+  // Forcing a kind which is not configured is an error rather than silently skipping the formatter
+  let config = load_config(
+    &temp_dir,
+    "[formatters.upper]\ncmd = \"tr\"\nargs = []\n\n[formatter_kinds]\nupper = \"wasm\"\n",
+  )?;
+  let wasm_formatter = WasmFormatter::from_config(&config)?;
+  let ctx = format_context(&config, &grammars, &wasm_formatter);
+  let err = format::resolve_formatter("upper", &ctx).unwrap_err();
+  assert!(err.to_string().contains("configured as wasm but is not a plugin"), "{err}");
+
+  let _ = fs::remove_dir_all(&temp_dir);
+  Ok(())
+}